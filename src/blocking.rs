@@ -0,0 +1,158 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use crate::{
+    operations::OperationResult,
+    InetStack,
+};
+use ::libc::EPROTO;
+use ::runtime::{
+    fail::Fail,
+    memory::Buffer,
+    network::NetworkRuntime,
+    task::SchedulerRuntime,
+    QDesc,
+    QToken,
+};
+use ::std::{
+    collections::HashMap,
+    net::SocketAddrV4,
+};
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+/// Blocking TCP sockets API, for quick scripts and tools that do not need the poll/wait model. Each operation drives
+/// the stack until it completes, much like the sockets in [std::net].
+pub struct BlockingInetStack<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
+    stack: InetStack<RT>,
+    /// Received data that did not fit in the buffer of a previous read, for each connection.
+    unread: HashMap<QDesc, Buffer>,
+}
+
+//==============================================================================
+// Associated Functions
+//==============================================================================
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> BlockingInetStack<RT> {
+    /// Wraps a stack.
+    pub fn new(stack: InetStack<RT>) -> Self {
+        Self {
+            stack,
+            unread: HashMap::new(),
+        }
+    }
+
+    /// Gets the wrapped stack, for operations that are not covered by the blocking API.
+    pub fn get_mut(&mut self) -> &mut InetStack<RT> {
+        &mut self.stack
+    }
+
+    /// Unwraps the stack.
+    pub fn into_inner(self) -> InetStack<RT> {
+        self.stack
+    }
+
+    /// Creates a socket that listens for connections on the given local endpoint.
+    pub fn listen(&mut self, local: SocketAddrV4, backlog: usize) -> Result<QDesc, Fail> {
+        let qd: QDesc = self.stack.socket(libc::AF_INET, libc::SOCK_STREAM, 0)?;
+        self.stack.bind(qd, local)?;
+        self.stack.listen(qd, backlog)?;
+        Ok(qd)
+    }
+
+    /// Waits for a connection on a listening socket. Returns the socket of the new connection, along with the remote
+    /// endpoint of it.
+    pub fn accept(&mut self, qd: QDesc) -> Result<(QDesc, SocketAddrV4), Fail> {
+        let qt: QToken = self.stack.accept(qd)?;
+        match self.wait(qt)? {
+            OperationResult::Accept(new_qd) => {
+                let (_, remote): (SocketAddrV4, SocketAddrV4) = self.stack.ipv4.tcp.endpoints(new_qd)?;
+                Ok((new_qd, remote))
+            },
+            r => Err(unexpected_result(r)),
+        }
+    }
+
+    /// Opens a connection to a remote endpoint.
+    pub fn connect(&mut self, remote: SocketAddrV4) -> Result<QDesc, Fail> {
+        let qd: QDesc = self.stack.socket(libc::AF_INET, libc::SOCK_STREAM, 0)?;
+        let qt: QToken = self.stack.connect(qd, remote)?;
+        match self.wait(qt)? {
+            OperationResult::Connect => Ok(qd),
+            r => Err(unexpected_result(r)),
+        }
+    }
+
+    /// Writes data to a connection. Returns how many bytes were written, which is always all of them.
+    pub fn write(&mut self, qd: QDesc, buf: &[u8]) -> Result<usize, Fail> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let qt: QToken = self.stack.push2(qd, buf)?;
+        match self.wait(qt)? {
+            OperationResult::Push => Ok(buf.len()),
+            r => Err(unexpected_result(r)),
+        }
+    }
+
+    /// Waits until all data that was written to a connection has been acknowledged by our peer.
+    pub fn flush(&mut self, qd: QDesc) -> Result<(), Fail> {
+        let qt: QToken = self.stack.flush(qd)?;
+        match self.wait(qt)? {
+            OperationResult::Flush => Ok(()),
+            r => Err(unexpected_result(r)),
+        }
+    }
+
+    /// Reads data from a connection into the given buffer. Returns how many bytes were read, which is zero once our
+    /// peer has closed its side of the connection.
+    pub fn read(&mut self, qd: QDesc, buf: &mut [u8]) -> Result<usize, Fail> {
+        let mut data: Buffer = match self.unread.remove(&qd) {
+            Some(data) => data,
+            None => {
+                let qt: QToken = self.stack.pop(qd)?;
+                match self.wait(qt)? {
+                    OperationResult::Pop(_, data) => data,
+                    r => return Err(unexpected_result(r)),
+                }
+            },
+        };
+        let nbytes: usize = data.len().min(buf.len());
+        buf[..nbytes].copy_from_slice(&data[..nbytes]);
+        if nbytes < data.len() {
+            data.adjust(nbytes);
+            self.unread.insert(qd, data);
+        }
+        Ok(nbytes)
+    }
+
+    /// Closes a socket.
+    pub fn close(&mut self, qd: QDesc) -> Result<(), Fail> {
+        self.unread.remove(&qd);
+        self.stack.close(qd)
+    }
+
+    /// Drives the stack until an operation completes.
+    fn wait(&mut self, qt: QToken) -> Result<OperationResult, Fail> {
+        match self.stack.wait2(qt)? {
+            (_, OperationResult::Failed(e)) => Err(e),
+            (_, r) => Ok(r),
+        }
+    }
+}
+
+//==============================================================================
+// Standalone Functions
+//==============================================================================
+
+/// Builds the error for an operation that completed with the result of some other kind of operation.
+fn unexpected_result(r: OperationResult) -> Fail {
+    error!("Unexpected operation result: {:?}", r);
+    Fail::new(EPROTO, "unexpected operation result")
+}
@@ -2,5 +2,7 @@
 // Licensed under the MIT license.
 
 pub mod hashttlcache;
+pub mod ringbuffer;
 
 pub use hashttlcache::HashTtlCache;
+pub use ringbuffer::RingBuffer;
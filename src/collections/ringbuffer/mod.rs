@@ -0,0 +1,144 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+#[cfg(test)]
+mod tests;
+
+use std::cmp;
+
+/// # Ring Buffer
+///
+/// Byte queue of a fixed capacity. Bytes are written at the tail and read
+/// from the head, wrapping around the end of the storage, so that neither
+/// writing nor reading allocates once the storage is in place. Bytes may also
+/// be written past the tail and made readable later, e.g. to reassemble data
+/// that arrives out of order. The storage is allocated as it is first needed,
+/// so a large capacity costs nothing until it is used.
+pub struct RingBuffer {
+    /// Storage, which grows up to the capacity.
+    buf: Vec<u8>,
+    /// Offset of the first unread byte in the storage.
+    head: usize,
+    /// Number of unread bytes.
+    len: usize,
+    /// Largest number of unread bytes.
+    capacity: usize,
+}
+
+impl RingBuffer {
+    /// Instantiates a ring buffer.
+    pub fn new(capacity: usize) -> RingBuffer {
+        RingBuffer {
+            buf: Vec::new(),
+            head: 0,
+            len: 0,
+            capacity,
+        }
+    }
+
+    /// Gets the largest number of bytes that the ring buffer holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Gets the number of unread bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the number of bytes that may still be written.
+    pub fn available(&self) -> usize {
+        self.capacity - self.len
+    }
+
+    /// Writes bytes at the tail. Bytes that do not fit are left out. Returns
+    /// the number of bytes written.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let nbytes: usize = self.write_at(0, data);
+        self.commit(nbytes)
+    }
+
+    /// Writes bytes `offset` bytes past the tail, without making them
+    /// readable. Bytes that do not fit are left out. Returns the number of
+    /// bytes written.
+    pub fn write_at(&mut self, offset: usize, data: &[u8]) -> usize {
+        let nbytes: usize = cmp::min(data.len(), self.available().saturating_sub(offset));
+        if nbytes == 0 {
+            return 0;
+        }
+        self.reserve(self.len + offset + nbytes);
+        let start: usize = (self.head + self.len + offset) % self.buf.len();
+        let first: usize = cmp::min(nbytes, self.buf.len() - start);
+        self.buf[start..(start + first)].copy_from_slice(&data[..first]);
+        self.buf[..(nbytes - first)].copy_from_slice(&data[first..nbytes]);
+        nbytes
+    }
+
+    /// Makes up to `nbytes` bytes past the tail readable, whether or not they
+    /// were written. Returns the number of bytes made readable.
+    pub fn commit(&mut self, nbytes: usize) -> usize {
+        let nbytes: usize = cmp::min(nbytes, self.available());
+        self.reserve(self.len + nbytes);
+        self.len += nbytes;
+        nbytes
+    }
+
+    /// Gets the unread bytes, which are split in two slices if they wrap
+    /// around the end of the storage.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let first: usize = cmp::min(self.len, self.buf.len() - self.head);
+        (
+            &self.buf[self.head..(self.head + first)],
+            &self.buf[..(self.len - first)],
+        )
+    }
+
+    /// Copies bytes from the head into `buf`, without consuming them. Returns
+    /// the number of bytes copied.
+    pub fn peek(&self, buf: &mut [u8]) -> usize {
+        let (front, back): (&[u8], &[u8]) = self.as_slices();
+        let first: usize = cmp::min(buf.len(), front.len());
+        let second: usize = cmp::min(buf.len() - first, back.len());
+        buf[..first].copy_from_slice(&front[..first]);
+        buf[first..(first + second)].copy_from_slice(&back[..second]);
+        first + second
+    }
+
+    /// Discards up to `nbytes` bytes from the head. Returns the number of
+    /// bytes discarded.
+    pub fn consume(&mut self, nbytes: usize) -> usize {
+        let nbytes: usize = cmp::min(nbytes, self.len);
+        if nbytes > 0 {
+            self.head = (self.head + nbytes) % self.buf.len();
+            self.len -= nbytes;
+        }
+        nbytes
+    }
+
+    /// Copies bytes from the head into `buf`, consuming them. Returns the
+    /// number of bytes copied.
+    pub fn pop_into(&mut self, buf: &mut [u8]) -> usize {
+        let nbytes: usize = self.peek(buf);
+        self.consume(nbytes)
+    }
+
+    /// Grows the storage to hold at least `len` bytes, moving its contents
+    /// to the beginning of the new storage. All of it is moved, not just the
+    /// unread bytes, as bytes may have been written past the tail.
+    fn reserve(&mut self, len: usize) {
+        if len <= self.buf.len() {
+            return;
+        }
+        let size: usize = cmp::min(cmp::max(len, 2 * self.buf.len()), self.capacity);
+        let mut buf: Vec<u8> = vec![0; size];
+        let (back, front): (&[u8], &[u8]) = self.buf.split_at(self.head);
+        buf[..front.len()].copy_from_slice(front);
+        buf[front.len()..self.buf.len()].copy_from_slice(back);
+        self.buf = buf;
+        self.head = 0;
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::RingBuffer;
+
+/// Tests that bytes written past the end of the storage are read back in order.
+#[test]
+fn wrap_around() {
+    let mut ring = RingBuffer::new(8);
+
+    // Fill the ring buffer to capacity. Further bytes are left out.
+    assert_eq!(ring.push(&[0, 1, 2, 3, 4, 5, 6, 7, 8]), 8);
+    assert_eq!(ring.len(), 8);
+    assert_eq!(ring.available(), 0);
+    assert_eq!(ring.push(&[8]), 0);
+
+    // Drain part of it.
+    let mut buf: [u8; 5] = [0; 5];
+    assert_eq!(ring.pop_into(&mut buf), 5);
+    assert_eq!(buf, [0, 1, 2, 3, 4]);
+
+    // Write bytes that wrap around the end of the storage.
+    assert_eq!(ring.push(&[8, 9, 10, 11, 12]), 5);
+    let (front, back): (&[u8], &[u8]) = ring.as_slices();
+    assert_eq!(front, [5, 6, 7]);
+    assert_eq!(back, [8, 9, 10, 11, 12]);
+
+    // Reading them back yields contiguous bytes.
+    let mut buf: [u8; 8] = [0; 8];
+    assert_eq!(ring.peek(&mut buf), 8);
+    assert_eq!(buf, [5, 6, 7, 8, 9, 10, 11, 12]);
+    let mut buf: [u8; 16] = [0; 16];
+    assert_eq!(ring.pop_into(&mut buf), 8);
+    assert_eq!(buf[..8], [5, 6, 7, 8, 9, 10, 11, 12]);
+    assert!(ring.is_empty());
+}
+
+/// Tests that the storage grows as bytes are written, keeping unread bytes in order.
+#[test]
+fn grow() {
+    let mut ring = RingBuffer::new(1024);
+    assert_eq!(ring.push(&[0, 1, 2, 3]), 4);
+    assert_eq!(ring.consume(2), 2);
+    let data: Vec<u8> = (4..100).collect();
+    assert_eq!(ring.push(&data), data.len());
+
+    let mut buf: [u8; 98] = [0; 98];
+    assert_eq!(ring.pop_into(&mut buf), 98);
+    assert!(buf.iter().copied().eq(2..100));
+    assert!(ring.is_empty());
+}
+
+/// Tests that bytes written past the tail become readable in order once the gap before them is filled.
+#[test]
+fn write_past_tail() {
+    let mut ring = RingBuffer::new(8);
+    assert_eq!(ring.push(&[0; 8]), 8);
+    assert_eq!(ring.consume(8), 8);
+    assert_eq!(ring.push(&[0, 1, 2, 3, 4, 5]), 6);
+    assert_eq!(ring.consume(4), 4);
+
+    // Write bytes one past the tail, which wrap around the end of the storage. They are not readable yet.
+    assert_eq!(ring.write_at(1, &[7, 8, 9, 10]), 4);
+    assert_eq!(ring.len(), 2);
+
+    // Bytes past the capacity are left out.
+    assert_eq!(ring.write_at(6, &[12, 13, 14]), 0);
+    assert_eq!(ring.write_at(5, &[11, 12, 13]), 1);
+
+    // Fill the gap, and make everything readable.
+    assert_eq!(ring.write_at(0, &[6]), 1);
+    assert_eq!(ring.commit(16), 6);
+    let mut buf: [u8; 8] = [0; 8];
+    assert_eq!(ring.pop_into(&mut buf), 8);
+    assert_eq!(buf, [4, 5, 6, 7, 8, 9, 10, 11]);
+    assert!(ring.is_empty());
+}
+
+/// Tests that bytes written past the tail survive the storage growing.
+#[test]
+fn grow_past_tail() {
+    let mut ring = RingBuffer::new(1024);
+    assert_eq!(ring.push(&[0, 1]), 2);
+    assert_eq!(ring.consume(1), 1);
+    assert_eq!(ring.write_at(1, &[3]), 1);
+    let data: Vec<u8> = (4..100).collect();
+    assert_eq!(ring.write_at(2, &data), data.len());
+    assert_eq!(ring.write_at(0, &[2]), 1);
+    assert_eq!(ring.commit(98), 98);
+
+    let mut buf: [u8; 99] = [0; 99];
+    assert_eq!(ring.pop_into(&mut buf), 99);
+    assert!(buf.iter().copied().eq(1..100));
+}
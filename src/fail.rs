@@ -0,0 +1,101 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use ::libc::{
+    EADDRINUSE,
+    EAGAIN,
+    EBADF,
+    EBADMSG,
+    ECONNABORTED,
+    ECONNREFUSED,
+    ECONNRESET,
+    EHOSTUNREACH,
+    EINVAL,
+    EMSGSIZE,
+    ENETUNREACH,
+    ENOTCONN,
+    ENOTSUP,
+    EPROTO,
+    ETIMEDOUT,
+};
+use ::runtime::fail::Fail;
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+/// Error Kind
+///
+/// Category of a [Fail], so that callers can match on a typed variant rather than on its raw error number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The remote peer refused the connection (`ECONNREFUSED`).
+    ConnectionRefused,
+    /// The connection was aborted locally (`ECONNABORTED`).
+    ConnectionAborted,
+    /// The connection was reset by the remote peer (`ECONNRESET`).
+    ConnectionReset,
+    /// The socket is not connected (`ENOTCONN`).
+    NotConnected,
+    /// The remote host or network cannot be reached (`EHOSTUNREACH`, `ENETUNREACH`).
+    Unreachable,
+    /// The operation timed out (`ETIMEDOUT`).
+    Timeout,
+    /// The operation cannot complete right now (`EAGAIN`).
+    WouldBlock,
+    /// A received message is malformed (`EBADMSG`, `EPROTO`).
+    BadMessage,
+    /// The message does not fit (`EMSGSIZE`).
+    MessageTooLarge,
+    /// The operation or message is not supported (`ENOTSUP`).
+    Unsupported,
+    /// The queue descriptor is not valid (`EBADF`).
+    BadDescriptor,
+    /// An argument is not valid (`EINVAL`).
+    InvalidArgument,
+    /// The address is already in use (`EADDRINUSE`).
+    AddressInUse,
+    /// Any other error.
+    Other,
+}
+
+/// Extensions of [Fail] that are not provided by the runtime.
+pub trait FailExt {
+    /// Gets the kind of the target error.
+    fn kind(&self) -> ErrorKind;
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+impl From<i32> for ErrorKind {
+    fn from(errno: i32) -> Self {
+        match errno {
+            ECONNREFUSED => ErrorKind::ConnectionRefused,
+            ECONNABORTED => ErrorKind::ConnectionAborted,
+            ECONNRESET => ErrorKind::ConnectionReset,
+            ENOTCONN => ErrorKind::NotConnected,
+            EHOSTUNREACH | ENETUNREACH => ErrorKind::Unreachable,
+            ETIMEDOUT => ErrorKind::Timeout,
+            EAGAIN => ErrorKind::WouldBlock,
+            EBADMSG | EPROTO => ErrorKind::BadMessage,
+            EMSGSIZE => ErrorKind::MessageTooLarge,
+            ENOTSUP => ErrorKind::Unsupported,
+            EBADF => ErrorKind::BadDescriptor,
+            EINVAL => ErrorKind::InvalidArgument,
+            EADDRINUSE => ErrorKind::AddressInUse,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl FailExt for Fail {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::from(self.errno)
+    }
+}
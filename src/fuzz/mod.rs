@@ -0,0 +1,36 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Entry points for fuzzing the parsers of the stack. Each one feeds arbitrary bytes into a parser, which must return
+//! an error on malformed input rather than panic.
+
+#[cfg(test)]
+mod tests;
+
+use crate::protocols::{
+    ip::IpProtocol,
+    ipv4::Ipv4Header,
+    tcp::segment::TcpHeader,
+};
+use ::runtime::{
+    fail::Fail,
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
+};
+use ::std::net::Ipv4Addr;
+
+/// Parses arbitrary bytes as an IPv4 datagram.
+pub fn fuzz_parse_ipv4(data: &[u8]) -> Result<(), Fail> {
+    Ipv4Header::parse(Buffer::Heap(DataBuffer::from_slice(data)))?;
+    Ok(())
+}
+
+/// Parses arbitrary bytes as a TCP segment. Checksum validation is disabled, as otherwise hardly any input would make
+/// it past the checksum to the rest of the parser.
+pub fn fuzz_parse_tcp(data: &[u8]) -> Result<(), Fail> {
+    let ipv4_header: Ipv4Header = Ipv4Header::new(Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST, IpProtocol::TCP);
+    TcpHeader::parse(&ipv4_header, Buffer::Heap(DataBuffer::from_slice(data)), true)?;
+    Ok(())
+}
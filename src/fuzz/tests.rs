@@ -0,0 +1,107 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    fuzz_parse_ipv4,
+    fuzz_parse_tcp,
+};
+use ::libc::{
+    EBADMSG,
+    ENOTSUP,
+};
+use ::runtime::fail::Fail;
+
+//=============================================================================
+
+/// Builds the bytes of a minimal IPv4 header, which is valid but for its checksum.
+fn cook_ipv4_header() -> [u8; 20] {
+    let mut bytes: [u8; 20] = [0; 20];
+    // Version and IHL.
+    bytes[0] = 0x45;
+    // Total length.
+    bytes[2..4].copy_from_slice(&20u16.to_be_bytes());
+    // Time to live.
+    bytes[8] = 64;
+    // Protocol.
+    bytes[9] = 0x06;
+    bytes
+}
+
+/// Builds the bytes of a minimal IPv4 header and applies `f` to them.
+fn mutate_ipv4_header<F: FnOnce(&mut [u8; 20])>(f: F) -> Vec<u8> {
+    let mut bytes: [u8; 20] = cook_ipv4_header();
+    f(&mut bytes);
+    bytes.to_vec()
+}
+
+/// Builds the bytes of a minimal TCP header with the given data offset (in 32-bit words), followed by `options`.
+fn cook_tcp_header(data_offset: u8, options: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0; 20];
+    bytes[12] = data_offset << 4;
+    bytes.extend_from_slice(options);
+    bytes
+}
+
+//=============================================================================
+
+/// Tests if malformed IPv4 datagrams are rejected with errors.
+#[test]
+fn test_fuzz_ipv4_corpus() {
+    let corpus: Vec<(Vec<u8>, i32)> = vec![
+        // Empty and truncated datagrams.
+        (vec![], EBADMSG),
+        (cook_ipv4_header()[..19].to_vec(), EBADMSG),
+        // Wrong version.
+        (mutate_ipv4_header(|bytes| bytes[0] = 0x65), ENOTSUP),
+        // Header length below the minimum.
+        (mutate_ipv4_header(|bytes| bytes[0] = 0x44), EBADMSG),
+        // Total length shorter than the header, and longer than the datagram.
+        (
+            mutate_ipv4_header(|bytes| bytes[2..4].copy_from_slice(&19u16.to_be_bytes())),
+            EBADMSG,
+        ),
+        (
+            mutate_ipv4_header(|bytes| bytes[2..4].copy_from_slice(&u16::MAX.to_be_bytes())),
+            EBADMSG,
+        ),
+        // Zero time to live.
+        (mutate_ipv4_header(|bytes| bytes[8] = 0), EBADMSG),
+        // Bad checksum.
+        (cook_ipv4_header().to_vec(), EBADMSG),
+    ];
+
+    for (bytes, errno) in corpus {
+        match fuzz_parse_ipv4(&bytes) {
+            Err(error) if error.errno == errno => (),
+            r => panic!("{:?} should have failed with {}, got {:?}", bytes, errno, r),
+        }
+    }
+}
+
+/// Tests if malformed TCP segments are rejected with errors.
+#[test]
+fn test_fuzz_tcp_corpus() {
+    let corpus: Vec<Vec<u8>> = vec![
+        // Empty and truncated segments.
+        vec![],
+        cook_tcp_header(5, &[])[..19].to_vec(),
+        // Data offset below the minimum, and beyond the end of the segment.
+        cook_tcp_header(4, &[]),
+        cook_tcp_header(6, &[]),
+        // Option lengths that do not match their kind.
+        cook_tcp_header(6, &[2, 3, 5, 180]),
+        cook_tcp_header(6, &[3, 4, 7, 0]),
+        // Options that run past the end of the header.
+        cook_tcp_header(6, &[8, 10, 0, 0]),
+        cook_tcp_header(7, &[5, 34, 0, 0, 0, 0, 0, 0]),
+        cook_tcp_header(6, &[1, 1, 1, 2]),
+        // Unknown option.
+        cook_tcp_header(6, &[99, 4, 0, 0]),
+    ];
+
+    for bytes in corpus {
+        // Truncated options are reported by the underlying reader, so only check that parsing failed.
+        let result: Result<(), Fail> = fuzz_parse_tcp(&bytes);
+        assert!(result.is_err(), "{:?} should have failed", bytes);
+    }
+}
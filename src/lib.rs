@@ -23,19 +23,31 @@ use crate::{
     futures::operation::FutureOperation,
     operations::OperationResult,
     protocols::{
-        arp::ArpPeer,
+        arp::{
+            ArpPeer,
+            Gateway,
+        },
+        dhcp::{
+            DhcpClient,
+            DhcpLease,
+        },
         ethernet2::{
             EtherType2,
             Ethernet2Header,
+            VlanTag,
         },
+        ip::IpProtocol,
+        ipv4::RoutingTable,
         tcp::operations::ConnectFuture,
         udp::UdpOperation,
+        Ipv4Stats,
         Peer,
     },
 };
 use ::libc::{
     c_int,
     EBADF,
+    EEXIST,
     EINVAL,
     ENOTSUP,
 };
@@ -73,7 +85,12 @@ use ::runtime::perftools::timer;
 #[cfg(test)]
 pub mod test_helpers;
 
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+
+pub mod blocking;
 pub mod collections;
+pub mod fail;
 pub mod futures;
 pub mod operations;
 pub mod options;
@@ -92,6 +109,11 @@ pub struct InetStack<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     file_table: IoQueueTable,
     rt: RT,
     ts_iters: usize,
+    rng_seed: [u8; 32],
+    /// DHCP client, if we acquire our address with DHCP.
+    dhcp: Option<DhcpClient<RT>>,
+    /// Number of incoming frames that were dropped because they were addressed to some other host.
+    dropped_link_addr_mismatch: usize,
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
@@ -106,6 +128,9 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
             file_table,
             rt,
             ts_iters: 0,
+            rng_seed,
+            dhcp: None,
+            dropped_link_addr_mismatch: 0,
         })
     }
 
@@ -113,6 +138,63 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
         &self.rt
     }
 
+    /// Returns the number of incoming frames that were dropped because they were addressed to some other host.
+    pub fn dropped_link_addr_mismatch(&self) -> usize {
+        self.dropped_link_addr_mismatch
+    }
+
+    /// Returns the number of incoming IPv4 datagrams by protocol, along with those dropped for an unknown protocol.
+    pub fn ipv4_stats(&self) -> &Ipv4Stats {
+        self.ipv4.stats()
+    }
+
+    /// Sets the IPv4 protocols that we accept datagrams for. Datagrams for other protocols are dropped and counted in
+    /// [Self::ipv4_stats].
+    pub fn set_allowed_protocols(&mut self, protocols: &[IpProtocol]) {
+        self.ipv4.set_allowed_protocols(protocols)
+    }
+
+    /// Sets the gateway that destinations outside of our subnet are reached through. If `None`, every destination is
+    /// taken to be on our subnet.
+    pub fn set_gateway(&mut self, gateway: Option<Gateway>) -> Result<(), Fail> {
+        self.arp.set_gateway(gateway)
+    }
+
+    /// Sets the routing table, which picks the next hop towards each destination by longest prefix match.
+    pub fn set_routing_table(&mut self, routes: RoutingTable) {
+        self.arp.set_routing_table(routes)
+    }
+
+    /// Sets the 802.1Q tag that is inserted into every frame that we send. If `None`, which is the default, frames are
+    /// sent untagged.
+    pub fn set_vlan_tag(&mut self, vlan_tag: Option<VlanTag>) {
+        self.arp.set_vlan_tag(vlan_tag)
+    }
+
+    /// Starts acquiring an address with DHCP. The client is driven by [Self::poll_frames], and keeps renewing its lease
+    /// from then on.
+    pub fn start_dhcp(&mut self) -> Result<(), Fail> {
+        if self.dhcp.is_some() {
+            return Err(Fail::new(EEXIST, "DHCP client already started"));
+        }
+        let qd: QDesc = self.file_table.alloc(QType::UdpSocket.into());
+        match DhcpClient::new(self.rt.clone(), self.rng_seed, &mut self.ipv4, qd) {
+            Ok(dhcp) => {
+                self.dhcp = Some(dhcp);
+                Ok(())
+            },
+            Err(e) => {
+                self.file_table.free(qd);
+                Err(e)
+            },
+        }
+    }
+
+    /// Gets the lease that the DHCP client holds, if any.
+    pub fn dhcp_lease(&self) -> Option<&DhcpLease> {
+        self.dhcp.as_ref().and_then(|dhcp| dhcp.lease())
+    }
+
     ///
     /// **Brief**
     ///
@@ -321,6 +403,54 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
         Ok(())
     }
 
+    ///
+    /// **Brief**
+    ///
+    /// Gets the local endpoint of the socket referred to by `qd`, which is the one that the socket was bound to, either
+    /// explicitly or when connecting. The endpoint is unspecified if the socket is not bound.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, the local endpoint is returned. Upon failure, `Fail` is returned instead.
+    ///
+    pub fn local_endpoint(&self, qd: QDesc) -> Result<SocketAddrV4, Fail> {
+        trace!("local_endpoint(): qd={:?}", qd);
+
+        match self.file_table.get(qd) {
+            Some(qtype) => match QType::try_from(qtype) {
+                Ok(QType::TcpSocket) => self.ipv4.tcp.local_endpoint(qd),
+                Ok(QType::UdpSocket) => self.ipv4.udp.local_endpoint(qd),
+                _ => Err(Fail::new(EINVAL, "invalid queue type")),
+            },
+            _ => Err(Fail::new(EBADF, "bad queue descriptor")),
+        }
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Gets the time to live that the last datagram arrived with on the socket referred to by `qd`. For TCP sockets,
+    /// this is the last datagram received on the connection. For UDP sockets, this is the last datagram popped from the
+    /// socket.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, the time to live is returned, or `None` if no datagram has arrived yet. Upon
+    /// failure, `Fail` is returned instead.
+    ///
+    pub fn received_ttl(&self, qd: QDesc) -> Result<Option<u8>, Fail> {
+        trace!("received_ttl(): qd={:?}", qd);
+
+        match self.file_table.get(qd) {
+            Some(qtype) => match QType::try_from(qtype) {
+                Ok(QType::TcpSocket) => self.ipv4.tcp.received_ttl(qd),
+                Ok(QType::UdpSocket) => self.ipv4.udp.received_ttl(qd),
+                _ => Err(Fail::new(EINVAL, "invalid queue type")),
+            },
+            _ => Err(Fail::new(EBADF, "bad queue descriptor")),
+        }
+    }
+
     /// Pushes a buffer to a TCP socket.
     /// TODO: Rename this function to push() once we have a common representation across all libOSes.
     pub fn do_push(&mut self, qd: QDesc, buf: Buffer) -> Result<FutureOperation<RT>, Fail> {
@@ -353,6 +483,43 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
         Ok(qt)
     }
 
+    /// Pushes the elements of a scatter-gather array to a TCP socket as one message. Unlike [Self::push2], the data is
+    /// not copied: it is sent straight from the buffers, which are held until the data is acknowledged.
+    pub fn push_sga(&mut self, qd: QDesc, sga: Vec<Buffer>) -> Result<QToken, Fail> {
+        #[cfg(feature = "profiler")]
+        timer!("inetstack::push_sga");
+        trace!("push_sga(): qd={:?}", qd);
+
+        let future: FutureOperation<RT> = match self.file_table.get(qd) {
+            Some(qtype) => match QType::try_from(qtype) {
+                Ok(QType::TcpSocket) => FutureOperation::from(self.ipv4.tcp.push_sga(qd, sga)),
+                _ => return Err(Fail::new(EINVAL, "invalid queue type")),
+            },
+            _ => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        let qt: QToken = self.rt.schedule(future).into_raw().into();
+        trace!("push_sga() qt={:?}", qt);
+        Ok(qt)
+    }
+
+    /// Waits until all data that was pushed to a TCP socket has been acknowledged by our peer.
+    pub fn flush(&mut self, qd: QDesc) -> Result<QToken, Fail> {
+        #[cfg(feature = "profiler")]
+        timer!("inetstack::flush");
+        trace!("flush(): qd={:?}", qd);
+
+        let future: FutureOperation<RT> = match self.file_table.get(qd) {
+            Some(qtype) => match QType::try_from(qtype) {
+                Ok(QType::TcpSocket) => FutureOperation::from(self.ipv4.tcp.flush(qd)),
+                _ => return Err(Fail::new(EINVAL, "invalid queue type")),
+            },
+            _ => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        let qt: QToken = self.rt.schedule(future).into_raw().into();
+        trace!("flush() qt={:?}", qt);
+        Ok(qt)
+    }
+
     /// Pushes a buffer to a UDP socket.
     /// TODO: Rename this function to pushto() once we have a common buffer representation across all libOSes.
     pub fn do_pushto(&mut self, qd: QDesc, buf: Buffer, to: SocketAddrV4) -> Result<FutureOperation<RT>, Fail> {
@@ -511,10 +678,9 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
         timer!("inetstack::engine::receive");
         let (header, payload) = Ethernet2Header::parse(bytes)?;
         debug!("Engine received {:?}", header);
-        if self.rt.local_link_addr() != header.dst_addr() && !header.dst_addr().is_broadcast() {
-            // ToDo: Add support for is_multicast() to MacAddress type.  Then remove following trace and restore return.
-            trace!("Need to add && !header.dst_addr().is_multicast()");
-            //return Err(Fail::new(EINVAL, "physical destination address mismatch"));
+        if !header.is_destined_to(self.rt.local_link_addr()) {
+            self.dropped_link_addr_mismatch += 1;
+            return Err(Fail::new(EINVAL, "physical destination address mismatch"));
         }
         match header.ether_type() {
             EtherType2::Arp => self.arp.receive(payload),
@@ -529,31 +695,45 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
     pub fn poll_bg_work(&mut self) {
         #[cfg(feature = "profiler")]
         timer!("inetstack::poll_bg_work");
+        self.poll_frames();
+    }
+
+    /// Advances the stack by one step, for embedders that drive it from their own event loop rather than through
+    /// [Self::wait2]. This polls all futures that are ready to make progress and routes incoming frames to the
+    /// correct protocol. Returns whether any incoming frames were processed. The futures that those frames wake only
+    /// run on the next step, so callers should keep stepping while this returns `true`. The scheduler does not report
+    /// whether it has ready futures, so `false` does not mean that the stack is idle: futures that are woken by
+    /// timers or by other futures still need further steps.
+    pub fn poll_frames(&mut self) -> bool {
+        #[cfg(feature = "profiler")]
+        timer!("inetstack::poll_frames");
         {
             #[cfg(feature = "profiler")]
-            timer!("inetstack::poll_bg_work::poll");
+            timer!("inetstack::poll_frames::poll");
             self.rt.poll();
         }
 
+        let mut received: bool = false;
         {
             #[cfg(feature = "profiler")]
-            timer!("inetstack::poll_bg_work::for");
+            timer!("inetstack::poll_frames::for");
 
             for _ in 0..MAX_RECV_ITERS {
                 let batch = {
                     #[cfg(feature = "profiler")]
-                    timer!("inetstack::poll_bg_work::for::receive");
+                    timer!("inetstack::poll_frames::for::receive");
 
                     self.rt.receive()
                 };
 
                 {
                     #[cfg(feature = "profiler")]
-                    timer!("inetstack::poll_bg_work::for::for");
+                    timer!("inetstack::poll_frames::for::for");
 
                     if batch.is_empty() {
                         break;
                     }
+                    received = true;
 
                     for pkt in batch {
                         if let Err(e) = self.do_receive(pkt) {
@@ -566,9 +746,17 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> InetStack<RT> {
             }
         }
 
+        if let Some(dhcp) = self.dhcp.as_mut() {
+            if let Err(e) = dhcp.poll(&mut self.ipv4, &self.arp) {
+                warn!("DHCP client failed: {:?}", e);
+            }
+        }
+
         if self.ts_iters == 0 {
             self.rt.advance_clock(Instant::now());
         }
         self.ts_iters = (self.ts_iters + 1) % TIMER_RESOLUTION;
+
+        received
     }
 }
@@ -23,7 +23,10 @@ pub enum OperationResult {
     Connect,
     Accept(QDesc),
     Push,
+    /// All data that was pushed has been acknowledged.
+    Flush,
     // TODO: Drop wrapping Option.
+    /// For TCP, an empty buffer signals that our peer has closed its side of the connection.
     Pop(Option<SocketAddrV4>, Buffer),
     Failed(Fail),
 }
@@ -38,6 +41,7 @@ impl fmt::Debug for OperationResult {
             OperationResult::Connect => write!(f, "Connect"),
             OperationResult::Accept(..) => write!(f, "Accept"),
             OperationResult::Push => write!(f, "Push"),
+            OperationResult::Flush => write!(f, "Flush"),
             OperationResult::Pop(..) => write!(f, "Pop"),
             OperationResult::Failed(ref e) => write!(f, "Failed({:?})", e),
         }
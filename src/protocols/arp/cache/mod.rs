@@ -21,6 +21,9 @@ use ::std::{
 
 const DUMMY_MAC_ADDRESS: MacAddress = MacAddress::new([0; 6]);
 
+/// Default amount of time for which a failed address resolution is remembered.
+const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
 //==============================================================================
 // Structures
 //==============================================================================
@@ -28,6 +31,8 @@ const DUMMY_MAC_ADDRESS: MacAddress = MacAddress::new([0; 6]);
 #[derive(Debug)]
 struct Record {
     link_addr: MacAddress,
+    /// Static entries never expire and are not overwritten by dynamic resolutions.
+    is_static: bool,
 }
 
 ///
@@ -40,6 +45,12 @@ pub struct ArpCache {
     /// Cache for IPv4 Addresses
     cache: HashTtlCache<Ipv4Addr, Record>,
 
+    /// Expiration times of failed address resolutions.
+    unreachable: HashMap<Ipv4Addr, Instant>,
+
+    /// Amount of time for which a failed address resolution is remembered. If `None`, failures are not cached.
+    negative_ttl: Option<Duration>,
+
     /// Disable ARP?
     disable: bool,
 }
@@ -58,6 +69,8 @@ impl ArpCache {
     ) -> ArpCache {
         let mut peer = ArpCache {
             cache: HashTtlCache::new(now, default_ttl),
+            unreachable: HashMap::default(),
+            negative_ttl: Some(DEFAULT_NEGATIVE_TTL),
             disable,
         };
 
@@ -71,12 +84,54 @@ impl ArpCache {
         peer
     }
 
-    /// Caches an address resolution.
+    /// Caches an address resolution. Static address resolutions are left untouched.
     pub fn insert(&mut self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Option<MacAddress> {
-        let record = Record { link_addr };
+        if let Some(record) = self.cache.get(&ipv4_addr) {
+            if record.is_static {
+                return Some(record.link_addr);
+            }
+        }
+        let record = Record {
+            link_addr,
+            is_static: false,
+        };
+        self.unreachable.remove(&ipv4_addr);
         self.cache.insert(ipv4_addr, record).map(|r| r.link_addr)
     }
 
+    /// Caches a static address resolution, which never expires.
+    pub fn insert_static(&mut self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Option<MacAddress> {
+        let record = Record {
+            link_addr,
+            is_static: true,
+        };
+        self.unreachable.remove(&ipv4_addr);
+        self.cache.insert_with_ttl(ipv4_addr, record, None).map(|r| r.link_addr)
+    }
+
+    /// Caches a failed address resolution.
+    pub fn insert_unreachable(&mut self, ipv4_addr: Ipv4Addr, now: Instant) {
+        if let Some(ttl) = self.negative_ttl {
+            self.unreachable.insert(ipv4_addr, now + ttl);
+        }
+    }
+
+    /// Asserts if resolution of an IPv4 address has recently failed.
+    pub fn is_unreachable(&self, ipv4_addr: Ipv4Addr, now: Instant) -> bool {
+        match self.unreachable.get(&ipv4_addr) {
+            Some(&expiration) => now < expiration,
+            None => false,
+        }
+    }
+
+    /// Sets the amount of time for which failed address resolutions are remembered.
+    pub fn set_negative_ttl(&mut self, ttl: Option<Duration>) {
+        if ttl.is_none() {
+            self.unreachable.clear();
+        }
+        self.negative_ttl = ttl;
+    }
+
     /// Gets the MAC address of given IPv4 address.
     pub fn get(&self, ipv4_addr: Ipv4Addr) -> Option<&MacAddress> {
         if self.disable {
@@ -88,12 +143,14 @@ impl ArpCache {
 
     /// Advances internal clock of the ARP Cache.
     pub fn advance_clock(&mut self, now: Instant) {
+        self.unreachable.retain(|_, expiration| now < *expiration);
         self.cache.advance_clock(now)
     }
 
     /// Clears the ARP cache.
     #[allow(unused)]
     pub fn clear(&mut self) {
+        self.unreachable.clear();
         self.cache.clear();
     }
 
@@ -60,3 +60,20 @@ fn export() {
         map.get_key_value(&test_helpers::ALICE_IPV4) == Some((&test_helpers::ALICE_IPV4, &test_helpers::ALICE_MAC))
     );
 }
+
+/// Tests that static entries are not overwritten by dynamic address resolutions.
+#[test]
+fn static_entry() {
+    let now = Instant::now();
+    let ttl = Duration::from_secs(1);
+
+    // Insert a static address resolution and attempt to overwrite it.
+    let mut cache = ArpCache::new(now, Some(ttl), None, false);
+    cache.insert_static(test_helpers::ALICE_IPV4, test_helpers::ALICE_MAC);
+    cache.insert(test_helpers::ALICE_IPV4, test_helpers::BOB_MAC);
+    assert!(cache.get(test_helpers::ALICE_IPV4) == Some(&test_helpers::ALICE_MAC));
+
+    // Static address resolutions may be explicitly replaced.
+    cache.insert_static(test_helpers::ALICE_IPV4, test_helpers::BOB_MAC);
+    assert!(cache.get(test_helpers::ALICE_IPV4) == Some(&test_helpers::BOB_MAC));
+}
@@ -8,4 +8,7 @@ mod peer;
 #[cfg(test)]
 mod tests;
 
-pub use peer::ArpPeer;
+pub use peer::{
+    ArpPeer,
+    Gateway,
+};
@@ -2,7 +2,11 @@
 // Licensed under the MIT license.
 
 use super::ArpHeader;
-use crate::protocols::ethernet2::Ethernet2Header;
+use crate::protocols::ethernet2::{
+    pad_body,
+    padding_size,
+    Ethernet2Header,
+};
 use ::runtime::{
     memory::Buffer,
     network::PacketBuf,
@@ -42,7 +46,7 @@ impl PacketBuf for ArpMessage {
     }
 
     fn body_size(&self) -> usize {
-        0
+        padding_size(self.header_size())
     }
 
     fn write_header(&self, buf: &mut [u8]) {
@@ -58,6 +62,10 @@ impl PacketBuf for ArpMessage {
     }
 
     fn take_body(self) -> Option<Buffer> {
-        None
+        // Pad short frames to the minimum Ethernet frame size.
+        match padding_size(self.header_size()) {
+            0 => None,
+            padding => Some(pad_body(&[], padding)),
+        }
     }
 }
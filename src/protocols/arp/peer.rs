@@ -14,9 +14,17 @@ use crate::{
         FutureOperation,
         UtilityMethods,
     },
-    protocols::ethernet2::{
-        EtherType2,
-        Ethernet2Header,
+    protocols::{
+        ethernet2::{
+            EtherType2,
+            Ethernet2Header,
+            VlanTag,
+        },
+        ipv4::{
+            NextHop,
+            Route,
+            RoutingTable,
+        },
     },
 };
 use ::futures::{
@@ -29,6 +37,8 @@ use ::futures::{
 };
 use ::libc::{
     EBADMSG,
+    EHOSTUNREACH,
+    EINVAL,
     ETIMEDOUT,
 };
 use ::runtime::{
@@ -43,7 +53,10 @@ use ::runtime::{
     task::SchedulerRuntime,
 };
 use ::std::{
-    cell::RefCell,
+    cell::{
+        Cell,
+        RefCell,
+    },
     collections::HashMap,
     future::Future,
     net::Ipv4Addr,
@@ -58,14 +71,31 @@ use ::std::{
 // Structures
 //==============================================================================
 
+/// Subnet that we are attached to, along with the gateway that destinations outside of it are reached through.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Gateway {
+    /// Mask of our subnet.
+    pub netmask: Ipv4Addr,
+    /// Address of the gateway, which must be on our subnet.
+    pub addr: Ipv4Addr,
+}
+
 ///
 /// Arp Peer
-/// - TODO: Allow multiple waiters for the same address
 #[derive(Clone)]
 pub struct ArpPeer<RT: NetworkRuntime> {
     rt: RT,
     cache: Rc<RefCell<ArpCache>>,
-    waiters: Rc<RefCell<HashMap<Ipv4Addr, Sender<MacAddress>>>>,
+    /// Queries that are waiting for a link address, grouped by target IP address.
+    waiters: Rc<RefCell<HashMap<Ipv4Addr, Vec<Sender<MacAddress>>>>>,
+    /// Time at which the last ARP request for a target IP address was transmitted.
+    requests: Rc<RefCell<HashMap<Ipv4Addr, Instant>>>,
+    /// Routes that are consulted before resolving the link address of a destination.
+    routes: Rc<RefCell<RoutingTable>>,
+    /// 802.1Q tag that is inserted into every frame that we send, if any.
+    vlan_tag: Rc<Cell<Option<VlanTag>>>,
+    /// Address that we send requests from and answer requests for instead of the one of the runtime, if any.
+    source_ipv4_addr: Rc<Cell<Option<Ipv4Addr>>>,
     options: ArpConfig,
 
     /// The background co-routine cleans up the ARP cache from time to time.
@@ -93,6 +123,10 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
             rt,
             cache,
             waiters: Rc::new(RefCell::new(HashMap::default())),
+            requests: Rc::new(RefCell::new(HashMap::default())),
+            routes: Rc::new(RefCell::new(RoutingTable::new())),
+            vlan_tag: Rc::new(Cell::new(None)),
+            source_ipv4_addr: Rc::new(Cell::new(None)),
             options,
             background: Rc::new(handle),
         };
@@ -100,16 +134,29 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
         Ok(peer)
     }
 
-    /// Drops a waiter for a target IP address.
+    /// Drops waiters for a target IP address that have given up.
     fn do_drop(&mut self, ipv4_addr: Ipv4Addr) {
-        self.waiters.borrow_mut().remove(&ipv4_addr);
+        let mut waiters = self.waiters.borrow_mut();
+        if let Some(senders) = waiters.get_mut(&ipv4_addr) {
+            senders.retain(|tx| !tx.is_canceled());
+            if senders.is_empty() {
+                waiters.remove(&ipv4_addr);
+                self.requests.borrow_mut().remove(&ipv4_addr);
+            }
+        }
     }
 
     fn do_insert(&mut self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Option<MacAddress> {
-        if let Some(sender) = self.waiters.borrow_mut().remove(&ipv4_addr) {
-            let _ = sender.send(link_addr);
+        let old_link_addr: Option<MacAddress> = self.cache.borrow_mut().insert(ipv4_addr, link_addr);
+        // Static entries take precedence over the link address that we have just learned.
+        let link_addr: MacAddress = *self.cache.borrow().get(ipv4_addr).unwrap_or(&link_addr);
+        if let Some(senders) = self.waiters.borrow_mut().remove(&ipv4_addr) {
+            for sender in senders {
+                let _ = sender.send(link_addr);
+            }
         }
-        self.cache.borrow_mut().insert(ipv4_addr, link_addr)
+        self.requests.borrow_mut().remove(&ipv4_addr);
+        old_link_addr
     }
 
     fn do_wait_link_addr(&mut self, ipv4_addr: Ipv4Addr) -> impl Future<Output = MacAddress> {
@@ -117,15 +164,25 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
         if let Some(&link_addr) = self.cache.borrow().get(ipv4_addr) {
             let _ = tx.send(link_addr);
         } else {
-            assert!(
-                self.waiters.borrow_mut().insert(ipv4_addr, tx).is_none(),
-                "Duplicate waiter for {:?}",
-                ipv4_addr
-            );
+            self.waiters.borrow_mut().entry(ipv4_addr).or_default().push(tx);
         }
         rx.map(|r| r.expect("Dropped waiter?"))
     }
 
+    /// Checks if an ARP request for a target IP address should be transmitted. Requests for the same address are sent
+    /// at most once per request timeout, so that concurrent queries share a single in-flight request.
+    fn do_throttle(&self, ipv4_addr: Ipv4Addr) -> bool {
+        let now: Instant = self.rt.now();
+        let mut requests = self.requests.borrow_mut();
+        match requests.get(&ipv4_addr) {
+            Some(&last) if now < last + self.options.get_request_timeout() => false,
+            _ => {
+                requests.insert(ipv4_addr, now);
+                true
+            },
+        }
+    }
+
     /// Background task that cleans up the ARP cache from time to time.
     async fn background(rt: RT, cache: Rc<RefCell<ArpCache>>) {
         loop {
@@ -164,7 +221,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
             }
         };
         // from RFC 826: ?Am I the target protocol address?
-        if header.get_destination_protocol_addr() != self.rt.local_ipv4_addr() {
+        if header.get_destination_protocol_addr() != self.source_ipv4_addr() {
             if merge_flag {
                 // we did do something.
                 return Ok(());
@@ -191,11 +248,12 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
                         header.get_sender_hardware_addr(),
                         self.rt.local_link_addr(),
                         EtherType2::Arp,
-                    ),
+                    )
+                    .with_vlan_tag(self.vlan_tag()),
                     ArpHeader::new(
                         ArpOperation::Reply,
                         self.rt.local_link_addr(),
-                        self.rt.local_ipv4_addr(),
+                        self.source_ipv4_addr(),
                         header.get_sender_hardware_addr(),
                         header.get_sender_protocol_addr(),
                     ),
@@ -218,25 +276,128 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
         }
     }
 
+    /// Adds a static address resolution, which never expires and is not overwritten by received ARP messages.
+    /// Inserting a static address resolution for an address that already has one replaces it.
+    pub fn insert_static(&mut self, ipv4_addr: Ipv4Addr, link_addr: MacAddress) -> Option<MacAddress> {
+        let old_link_addr: Option<MacAddress> = self.cache.borrow_mut().insert_static(ipv4_addr, link_addr);
+        if let Some(senders) = self.waiters.borrow_mut().remove(&ipv4_addr) {
+            for sender in senders {
+                let _ = sender.send(link_addr);
+            }
+        }
+        self.requests.borrow_mut().remove(&ipv4_addr);
+        old_link_addr
+    }
+
+    /// Sets the amount of time for which failed address resolutions are remembered. While a failure is remembered,
+    /// queries for that address fail immediately. If `None`, failures are not remembered.
+    pub fn set_negative_cache_ttl(&self, ttl: Option<Duration>) {
+        self.cache.borrow_mut().set_negative_ttl(ttl);
+    }
+
+    /// Sets the gateway that destinations outside of our subnet are reached through. This replaces the routing table
+    /// with a route to our subnet and a default route through the gateway. If `None`, the routing table is emptied, so
+    /// every destination is taken to be on-link.
+    pub fn set_gateway(&self, gateway: Option<Gateway>) -> Result<(), Fail> {
+        let mut routes: RoutingTable = RoutingTable::new();
+        if let Some(gateway) = gateway {
+            let netmask: u32 = u32::from(gateway.netmask);
+            if netmask.leading_ones() + netmask.trailing_zeros() != 32 {
+                return Err(Fail::new(EINVAL, "invalid subnet mask"));
+            }
+            let subnet: Route = Route::new(
+                Ipv4Addr::from(u32::from(self.rt.local_ipv4_addr()) & netmask),
+                netmask.leading_ones() as u8,
+                NextHop::OnLink,
+            )?;
+            if !subnet.contains(gateway.addr) {
+                return Err(Fail::new(EINVAL, "gateway is not on our subnet"));
+            }
+            routes.insert(subnet);
+            routes.insert(Route::new(Ipv4Addr::UNSPECIFIED, 0, NextHop::Gateway(gateway.addr))?);
+        }
+        self.set_routing_table(routes);
+        Ok(())
+    }
+
+    /// Sets the routing table, which is consulted before resolving the link address of a destination. Gateways must be
+    /// on-link.
+    pub fn set_routing_table(&self, routes: RoutingTable) {
+        *self.routes.borrow_mut() = routes;
+    }
+
+    /// Sets the 802.1Q tag that is inserted into every frame that we send. If `None`, frames are sent untagged.
+    pub fn set_vlan_tag(&self, vlan_tag: Option<VlanTag>) {
+        self.vlan_tag.set(vlan_tag);
+    }
+
+    /// Gets the 802.1Q tag that is inserted into every frame that we send, if any.
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        self.vlan_tag.get()
+    }
+
+    /// Sets the address that we send requests from and answer requests for. If `None`, which is the default, that is
+    /// the address of the runtime.
+    pub fn set_source_ipv4_addr(&self, addr: Option<Ipv4Addr>) {
+        self.source_ipv4_addr.set(addr);
+    }
+
+    /// Gets the address that we send requests from and answer requests for.
+    fn source_ipv4_addr(&self) -> Ipv4Addr {
+        self.source_ipv4_addr.get().unwrap_or(self.rt.local_ipv4_addr())
+    }
+
+    /// Gets the address whose link address datagrams to an IPv4 address are sent to. That is the address itself if it
+    /// is on-link, or the gateway that the routing table reaches it through otherwise.
+    pub fn next_hop(&self, ipv4_addr: Ipv4Addr) -> Ipv4Addr {
+        if ipv4_addr == self.rt.local_ipv4_addr() || ipv4_addr.is_broadcast() {
+            return ipv4_addr;
+        }
+        self.routes.borrow().next_hop(ipv4_addr)
+    }
+
+    /// Looks up the link address of the next hop towards an IPv4 address in the cache. Our own address always resolves
+    /// to our own link address, so that segments that we address to ourselves are never held back, and the broadcast
+    /// address always resolves to the broadcast link address.
     pub fn try_query(&self, ipv4_addr: Ipv4Addr) -> Option<MacAddress> {
+        let ipv4_addr: Ipv4Addr = self.next_hop(ipv4_addr);
+        if ipv4_addr == self.rt.local_ipv4_addr() {
+            return Some(self.rt.local_link_addr());
+        }
+        if ipv4_addr.is_broadcast() {
+            return Some(MacAddress::broadcast());
+        }
         self.cache.borrow().get(ipv4_addr).cloned()
     }
 
+    /// Resolves the link address of the next hop towards an IPv4 address, sending ARP requests if it is not cached.
     pub fn query(&self, ipv4_addr: Ipv4Addr) -> impl Future<Output = Result<MacAddress, Fail>> {
+        let ipv4_addr: Ipv4Addr = self.next_hop(ipv4_addr);
         let rt = self.rt.clone();
         let mut arp = self.clone();
         let cache = self.cache.clone();
         let arp_options = self.options.clone();
         async move {
+            if ipv4_addr == rt.local_ipv4_addr() {
+                return Ok(rt.local_link_addr());
+            }
+            if ipv4_addr.is_broadcast() {
+                return Ok(MacAddress::broadcast());
+            }
             if let Some(&link_addr) = cache.borrow().get(ipv4_addr) {
                 return Ok(link_addr);
             }
+            // Fail fast if we have recently failed to resolve this address.
+            if cache.borrow().is_unreachable(ipv4_addr, rt.now()) {
+                return Err(Fail::new(EHOSTUNREACH, "host unreachable"));
+            }
             let msg = ArpMessage::new(
-                Ethernet2Header::new(MacAddress::broadcast(), rt.local_link_addr(), EtherType2::Arp),
+                Ethernet2Header::new(MacAddress::broadcast(), rt.local_link_addr(), EtherType2::Arp)
+                    .with_vlan_tag(arp.vlan_tag()),
                 ArpHeader::new(
                     ArpOperation::Request,
                     rt.local_link_addr(),
-                    rt.local_ipv4_addr(),
+                    arp.source_ipv4_addr(),
                     MacAddress::broadcast(),
                     ipv4_addr,
                 ),
@@ -247,8 +408,12 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
             // > The frequency of the ARP request is very close to one per
             // > second, the maximum suggested by [RFC1122].
             let result = {
+                // Send the initial request, followed by up to `retry_count` retries.
                 for i in 0..arp_options.get_retry_count() + 1 {
-                    rt.transmit(msg.clone());
+                    // Skip transmission if another query has recently sent a request for the same address.
+                    if arp.do_throttle(ipv4_addr) {
+                        rt.transmit(msg.clone());
+                    }
                     let timer = rt.wait(arp_options.get_request_timeout());
 
                     match arp_response.with_timeout(timer).await {
@@ -261,9 +426,11 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ArpPeer<RT> {
                         },
                     }
                 }
+                cache.borrow_mut().insert_unreachable(ipv4_addr, rt.now());
                 Err(Fail::new(ETIMEDOUT, "ARP query timeout"))
             };
 
+            drop(arp_response);
             arp.do_drop(ipv4_addr);
 
             result
@@ -1,14 +1,21 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use super::packet::{
-    ArpHeader,
-    ArpOperation,
+use super::{
+    packet::{
+        ArpHeader,
+        ArpOperation,
+    },
+    Gateway,
 };
 use crate::{
-    protocols::ethernet2::Ethernet2Header,
+    protocols::{
+        ethernet2::Ethernet2Header,
+        ipv4::Ipv4Header,
+    },
     test_helpers::{
         self,
+        Engine,
         TestRuntime,
     },
 };
@@ -21,17 +28,34 @@ use ::futures::{
 };
 use ::libc::{
     EBADMSG,
+    EHOSTUNREACH,
+    EINVAL,
     ETIMEDOUT,
 };
 use ::runtime::{
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
     network::{
+        config::{
+            ArpConfig,
+            TcpConfig,
+            UdpConfig,
+        },
         types::MacAddress,
         NetworkRuntime,
     },
     task::SchedulerRuntime,
+    QDesc,
 };
 use ::std::{
+    collections::HashMap,
     future::Future,
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
     task::Poll,
     time::{
         Duration,
@@ -186,3 +210,216 @@ fn no_reply() {
     }
     .unwrap();
 }
+
+/// Tests that concurrent queries for the same address share a single ARP request.
+#[test]
+fn concurrent_queries() {
+    let now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+    let mut carrie = test_helpers::new_carrie(now);
+
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut1 = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    let mut fut2 = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    let mut fut3 = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    assert!(Future::poll(fut1.as_mut(), &mut ctx).is_pending());
+    assert!(Future::poll(fut2.as_mut(), &mut ctx).is_pending());
+    assert!(Future::poll(fut3.as_mut(), &mut ctx).is_pending());
+
+    // Only a single request should have been emitted.
+    let request = alice.rt().pop_frame();
+    assert!(alice.rt().pop_frame_unchecked().is_none());
+
+    carrie.receive(request).unwrap();
+    let reply = carrie.rt().pop_frame();
+    alice.receive(reply).unwrap();
+
+    // All queries should complete with the same result.
+    for fut in [&mut fut1, &mut fut2, &mut fut3] {
+        match Future::poll(fut.as_mut(), &mut ctx) {
+            Poll::Ready(Ok(link_addr)) => assert_eq!(link_addr, test_helpers::CARRIE_MAC),
+            _ => panic!("ARP query should have completed"),
+        }
+    }
+}
+
+/// Tests that failed resolutions are remembered for a while.
+#[test]
+fn negative_cache() {
+    let mut now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+    let options = alice.rt().arp_options();
+    let ttl: Duration = Duration::from_secs(5);
+    alice.arp.set_negative_cache_ttl(Some(ttl));
+
+    // Fail to resolve the address.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    for _ in 0..options.get_retry_count() {
+        alice.rt().pop_frame();
+        now += options.get_request_timeout();
+        alice.rt().advance_clock(now);
+        assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    }
+    alice.rt().pop_frame();
+    now += options.get_request_timeout();
+    alice.rt().advance_clock(now);
+    match Future::poll(fut.as_mut(), &mut ctx) {
+        Poll::Ready(Err(error)) if error.errno == ETIMEDOUT => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // The next query should fail fast, without emitting any request.
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    match Future::poll(fut.as_mut(), &mut ctx) {
+        Poll::Ready(Err(error)) if error.errno == EHOSTUNREACH => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    assert!(alice.rt().pop_frame_unchecked().is_none());
+
+    // Once the negative entry expires, queries should go to the network again.
+    now += ttl;
+    alice.rt().advance_clock(now);
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    let bytes = alice.rt().pop_frame();
+    let (_, payload) = Ethernet2Header::parse(bytes).unwrap();
+    let arp = ArpHeader::parse(payload).unwrap();
+    assert_eq!(arp.get_operation(), ArpOperation::Request);
+}
+
+/// Tests that static entries added at runtime are resolved without ARP requests.
+#[test]
+fn static_entry() {
+    let now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+    alice
+        .arp
+        .insert_static(test_helpers::CARRIE_IPV4, test_helpers::CARRIE_MAC);
+
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    match Future::poll(fut.as_mut(), &mut ctx) {
+        Poll::Ready(Ok(link_addr)) => assert_eq!(link_addr, test_helpers::CARRIE_MAC),
+        _ => panic!("ARP query should have completed"),
+    }
+    assert!(alice.rt().pop_frame_unchecked().is_none());
+}
+
+/// Tests that queries honor the configured number of retries.
+#[test]
+fn retry_count() {
+    let mut now = Instant::now();
+    let retry_count: usize = 3;
+    let request_timeout: Duration = Duration::from_secs(1);
+    let arp_options = ArpConfig::new(
+        Some(Duration::from_secs(600)),
+        Some(request_timeout),
+        Some(retry_count),
+        Some(HashMap::new()),
+        Some(false),
+    );
+    let rt = TestRuntime::new(
+        now,
+        arp_options,
+        UdpConfig::default(),
+        TcpConfig::default(),
+        test_helpers::ALICE_MAC,
+        test_helpers::ALICE_IPV4,
+    );
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    let mut alice: Engine<TestRuntime> = Engine::new(rt, rng_seed).unwrap();
+
+    // Initial request.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut = alice.arp_query(test_helpers::CARRIE_IPV4).boxed_local();
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    alice.rt().pop_frame();
+
+    // Each retry is sent once the request timeout expires, and not before that.
+    for _ in 0..retry_count {
+        now += request_timeout / 2;
+        alice.rt().advance_clock(now);
+        assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+        assert!(alice.rt().pop_frame_unchecked().is_none());
+
+        now += request_timeout / 2;
+        alice.rt().advance_clock(now);
+        assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+        let bytes = alice.rt().pop_frame();
+        let (_, payload) = Ethernet2Header::parse(bytes).unwrap();
+        let arp = ArpHeader::parse(payload).unwrap();
+        assert_eq!(arp.get_operation(), ArpOperation::Request);
+    }
+
+    // Give up with no further requests.
+    now += request_timeout;
+    alice.rt().advance_clock(now);
+    match Future::poll(fut.as_mut(), &mut ctx) {
+        Poll::Ready(Err(error)) if error.errno == ETIMEDOUT => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    assert!(alice.rt().pop_frame_unchecked().is_none());
+}
+
+/// Tests that destinations outside of our subnet are reached through the gateway, whose link address is resolved in
+/// place of theirs.
+#[test]
+fn gateway() {
+    let now = Instant::now();
+    let mut alice = test_helpers::new_alice(now);
+    let mut carrie = test_helpers::new_carrie(now);
+    let remote: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+
+    // The gateway must be on our subnet.
+    let netmask: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+    match alice.arp.set_gateway(Some(Gateway {
+        netmask,
+        addr: remote.ip().clone(),
+    })) {
+        Err(e) if e.errno == EINVAL => (),
+        r => panic!("gateway should have been refused: {:?}", r),
+    }
+    alice
+        .arp
+        .set_gateway(Some(Gateway {
+            netmask,
+            addr: test_helpers::CARRIE_IPV4,
+        }))
+        .unwrap();
+
+    // The ARP request is for the gateway, rather than for the destination.
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut fut = alice.arp_query(remote.ip().clone()).boxed_local();
+    assert!(Future::poll(fut.as_mut(), &mut ctx).is_pending());
+    let request = alice.rt().pop_frame();
+    let (_, payload) = Ethernet2Header::parse(request.clone()).unwrap();
+    let arp = ArpHeader::parse(payload).unwrap();
+    assert_eq!(arp.get_destination_protocol_addr(), test_helpers::CARRIE_IPV4);
+
+    carrie.receive(request).unwrap();
+    carrie.rt().poll_scheduler();
+    alice.receive(carrie.rt().pop_frame()).unwrap();
+    match Future::poll(fut.as_mut(), &mut ctx) {
+        Poll::Ready(Ok(link_addr)) => assert_eq!(link_addr, test_helpers::CARRIE_MAC),
+        r => panic!("ARP query should have completed: {:?}", r),
+    }
+
+    // Datagrams to the destination are sent to the link address of the gateway.
+    let fd: QDesc = alice.udp_socket().unwrap();
+    alice
+        .udp_bind(fd, SocketAddrV4::new(test_helpers::ALICE_IPV4, 80))
+        .unwrap();
+    alice
+        .udp_pushto(fd, Buffer::Heap(DataBuffer::from_slice(&[0x5a; 32])), remote)
+        .unwrap();
+    alice.rt().poll_scheduler();
+    let (eth2_header, payload) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    assert_eq!(eth2_header.dst_addr(), test_helpers::CARRIE_MAC);
+    let (ipv4_header, _) = Ipv4Header::parse(payload).unwrap();
+    assert_eq!(ipv4_header.get_dest_addr(), remote.ip().clone());
+}
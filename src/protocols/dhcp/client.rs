@@ -0,0 +1,338 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use super::message::{
+    DhcpMessage,
+    DhcpMessageType,
+    DhcpOperation,
+};
+use crate::protocols::{
+    arp::ArpPeer,
+    ipv4::{
+        NextHop,
+        Route,
+        RoutingTable,
+    },
+    Peer,
+};
+use ::futures::FutureExt;
+use ::libc::EINVAL;
+use ::rand::{
+    prelude::SmallRng,
+    Rng,
+    SeedableRng,
+};
+use ::runtime::{
+    fail::Fail,
+    memory::Buffer,
+    network::{
+        types::MacAddress,
+        NetworkRuntime,
+    },
+    task::SchedulerRuntime,
+    QDesc,
+};
+use ::std::{
+    cmp,
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+//==============================================================================
+// Constants
+//==============================================================================
+
+/// Port that DHCP servers listen on.
+pub const DHCP_SERVER_PORT: u16 = 67;
+
+/// Port that DHCP clients listen on.
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Time that we wait for a reply before sending a message again.
+const DHCP_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(4);
+
+//==============================================================================
+// Enumerations
+//==============================================================================
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DhcpState {
+    /// Looking for a server, with DISCOVER messages.
+    Selecting,
+    /// Asking a server for the address that it offered.
+    Requesting { addr: Ipv4Addr, server: Ipv4Addr },
+    /// Holding a lease.
+    Bound,
+    /// Asking the server of our lease to extend it.
+    Renewing,
+}
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+/// Address that a DHCP server leased to us.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub addr: Ipv4Addr,
+    pub netmask: Option<Ipv4Addr>,
+    pub router: Option<Ipv4Addr>,
+    /// Server that granted the lease.
+    pub server: Ipv4Addr,
+    pub duration: Duration,
+    /// When the lease was granted or last extended.
+    pub acquired: Instant,
+}
+
+///
+/// # DHCP Client
+///
+/// Acquires an address with the DISCOVER/OFFER/REQUEST/ACK exchange, and renews it halfway through the lease. The
+/// address of the runtime is fixed, so the leased address is installed on the IPv4 peer instead, along with routes to
+/// the subnet and through the router that the server lists. TCP and UDP sockets that are not bound to a specific
+/// address send from the leased one, and ARP requests for it are answered.
+///
+/// The client has no background task of its own: [DhcpClient::poll] must be called as the stack is polled.
+///
+pub struct DhcpClient<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
+    rt: RT,
+    /// UDP socket that is bound to the client port.
+    qd: QDesc,
+    link_addr: MacAddress,
+    rng: SmallRng,
+    /// Transaction identifier of the exchange in progress.
+    xid: u32,
+    state: DhcpState,
+    /// When we next send a message if we get no reply, renew our lease, or give it up.
+    deadline: Instant,
+    lease: Option<DhcpLease>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> DhcpClient<RT> {
+    /// Creates a DHCP client that owns a UDP queue descriptor. The first DISCOVER is sent when the client is first
+    /// polled. The seed of the client is derived from `rng_seed`, so that the transaction identifiers that it
+    /// broadcasts do not disclose the random numbers that other protocols draw from the same seed.
+    pub fn new(rt: RT, rng_seed: [u8; 32], ipv4: &mut Peer<RT>, qd: QDesc) -> Result<Self, Fail> {
+        ipv4.udp.do_socket(qd)?;
+        if let Err(e) = ipv4
+            .udp
+            .do_bind(qd, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DHCP_CLIENT_PORT))
+        {
+            let _ = ipv4.udp.do_close(qd);
+            return Err(e);
+        }
+        let mut rng: SmallRng = SmallRng::from_seed(Self::derive_seed(rng_seed));
+        let xid: u32 = rng.gen();
+        let now: Instant = rt.now();
+        Ok(Self {
+            link_addr: rt.local_link_addr(),
+            rt,
+            qd,
+            rng,
+            xid,
+            state: DhcpState::Selecting,
+            deadline: now,
+            lease: None,
+        })
+    }
+
+    /// Derives the seed of a DHCP client from the one of the stack.
+    fn derive_seed(rng_seed: [u8; 32]) -> [u8; 32] {
+        let mut seed: [u8; 32] = [0; 32];
+        for (i, chunk) in seed.chunks_mut(16).enumerate() {
+            let mut context: md5::Context = md5::Context::new();
+            context.consume(b"dhcp");
+            context.consume([i as u8]);
+            context.consume(rng_seed);
+            chunk.copy_from_slice(&context.compute().0);
+        }
+        seed
+    }
+
+    /// Gets the lease that we hold, if any.
+    pub fn lease(&self) -> Option<&DhcpLease> {
+        self.lease.as_ref()
+    }
+
+    /// Handles the replies that have arrived, and sends the messages that are due.
+    pub fn poll(&mut self, ipv4: &mut Peer<RT>, arp: &ArpPeer<RT>) -> Result<(), Fail> {
+        while let Some(result) = ipv4.udp.do_pop(self.qd).now_or_never() {
+            let (_, buf): (SocketAddrV4, Buffer) = result?;
+            match DhcpMessage::parse(buf) {
+                Ok(message) => self.receive(ipv4, arp, message)?,
+                Err(e) => warn!("Dropped DHCP message: {:?}", e),
+            }
+        }
+
+        let now: Instant = self.rt.now();
+        if now < self.deadline {
+            return Ok(());
+        }
+        match self.state {
+            DhcpState::Selecting => self.send_discover(ipv4, now),
+            DhcpState::Requesting { addr, server } => self.send_request(ipv4, now, addr, server),
+            DhcpState::Bound => {
+                self.state = DhcpState::Renewing;
+                self.xid = self.rng.gen();
+                self.send_renew(ipv4, now)
+            },
+            DhcpState::Renewing => {
+                let expiry: Instant = self.lease.as_ref().map_or(now, |l| l.acquired + l.duration);
+                match now < expiry {
+                    true => self.send_renew(ipv4, now),
+                    false => {
+                        warn!("DHCP lease expired");
+                        self.restart(ipv4, arp, now)
+                    },
+                }
+            },
+        }
+    }
+
+    fn receive(&mut self, ipv4: &mut Peer<RT>, arp: &ArpPeer<RT>, message: DhcpMessage) -> Result<(), Fail> {
+        // Drop messages that do not answer our exchange in progress.
+        if message.op != DhcpOperation::Reply || message.xid != self.xid || message.chaddr != self.link_addr {
+            return Ok(());
+        }
+        let now: Instant = self.rt.now();
+        match (self.state, message.message_type) {
+            (DhcpState::Selecting, DhcpMessageType::Offer) => match message.server_id {
+                Some(server) => self.send_request(ipv4, now, message.yiaddr, server),
+                None => Ok(()),
+            },
+            (DhcpState::Requesting { .. }, DhcpMessageType::Ack) | (DhcpState::Renewing, DhcpMessageType::Ack) => {
+                let (lease_time, server) = match (message.lease_time, message.server_id) {
+                    (Some(lease_time), Some(server)) => (lease_time, server),
+                    _ => return Ok(()),
+                };
+                let lease: DhcpLease = DhcpLease {
+                    addr: message.yiaddr,
+                    netmask: message.netmask,
+                    router: message.router,
+                    server,
+                    duration: lease_time,
+                    acquired: now,
+                };
+                self.install(ipv4, arp, lease)?;
+                self.state = DhcpState::Bound;
+                self.deadline = now + lease_time / 2;
+                Ok(())
+            },
+            (DhcpState::Requesting { .. }, DhcpMessageType::Nak) | (DhcpState::Renewing, DhcpMessageType::Nak) => {
+                self.restart(ipv4, arp, now)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn send_discover(&mut self, ipv4: &mut Peer<RT>, now: Instant) -> Result<(), Fail> {
+        let mut message: DhcpMessage = DhcpMessage::new(
+            DhcpOperation::Request,
+            DhcpMessageType::Discover,
+            self.xid,
+            self.link_addr,
+        );
+        message.broadcast = true;
+        self.deadline = now + DHCP_RETRANSMIT_TIMEOUT;
+        self.send(ipv4, message, Ipv4Addr::BROADCAST)
+    }
+
+    fn send_request(
+        &mut self,
+        ipv4: &mut Peer<RT>,
+        now: Instant,
+        addr: Ipv4Addr,
+        server: Ipv4Addr,
+    ) -> Result<(), Fail> {
+        let mut message: DhcpMessage = DhcpMessage::new(
+            DhcpOperation::Request,
+            DhcpMessageType::Request,
+            self.xid,
+            self.link_addr,
+        );
+        message.broadcast = true;
+        message.requested_addr = Some(addr);
+        message.server_id = Some(server);
+        self.state = DhcpState::Requesting { addr, server };
+        self.deadline = now + DHCP_RETRANSMIT_TIMEOUT;
+        self.send(ipv4, message, Ipv4Addr::BROADCAST)
+    }
+
+    /// Asks to extend our lease. Unlike the others, the request is unicast to the server of the lease, from the leased
+    /// address (see RFC 2131, Section 4.4.5).
+    fn send_renew(&mut self, ipv4: &mut Peer<RT>, now: Instant) -> Result<(), Fail> {
+        let (addr, server, expiry): (Ipv4Addr, Ipv4Addr, Instant) = match self.lease.as_ref() {
+            Some(lease) => (lease.addr, lease.server, lease.acquired + lease.duration),
+            None => return Err(Fail::new(EINVAL, "no DHCP lease to renew")),
+        };
+        let mut message: DhcpMessage = DhcpMessage::new(
+            DhcpOperation::Request,
+            DhcpMessageType::Request,
+            self.xid,
+            self.link_addr,
+        );
+        message.ciaddr = addr;
+        self.deadline = cmp::min(now + DHCP_RETRANSMIT_TIMEOUT, expiry);
+        self.send(ipv4, message, server)
+    }
+
+    fn send(&mut self, ipv4: &mut Peer<RT>, message: DhcpMessage, server: Ipv4Addr) -> Result<(), Fail> {
+        debug!("DHCP send {:?}", message);
+        let remote: SocketAddrV4 = SocketAddrV4::new(server, DHCP_SERVER_PORT);
+        ipv4.udp.do_pushto(self.qd, message.serialize(), remote)
+    }
+
+    /// Gives up our lease, if any, and starts over with a new exchange.
+    fn restart(&mut self, ipv4: &mut Peer<RT>, arp: &ArpPeer<RT>, now: Instant) -> Result<(), Fail> {
+        if let Some(lease) = self.lease.take() {
+            ipv4.remove_ipv4_addr(lease.addr);
+            ipv4.set_source_ipv4_addr(None);
+            arp.set_source_ipv4_addr(None);
+            arp.set_routing_table(RoutingTable::new());
+        }
+        self.state = DhcpState::Selecting;
+        self.xid = self.rng.gen();
+        self.send_discover(ipv4, now)
+    }
+
+    /// Installs the address and routes of a lease, replacing those of the lease that we held before.
+    fn install(&mut self, ipv4: &mut Peer<RT>, arp: &ArpPeer<RT>, lease: DhcpLease) -> Result<(), Fail> {
+        let mut routes: RoutingTable = RoutingTable::new();
+        if let Some(netmask) = lease.netmask {
+            let netmask: u32 = u32::from(netmask);
+            if netmask.leading_ones() + netmask.trailing_zeros() != 32 {
+                return Err(Fail::new(EINVAL, "invalid subnet mask"));
+            }
+            let prefix: Ipv4Addr = Ipv4Addr::from(u32::from(lease.addr) & netmask);
+            routes.insert(Route::new(prefix, netmask.leading_ones() as u8, NextHop::OnLink)?);
+        }
+        if let Some(router) = lease.router {
+            routes.insert(Route::new(Ipv4Addr::UNSPECIFIED, 0, NextHop::Gateway(router))?);
+        }
+
+        if let Some(old_lease) = self.lease.take() {
+            ipv4.remove_ipv4_addr(old_lease.addr);
+        }
+        ipv4.add_ipv4_addr(lease.addr);
+        ipv4.set_source_ipv4_addr(Some(lease.addr));
+        arp.set_source_ipv4_addr(Some(lease.addr));
+        arp.set_routing_table(routes);
+        info!("DHCP leased {} for {:?}", lease.addr, lease.duration);
+        self.lease = Some(lease);
+        Ok(())
+    }
+}
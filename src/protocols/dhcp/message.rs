@@ -0,0 +1,244 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use ::byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use ::libc::{
+    EBADMSG,
+    ENOTSUP,
+};
+use ::num_traits::FromPrimitive;
+use ::runtime::{
+    fail::Fail,
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
+    network::types::MacAddress,
+};
+use ::std::{
+    net::Ipv4Addr,
+    time::Duration,
+};
+
+//==============================================================================
+// Constants
+//==============================================================================
+
+const DHCP_HTYPE_ETHER2: u8 = 1;
+const DHCP_HLEN_ETHER2: u8 = 6;
+/// Flag that asks servers to broadcast their replies, as we do not accept unicast datagrams before we have an address.
+const DHCP_FLAG_BROADCAST: u16 = 0x8000;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Size of the fixed fields, including the magic cookie that precedes the options.
+const DHCP_FIXED_SIZE: usize = 240;
+
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+const DHCP_OPTION_ROUTER: u8 = 3;
+const DHCP_OPTION_REQUESTED_ADDR: u8 = 50;
+const DHCP_OPTION_LEASE_TIME: u8 = 51;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_SERVER_ID: u8 = 54;
+const DHCP_OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const DHCP_OPTION_END: u8 = 255;
+
+//==============================================================================
+// Enumerations
+//==============================================================================
+
+#[repr(u8)]
+#[derive(FromPrimitive, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DhcpOperation {
+    Request = 1,
+    Reply = 2,
+}
+
+#[repr(u8)]
+#[derive(FromPrimitive, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+}
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+///
+/// # DHCP Message
+///
+/// Fields of a message that we make use of. Other fields are left zeroed when serializing, and options that we do not
+/// understand are skipped when parsing.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub op: DhcpOperation,
+    pub message_type: DhcpMessageType,
+    /// Transaction identifier, which ties replies to the request that they answer.
+    pub xid: u32,
+    /// Whether the server should broadcast its reply.
+    pub broadcast: bool,
+    /// Address that the client already holds, if renewing.
+    pub ciaddr: Ipv4Addr,
+    /// Address that the server offers or assigns to the client.
+    pub yiaddr: Ipv4Addr,
+    /// Link address of the client.
+    pub chaddr: MacAddress,
+    pub requested_addr: Option<Ipv4Addr>,
+    pub server_id: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    /// First router on the subnet of the client, if the server lists any.
+    pub router: Option<Ipv4Addr>,
+    pub lease_time: Option<Duration>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+impl DhcpMessage {
+    /// Creates a DHCP message with no addresses or options.
+    pub fn new(op: DhcpOperation, message_type: DhcpMessageType, xid: u32, chaddr: MacAddress) -> Self {
+        Self {
+            op,
+            message_type,
+            xid,
+            broadcast: false,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            requested_addr: None,
+            server_id: None,
+            netmask: None,
+            router: None,
+            lease_time: None,
+        }
+    }
+
+    pub fn parse(buf: Buffer) -> Result<Self, Fail> {
+        if buf.len() < DHCP_FIXED_SIZE {
+            return Err(Fail::new(EBADMSG, "DHCP message too short"));
+        }
+        let op: DhcpOperation = FromPrimitive::from_u8(buf[0]).ok_or(Fail::new(ENOTSUP, "unsupported OP"))?;
+        if buf[1] != DHCP_HTYPE_ETHER2 {
+            return Err(Fail::new(ENOTSUP, "unsupported HTYPE"));
+        }
+        if buf[2] != DHCP_HLEN_ETHER2 {
+            return Err(Fail::new(ENOTSUP, "unsupported HLEN"));
+        }
+        if buf[236..240] != DHCP_MAGIC_COOKIE {
+            return Err(Fail::new(EBADMSG, "bad DHCP magic cookie"));
+        }
+        let xid: u32 = NetworkEndian::read_u32(&buf[4..8]);
+        let broadcast: bool = NetworkEndian::read_u16(&buf[10..12]) & DHCP_FLAG_BROADCAST != 0;
+        let ciaddr: Ipv4Addr = Ipv4Addr::from(NetworkEndian::read_u32(&buf[12..16]));
+        let yiaddr: Ipv4Addr = Ipv4Addr::from(NetworkEndian::read_u32(&buf[16..20]));
+        let chaddr: MacAddress = MacAddress::from_bytes(&buf[28..34]);
+
+        let mut message_type: Option<DhcpMessageType> = None;
+        let mut message: Self = Self::new(op, DhcpMessageType::Discover, xid, chaddr);
+        message.broadcast = broadcast;
+        message.ciaddr = ciaddr;
+        message.yiaddr = yiaddr;
+
+        let mut options: &[u8] = &buf[DHCP_FIXED_SIZE..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                DHCP_OPTION_PAD => {
+                    options = rest;
+                    continue;
+                },
+                DHCP_OPTION_END => break,
+                _ => (),
+            }
+            let len: usize = match rest.first() {
+                Some(&len) if rest.len() > len as usize => len as usize,
+                _ => return Err(Fail::new(EBADMSG, "truncated DHCP option")),
+            };
+            let value: &[u8] = &rest[1..(1 + len)];
+            options = &rest[(1 + len)..];
+            match (code, value.len()) {
+                (DHCP_OPTION_MESSAGE_TYPE, 1) => message_type = FromPrimitive::from_u8(value[0]),
+                (DHCP_OPTION_SUBNET_MASK, 4) => message.netmask = Some(read_ipv4_addr(value)),
+                (DHCP_OPTION_ROUTER, n) if n >= 4 => message.router = Some(read_ipv4_addr(value)),
+                (DHCP_OPTION_REQUESTED_ADDR, 4) => message.requested_addr = Some(read_ipv4_addr(value)),
+                (DHCP_OPTION_SERVER_ID, 4) => message.server_id = Some(read_ipv4_addr(value)),
+                (DHCP_OPTION_LEASE_TIME, 4) => {
+                    message.lease_time = Some(Duration::from_secs(NetworkEndian::read_u32(value) as u64))
+                },
+                // Skip options that we do not understand.
+                _ => (),
+            }
+        }
+
+        message.message_type = message_type.ok_or(Fail::new(EBADMSG, "missing DHCP message type"))?;
+        Ok(message)
+    }
+
+    /// Serializes the target DHCP message.
+    pub fn serialize(&self) -> Buffer {
+        let mut buf: Vec<u8> = vec![0; DHCP_FIXED_SIZE];
+        buf[0] = self.op as u8;
+        buf[1] = DHCP_HTYPE_ETHER2;
+        buf[2] = DHCP_HLEN_ETHER2;
+        NetworkEndian::write_u32(&mut buf[4..8], self.xid);
+        if self.broadcast {
+            NetworkEndian::write_u16(&mut buf[10..12], DHCP_FLAG_BROADCAST);
+        }
+        buf[12..16].copy_from_slice(&self.ciaddr.octets());
+        buf[16..20].copy_from_slice(&self.yiaddr.octets());
+        buf[28..34].copy_from_slice(&self.chaddr.octets());
+        buf[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+        buf.extend_from_slice(&[DHCP_OPTION_MESSAGE_TYPE, 1, self.message_type as u8]);
+        let addrs: [(u8, Option<Ipv4Addr>); 4] = [
+            (DHCP_OPTION_REQUESTED_ADDR, self.requested_addr),
+            (DHCP_OPTION_SERVER_ID, self.server_id),
+            (DHCP_OPTION_SUBNET_MASK, self.netmask),
+            (DHCP_OPTION_ROUTER, self.router),
+        ];
+        for (code, addr) in addrs {
+            if let Some(addr) = addr {
+                buf.extend_from_slice(&[code, 4]);
+                buf.extend_from_slice(&addr.octets());
+            }
+        }
+        if let Some(lease_time) = self.lease_time {
+            let secs: u32 = lease_time.as_secs().try_into().unwrap_or(u32::MAX);
+            buf.extend_from_slice(&[DHCP_OPTION_LEASE_TIME, 4]);
+            buf.extend_from_slice(&secs.to_be_bytes());
+        }
+        // Clients ask for the parameters that they make use of.
+        if self.op == DhcpOperation::Request {
+            buf.extend_from_slice(&[
+                DHCP_OPTION_PARAMETER_REQUEST_LIST,
+                2,
+                DHCP_OPTION_SUBNET_MASK,
+                DHCP_OPTION_ROUTER,
+            ]);
+        }
+        buf.push(DHCP_OPTION_END);
+
+        Buffer::Heap(DataBuffer::from_slice(&buf))
+    }
+}
+
+//==============================================================================
+// Standalone Functions
+//==============================================================================
+
+fn read_ipv4_addr(buf: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::from(NetworkEndian::read_u32(&buf[0..4]))
+}
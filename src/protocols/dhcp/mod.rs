@@ -0,0 +1,33 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! # Dynamic Host Configuration Protocol
+//!
+//! # References
+//!
+//! - https://datatracker.ietf.org/doc/html/rfc2131.
+//! - https://datatracker.ietf.org/doc/html/rfc2132.
+
+mod client;
+mod message;
+
+#[cfg(test)]
+mod tests;
+
+//==============================================================================
+// Exports
+//==============================================================================
+
+pub use self::{
+    client::{
+        DhcpClient,
+        DhcpLease,
+        DHCP_CLIENT_PORT,
+        DHCP_SERVER_PORT,
+    },
+    message::{
+        DhcpMessage,
+        DhcpMessageType,
+        DhcpOperation,
+    },
+};
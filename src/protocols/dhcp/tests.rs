@@ -0,0 +1,225 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    DhcpClient,
+    DhcpMessage,
+    DhcpMessageType,
+    DhcpOperation,
+    DHCP_CLIENT_PORT,
+    DHCP_SERVER_PORT,
+};
+use crate::{
+    protocols::{
+        ethernet2::Ethernet2Header,
+        ipv4::Ipv4Header,
+        tcp::operations::{
+            AcceptFuture,
+            ConnectFuture,
+        },
+    },
+    test_helpers::{
+        self,
+        Engine,
+        TestEngine,
+        TestRuntime,
+    },
+};
+use ::futures::FutureExt;
+use ::rand::{
+    prelude::SmallRng,
+    Rng,
+    SeedableRng,
+};
+use ::runtime::{
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
+    network::config::{
+        ArpConfig,
+        TcpConfig,
+        UdpConfig,
+    },
+    task::SchedulerRuntime,
+    QDesc,
+    QType,
+};
+use ::std::{
+    collections::HashMap,
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+//==============================================================================
+// Constants
+//==============================================================================
+
+const LEASE_TIME: Duration = Duration::from_secs(3600);
+const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+
+//==============================================================================
+// Helper Functions
+//==============================================================================
+
+/// Builds an engine that has no address of its own yet.
+fn new_unconfigured(now: Instant) -> TestEngine {
+    let arp_options = ArpConfig::new(
+        Some(Duration::from_secs(600)),
+        Some(Duration::from_secs(1)),
+        Some(2),
+        Some(HashMap::new()),
+        Some(false),
+    );
+    let rt = TestRuntime::new(
+        now,
+        arp_options,
+        UdpConfig::default(),
+        TcpConfig::default(),
+        test_helpers::ALICE_MAC,
+        Ipv4Addr::UNSPECIFIED,
+    );
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    Engine::new(rt, rng_seed).unwrap()
+}
+
+/// Stub DHCP server: answers the message that is waiting on its socket, offering and then leasing `addr`. Returns the
+/// message that it answered.
+fn serve(server: &mut TestEngine, fd: QDesc, addr: Ipv4Addr) -> DhcpMessage {
+    let (_, buf): (SocketAddrV4, Buffer) = server.udp_pop(fd).now_or_never().unwrap().unwrap();
+    let request: DhcpMessage = DhcpMessage::parse(buf).unwrap();
+    assert_eq!(request.op, DhcpOperation::Request);
+    let message_type: DhcpMessageType = match request.message_type {
+        DhcpMessageType::Discover => DhcpMessageType::Offer,
+        DhcpMessageType::Request => DhcpMessageType::Ack,
+        message_type => panic!("unexpected DHCP message {:?}", message_type),
+    };
+    let mut reply: DhcpMessage = DhcpMessage::new(DhcpOperation::Reply, message_type, request.xid, request.chaddr);
+    reply.yiaddr = addr;
+    reply.server_id = Some(test_helpers::BOB_IPV4);
+    reply.netmask = Some(NETMASK);
+    reply.lease_time = Some(LEASE_TIME);
+    let remote: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT);
+    server.udp_pushto(fd, reply.serialize(), remote).unwrap();
+    request
+}
+
+//==============================================================================
+// Tests
+//==============================================================================
+
+/// Tests that the client adopts the address that a server leases to it, can then send traffic from it, and renews the
+/// lease halfway through with a request that is unicast to the server.
+#[test]
+fn dhcp_lease_and_renew() {
+    let mut now = Instant::now();
+    let mut client: TestEngine = new_unconfigured(now);
+    let mut server: TestEngine = test_helpers::new_bob(now);
+    let server_fd: QDesc = server.udp_socket().unwrap();
+    server
+        .udp_bind(server_fd, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DHCP_SERVER_PORT))
+        .unwrap();
+
+    let qd: QDesc = client.file_table.alloc(QType::UdpSocket.into());
+    let rng_seed: [u8; 32] = client.rt().rng_seed();
+    let mut dhcp: DhcpClient<TestRuntime> =
+        DhcpClient::new(client.rt().clone(), rng_seed, &mut client.ipv4, qd).unwrap();
+
+    // DISCOVER and OFFER.
+    dhcp.poll(&mut client.ipv4, &client.arp).unwrap();
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    let discover: DhcpMessage = serve(&mut server, server_fd, test_helpers::ALICE_IPV4);
+    assert_eq!(discover.message_type, DhcpMessageType::Discover);
+    assert_eq!(discover.chaddr, test_helpers::ALICE_MAC);
+    assert_ne!(discover.xid, SmallRng::from_seed(rng_seed).gen::<u32>());
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+
+    // REQUEST and ACK.
+    dhcp.poll(&mut client.ipv4, &client.arp).unwrap();
+    assert!(dhcp.lease().is_none());
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    let request: DhcpMessage = serve(&mut server, server_fd, test_helpers::ALICE_IPV4);
+    assert_eq!(request.message_type, DhcpMessageType::Request);
+    assert_eq!(request.requested_addr, Some(test_helpers::ALICE_IPV4));
+    assert_eq!(request.server_id, Some(test_helpers::BOB_IPV4));
+    assert_eq!(request.xid, discover.xid);
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+
+    // The client adopts the leased address.
+    dhcp.poll(&mut client.ipv4, &client.arp).unwrap();
+    let acquired: Instant = {
+        let lease = dhcp.lease().unwrap();
+        assert_eq!(lease.addr, test_helpers::ALICE_IPV4);
+        assert_eq!(lease.netmask, Some(NETMASK));
+        assert_eq!(lease.server, test_helpers::BOB_IPV4);
+        assert_eq!(lease.duration, LEASE_TIME);
+        lease.acquired
+    };
+
+    // The client sends traffic from the leased address.
+    let server_echo_fd: QDesc = server.udp_socket().unwrap();
+    let server_echo_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, 7);
+    server.udp_bind(server_echo_fd, server_echo_addr).unwrap();
+    let client_fd: QDesc = client.udp_socket().unwrap();
+    let client_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::ALICE_IPV4, 7000);
+    client.udp_bind(client_fd, client_addr).unwrap();
+    let data: Buffer = Buffer::Heap(DataBuffer::from_slice(&[0x5a; 32]));
+    client.udp_pushto(client_fd, data.clone(), server_echo_addr).unwrap();
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    let (remote, received): (SocketAddrV4, Buffer) = server.udp_pop(server_echo_fd).now_or_never().unwrap().unwrap();
+    assert_eq!(remote, client_addr);
+    assert_eq!(received[..], data[..]);
+
+    // Connections from sockets that are not bound to a specific address come from the leased address.
+    let server_listen_fd: QDesc = server.tcp_socket().unwrap();
+    let server_listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, 80);
+    server.tcp_bind(server_listen_fd, server_listen_addr).unwrap();
+    server.tcp_listen(server_listen_fd, 1).unwrap();
+    let accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(server_listen_fd);
+    let client_tcp_fd: QDesc = client.tcp_socket().unwrap();
+    let connect_future: ConnectFuture<TestRuntime> = client.tcp_connect(client_tcp_fd, server_listen_addr);
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    connect_future.now_or_never().unwrap().unwrap();
+    let server_tcp_fd: QDesc = accept_future.now_or_never().unwrap().unwrap();
+    let (_, server_remote): (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_tcp_fd).unwrap();
+    assert_eq!(*server_remote.ip(), test_helpers::ALICE_IPV4);
+    assert_eq!(
+        server.export_arp_cache().get(&test_helpers::ALICE_IPV4),
+        Some(&test_helpers::ALICE_MAC)
+    );
+
+    // Nothing is sent before the lease is halfway through.
+    now += LEASE_TIME / 2 - Duration::from_secs(1);
+    client.rt().advance_clock(now);
+    server.rt().advance_clock(now);
+    dhcp.poll(&mut client.ipv4, &client.arp).unwrap();
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    assert!(server.udp_pop(server_fd).now_or_never().is_none());
+
+    // The client then renews its lease, from the leased address and directly with the server.
+    now += Duration::from_secs(1);
+    client.rt().advance_clock(now);
+    server.rt().advance_clock(now);
+    dhcp.poll(&mut client.ipv4, &client.arp).unwrap();
+    let frame: Buffer = client.rt().pop_frame();
+    let (_, ipv4_payload): (Ethernet2Header, Buffer) = Ethernet2Header::parse(frame.clone()).unwrap();
+    let (ipv4_hdr, _): (Ipv4Header, Buffer) = Ipv4Header::parse(ipv4_payload).unwrap();
+    assert_eq!(ipv4_hdr.get_src_addr(), test_helpers::ALICE_IPV4);
+    assert_eq!(ipv4_hdr.get_dest_addr(), test_helpers::BOB_IPV4);
+    server.receive(frame).unwrap();
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    let renew: DhcpMessage = serve(&mut server, server_fd, test_helpers::ALICE_IPV4);
+    assert_eq!(renew.message_type, DhcpMessageType::Request);
+    assert_eq!(renew.ciaddr, test_helpers::ALICE_IPV4);
+    test_helpers::run_until_stalled(&mut [&mut client, &mut server]);
+    dhcp.poll(&mut client.ipv4, &client.arp).unwrap();
+    let lease = dhcp.lease().unwrap();
+    assert_eq!(lease.addr, test_helpers::ALICE_IPV4);
+    assert_eq!(lease.acquired, acquired + LEASE_TIME / 2);
+}
@@ -6,19 +6,96 @@ use ::byteorder::{
     ByteOrder,
     NetworkEndian,
 };
-use ::libc::EBADMSG;
+use ::libc::{
+    EBADMSG,
+    EINVAL,
+};
 use ::runtime::{
     fail::Fail,
-    memory::Buffer,
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
     network::types::MacAddress,
 };
-use ::std::convert::{
-    TryFrom,
-    TryInto,
-};
+use ::std::convert::TryFrom;
 
 pub const ETHERNET2_HEADER_SIZE: usize = 14;
 pub const MIN_PAYLOAD_SIZE: usize = 46;
+/// Size of an IEEE 802.1Q tag (in bytes).
+pub const VLAN_TAG_SIZE: usize = 4;
+/// Tag protocol identifier of IEEE 802.1Q tagged frames.
+const VLAN_TPID: u16 = 0x8100;
+/// Minimum size of an Ethernet frame, not including the frame check sequence (in bytes).
+pub const MIN_FRAME_SIZE: usize = ETHERNET2_HEADER_SIZE + MIN_PAYLOAD_SIZE;
+
+/// Computes how many padding bytes are required to bring a frame to the minimum Ethernet frame size.
+pub fn padding_size(frame_size: usize) -> usize {
+    MIN_FRAME_SIZE.saturating_sub(frame_size)
+}
+
+/// Appends zeroed padding bytes to the body of a frame.
+pub fn pad_body(body: &[u8], padding: usize) -> Buffer {
+    let mut buf: DataBuffer = DataBuffer::new(body.len() + padding).expect("failed to allocate padded frame body");
+    buf[..body.len()].copy_from_slice(body);
+    for b in &mut buf[body.len()..] {
+        *b = 0;
+    }
+    Buffer::Heap(buf)
+}
+
+/// IEEE 802.1Q Tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VlanTag {
+    /// Priority code point (3 bits).
+    pcp: u8,
+    /// Drop eligible indicator.
+    dei: bool,
+    /// VLAN identifier (12 bits).
+    vid: u16,
+}
+
+impl VlanTag {
+    /// Creates a VLAN tag, which fails if the priority code point or the VLAN identifier does not fit in its field.
+    pub fn new(pcp: u8, dei: bool, vid: u16) -> Result<Self, Fail> {
+        if pcp > 0x7 {
+            return Err(Fail::new(EINVAL, "priority code point out of range"));
+        }
+        if vid > 0xfff {
+            return Err(Fail::new(EINVAL, "VLAN identifier out of range"));
+        }
+        Ok(Self { pcp, dei, vid })
+    }
+
+    /// Returns the priority code point stored in the target VLAN tag.
+    pub fn pcp(&self) -> u8 {
+        self.pcp
+    }
+
+    /// Returns the drop eligible indicator stored in the target VLAN tag.
+    pub fn dei(&self) -> bool {
+        self.dei
+    }
+
+    /// Returns the VLAN identifier stored in the target VLAN tag.
+    pub fn vid(&self) -> u16 {
+        self.vid
+    }
+
+    /// Parses the tag control information of a VLAN tag.
+    fn from_tci(tci: u16) -> Self {
+        Self {
+            pcp: (tci >> 13) as u8,
+            dei: (tci & 0x1000) != 0,
+            vid: tci & 0xfff,
+        }
+    }
+
+    /// Computes the tag control information of the target VLAN tag.
+    fn tci(&self) -> u16 {
+        ((self.pcp as u16) << 13) | ((self.dei as u16) << 12) | self.vid
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Ethernet2Header {
@@ -26,7 +103,9 @@ pub struct Ethernet2Header {
     dst_addr: MacAddress,
     // Bytes 6..12
     src_addr: MacAddress,
-    // Bytes 12..14
+    // Bytes 12..16, only present in tagged frames.
+    vlan_tag: Option<VlanTag>,
+    // Bytes 12..14 (16..18 in tagged frames)
     ether_type: EtherType2,
 }
 
@@ -36,12 +115,22 @@ impl Ethernet2Header {
         Self {
             dst_addr,
             src_addr,
+            vlan_tag: None,
             ether_type,
         }
     }
 
+    /// Inserts an IEEE 802.1Q tag in the target header.
+    pub fn with_vlan_tag(mut self, vlan_tag: Option<VlanTag>) -> Self {
+        self.vlan_tag = vlan_tag;
+        self
+    }
+
     pub fn compute_size(&self) -> usize {
-        ETHERNET2_HEADER_SIZE
+        match self.vlan_tag {
+            Some(_) => ETHERNET2_HEADER_SIZE + VLAN_TAG_SIZE,
+            None => ETHERNET2_HEADER_SIZE,
+        }
     }
 
     pub fn parse(mut buf: Buffer) -> Result<(Self, Buffer), Fail> {
@@ -51,22 +140,44 @@ impl Ethernet2Header {
         let hdr_buf = &buf[..ETHERNET2_HEADER_SIZE];
         let dst_addr = MacAddress::from_bytes(&hdr_buf[0..6]);
         let src_addr = MacAddress::from_bytes(&hdr_buf[6..12]);
-        let ether_type = EtherType2::try_from(NetworkEndian::read_u16(&hdr_buf[12..14]))?;
+
+        // Check for an IEEE 802.1Q tag.
+        let (vlan_tag, hdr_size): (Option<VlanTag>, usize) = match NetworkEndian::read_u16(&hdr_buf[12..14]) {
+            VLAN_TPID => {
+                if buf.len() < ETHERNET2_HEADER_SIZE + VLAN_TAG_SIZE {
+                    return Err(Fail::new(EBADMSG, "tagged frame too small"));
+                }
+                let tci: u16 = NetworkEndian::read_u16(&buf[14..16]);
+                (Some(VlanTag::from_tci(tci)), ETHERNET2_HEADER_SIZE + VLAN_TAG_SIZE)
+            },
+            _ => (None, ETHERNET2_HEADER_SIZE),
+        };
+
+        let ether_type = EtherType2::try_from(NetworkEndian::read_u16(&buf[(hdr_size - 2)..hdr_size]))?;
         let hdr = Self {
             dst_addr,
             src_addr,
+            vlan_tag,
             ether_type,
         };
 
-        buf.adjust(ETHERNET2_HEADER_SIZE);
+        buf.adjust(hdr_size);
         Ok((hdr, buf))
     }
 
     pub fn serialize(&self, buf: &mut [u8]) {
-        let buf: &mut [u8; ETHERNET2_HEADER_SIZE] = buf.try_into().unwrap();
+        assert_eq!(buf.len(), self.compute_size());
         buf[0..6].copy_from_slice(&self.dst_addr.octets());
         buf[6..12].copy_from_slice(&self.src_addr.octets());
-        NetworkEndian::write_u16(&mut buf[12..14], self.ether_type as u16);
+        let buf: &mut [u8] = match self.vlan_tag {
+            Some(vlan_tag) => {
+                NetworkEndian::write_u16(&mut buf[12..14], VLAN_TPID);
+                NetworkEndian::write_u16(&mut buf[14..16], vlan_tag.tci());
+                &mut buf[16..18]
+            },
+            None => &mut buf[12..14],
+        };
+        NetworkEndian::write_u16(buf, self.ether_type as u16);
     }
 
     pub fn dst_addr(&self) -> MacAddress {
@@ -80,4 +191,16 @@ impl Ethernet2Header {
     pub fn ether_type(&self) -> EtherType2 {
         self.ether_type
     }
+
+    /// Returns the IEEE 802.1Q tag of the target header, if any.
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        self.vlan_tag
+    }
+
+    /// Asserts if the target frame should be processed by a host with the given link address. This is the case for
+    /// frames that are addressed to that host, and for broadcast and multicast frames.
+    pub fn is_destined_to(&self, link_addr: MacAddress) -> bool {
+        // The least significant bit of the first octet is set for group (broadcast and multicast) addresses.
+        self.dst_addr == link_addr || self.dst_addr.is_broadcast() || (self.dst_addr.octets()[0] & 0x01) != 0
+    }
 }
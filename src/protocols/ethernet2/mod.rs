@@ -4,11 +4,19 @@
 mod frame;
 mod protocol;
 
+#[cfg(test)]
+mod tests;
+
 pub use self::{
     frame::{
+        pad_body,
+        padding_size,
         Ethernet2Header,
+        VlanTag,
         ETHERNET2_HEADER_SIZE,
+        MIN_FRAME_SIZE,
         MIN_PAYLOAD_SIZE,
+        VLAN_TAG_SIZE,
     },
     protocol::EtherType2,
 };
@@ -0,0 +1,73 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    EtherType2,
+    Ethernet2Header,
+    VlanTag,
+    ETHERNET2_HEADER_SIZE,
+    VLAN_TAG_SIZE,
+};
+use crate::test_helpers;
+use ::libc::EINVAL;
+use ::runtime::memory::{
+    Buffer,
+    DataBuffer,
+};
+
+/// Serializes an Ethernet header followed by some payload.
+fn serialize_frame(hdr: &Ethernet2Header, payload: &[u8]) -> Buffer {
+    let hdr_size: usize = hdr.compute_size();
+    let mut buf: DataBuffer = DataBuffer::new(hdr_size + payload.len()).unwrap();
+    hdr.serialize(&mut buf[..hdr_size]);
+    buf[hdr_size..].copy_from_slice(payload);
+    Buffer::Heap(buf)
+}
+
+/// Tests if tagged frames are round-tripped.
+#[test]
+fn vlan_tagged_frame() {
+    let payload: [u8; 4] = [0x1, 0x2, 0x3, 0x4];
+    let vlan_tag: VlanTag = VlanTag::new(5, false, 0xabc).unwrap();
+    let hdr: Ethernet2Header = Ethernet2Header::new(test_helpers::BOB_MAC, test_helpers::ALICE_MAC, EtherType2::Ipv4)
+        .with_vlan_tag(Some(vlan_tag));
+    assert_eq!(hdr.compute_size(), ETHERNET2_HEADER_SIZE + VLAN_TAG_SIZE);
+
+    let buf: Buffer = serialize_frame(&hdr, &payload);
+    assert_eq!(buf[12..14], [0x81, 0x00]);
+
+    let (hdr, data): (Ethernet2Header, Buffer) = Ethernet2Header::parse(buf).unwrap();
+    assert_eq!(hdr.dst_addr(), test_helpers::BOB_MAC);
+    assert_eq!(hdr.src_addr(), test_helpers::ALICE_MAC);
+    assert_eq!(hdr.ether_type(), EtherType2::Ipv4);
+    assert_eq!(hdr.vlan_tag(), Some(vlan_tag));
+    assert_eq!(hdr.vlan_tag().unwrap().vid(), 0xabc);
+    assert_eq!(hdr.vlan_tag().unwrap().pcp(), 5);
+    assert_eq!(data[..], payload);
+}
+
+/// Tests if VLAN tags whose fields do not fit are rejected.
+#[test]
+fn vlan_tag_out_of_range() {
+    for (pcp, vid) in [(8, 0), (0, 0x1000), (0xff, 0xffff)] {
+        match VlanTag::new(pcp, false, vid) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("PCP {} and VID {} should have been rejected", pcp, vid),
+        }
+    }
+    assert!(VlanTag::new(0x7, true, 0xfff).is_ok());
+}
+
+/// Tests if untagged frames still parse.
+#[test]
+fn untagged_frame() {
+    let payload: [u8; 4] = [0x1, 0x2, 0x3, 0x4];
+    let hdr: Ethernet2Header = Ethernet2Header::new(test_helpers::BOB_MAC, test_helpers::ALICE_MAC, EtherType2::Arp);
+    assert_eq!(hdr.compute_size(), ETHERNET2_HEADER_SIZE);
+
+    let buf: Buffer = serialize_frame(&hdr, &payload);
+    let (hdr, data): (Ethernet2Header, Buffer) = Ethernet2Header::parse(buf).unwrap();
+    assert_eq!(hdr.ether_type(), EtherType2::Arp);
+    assert_eq!(hdr.vlan_tag(), None);
+    assert_eq!(data[..], payload);
+}
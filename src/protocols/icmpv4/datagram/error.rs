@@ -0,0 +1,85 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::protocol::Icmpv4Type2;
+use crate::protocols::ip::IpProtocol;
+use ::byteorder::{
+    ByteOrder,
+    NetworkEndian,
+};
+use ::libc::{
+    EBADMSG,
+    ECONNREFUSED,
+    EHOSTUNREACH,
+    EMSGSIZE,
+    ENETUNREACH,
+    ENOBUFS,
+    ENOPROTOOPT,
+    EPROTO,
+};
+use ::runtime::{
+    fail::Fail,
+    memory::Buffer,
+};
+use ::std::{
+    convert::TryFrom,
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
+};
+
+/// Size of the part of a datagram that an ICMP error message quotes, after its IPv4 header (in bytes).
+const QUOTED_PAYLOAD_MIN_SIZE: usize = 4;
+
+/// An ICMP error message about a datagram that we sent.
+#[derive(Debug)]
+pub struct Icmpv4Error {
+    /// Transport protocol of the datagram.
+    pub protocol: IpProtocol,
+    /// Endpoint from which we sent the datagram.
+    pub local: SocketAddrV4,
+    /// Endpoint to which we sent the datagram.
+    pub remote: SocketAddrV4,
+    /// What went wrong, as reported by the message.
+    pub cause: Fail,
+}
+
+/// Associated Functions for Icmpv4Error
+impl Icmpv4Error {
+    /// Parses the datagram quoted by an ICMP error message. RFC 792 has the message carry the IPv4 header of the
+    /// datagram along with the first 64 bits of its payload, of which we only need the ports.
+    pub fn parse(icmpv4_type: Icmpv4Type2, code: u8, quoted: Buffer) -> Result<Self, Fail> {
+        let cause: Fail = match (icmpv4_type, code) {
+            (Icmpv4Type2::DestinationUnreachable, 0) => Fail::new(ENETUNREACH, "network unreachable"),
+            (Icmpv4Type2::DestinationUnreachable, 2) => Fail::new(ENOPROTOOPT, "protocol unreachable"),
+            (Icmpv4Type2::DestinationUnreachable, 3) => Fail::new(ECONNREFUSED, "port unreachable"),
+            (Icmpv4Type2::DestinationUnreachable, 4) => Fail::new(EMSGSIZE, "fragmentation needed"),
+            (Icmpv4Type2::DestinationUnreachable, _) => Fail::new(EHOSTUNREACH, "host unreachable"),
+            (Icmpv4Type2::SourceQuench, _) => Fail::new(ENOBUFS, "source quench"),
+            (Icmpv4Type2::TimeExceeded, _) => Fail::new(EHOSTUNREACH, "time to live exceeded"),
+            (Icmpv4Type2::BadIpHeader, _) => Fail::new(EPROTO, "parameter problem"),
+            _ => return Err(Fail::new(EBADMSG, "not an ICMPv4 error message")),
+        };
+
+        if quoted.len() < 20 {
+            return Err(Fail::new(EBADMSG, "ICMPv4 error message too small for quoted header"));
+        }
+        let ihl: usize = ((quoted[0] & 0xF) as usize) * 4;
+        if ihl < 20 || quoted.len() < ihl + QUOTED_PAYLOAD_MIN_SIZE {
+            return Err(Fail::new(EBADMSG, "ICMPv4 error message too small for quoted payload"));
+        }
+        let protocol: IpProtocol = IpProtocol::try_from(quoted[9])?;
+        let src_addr: Ipv4Addr = Ipv4Addr::new(quoted[12], quoted[13], quoted[14], quoted[15]);
+        let dst_addr: Ipv4Addr = Ipv4Addr::new(quoted[16], quoted[17], quoted[18], quoted[19]);
+        let src_port: u16 = NetworkEndian::read_u16(&quoted[ihl..(ihl + 2)]);
+        let dst_port: u16 = NetworkEndian::read_u16(&quoted[(ihl + 2)..(ihl + 4)]);
+
+        Ok(Self {
+            protocol,
+            local: SocketAddrV4::new(src_addr, src_port),
+            remote: SocketAddrV4::new(dst_addr, dst_port),
+            cause,
+        })
+    }
+}
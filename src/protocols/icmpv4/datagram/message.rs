@@ -3,11 +3,18 @@
 
 use super::Icmpv4Header;
 use crate::protocols::{
-    ethernet2::Ethernet2Header,
+    ethernet2::{
+        pad_body,
+        padding_size,
+        Ethernet2Header,
+    },
     ipv4::Ipv4Header,
 };
 use ::runtime::{
-    memory::Buffer,
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
     network::PacketBuf,
 };
 
@@ -16,16 +23,28 @@ pub struct Icmpv4Message {
     ethernet2_hdr: Ethernet2Header,
     ipv4_hdr: Ipv4Header,
     icmpv4_hdr: Icmpv4Header,
+    data: Buffer,
 }
 
 /// Associated Functions for Icmpv4Message
 impl Icmpv4Message {
     /// Creates an ICMP message.
     pub fn new(ethernet2_hdr: Ethernet2Header, ipv4_hdr: Ipv4Header, icmpv4_hdr: Icmpv4Header) -> Self {
+        Self::with_data(ethernet2_hdr, ipv4_hdr, icmpv4_hdr, Buffer::Heap(DataBuffer::empty()))
+    }
+
+    /// Creates an ICMP message that carries data, such as the start of the datagram that an error message is about.
+    pub fn with_data(
+        ethernet2_hdr: Ethernet2Header,
+        ipv4_hdr: Ipv4Header,
+        icmpv4_hdr: Icmpv4Header,
+        data: Buffer,
+    ) -> Self {
         Self {
             ethernet2_hdr,
             ipv4_hdr,
             icmpv4_hdr,
+            data,
         }
     }
 }
@@ -37,7 +56,7 @@ impl PacketBuf for Icmpv4Message {
     }
 
     fn body_size(&self) -> usize {
-        0
+        self.data.len() + padding_size(self.header_size() + self.data.len())
     }
 
     fn write_header(&self, buf: &mut [u8]) {
@@ -50,16 +69,20 @@ impl PacketBuf for Icmpv4Message {
             .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
         cur_pos += eth_hdr_size;
 
-        let ipv4_payload_len = icmpv4_hdr_size;
+        let ipv4_payload_len = icmpv4_hdr_size + self.data.len();
         self.ipv4_hdr
             .serialize(&mut buf[cur_pos..(cur_pos + ipv4_hdr_size)], ipv4_payload_len);
         cur_pos += ipv4_hdr_size;
 
         self.icmpv4_hdr
-            .serialize(&mut buf[cur_pos..(cur_pos + icmpv4_hdr_size)]);
+            .serialize(&mut buf[cur_pos..(cur_pos + icmpv4_hdr_size)], &self.data[..]);
     }
 
     fn take_body(self) -> Option<Buffer> {
-        None
+        // Pad short frames to the minimum Ethernet frame size.
+        match padding_size(self.header_size() + self.data.len()) {
+            0 => Some(self.data),
+            padding => Some(pad_body(&self.data[..], padding)),
+        }
     }
 }
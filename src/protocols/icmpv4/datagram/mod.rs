@@ -1,10 +1,12 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+mod error;
 mod header;
 mod message;
 mod protocol;
 
+pub use error::Icmpv4Error;
 pub use header::Icmpv4Header;
 pub use message::Icmpv4Message;
 pub use protocol::Icmpv4Type2;
@@ -7,4 +7,10 @@ mod peer;
 #[cfg(test)]
 mod tests;
 
+pub use datagram::{
+    Icmpv4Error,
+    Icmpv4Header,
+    Icmpv4Message,
+    Icmpv4Type2,
+};
 pub use peer::Icmpv4Peer;
@@ -13,6 +13,7 @@ use crate::{
             Ethernet2Header,
         },
         icmpv4::datagram::{
+            Icmpv4Error,
             Icmpv4Header,
             Icmpv4Message,
             Icmpv4Type2,
@@ -160,16 +161,18 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Icmpv4Peer<RT> {
             debug!("reply ping ({}, {}, {})", dst_ipv4_addr, id, seq_num);
             // Send reply message.
             rt.transmit(Icmpv4Message::new(
-                Ethernet2Header::new(dst_link_addr, rt.local_link_addr(), EtherType2::Ipv4),
+                Ethernet2Header::new(dst_link_addr, rt.local_link_addr(), EtherType2::Ipv4)
+                    .with_vlan_tag(arp.vlan_tag()),
                 Ipv4Header::new(rt.local_ipv4_addr(), dst_ipv4_addr, IpProtocol::ICMPv4),
                 Icmpv4Header::new(Icmpv4Type2::EchoReply { id, seq_num }, 0),
             ));
         }
     }
 
-    /// Parses and handles a ICMP message.
-    pub fn receive(&mut self, ipv4_header: &Ipv4Header, buf: Buffer) -> Result<(), Fail> {
-        let (icmpv4_hdr, _) = Icmpv4Header::parse(buf)?;
+    /// Parses and handles a ICMP message. Error messages about datagrams that we sent are handed back to the caller,
+    /// so that they reach the transport protocol that sent the datagram.
+    pub fn receive(&mut self, ipv4_header: &Ipv4Header, buf: Buffer) -> Result<Option<Icmpv4Error>, Fail> {
+        let (icmpv4_hdr, data) = Icmpv4Header::parse(buf)?;
         debug!("ICMPv4 received {:?}", icmpv4_hdr);
         match icmpv4_hdr.get_protocol() {
             Icmpv4Type2::EchoRequest { id, seq_num } => {
@@ -182,11 +185,18 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Icmpv4Peer<RT> {
                     let _ = tx.send(());
                 }
             },
+            Icmpv4Type2::DestinationUnreachable
+            | Icmpv4Type2::SourceQuench
+            | Icmpv4Type2::TimeExceeded
+            | Icmpv4Type2::BadIpHeader => {
+                let error: Icmpv4Error = Icmpv4Error::parse(icmpv4_hdr.get_protocol(), icmpv4_hdr.get_code(), data)?;
+                return Ok(Some(error));
+            },
             _ => {
                 warn!("Unsupported ICMPv4 message: {:?}", icmpv4_hdr);
             },
         }
-        Ok(())
+        Ok(None)
     }
 
     /// Computes the identifier for an ICPM message.
@@ -237,7 +247,8 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Icmpv4Peer<RT> {
             debug!("ARP query complete ({} -> {})", dst_ipv4_addr, dst_link_addr);
 
             let msg = Icmpv4Message::new(
-                Ethernet2Header::new(dst_link_addr, rt.local_link_addr(), EtherType2::Ipv4),
+                Ethernet2Header::new(dst_link_addr, rt.local_link_addr(), EtherType2::Ipv4)
+                    .with_vlan_tag(arp.vlan_tag()),
                 Ipv4Header::new(rt.local_ipv4_addr(), dst_ipv4_addr, IpProtocol::ICMPv4),
                 Icmpv4Header::new(echo_request, 0),
             );
@@ -19,10 +19,7 @@ use ::runtime::{
     memory::Buffer,
 };
 use ::std::{
-    convert::{
-        TryFrom,
-        TryInto,
-    },
+    convert::TryFrom,
     net::Ipv4Addr,
 };
 
@@ -42,6 +39,9 @@ const IPV4_HEADER_MIN_SIZE: u16 = IPV4_DATAGRAM_MIN_SIZE;
 /// IPv4 header length when no options are present (in 32-bit words).
 const IPV4_IHL_NO_OPTIONS: u8 = (IPV4_HEADER_MIN_SIZE as u8) / 4;
 
+/// Maximum size for the payload of an IPv4 datagram (in bytes).
+pub const IPV4_PAYLOAD_MAX_SIZE: usize = (u16::MAX - IPV4_HEADER_MIN_SIZE) as usize;
+
 /// Default time to live value.
 const DEFAULT_IPV4_TTL: u8 = 255;
 
@@ -73,7 +73,6 @@ pub struct Ipv4Header {
     /// Explicit Congestion Notification (2 bits).
     ecn: u8,
     /// Total length of the packet including header and data (16 bits).
-    #[allow(unused)]
     total_length: u16,
     /// Used to identify the datagram to which a fragment belongs (16 bits).
     identification: u16,
@@ -253,9 +252,11 @@ impl Ipv4Header {
         Ok((header, buf))
     }
 
-    /// Serializes the target IPv4 header.
+    /// Serializes the target IPv4 header into the first [Self::compute_size] bytes of `buf`. Callers must ensure that
+    /// the payload is no larger than [IPV4_PAYLOAD_MAX_SIZE].
     pub fn serialize(&self, buf: &mut [u8], payload_len: usize) {
-        let buf: &mut [u8; (IPV4_HEADER_MIN_SIZE as usize)] = buf.try_into().expect("buffer to small");
+        debug_assert!(payload_len <= IPV4_PAYLOAD_MAX_SIZE);
+        let buf: &mut [u8] = &mut buf[..(IPV4_HEADER_MIN_SIZE as usize)];
 
         // Version + IHL.
         buf[0] = (self.version << 4) | self.ihl;
@@ -304,22 +305,34 @@ impl Ipv4Header {
         self.dst_addr
     }
 
+    /// Returns the total length field stored in the target IPv4 header.
+    pub fn get_total_length(&self) -> u16 {
+        self.total_length
+    }
+
+    /// Returns the time to live field stored in the target IPv4 header.
+    pub fn get_ttl(&self) -> u8 {
+        self.ttl
+    }
+
     /// Returns the protocol field stored in the target IPv4 header.
     pub fn get_protocol(&self) -> IpProtocol {
         self.protocol
     }
 
-    /// Computes the checksum of the target IPv4 header.
+    /// Computes the checksum of the target IPv4 header. Only the first [IPV4_HEADER_MIN_SIZE] bytes of `buf` are
+    /// considered, and missing bytes are treated as zero.
     pub fn compute_checksum(buf: &[u8]) -> u16 {
-        let buf: &[u8; IPV4_HEADER_MIN_SIZE as usize] = buf.try_into().expect("Invalid header size");
         let mut state: u32 = 0xffffu32;
-        for i in 0..5 {
-            state += NetworkEndian::read_u16(&buf[(2 * i)..(2 * i + 2)]) as u32;
-        }
-        // Skip the 5th u16 since octets 10-12 are the header checksum, whose value should be zero when
-        // computing a checksum.
-        for i in 6..10 {
-            state += NetworkEndian::read_u16(&buf[(2 * i)..(2 * i + 2)]) as u32;
+        for (i, word) in buf.chunks(2).take((IPV4_HEADER_MIN_SIZE / 2) as usize).enumerate() {
+            // Skip the 5th u16 since octets 10-12 are the header checksum, whose value should be zero when
+            // computing a checksum.
+            if i == 5 {
+                continue;
+            }
+            let high: u8 = word[0];
+            let low: u8 = word.get(1).copied().unwrap_or(0);
+            state += u16::from_be_bytes([high, low]) as u32;
         }
         while state > 0xffff {
             state -= 0xffff;
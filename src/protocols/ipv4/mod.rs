@@ -2,6 +2,7 @@
 // Licensed under the MIT license.
 
 mod datagram;
+mod routing;
 
 #[cfg(test)]
 mod tests;
@@ -10,7 +11,15 @@ mod tests;
 // Exports
 //==============================================================================
 
-pub use self::datagram::{
-    Ipv4Header,
-    IPV4_HEADER_DEFAULT_SIZE,
+pub use self::{
+    datagram::{
+        Ipv4Header,
+        IPV4_HEADER_DEFAULT_SIZE,
+        IPV4_PAYLOAD_MAX_SIZE,
+    },
+    routing::{
+        NextHop,
+        Route,
+        RoutingTable,
+    },
 };
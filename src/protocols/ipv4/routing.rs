@@ -0,0 +1,133 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use ::libc::EINVAL;
+use ::runtime::fail::Fail;
+use ::std::net::Ipv4Addr;
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+/// Where datagrams that match a route are sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NextHop {
+    /// Destinations are on a link that we are attached to, so their own link address is resolved.
+    OnLink,
+    /// Destinations are reached through a gateway, whose link address is resolved in place of theirs.
+    Gateway(Ipv4Addr),
+}
+
+/// Route to the destinations that share a prefix.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Route {
+    prefix: Ipv4Addr,
+    prefix_len: u8,
+    next_hop: NextHop,
+}
+
+/// Routing table, which picks the next hop towards a destination by longest prefix match.
+#[derive(Clone, Debug, Default)]
+pub struct RoutingTable {
+    /// Routes, sorted from the longest prefix to the shortest one.
+    routes: Vec<Route>,
+}
+
+//==============================================================================
+// Associated Functions
+//==============================================================================
+
+impl Route {
+    /// Creates a route. The prefix may not have any bits set past its length, so 0.0.0.0/0 is the default route.
+    pub fn new(prefix: Ipv4Addr, prefix_len: u8, next_hop: NextHop) -> Result<Self, Fail> {
+        if prefix_len > 32 {
+            return Err(Fail::new(EINVAL, "prefix length is too long"));
+        }
+        if u32::from(prefix) & !netmask(prefix_len) != 0 {
+            return Err(Fail::new(EINVAL, "prefix has bits set past its length"));
+        }
+        Ok(Self {
+            prefix,
+            prefix_len,
+            next_hop,
+        })
+    }
+
+    pub fn get_prefix(&self) -> Ipv4Addr {
+        self.prefix
+    }
+
+    pub fn get_prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn get_next_hop(&self) -> NextHop {
+        self.next_hop
+    }
+
+    /// Checks if a destination matches this route.
+    pub fn contains(&self, ipv4_addr: Ipv4Addr) -> bool {
+        u32::from(ipv4_addr) & netmask(self.prefix_len) == u32::from(self.prefix)
+    }
+}
+
+impl RoutingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Inserts a route. Returns the route that it replaces, if any had the same prefix.
+    pub fn insert(&mut self, route: Route) -> Option<Route> {
+        let old_route: Option<Route> = self.remove(route.prefix, route.prefix_len);
+        let index: usize = self
+            .routes
+            .iter()
+            .position(|r| r.prefix_len < route.prefix_len)
+            .unwrap_or(self.routes.len());
+        self.routes.insert(index, route);
+        old_route
+    }
+
+    /// Removes the route for a prefix.
+    pub fn remove(&mut self, prefix: Ipv4Addr, prefix_len: u8) -> Option<Route> {
+        let index: usize = self
+            .routes
+            .iter()
+            .position(|r| r.prefix == prefix && r.prefix_len == prefix_len)?;
+        Some(self.routes.remove(index))
+    }
+
+    /// Looks up the route with the longest prefix that a destination matches.
+    pub fn lookup(&self, ipv4_addr: Ipv4Addr) -> Option<&Route> {
+        self.routes.iter().find(|r| r.contains(ipv4_addr))
+    }
+
+    /// Gets the address whose link address datagrams to a destination are sent to. Destinations that match no route
+    /// are taken to be on-link.
+    pub fn next_hop(&self, ipv4_addr: Ipv4Addr) -> Ipv4Addr {
+        match self.lookup(ipv4_addr).map(|r| r.next_hop) {
+            Some(NextHop::Gateway(gateway)) => gateway,
+            Some(NextHop::OnLink) | None => ipv4_addr,
+        }
+    }
+}
+
+//==============================================================================
+// Standalone Functions
+//==============================================================================
+
+/// Gets the mask of a prefix length.
+fn netmask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        n => u32::MAX << (32 - n),
+    }
+}
@@ -8,9 +8,17 @@
 use crate::{
     protocols::{
         ip::IpProtocol,
-        ipv4::Ipv4Header,
+        ipv4::{
+            Ipv4Header,
+            NextHop,
+            Route,
+            RoutingTable,
+        },
     },
     test_helpers::{
+        self,
+        Engine,
+        TestRuntime,
         ALICE_IPV4,
         BOB_IPV4,
     },
@@ -19,9 +27,31 @@ use ::byteorder::{
     ByteOrder,
     NetworkEndian,
 };
-use ::runtime::memory::{
-    Buffer,
-    DataBuffer,
+use ::futures::task::noop_waker_ref;
+use ::libc::{
+    EINVAL,
+    ENOTSUP,
+    EPERM,
+};
+use ::runtime::{
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
+    QDesc,
+};
+use ::std::{
+    future::Future,
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Instant,
 };
 
 //==============================================================================
@@ -619,3 +649,143 @@ fn test_ipv4_header_parse_unsupported_protocol() {
         };
     }
 }
+
+//==============================================================================
+// Unit-Tests for Demultiplexing
+//==============================================================================
+
+/// Receives a well-formed datagram for a protocol that we do not handle.
+#[test]
+fn test_ipv4_receive_unknown_protocol() {
+    const HEADER_SIZE: usize = 20;
+    const PAYLOAD_SIZE: usize = 0;
+    const DATAGRAM_SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE;
+    const OSPF: u8 = 89;
+    let mut buf: [u8; DATAGRAM_SIZE] = [0; DATAGRAM_SIZE];
+    let mut engine: Engine<TestRuntime> = test_helpers::new_bob2(Instant::now());
+
+    build_ipv4_header(
+        &mut buf,
+        4,
+        5,
+        0,
+        0,
+        DATAGRAM_SIZE as u16,
+        0,
+        0x2,
+        0,
+        1,
+        OSPF,
+        &ALICE_IPV4.octets(),
+        &BOB_IPV4.octets(),
+        None,
+    );
+
+    // Do it.
+    let buf_bytes: Buffer = Buffer::Heap(DataBuffer::from_slice(&buf));
+    match engine.ipv4.receive(buf_bytes) {
+        Err(e) if e.errno == ENOTSUP => {},
+        r => assert!(false, "received datagram with unknown protocol: {:?}", r),
+    }
+    assert_eq!(engine.ipv4.stats().unknown_protocol(), 1);
+    assert_eq!(engine.ipv4.stats().received(OSPF), 1);
+    assert_eq!(engine.ipv4.stats().received(IpProtocol::UDP as u8), 0);
+
+    // A corrupted datagram is not accounted for.
+    buf[10] ^= 0xff;
+    let buf_bytes: Buffer = Buffer::Heap(DataBuffer::from_slice(&buf));
+    assert!(engine.ipv4.receive(buf_bytes).is_err());
+    assert_eq!(engine.ipv4.stats().unknown_protocol(), 1);
+    assert_eq!(engine.ipv4.stats().received(OSPF), 1);
+}
+
+/// Drops datagrams for protocols that are not allowed before they reach the peer of their protocol.
+#[test]
+fn test_ipv4_receive_protocol_allowlist() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now: Instant = Instant::now();
+    let mut alice: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let alice_fd: QDesc = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, SocketAddrV4::new(ALICE_IPV4, 80)).unwrap();
+
+    // Bob only accepts TCP, but has a UDP socket nonetheless.
+    let mut bob: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    bob.ipv4.set_allowed_protocols(&[IpProtocol::TCP]);
+    let bob_addr: SocketAddrV4 = SocketAddrV4::new(BOB_IPV4, 80);
+    let bob_fd: QDesc = bob.udp_socket().unwrap();
+    bob.udp_bind(bob_fd, bob_addr).unwrap();
+
+    // Send a datagram to Bob.
+    let buf: Buffer = Buffer::Heap(DataBuffer::from_slice(&[0x5a; 32]));
+    alice.udp_pushto(alice_fd, buf, bob_addr).unwrap();
+    alice.rt().poll_scheduler();
+    match bob.receive(alice.rt().pop_frame()) {
+        Err(e) if e.errno == EPERM => {},
+        r => assert!(false, "received datagram with a protocol that is not allowed: {:?}", r),
+    }
+    assert_eq!(bob.ipv4.stats().filtered(), 1);
+    assert_eq!(bob.ipv4.stats().received(IpProtocol::UDP as u8), 0);
+
+    // The datagram never reached the UDP socket.
+    let mut pop_future = bob.udp_pop(bob_fd);
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Pending => {},
+        _ => assert!(false, "datagram should have been dropped"),
+    }
+}
+
+//==============================================================================
+// Unit-Tests for Routing
+//==============================================================================
+
+/// Resolves on-link destinations themselves, and the others through the gateway of the default route.
+#[test]
+fn test_ipv4_routing_on_link_and_gateway() {
+    let engine: Engine<TestRuntime> = test_helpers::new_alice2(Instant::now());
+    let gateway: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 254);
+    let off_link: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    // With no routes, every destination is on-link.
+    assert_eq!(engine.arp.next_hop(off_link), off_link);
+
+    let mut routes: RoutingTable = RoutingTable::new();
+    routes.insert(Route::new(Ipv4Addr::new(192, 168, 1, 0), 24, NextHop::OnLink).unwrap());
+    routes.insert(Route::new(Ipv4Addr::UNSPECIFIED, 0, NextHop::Gateway(gateway)).unwrap());
+    engine.arp.set_routing_table(routes);
+    assert_eq!(engine.arp.next_hop(BOB_IPV4), BOB_IPV4);
+    assert_eq!(engine.arp.next_hop(off_link), gateway);
+
+    // Our own address and the broadcast address are never routed.
+    assert_eq!(engine.arp.next_hop(ALICE_IPV4), ALICE_IPV4);
+    assert_eq!(engine.arp.next_hop(Ipv4Addr::BROADCAST), Ipv4Addr::BROADCAST);
+}
+
+/// Picks the route with the longest prefix among those that a destination matches.
+#[test]
+fn test_ipv4_routing_longest_prefix_match() {
+    let default_gateway: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 254);
+    let private_gateway: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 253);
+    let mut routes: RoutingTable = RoutingTable::new();
+    routes.insert(Route::new(Ipv4Addr::UNSPECIFIED, 0, NextHop::Gateway(default_gateway)).unwrap());
+    routes.insert(Route::new(Ipv4Addr::new(10, 1, 0, 0), 16, NextHop::OnLink).unwrap());
+    routes.insert(Route::new(Ipv4Addr::new(10, 0, 0, 0), 8, NextHop::Gateway(private_gateway)).unwrap());
+
+    assert_eq!(routes.next_hop(Ipv4Addr::new(10, 1, 2, 3)), Ipv4Addr::new(10, 1, 2, 3));
+    assert_eq!(routes.next_hop(Ipv4Addr::new(10, 2, 3, 4)), private_gateway);
+    assert_eq!(routes.next_hop(Ipv4Addr::new(8, 8, 8, 8)), default_gateway);
+    assert_eq!(routes.lookup(Ipv4Addr::new(10, 1, 2, 3)).unwrap().get_prefix_len(), 16);
+
+    // Replacing and removing routes.
+    let old_route: Option<Route> =
+        routes.insert(Route::new(Ipv4Addr::new(10, 1, 0, 0), 16, NextHop::Gateway(default_gateway)).unwrap());
+    assert_eq!(old_route.unwrap().get_next_hop(), NextHop::OnLink);
+    assert_eq!(routes.next_hop(Ipv4Addr::new(10, 1, 2, 3)), default_gateway);
+    assert!(routes.remove(Ipv4Addr::new(10, 0, 0, 0), 8).is_some());
+    assert_eq!(routes.next_hop(Ipv4Addr::new(10, 2, 3, 4)), default_gateway);
+
+    // Prefixes with bits set past their length are refused.
+    match Route::new(Ipv4Addr::new(10, 1, 0, 0), 8, NextHop::OnLink) {
+        Err(e) if e.errno == EINVAL => {},
+        r => assert!(false, "created route with an invalid prefix: {:?}", r),
+    }
+}
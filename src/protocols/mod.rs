@@ -2,6 +2,7 @@
 // Licensed under the MIT license.
 
 pub mod arp;
+pub mod dhcp;
 pub mod ethernet2;
 pub mod icmpv4;
 pub mod ip;
@@ -10,7 +11,10 @@ mod peer;
 pub mod tcp;
 pub mod udp;
 
-pub use peer::Peer;
+pub use peer::{
+    Ipv4Stats,
+    Peer,
+};
 
 pub enum Protocol {
     Tcp,
@@ -9,7 +9,11 @@ use crate::protocols::{
     tcp::TcpPeer,
     udp::UdpPeer,
 };
-use ::libc::ENOTCONN;
+use ::libc::{
+    ENOTCONN,
+    ENOTSUP,
+    EPERM,
+};
 use ::runtime::{
     fail::Fail,
     memory::Buffer,
@@ -20,6 +24,8 @@ use ::runtime::{
     task::SchedulerRuntime,
 };
 use ::std::{
+    collections::HashMap,
+    convert::TryFrom,
     future::Future,
     net::Ipv4Addr,
     time::Duration,
@@ -28,8 +34,26 @@ use ::std::{
 #[cfg(test)]
 use ::runtime::QDesc;
 
+/// IPv4 Demultiplexing Statistics
+///
+/// Counters of the datagrams that the IPv4 receiver has handed over, or failed to hand over, to upper-layer protocols.
+#[derive(Clone, Debug, Default)]
+pub struct Ipv4Stats {
+    /// Number of well-formed datagrams received, by protocol number.
+    received: HashMap<u8, u64>,
+    /// Number of well-formed datagrams dropped because we do not handle their protocol.
+    unknown_protocol: u64,
+    /// Number of datagrams dropped because their protocol is not allowed.
+    filtered: u64,
+}
+
 pub struct Peer<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     rt: RT,
+    // Addresses that we accept datagrams for, besides the one of the runtime.
+    secondary_ipv4_addrs: Vec<Ipv4Addr>,
+    // Protocols that we accept datagrams for.
+    allowed_protocols: Vec<IpProtocol>,
+    stats: Ipv4Stats,
     icmpv4: Icmpv4Peer<RT>,
     pub tcp: TcpPeer<RT>,
     pub udp: UdpPeer<RT>,
@@ -51,17 +75,99 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Peer<RT> {
         let icmpv4: Icmpv4Peer<RT> = Icmpv4Peer::new(rt.clone(), arp.clone(), rng_seed);
         let tcp: TcpPeer<RT> = TcpPeer::new(rt.clone(), arp, rng_seed);
 
-        Peer { rt, icmpv4, tcp, udp }
+        Peer {
+            rt,
+            secondary_ipv4_addrs: Vec::new(),
+            allowed_protocols: vec![IpProtocol::ICMPv4, IpProtocol::TCP, IpProtocol::UDP],
+            stats: Ipv4Stats::default(),
+            icmpv4,
+            tcp,
+            udp,
+        }
+    }
+
+    /// Adds an address that we accept datagrams for, besides the one of the runtime. We do not answer ARP requests
+    /// for it, so other hosts need to have it in their ARP caches.
+    pub fn add_ipv4_addr(&mut self, addr: Ipv4Addr) {
+        if addr != self.rt.local_ipv4_addr() && !self.secondary_ipv4_addrs.contains(&addr) {
+            self.secondary_ipv4_addrs.push(addr);
+        }
+    }
+
+    /// Removes an address that was added with [Self::add_ipv4_addr], so that we no longer accept datagrams for it.
+    pub fn remove_ipv4_addr(&mut self, addr: Ipv4Addr) {
+        self.secondary_ipv4_addrs.retain(|a| *a != addr);
+    }
+
+    /// Sets the address that TCP and UDP sockets which are not bound to a specific one send from. If `None`, which is the
+    /// default, they send from the address of the runtime.
+    pub fn set_source_ipv4_addr(&mut self, addr: Option<Ipv4Addr>) {
+        self.tcp.set_source_ipv4_addr(addr);
+        self.udp.set_source_ipv4_addr(addr);
+    }
+
+    /// Sets the protocols that we accept datagrams for. Datagrams for other protocols are dropped before their header is
+    /// parsed, so that a single-purpose stack does not spend any work on them. All protocols are accepted by default.
+    pub fn set_allowed_protocols(&mut self, protocols: &[IpProtocol]) {
+        self.allowed_protocols = protocols.to_vec();
+    }
+
+    fn is_local_ipv4_addr(&self, addr: Ipv4Addr) -> bool {
+        addr == self.rt.local_ipv4_addr() || self.secondary_ipv4_addrs.contains(&addr)
+    }
+
+    fn has_valid_checksum(hdr_buf: &[u8]) -> bool {
+        match hdr_buf.get(10..12) {
+            Some(checksum) => u16::from_be_bytes([checksum[0], checksum[1]]) == Ipv4Header::compute_checksum(hdr_buf),
+            None => false,
+        }
+    }
+
+    /// Gets the demultiplexing statistics of the IPv4 receiver.
+    pub fn stats(&self) -> &Ipv4Stats {
+        &self.stats
     }
 
     pub fn receive(&mut self, buf: Buffer) -> Result<(), Fail> {
-        let (header, payload) = Ipv4Header::parse(buf)?;
+        // Peek at the protocol number, as the header of a datagram for a protocol that we do not handle is not parsed.
+        let protocol: Option<u8> = buf[..].get(9).copied();
+        if let Some(Ok(protocol)) = protocol.map(IpProtocol::try_from) {
+            if !self.allowed_protocols.contains(&protocol) {
+                self.stats.filtered += 1;
+                return Err(Fail::new(EPERM, "protocol is not allowed"));
+            }
+        }
+        // The protocol of a datagram is checked before its header checksum, so verify the checksum of a datagram for a
+        // protocol that we do not handle here, as only well-formed datagrams are accounted for.
+        let unknown_protocol: Option<u8> = match protocol {
+            Some(protocol) if IpProtocol::try_from(protocol).is_err() && Self::has_valid_checksum(&buf[..]) => {
+                Some(protocol)
+            },
+            _ => None,
+        };
+        let (header, payload) = match Ipv4Header::parse(buf) {
+            Ok(result) => result,
+            Err(e) => {
+                if let (ENOTSUP, Some(protocol)) = (e.errno, unknown_protocol) {
+                    self.stats.record(protocol);
+                    self.stats.unknown_protocol += 1;
+                }
+                return Err(e);
+            },
+        };
+        self.stats.record(header.get_protocol() as u8);
         debug!("Ipv4 received {:?}", header);
-        if header.get_dest_addr() != self.rt.local_ipv4_addr() && !header.get_dest_addr().is_broadcast() {
+        if !self.is_local_ipv4_addr(header.get_dest_addr()) && !header.get_dest_addr().is_broadcast() {
             return Err(Fail::new(ENOTCONN, "invalid destination address"));
         }
         match header.get_protocol() {
-            IpProtocol::ICMPv4 => self.icmpv4.receive(&header, payload),
+            IpProtocol::ICMPv4 => match self.icmpv4.receive(&header, payload)? {
+                Some(error) if error.protocol == IpProtocol::TCP => {
+                    self.tcp.receive_icmp_error(error.local, error.remote, error.cause);
+                    Ok(())
+                },
+                _ => Ok(()),
+            },
             IpProtocol::TCP => self.tcp.receive(&header, payload),
             IpProtocol::UDP => self.udp.do_receive(&header, payload),
         }
@@ -76,10 +182,31 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Peer<RT> {
     }
 }
 
+impl Ipv4Stats {
+    /// Gets the number of well-formed datagrams received with a protocol number.
+    pub fn received(&self, protocol: u8) -> u64 {
+        self.received.get(&protocol).copied().unwrap_or(0)
+    }
+
+    /// Gets the number of well-formed datagrams dropped because we do not handle their protocol.
+    pub fn unknown_protocol(&self) -> u64 {
+        self.unknown_protocol
+    }
+
+    /// Gets the number of datagrams dropped because their protocol is not allowed.
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
+
+    fn record(&mut self, protocol: u8) {
+        *self.received.entry(protocol).or_insert(0) += 1;
+    }
+}
+
 #[cfg(test)]
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Peer<RT> {
     pub fn tcp_mss(&self, fd: QDesc) -> Result<usize, Fail> {
-        self.tcp.remote_mss(fd)
+        self.tcp.effective_send_mss(fd)
     }
 
     pub fn tcp_rto(&self, fd: QDesc) -> Result<Duration, Fail> {
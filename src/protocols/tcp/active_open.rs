@@ -2,8 +2,16 @@
 // Licensed under the MIT license.
 
 use super::{
-    constants::FALLBACK_MSS,
-    established::ControlBlock,
+    config::TcpConfigExt,
+    constants::MAX_WINDOW_SCALE,
+    established::{
+        ConnectionParams,
+        ControlBlock,
+    },
+    loopback::{
+        self,
+        LoopbackSender,
+    },
     SeqNumber,
 };
 use crate::{
@@ -17,10 +25,7 @@ use crate::{
         ip::IpProtocol,
         ipv4::Ipv4Header,
         tcp::{
-            established::congestion_control::{
-                self,
-                CongestionControl,
-            },
+            established::congestion_control::CongestionControlConstructor,
             segment::{
                 TcpHeader,
                 TcpOptions2,
@@ -32,6 +37,8 @@ use crate::{
 use ::futures::FutureExt;
 use ::libc::{
     ECONNREFUSED,
+    EHOSTUNREACH,
+    ENOPROTOOPT,
     ETIMEDOUT,
 };
 use ::runtime::{
@@ -46,7 +53,7 @@ use ::runtime::{
 };
 use ::std::{
     cell::RefCell,
-    convert::TryInto,
+    cmp,
     future::Future,
     net::SocketAddrV4,
     rc::Rc,
@@ -55,11 +62,16 @@ use ::std::{
         Poll,
         Waker,
     },
+    time::Instant,
 };
 
 struct ConnectResult<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     waker: Option<Waker>,
     result: Option<Result<ControlBlock<RT>, Fail>>,
+    /// Last soft error reported by ICMP, which is the outcome of the connection request if it times out.
+    soft_error: Option<Fail>,
+    /// Time at which our most recent SYN was sent.
+    syn_sent: Option<Instant>,
 }
 
 pub struct ActiveOpenSocket<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
@@ -70,6 +82,11 @@ pub struct ActiveOpenSocket<RT: SchedulerRuntime + NetworkRuntime + Clone + 'sta
 
     rt: RT,
     arp: ArpPeer<RT>,
+    loopback: LoopbackSender,
+    config: TcpConfigExt,
+    congestion_control: CongestionControlConstructor<RT>,
+    // Time at which the connection request started.
+    started: Instant,
 
     #[allow(unused)]
     handle: SchedulerHandle,
@@ -77,14 +94,36 @@ pub struct ActiveOpenSocket<RT: SchedulerRuntime + NetworkRuntime + Clone + 'sta
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<RT> {
-    pub fn new(local_isn: SeqNumber, local: SocketAddrV4, remote: SocketAddrV4, rt: RT, arp: ArpPeer<RT>) -> Self {
+    pub fn new(
+        local_isn: SeqNumber,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        rt: RT,
+        arp: ArpPeer<RT>,
+        loopback: LoopbackSender,
+        config: TcpConfigExt,
+        congestion_control: CongestionControlConstructor<RT>,
+    ) -> Self {
         let result = ConnectResult {
             waker: None,
             result: None,
+            soft_error: None,
+            syn_sent: None,
         };
         let result = Rc::new(RefCell::new(result));
+        let started: Instant = rt.now();
 
-        let future = Self::background(local_isn, local, remote, rt.clone(), arp.clone(), result.clone());
+        let future = Self::background(
+            local_isn,
+            local,
+            remote,
+            rt.clone(),
+            arp.clone(),
+            loopback.clone(),
+            config.clone(),
+            result.clone(),
+            started,
+        );
         let handle: SchedulerHandle = rt.spawn(FutureOperation::Background::<RT>(future.boxed_local()));
 
         // TODO: Add fast path here when remote is already in the ARP cache (and subtract one retry).
@@ -94,6 +133,10 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<R
             remote,
             rt,
             arp,
+            loopback,
+            config,
+            congestion_control,
+            started,
 
             handle,
             result,
@@ -119,6 +162,16 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<R
         r.result.replace(result);
     }
 
+    /// Handles an ICMP error message about our SYN. As per RFC 1122, section 4.2.3.9, an unreachable port or protocol
+    /// aborts the connection request, while any other error is only reported if it eventually times out.
+    pub fn receive_icmp_error(&mut self, cause: Fail) {
+        if cause.errno == ECONNREFUSED || cause.errno == ENOPROTOOPT {
+            self.set_result(Err(cause));
+        } else {
+            self.result.borrow_mut().soft_error.replace(cause);
+        }
+    }
+
     pub fn receive(&mut self, header: &TcpHeader) {
         let expected_seq = self.local_isn + SeqNumber::from(1);
 
@@ -141,65 +194,85 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<R
         debug!("Received SYN+ACK: {:?}", header);
 
         // Acknowledge the SYN+ACK segment.
+        // The link address was resolved before sending our SYN, but it may have since been evicted. Drop the segment
+        // and let the remote retransmit it once the link address is known again.
         let remote_link_addr = match self.arp.try_query(self.remote.ip().clone()) {
             Some(r) => r,
-            None => panic!("TODO: Clean up ARP query control flow"),
+            None => {
+                warn!("Dropping SYN+ACK: link address of {} is not cached", self.remote.ip());
+                return;
+            },
         };
         let remote_seq_num = header.seq_num + SeqNumber::from(1);
 
         let tcp_options = self.rt.tcp_options();
 
+        // RFC 7323: timestamps are agreed if both our SYN and the SYN+ACK carry them.
+        let ts_recent: Option<u32> = match self.config.get_timestamps() {
+            true => header.timestamp_option().map(|(timestamp, _)| timestamp),
+            false => None,
+        };
+
+        let ipv4_hdr: Ipv4Header = Ipv4Header::new(self.local.ip().clone(), self.remote.ip().clone(), IpProtocol::TCP);
         let mut tcp_hdr = TcpHeader::new(self.local.port(), self.remote.port());
         tcp_hdr.ack = true;
         tcp_hdr.ack_num = remote_seq_num;
-        tcp_hdr.window_size = tcp_options.get_receive_window_size();
+        tcp_hdr.window_size = self.config.get_handshake_window_size(&tcp_options);
         tcp_hdr.seq_num = self.local_isn + SeqNumber::from(1);
+        if let Some(ts_recent) = ts_recent {
+            tcp_hdr.push_option(TcpOptions2::timestamp(self.started, self.rt.now(), ts_recent));
+        }
+        if let Some(key) = self.config.get_md5_key(self.remote.ip()) {
+            tcp_hdr.sign_md5(&ipv4_hdr, &[], key);
+        }
         debug!("Sending ACK: {:?}", tcp_hdr);
 
         let segment = TcpSegment {
-            ethernet2_hdr: Ethernet2Header::new(remote_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4),
-            ipv4_hdr: Ipv4Header::new(self.local.ip().clone(), self.remote.ip().clone(), IpProtocol::TCP),
+            ethernet2_hdr: Ethernet2Header::new(remote_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4)
+                .with_vlan_tag(self.arp.vlan_tag()),
+            ipv4_hdr,
             tcp_hdr,
             data: Buffer::Heap(DataBuffer::empty()),
             tx_checksum_offload: tcp_options.get_rx_checksum_offload(),
         };
-        self.rt.transmit(segment);
+        loopback::transmit(&self.rt, &self.loopback, self.config.get_transmit_hook(), segment);
 
         let mut remote_window_scale = None;
-        let mut mss = FALLBACK_MSS;
+        let mut mss: usize = self.config.get_fallback_mss();
         for option in header.iter_options() {
             match option {
                 TcpOptions2::WindowScale(w) => {
                     info!("Received window scale: {}", w);
-                    remote_window_scale = Some(*w);
+                    // RFC 7323: a shift count larger than the maximum is treated as the maximum.
+                    if *w > MAX_WINDOW_SCALE {
+                        warn!("Clamping window scale {} to {}", w, MAX_WINDOW_SCALE);
+                    }
+                    remote_window_scale = Some(cmp::min(*w, MAX_WINDOW_SCALE));
                 },
                 TcpOptions2::MaximumSegmentSize(m) => {
                     info!("Received advertised MSS: {}", m);
+                    if (*m as usize) < self.config.get_min_mss() {
+                        warn!("Raising advertised MSS {} to {}", m, self.config.get_min_mss());
+                    }
                     mss = *m as usize;
                 },
                 _ => continue,
             }
         }
+        let remote_mss: usize = mss;
+        let mss: usize = self.config.get_send_mss(remote_mss, ts_recent.is_some());
+        // RFC 3168: an ECN-setup SYN+ACK has ECE set and CWR clear, and may only be sent in reply to an ECN-setup SYN.
+        let ecn: bool = self.config.get_ecn() && header.ece && !header.cwr;
 
+        let window_scaling: bool = remote_window_scale.is_some();
         let (local_window_scale, remote_window_scale) = match remote_window_scale {
             Some(w) => (tcp_options.get_window_scale() as u32, w),
             None => (0, 0),
         };
 
-        // TODO(RFC1323): Clamp the scale to 14 instead of panicking.
-        assert!(local_window_scale <= 14 && remote_window_scale <= 14);
-
-        let rx_window_size: u32 = (tcp_options.get_receive_window_size())
-            .checked_shl(local_window_scale as u32)
-            .expect("TODO: Window size overflow")
-            .try_into()
-            .expect("TODO: Window size overflow");
+        let rx_window_size: u32 = self.config.get_receive_buffer_size(&tcp_options, local_window_scale);
 
-        let tx_window_size: u32 = (header.window_size)
-            .checked_shl(remote_window_scale as u32)
-            .expect("TODO: Window size overflow")
-            .try_into()
-            .expect("TODO: Window size overflow");
+        let tx_window_size: u32 = (header.window_size as u32) << remote_window_scale;
 
         info!("Window sizes: local {}, remote {}", rx_window_size, tx_window_size);
         info!(
@@ -212,17 +285,37 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<R
             self.remote,
             self.rt.clone(),
             self.arp.clone(),
+            self.loopback.clone(),
             remote_seq_num,
-            self.rt.tcp_options().get_ack_delay_timeout(),
+            self.config.get_ack_delay_timeout(&self.rt.tcp_options()),
             rx_window_size,
             local_window_scale,
             expected_seq,
             tx_window_size,
             remote_window_scale,
             mss,
-            congestion_control::None::new,
+            self.congestion_control,
             None,
+            self.config.clone(),
+            ConnectionParams {
+                mss,
+                remote_mss,
+                window_scaling,
+                local_window_scale: local_window_scale as u8,
+                remote_window_scale,
+                sack_permitted: false,
+                timestamps: ts_recent.is_some(),
+                ecn,
+            },
+            self.started,
         );
+        if let Some(ts_recent) = ts_recent {
+            cb.set_ts_recent(ts_recent);
+        }
+        // The handshake round-trip time spans from our most recent SYN to its SYN+ACK, which we just acknowledged.
+        if let Some(syn_sent) = self.result.borrow().syn_sent {
+            cb.set_handshake_rtt(self.rt.now().saturating_duration_since(syn_sent));
+        }
         self.set_result(Ok(cb));
     }
 
@@ -232,16 +325,25 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<R
         remote: SocketAddrV4,
         rt: RT,
         arp: ArpPeer<RT>,
+        loopback: LoopbackSender,
+        config: TcpConfigExt,
         result: Rc<RefCell<ConnectResult<RT>>>,
+        started: Instant,
     ) -> impl Future<Output = ()> {
         let tcp_options = rt.tcp_options();
         let handshake_retries: usize = tcp_options.get_handshake_retries();
         let handshake_timeout = tcp_options.get_handshake_timeout();
 
         async move {
+            // Whether or not we have been able to resolve the remote link address at least once. This is used to tell
+            // apart failures in which the remote host is unreachable from those in which it simply did not respond.
+            let mut resolved_link_addr: bool = false;
             for _ in 0..handshake_retries {
                 let remote_link_addr = match arp.query(remote.ip().clone()).await {
-                    Ok(r) => r,
+                    Ok(r) => {
+                        resolved_link_addr = true;
+                        r
+                    },
                     Err(e) => {
                         warn!("ARP query failed: {:?}", e);
                         continue;
@@ -251,31 +353,52 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ActiveOpenSocket<R
                 let mut tcp_hdr = TcpHeader::new(local.port(), remote.port());
                 tcp_hdr.syn = true;
                 tcp_hdr.seq_num = local_isn;
-                tcp_hdr.window_size = tcp_options.get_receive_window_size();
-
-                let mss = tcp_options.get_advertised_mss() as u16;
+                tcp_hdr.window_size = config.get_handshake_window_size(&tcp_options);
+                // RFC 3168: an ECN-setup SYN has both ECE and CWR set.
+                if config.get_ecn() {
+                    tcp_hdr.ece = true;
+                    tcp_hdr.cwr = true;
+                }
+
+                let mss = config.get_advertised_mss(&tcp_options) as u16;
                 tcp_hdr.push_option(TcpOptions2::MaximumSegmentSize(mss));
                 info!("Advertising MSS: {}", mss);
 
                 tcp_hdr.push_option(TcpOptions2::WindowScale(tcp_options.get_window_scale()));
                 info!("Advertising window scale: {}", tcp_options.get_window_scale());
 
+                if config.get_timestamps() {
+                    tcp_hdr.push_option(TcpOptions2::timestamp(started, rt.now(), 0));
+                }
+
+                let ipv4_hdr: Ipv4Header = Ipv4Header::new(local.ip().clone(), remote.ip().clone(), IpProtocol::TCP);
+                if let Some(key) = config.get_md5_key(remote.ip()) {
+                    tcp_hdr.sign_md5(&ipv4_hdr, &[], key);
+                }
+
                 debug!("Sending SYN {:?}", tcp_hdr);
+                result.borrow_mut().syn_sent = Some(rt.now());
                 let segment = TcpSegment {
-                    ethernet2_hdr: Ethernet2Header::new(remote_link_addr, rt.local_link_addr(), EtherType2::Ipv4),
-                    ipv4_hdr: Ipv4Header::new(local.ip().clone(), remote.ip().clone(), IpProtocol::TCP),
+                    ethernet2_hdr: Ethernet2Header::new(remote_link_addr, rt.local_link_addr(), EtherType2::Ipv4)
+                        .with_vlan_tag(arp.vlan_tag()),
+                    ipv4_hdr,
                     tcp_hdr,
                     data: Buffer::Heap(DataBuffer::empty()),
                     tx_checksum_offload: tcp_options.get_rx_checksum_offload(),
                 };
-                rt.transmit(segment);
+                loopback::transmit(&rt, &loopback, config.get_transmit_hook(), segment);
                 rt.wait(handshake_timeout).await;
             }
             let mut r = result.borrow_mut();
             if let Some(w) = r.waker.take() {
                 w.wake()
             }
-            r.result.replace(Err(Fail::new(ETIMEDOUT, "handshake timeout")));
+            let error: Fail = match r.soft_error.take() {
+                Some(e) => e,
+                None if resolved_link_addr => Fail::new(ETIMEDOUT, "handshake timeout"),
+                None => Fail::new(EHOSTUNREACH, "ARP resolution failed"),
+            };
+            r.result.replace(Err(error));
         }
     }
 }
@@ -0,0 +1,528 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use crate::protocols::{
+    ipv4::IPV4_HEADER_DEFAULT_SIZE,
+    tcp::{
+        constants::{
+            FALLBACK_MSS,
+            MAX_MSS,
+            MIN_MSS,
+            TIMESTAMPS_OPTION_OVERHEAD,
+        },
+        segment::TcpSegment,
+        MIN_TCP_HEADER_SIZE,
+    },
+};
+use ::libc::EINVAL;
+use ::runtime::{
+    fail::Fail,
+    network::config::TcpConfig,
+};
+use ::std::{
+    cmp,
+    collections::HashMap,
+    fmt,
+    net::Ipv4Addr,
+    rc::Rc,
+    time::Duration,
+};
+
+//==============================================================================
+// Constants
+//==============================================================================
+
+/// Default maximum number of consecutive retransmission timeouts before a connection is aborted.
+pub const DEFAULT_MAX_RETRANSMITS: usize = 15;
+
+/// Default number of duplicate ACKs that trigger a fast retransmit.
+pub const DEFAULT_DUPACK_THRESHOLD: u32 = 3;
+
+/// Default lower bound of the retransmission timeout.
+pub const DEFAULT_MIN_RTO: Duration = Duration::from_millis(100);
+
+/// Lower bound of the delayed ACK timeout when it is derived from the round-trip time.
+pub const MIN_ACK_DELAY: Duration = Duration::from_millis(1);
+
+/// Upper bound of the delayed ACK timeout (see RFC 1122, Section 4.2.3.2).
+pub const MAX_ACK_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest key of the MD5 signature option (in bytes), as suggested by RFC 2385.
+pub const MAX_MD5_KEY_SIZE: usize = 80;
+
+/// Lower bound of the retransmission timeout in the [SocketProfile::LowLatency] profile.
+pub const LOW_LATENCY_MIN_RTO: Duration = Duration::from_millis(10);
+
+//==============================================================================
+// Types
+//==============================================================================
+
+/// Transform that is applied to each TCP segment right before it is sent, which may rewrite its headers (e.g. to
+/// translate addresses and ports) or return `false` to drop it. Checksums are computed afterwards, but MD5 signatures
+/// are not. Only the segments of the connections that are configured with it pass through it: UDP datagrams, ICMP
+/// messages and ARP packets are sent as they are.
+pub type TcpTransmitHook = Rc<dyn Fn(&mut TcpSegment) -> bool>;
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+/// Presets of TCP Configuration Extensions for common workloads.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocketProfile {
+    /// Settings of the TCP peer as they are.
+    Default,
+    /// Trades bandwidth for latency: ACKs are sent right away instead of being delayed and lost segments are
+    /// retransmitted sooner, with [LOW_LATENCY_MIN_RTO] as the lower bound of the retransmission timeout. There is no
+    /// setting for small segments, as Nagle's algorithm is not implemented and they are always sent right away.
+    LowLatency,
+}
+
+/// TCP Configuration Extensions
+///
+/// Settings of the TCP peer that are not covered by [TcpConfig]. These apply to sockets that are created after they
+/// are installed in the TCP peer.
+#[derive(Clone, Debug, Default)]
+pub struct TcpConfigExt {
+    /// Maximum transmission unit of the underlying interface (in bytes). If `None`, the advertised MSS in [TcpConfig]
+    /// is used instead.
+    mtu: Option<usize>,
+    /// Maximum segment size that we advertise to remote peers (in bytes), which takes precedence over the one derived
+    /// from the maximum transmission unit. If `None`, it is derived as described for [Self::mtu].
+    advertised_mss: Option<usize>,
+    /// Maximum number of consecutive retransmission timeouts before a connection is aborted. If `None`, the
+    /// [DEFAULT_MAX_RETRANSMITS] is used.
+    max_retransmits: Option<usize>,
+    /// Spread segments over the round-trip time instead of sending a whole window at once?
+    pacing: bool,
+    /// Number of duplicate ACKs that trigger a fast retransmit. If `None`, the [DEFAULT_DUPACK_THRESHOLD] is used.
+    dupack_threshold: Option<u32>,
+    /// Maximum rate at which a connection sends data (in bytes per second), along with how many bytes it may send
+    /// at once after being idle. If `None`, sending is only limited by flow and congestion control.
+    rate_limit: Option<(usize, usize)>,
+    /// Size of the buffer that holds received data until the application reads it (in bytes), which bounds the
+    /// window that a connection advertises. If `None`, it is the receive window size in [TcpConfig].
+    recv_buffer_size: Option<u32>,
+    /// Maximum segment size assumed for remote peers that do not advertise one (in bytes). If `None`, the
+    /// [FALLBACK_MSS] is used.
+    fallback_mss: Option<usize>,
+    /// Smallest maximum segment size that we honor (in bytes), which guards against peers that advertise tiny ones to
+    /// make us send a flood of small segments. If `None`, the [MIN_MSS] is used.
+    min_mss: Option<usize>,
+    /// Non-validated period of congestion window validation (RFC 7661), after which the congestion window of an
+    /// application-limited connection is reduced. If `None`, the congestion window is not validated.
+    cwnd_validation: Option<Duration>,
+    /// Split received data at the segments that have the PSH flag set, so that each receive returns at most one pushed
+    /// message? This is a best-effort hint, as boundaries of segments that arrive out of order are not recorded.
+    push_boundaries: bool,
+    /// Time without data being sent or received after which a connection is aborted. If `None`, idle connections are
+    /// kept open.
+    idle_timeout: Option<Duration>,
+    /// Longest time that sent data may remain unacknowledged before a connection is aborted, regardless of how many
+    /// retransmissions are left (RFC 5482). If `None`, only the retransmission limit applies.
+    user_timeout: Option<Duration>,
+    /// Keys that sign and authenticate segments exchanged with each remote host with the MD5 signature option
+    /// (RFC 2385). Segments from hosts without a key must not be signed.
+    md5_keys: HashMap<Ipv4Addr, Vec<u8>>,
+    /// Time that ACKs are held back in the hope of piggybacking them on outgoing data. If `None`, the ACK delay timeout
+    /// in [TcpConfig] is used.
+    ack_delay: Option<Duration>,
+    /// Lower bound of the retransmission timeout. If `None`, the [DEFAULT_MIN_RTO] is used.
+    min_rto: Option<Duration>,
+    /// Negotiate Explicit Congestion Notification (RFC 3168) in the handshake?
+    ecn: bool,
+    /// Negotiate the timestamps option (RFC 7323) in the handshake?
+    timestamps: bool,
+    /// Least amount of received data (in bytes) that completes a receive, like `SO_RCVLOWAT`. If `None`, any amount
+    /// does.
+    recv_lowat: Option<usize>,
+    /// Transform that is applied to each segment before it is sent. If `None`, segments are sent as they are.
+    transmit_hook: Option<DebugHook>,
+}
+
+/// Wrapper that lets a [TcpTransmitHook] be part of a structure that derives [fmt::Debug].
+#[derive(Clone)]
+struct DebugHook(TcpTransmitHook);
+
+//==============================================================================
+// Associated Functions
+//==============================================================================
+
+impl TcpConfigExt {
+    /// Sets the maximum transmission unit of the underlying interface, which fails if it cannot fit a TCP segment or
+    /// does not fit in the length field of an IPv4 header. Only the maximum segment size of TCP connections is derived
+    /// from it: UDP datagrams and ICMP messages are not checked against it.
+    pub fn mtu(mut self, value: Option<usize>) -> Result<Self, Fail> {
+        if let Some(mtu) = value {
+            if mtu <= IPV4_HEADER_DEFAULT_SIZE + MIN_TCP_HEADER_SIZE || mtu > u16::MAX as usize {
+                return Err(Fail::new(EINVAL, "MTU out of range"));
+            }
+        }
+        self.mtu = value;
+        Ok(self)
+    }
+
+    /// Gets the maximum transmission unit of the underlying interface.
+    pub fn get_mtu(&self) -> Option<usize> {
+        self.mtu
+    }
+
+    /// Sets the maximum segment size that we advertise to remote peers, which fails if it is out of the range of
+    /// segment sizes that we support or below the smallest one that we honor.
+    pub fn advertised_mss(mut self, value: Option<usize>) -> Result<Self, Fail> {
+        if let Some(mss) = value {
+            if !(MIN_MSS..=MAX_MSS).contains(&mss) {
+                return Err(Fail::new(EINVAL, "advertised MSS out of range"));
+            }
+            if mss < self.get_min_mss() {
+                return Err(Fail::new(EINVAL, "advertised MSS below the minimum MSS"));
+            }
+        }
+        self.advertised_mss = value;
+        Ok(self)
+    }
+
+    /// Sets the maximum number of consecutive retransmission timeouts before a connection is aborted.
+    pub fn max_retransmits(mut self, value: Option<usize>) -> Self {
+        self.max_retransmits = value;
+        self
+    }
+
+    /// Gets the maximum number of consecutive retransmission timeouts before a connection is aborted.
+    pub fn get_max_retransmits(&self) -> usize {
+        self.max_retransmits.unwrap_or(DEFAULT_MAX_RETRANSMITS)
+    }
+
+    /// Sets whether segments are paced over the round-trip time.
+    pub fn pacing(mut self, value: bool) -> Self {
+        self.pacing = value;
+        self
+    }
+
+    /// Gets whether segments are paced over the round-trip time.
+    pub fn get_pacing(&self) -> bool {
+        self.pacing
+    }
+
+    /// Sets the number of duplicate ACKs that trigger a fast retransmit, which fails if it is zero.
+    pub fn dupack_threshold(mut self, value: Option<u32>) -> Result<Self, Fail> {
+        if value == Some(0) {
+            return Err(Fail::new(EINVAL, "duplicate ACK threshold must be positive"));
+        }
+        self.dupack_threshold = value;
+        Ok(self)
+    }
+
+    /// Gets the number of duplicate ACKs that trigger a fast retransmit.
+    pub fn get_dupack_threshold(&self) -> u32 {
+        self.dupack_threshold.unwrap_or(DEFAULT_DUPACK_THRESHOLD)
+    }
+
+    /// Sets the maximum rate at which a connection sends data (in bytes per second) and its burst size (in bytes), which
+    /// fails if either of them is zero.
+    pub fn rate_limit(mut self, value: Option<(usize, usize)>) -> Result<Self, Fail> {
+        if let Some((rate, burst)) = value {
+            if rate == 0 || burst == 0 {
+                return Err(Fail::new(EINVAL, "rate and burst size must be positive"));
+            }
+        }
+        self.rate_limit = value;
+        Ok(self)
+    }
+
+    /// Gets the maximum rate at which a connection sends data (in bytes per second) and its burst size (in bytes).
+    pub fn get_rate_limit(&self) -> Option<(usize, usize)> {
+        self.rate_limit
+    }
+
+    /// Sets the size of the buffer that holds received data until the application reads it, which fails if it cannot
+    /// hold a segment of the smallest size that we support.
+    pub fn recv_buffer_size(mut self, value: Option<u32>) -> Result<Self, Fail> {
+        if let Some(size) = value {
+            if (size as usize) < MIN_MSS {
+                return Err(Fail::new(EINVAL, "receive buffer smaller than the minimum MSS"));
+            }
+        }
+        self.recv_buffer_size = value;
+        Ok(self)
+    }
+
+    /// Gets the size of the buffer that holds received data until the application reads it, if one is set.
+    pub fn get_recv_buffer_size(&self) -> Option<u32> {
+        self.recv_buffer_size
+    }
+
+    /// Sets the maximum segment size assumed for remote peers that do not advertise one, which fails if it is out of
+    /// the range of segment sizes that we support or below the smallest one that we honor.
+    pub fn fallback_mss(mut self, value: Option<usize>) -> Result<Self, Fail> {
+        if let Some(mss) = value {
+            if !(MIN_MSS..=MAX_MSS).contains(&mss) {
+                return Err(Fail::new(EINVAL, "fallback MSS out of range"));
+            }
+            if mss < self.get_min_mss() {
+                return Err(Fail::new(EINVAL, "fallback MSS below the minimum MSS"));
+            }
+        }
+        self.fallback_mss = value;
+        Ok(self)
+    }
+
+    /// Gets the maximum segment size assumed for remote peers that do not advertise one.
+    pub fn get_fallback_mss(&self) -> usize {
+        self.fallback_mss.unwrap_or(FALLBACK_MSS)
+    }
+
+    /// Sets the smallest maximum segment size that we honor, which fails if it is zero or exceeds the segment size
+    /// that we fall back to or advertise, as those are what the link is known to carry. Hence, these are to be set
+    /// first.
+    pub fn min_mss(mut self, value: Option<usize>) -> Result<Self, Fail> {
+        if let Some(mss) = value {
+            if mss == 0 || mss > MAX_MSS {
+                return Err(Fail::new(EINVAL, "minimum MSS out of range"));
+            }
+            if mss > self.get_fallback_mss() || self.advertised_mss.map_or(false, |advertised| mss > advertised) {
+                return Err(Fail::new(EINVAL, "minimum MSS exceeds the fallback or advertised MSS"));
+            }
+        }
+        self.min_mss = value;
+        Ok(self)
+    }
+
+    /// Gets the smallest maximum segment size that we honor.
+    pub fn get_min_mss(&self) -> usize {
+        self.min_mss.unwrap_or(MIN_MSS)
+    }
+
+    /// Sets the non-validated period of congestion window validation.
+    pub fn cwnd_validation(mut self, value: Option<Duration>) -> Self {
+        self.cwnd_validation = value;
+        self
+    }
+
+    /// Gets the non-validated period of congestion window validation, if it is enabled.
+    pub fn get_cwnd_validation(&self) -> Option<Duration> {
+        self.cwnd_validation
+    }
+
+    /// Sets whether received data is split at push boundaries.
+    pub fn push_boundaries(mut self, value: bool) -> Self {
+        self.push_boundaries = value;
+        self
+    }
+
+    /// Gets whether received data is split at push boundaries.
+    pub fn get_push_boundaries(&self) -> bool {
+        self.push_boundaries
+    }
+
+    /// Sets the time without data being sent or received after which a connection is aborted, which fails if it is
+    /// zero.
+    pub fn idle_timeout(mut self, value: Option<Duration>) -> Result<Self, Fail> {
+        if value == Some(Duration::ZERO) {
+            return Err(Fail::new(EINVAL, "idle timeout must be positive"));
+        }
+        self.idle_timeout = value;
+        Ok(self)
+    }
+
+    /// Gets the time without data being sent or received after which a connection is aborted, if any.
+    pub fn get_idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Sets the longest time that sent data may remain unacknowledged before a connection is aborted, which fails if
+    /// it is zero.
+    pub fn user_timeout(mut self, value: Option<Duration>) -> Result<Self, Fail> {
+        if value == Some(Duration::ZERO) {
+            return Err(Fail::new(EINVAL, "user timeout must be positive"));
+        }
+        self.user_timeout = value;
+        Ok(self)
+    }
+
+    /// Gets the longest time that sent data may remain unacknowledged before a connection is aborted, if any.
+    pub fn get_user_timeout(&self) -> Option<Duration> {
+        self.user_timeout
+    }
+
+    /// Sets the key of the MD5 signature option for segments exchanged with a remote host, or removes it if `None`.
+    /// This fails if the key is empty or longer than [MAX_MD5_KEY_SIZE].
+    pub fn md5_key(mut self, remote: Ipv4Addr, key: Option<Vec<u8>>) -> Result<Self, Fail> {
+        match key {
+            Some(key) => {
+                if key.is_empty() || key.len() > MAX_MD5_KEY_SIZE {
+                    return Err(Fail::new(EINVAL, "MD5 key size out of range"));
+                }
+                self.md5_keys.insert(remote, key);
+            },
+            None => {
+                self.md5_keys.remove(&remote);
+            },
+        }
+        Ok(self)
+    }
+
+    /// Gets the key of the MD5 signature option for segments exchanged with a remote host, if any.
+    pub fn get_md5_key(&self, remote: &Ipv4Addr) -> Option<&[u8]> {
+        self.md5_keys.get(remote).map(|key| key.as_slice())
+    }
+
+    /// Sets the longest time that ACKs are held back. Once the round-trip time has been measured, ACKs are held back
+    /// for a fraction of it instead, if that is shorter. A zero delay sends them right away.
+    pub fn ack_delay(mut self, value: Option<Duration>) -> Self {
+        self.ack_delay = value;
+        self
+    }
+
+    /// Gets the longest time that ACKs are held back, which is at most [MAX_ACK_DELAY].
+    pub fn get_ack_delay_timeout(&self, tcp_options: &TcpConfig) -> Duration {
+        cmp::min(
+            self.ack_delay.unwrap_or(tcp_options.get_ack_delay_timeout()),
+            MAX_ACK_DELAY,
+        )
+    }
+
+    /// Sets the lower bound of the retransmission timeout, which fails if it is zero.
+    pub fn min_rto(mut self, value: Option<Duration>) -> Result<Self, Fail> {
+        if value == Some(Duration::ZERO) {
+            return Err(Fail::new(EINVAL, "minimum RTO must be positive"));
+        }
+        self.min_rto = value;
+        Ok(self)
+    }
+
+    /// Gets the lower bound of the retransmission timeout.
+    pub fn get_min_rto(&self) -> Duration {
+        self.min_rto.unwrap_or(DEFAULT_MIN_RTO)
+    }
+
+    /// Sets whether Explicit Congestion Notification is negotiated in the handshake.
+    pub fn ecn(mut self, value: bool) -> Self {
+        self.ecn = value;
+        self
+    }
+
+    /// Gets whether Explicit Congestion Notification is negotiated in the handshake.
+    pub fn get_ecn(&self) -> bool {
+        self.ecn
+    }
+
+    /// Sets whether the timestamps option is negotiated in the handshake. Once agreed, every segment carries it.
+    pub fn timestamps(mut self, value: bool) -> Self {
+        self.timestamps = value;
+        self
+    }
+
+    /// Gets whether the timestamps option is negotiated in the handshake.
+    pub fn get_timestamps(&self) -> bool {
+        self.timestamps
+    }
+
+    /// Sets the least amount of received data that completes a receive, which fails if it is zero.
+    pub fn recv_lowat(mut self, value: Option<usize>) -> Result<Self, Fail> {
+        if value == Some(0) {
+            return Err(Fail::new(EINVAL, "receive low-water mark must be positive"));
+        }
+        self.recv_lowat = value;
+        Ok(self)
+    }
+
+    /// Gets the least amount of received data that completes a receive. The end of the stream completes a receive
+    /// regardless.
+    pub fn get_recv_lowat(&self) -> usize {
+        self.recv_lowat.unwrap_or(1)
+    }
+
+    /// Sets the transform that is applied to each TCP segment before it is sent.
+    pub fn transmit_hook(mut self, value: Option<TcpTransmitHook>) -> Self {
+        self.transmit_hook = value.map(DebugHook);
+        self
+    }
+
+    /// Gets the transform that is applied to each segment before it is sent, if any.
+    pub fn get_transmit_hook(&self) -> Option<&TcpTransmitHook> {
+        self.transmit_hook.as_ref().map(|hook| &hook.0)
+    }
+
+    /// Applies the settings of a profile, overriding those that it covers.
+    pub fn profile(mut self, profile: SocketProfile) -> Self {
+        match profile {
+            SocketProfile::Default => {
+                self.ack_delay = None;
+                self.min_rto = None;
+            },
+            SocketProfile::LowLatency => {
+                self.ack_delay = Some(Duration::ZERO);
+                self.min_rto = Some(LOW_LATENCY_MIN_RTO);
+            },
+        }
+        self
+    }
+
+    /// Gets the size of the receive buffer of a connection that uses the given window scale. The buffer is clamped to
+    /// the largest window that can be advertised with that scale.
+    pub fn get_receive_buffer_size(&self, tcp_options: &TcpConfig, window_scale: u32) -> u32 {
+        match self.recv_buffer_size {
+            Some(size) => size.min((u16::MAX as u32) << window_scale),
+            None => (tcp_options.get_receive_window_size() as u32) << window_scale,
+        }
+    }
+
+    /// Gets the window that we advertise in the handshake, which our peer scales by our window scale.
+    pub fn get_handshake_window_size(&self, tcp_options: &TcpConfig) -> u16 {
+        match self.recv_buffer_size {
+            Some(size) => {
+                let window_size: u32 = size >> tcp_options.get_window_scale();
+                window_size.min(tcp_options.get_receive_window_size() as u32) as u16
+            },
+            None => tcp_options.get_receive_window_size(),
+        }
+    }
+
+    /// Gets the largest segment size that fits in the maximum transmission unit, if one is set.
+    pub fn get_mtu_mss(&self) -> Option<usize> {
+        self.mtu.map(|mtu| mtu - IPV4_HEADER_DEFAULT_SIZE - MIN_TCP_HEADER_SIZE)
+    }
+
+    /// Gets the maximum segment size that we advertise to remote peers.
+    pub fn get_advertised_mss(&self, tcp_options: &TcpConfig) -> usize {
+        self.advertised_mss
+            .or_else(|| self.get_mtu_mss())
+            .unwrap_or(tcp_options.get_advertised_mss())
+    }
+
+    /// Clamps the maximum segment size advertised by a remote peer to what fits in our maximum transmission unit.
+    pub fn clamp_remote_mss(&self, mss: usize) -> usize {
+        match self.get_mtu_mss() {
+            Some(max_mss) => mss.min(max_mss),
+            None => mss,
+        }
+    }
+
+    /// Gets the largest amount of data that we send in a segment, given the maximum segment size advertised by a remote
+    /// peer and whether timestamps were agreed. Advertised sizes below our minimum are raised to it. As per RFC 6691,
+    /// the advertised size does not account for TCP options, so the timestamps option that goes along with every
+    /// segment shrinks the payload.
+    pub fn get_send_mss(&self, remote_mss: usize, timestamps: bool) -> usize {
+        let mss: usize = self.clamp_remote_mss(remote_mss.max(self.get_min_mss()));
+        if timestamps {
+            mss.saturating_sub(TIMESTAMPS_OPTION_OVERHEAD)
+        } else {
+            mss
+        }
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+impl fmt::Debug for DebugHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TcpTransmitHook")
+    }
+}
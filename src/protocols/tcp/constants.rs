@@ -7,3 +7,14 @@ pub use ::runtime::network::consts::{
     MAX_MSS,
     MIN_MSS,
 };
+use ::std::time::Duration;
+
+/// Maximum Segment Lifetime (MSL), as suggested by RFC 793. Connections linger in TIME-WAIT for twice this long.
+pub const MSL: Duration = Duration::from_secs(120);
+
+/// Largest window scale shift count that may be used, as set by RFC 7323.
+pub const MAX_WINDOW_SCALE: u8 = 14;
+
+/// Space that the timestamps option takes up in every segment once it is agreed, including the padding that aligns it
+/// (in bytes).
+pub const TIMESTAMPS_OPTION_OVERHEAD: usize = 12;
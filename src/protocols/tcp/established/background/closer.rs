@@ -0,0 +1,40 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::ControlBlock;
+use ::futures::{
+    future::{
+        self,
+        Either,
+    },
+    FutureExt,
+};
+use ::runtime::{
+    fail::Fail,
+    network::NetworkRuntime,
+    task::SchedulerRuntime,
+};
+use std::rc::Rc;
+
+pub async fn closer<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
+    cb: Rc<ControlBlock<RT>>,
+) -> Result<!, Fail> {
+    loop {
+        // Wait for the time-wait timer (if any) to expire.
+        let (time_wait_deadline, time_wait_deadline_changed) = cb.watch_time_wait_deadline();
+        futures::pin_mut!(time_wait_deadline_changed);
+
+        let time_wait_future = match time_wait_deadline {
+            Some(t) => Either::Left(cb.rt().wait_until(t).fuse()),
+            None => Either::Right(future::pending()),
+        };
+        futures::pin_mut!(time_wait_future);
+
+        futures::select_biased! {
+            _ = time_wait_deadline_changed => continue,
+            _ = time_wait_future => {
+                cb.on_time_wait_timeout();
+            },
+        }
+    }
+}
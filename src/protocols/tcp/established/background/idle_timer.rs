@@ -0,0 +1,38 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::ControlBlock;
+use crate::protocols::tcp::established::CloseReason;
+use ::futures::future;
+use ::libc::ETIMEDOUT;
+use ::runtime::{
+    fail::Fail,
+    network::NetworkRuntime,
+    task::SchedulerRuntime,
+};
+use ::std::{
+    rc::Rc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+pub async fn idle_timer<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
+    cb: Rc<ControlBlock<RT>>,
+) -> Result<!, Fail> {
+    let idle_timeout: Duration = match cb.get_config().get_idle_timeout() {
+        Some(t) => t,
+        None => return future::pending().await,
+    };
+
+    loop {
+        // Wait until the connection would have been idle for too long, unless data is sent or received meanwhile.
+        let deadline: Instant = cb.get_last_activity() + idle_timeout;
+        cb.rt().wait_until(deadline).await;
+        if cb.rt().now() >= cb.get_last_activity() + idle_timeout {
+            cb.abort(CloseReason::Timeout);
+            return Err(Fail::new(ETIMEDOUT, "idle timeout exceeded"));
+        }
+    }
+}
@@ -2,11 +2,15 @@
 // Licensed under the MIT license.
 
 mod acknowledger;
+mod closer;
+mod idle_timer;
 mod retransmitter;
 mod sender;
 
 use self::{
     acknowledger::acknowledger,
+    closer::closer,
+    idle_timer::idle_timer,
     retransmitter::retransmitter,
     sender::sender,
 };
@@ -42,10 +46,18 @@ pub fn background<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
         let sender = sender(cb.clone()).fuse();
         futures::pin_mut!(sender);
 
+        let closer = closer(cb.clone()).fuse();
+        futures::pin_mut!(closer);
+
+        let idle_timer = idle_timer(cb.clone()).fuse();
+        futures::pin_mut!(idle_timer);
+
         let r = futures::select_biased! {
             r = acknowledger => r,
             r = retransmitter => r,
             r = sender => r,
+            r = closer => r,
+            r = idle_timer => r,
         };
         error!("Connection (fd {:?}) terminated: {:?}", fd, r);
 
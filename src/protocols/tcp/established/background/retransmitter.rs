@@ -2,7 +2,11 @@
 // Licensed under the MIT license.
 
 use super::ControlBlock;
-use crate::protocols::tcp::segment::TcpHeader;
+use crate::protocols::tcp::{
+    established::CloseReason,
+    segment::TcpHeader,
+    SeqNumber,
+};
 use ::futures::{
     future::{
         self,
@@ -10,8 +14,10 @@ use ::futures::{
     },
     FutureExt,
 };
+use ::libc::ETIMEDOUT;
 use ::runtime::{
     fail::Fail,
+    memory::Buffer,
     network::{
         types::MacAddress,
         NetworkRuntime,
@@ -36,15 +42,10 @@ async fn retransmit<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
     cause: RetransmitCause,
     cb: &Rc<ControlBlock<RT>>,
 ) -> Result<(), Fail> {
-    // ToDo: Handle retransmission of FIN.
-
-    // ToDo: Fix this routine.  It is currently trashing our unacknowledged queue state.  It shouldn't remove any
-    // unacknowledged data from the unacknowledged queue, as retransmiting data doesn't magically make it acknowledged.
-    // Any sent data, whether sent once or multiple times, must remain on the unacknowledged queue until it is ACKed.
-
-    // Pop unack'ed segment.
-    let mut segment = match cb.pop_unacked_segment() {
-        Some(s) => s,
+    // Get the oldest unack'ed segment.  Note that it stays on the unacknowledged queue until it is ACKed.
+    let (send_unacked, _) = cb.get_send_unacked();
+    let (seq_no, bytes): (SeqNumber, Buffer) = match cb.peek_unacked_segment(send_unacked) {
+        Some(segment) => segment,
         None => {
             // We shouldn't enter the retransmit routine with an empty unacknowledged queue.  So maybe we should assert
             // here?  But this is relatively benign if it happens, and could be the result of a race-condition or a
@@ -69,14 +70,14 @@ async fn retransmit<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
     // Our retransmission timer fired, so we need to resend a packet.
     let remote_link_addr: MacAddress = cb.arp().query(cb.get_remote().ip().clone()).await?;
 
-    // Unset the initial timestamp so we don't use this for RTT estimation.
-    segment.initial_tx.take();
-
     // Prepare and send the segment.
-    let (seq_no, _) = cb.get_send_unacked();
     let mut header: TcpHeader = cb.tcp_header();
     header.seq_num = seq_no;
-    cb.emit(header, segment.bytes, remote_link_addr);
+    if bytes.len() == 0 {
+        // This buffer is the end-of-send marker, so retransmit our FIN.  It occupies one sequence number.
+        header.fin = true;
+    }
+    cb.emit(header, bytes, remote_link_addr);
 
     // Set new retransmit deadline.
     // ToDo: Review this.  Shouldn't we only do this for RetransmitCause::Timeout?
@@ -104,6 +105,7 @@ pub async fn retransmitter<RT: SchedulerRuntime + NetworkRuntime + Clone + 'stat
         let (rtx_fast_retransmit, rtx_fast_retransmit_changed) = cb.congestion_control_watch_retransmit_now_flag();
         if rtx_fast_retransmit {
             cb.congestion_control_on_fast_retransmit();
+            cb.congestion_control_on_loss(false);
             retransmit(RetransmitCause::FastRetransmit, &cb).await?;
             continue;
         }
@@ -114,11 +116,21 @@ pub async fn retransmitter<RT: SchedulerRuntime + NetworkRuntime + Clone + 'stat
             _ = rtx_fast_retransmit_changed => continue,
             _ = rtx_future => {
                 trace!("Retransmission Timer Expired");
+                // Give up if the oldest outstanding data has gone unacknowledged for longer than the user timeout.
+                if cb.get_user_timeout_deadline().map_or(false, |deadline| cb.rt().now() >= deadline) {
+                    cb.abort(CloseReason::UserTimeout);
+                    return Err(Fail::new(ETIMEDOUT, "user timeout exceeded"));
+                }
+                // Give up if our peer has not acknowledged anything for too long.
+                if cb.record_retransmit_timeout() > cb.get_config().get_max_retransmits() {
+                    cb.abort(CloseReason::Timeout);
+                    return Err(Fail::new(ETIMEDOUT, "retransmission limit exceeded"));
+                }
                 let (send_unacknowledged, _) = cb.get_send_unacked();
-                cb.congestion_control_on_rto(send_unacknowledged);
-                // ToDo: Fix retransmit routine, uncomment next line and delete subsequent line.
-                // retransmit(RetransmitCause::TimeOut, &cb).await?;
-                cb.set_retransmit_deadline(None);
+                let (send_next, _) = cb.get_send_next();
+                cb.congestion_control_on_rto(send_unacknowledged, send_next);
+                cb.congestion_control_on_loss(true);
+                retransmit(RetransmitCause::TimeOut, &cb).await?;
             },
         }
     }
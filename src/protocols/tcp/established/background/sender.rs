@@ -62,6 +62,7 @@ pub async fn sender<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
             let unacked_segment = UnackedSegment {
                 bytes: buf.clone(),
                 initial_tx: Some(cb.rt().now()),
+                first_tx: cb.rt().now(),
             };
             cb.push_unacked_segment(unacked_segment);
 
@@ -102,11 +103,10 @@ pub async fn sender<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
         let effective_cwnd: u32 = cwnd + ltci;
         let next_buf_size: usize = cb.unsent_top_size().expect("no buffer in unsent queue");
 
+        // Wait until there is room in the window of our peer. A buffer that does not fit in the window is split, so
+        // there only needs to be room for part of it.
         let sent_data: u32 = (send_next - send_unacked).into();
-        if win_sz <= (sent_data + next_buf_size as u32)
-            || effective_cwnd <= sent_data
-            || (effective_cwnd - sent_data) <= cb.get_mss() as u32
-        {
+        if win_sz <= sent_data || effective_cwnd <= sent_data || (effective_cwnd - sent_data) <= cb.get_mss() as u32 {
             futures::select_biased! {
                 _ = send_unacked_changed => continue 'top,
                 _ = send_next_changed => continue 'top,
@@ -118,28 +118,39 @@ pub async fn sender<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
 
         // Past this point we have data to send and it's valid to send it!
 
-        // TODO: Nagle's algorithm - We need to coalese small buffers together to send MSS sized packets.
+        // TODO: Nagle's algorithm - We need to coalese small buffers together to send MSS sized packets.  When we do,
+        // a sub-MSS segment must still go out immediately if there is no unacknowledged data, as otherwise we deadlock
+        // with a peer that delays its ACKs (see the PSH handling in ControlBlock::receive()).
         // TODO: Silly window syndrome - See RFC 1122's discussion of the SWS avoidance algorithm.
 
         // ToDo: Link-level concerns don't belong here, we should call an IP-level send routine below.
         let remote_link_addr = cb.arp().query(cb.get_remote().ip().clone()).await?;
 
-        // Form an outgoing packet.
-        let max_size: usize = cmp::min(
-            cmp::min((win_sz - sent_data) as usize, cb.get_mss()),
-            (effective_cwnd - sent_data) as usize,
-        );
+        // Form an outgoing packet, which carries no more than the send quantum: the smallest of the MSS, the usable
+        // window of our peer and the room left in the congestion window.
+        let usable_window: usize = (win_sz - sent_data) as usize;
+        let cwnd_room: usize = (effective_cwnd - sent_data) as usize;
+        let max_size: usize = cmp::min(cmp::min(cb.get_mss(), usable_window), cwnd_room);
+
+        // Hold off until the rate limiter lets this segment out.
+        if let Some(delay) = cb.rate_limit_delay(cmp::min(max_size, next_buf_size)) {
+            cb.rt().wait(delay).await;
+            continue 'top;
+        }
+
         let segment_data: Buffer = cb
             .pop_unsent_segment(max_size)
             .expect("No unsent data with sequence number gap?");
         let mut segment_data_len: u32 = segment_data.len() as u32;
 
         let rto: Duration = cb.rto_estimate();
-        cb.congestion_control_on_send(rto, sent_data);
+        cb.congestion_control_on_send(rto, segment_data_len);
 
         // Prepare the segment and send it.
         let mut header: TcpHeader = cb.tcp_header();
         header.seq_num = send_next;
+        // Set PSH if this segment drains the unsent queue.
+        header.psh = segment_data_len != 0 && cb.unsent_top_size().is_none();
         if segment_data_len == 0 {
             // This buffer is the end-of-send marker.
             debug_assert!(cb.user_is_done_sending.get());
@@ -148,6 +159,7 @@ pub async fn sender<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
             segment_data_len = 1;
         }
         cb.emit(header, segment_data.clone(), remote_link_addr);
+        cb.rate_limit_consume(segment_data.len());
 
         // Update SND.NXT.
         cb.modify_send_next(|s| s + SeqNumber::from(segment_data_len));
@@ -156,6 +168,7 @@ pub async fn sender<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
         let unacked_segment = UnackedSegment {
             bytes: segment_data,
             initial_tx: Some(cb.rt().now()),
+            first_tx: cb.rt().now(),
         };
         cb.push_unacked_segment(unacked_segment);
 
@@ -166,5 +179,12 @@ pub async fn sender<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
             let rto: Duration = cb.rto_estimate();
             cb.set_retransmit_deadline(Some(cb.rt().now() + rto));
         }
+
+        // When pacing, hold off the next segment so that the usable window is spread over one round-trip time.
+        if cb.get_config().get_pacing() {
+            let window: u32 = cmp::min(win_sz, effective_cwnd);
+            let interval: Duration = cb.pacing_interval(segment_data_len, window);
+            cb.rt().wait(interval).await;
+        }
     }
 }
@@ -0,0 +1,395 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+// This is an implementation of BBR ("Bottleneck Bandwidth and Round-trip propagation time"), as described in the
+// draft-cardwell-iccrg-bbr-congestion-control Internet-Draft.  Rather than reacting to loss, BBR models the path by its
+// bottleneck bandwidth (the maximum recent delivery rate) and its round-trip propagation time (the minimum recent RTT),
+// and paces data out at the estimated bandwidth while keeping about one bandwidth-delay product (BDP) in flight.
+//
+// Delivery rate samples are taken once per round trip, as the amount of data acknowledged over that round divided by
+// the latest RTT measured in it.  Pacing only takes effect on connections that are configured to pace (see
+// TcpConfigExt::pacing).
+
+use super::{
+    CongestionControl,
+    CongestionPhase,
+    FastRetransmitRecovery,
+    LimitedTransmit,
+    Options,
+    SlowStartCongestionAvoidance,
+};
+use crate::protocols::tcp::{
+    config::DEFAULT_DUPACK_THRESHOLD,
+    SeqNumber,
+};
+use ::runtime::{
+    network::NetworkRuntime,
+    watched::{
+        WatchFuture,
+        WatchedValue,
+    },
+};
+use ::std::{
+    cell::{
+        Cell,
+        RefCell,
+    },
+    cmp::max,
+    collections::VecDeque,
+    convert::TryInto,
+    fmt::Debug,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// State of the BBR state machine.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BbrState {
+    /// Ramping up the sending rate quickly, until the bottleneck bandwidth is found.
+    Startup,
+    /// Draining the queue that built up during startup.
+    Drain,
+    /// Cycling the sending rate around the bottleneck bandwidth, to probe for more of it.
+    ProbeBw,
+    /// Briefly cutting the amount of data in flight, to measure the round-trip propagation time afresh.
+    ProbeRtt,
+}
+
+#[derive(Debug)]
+pub struct Bbr {
+    pub mss: u32,                // Maximum segment size, in bytes.
+    pub state: Cell<BbrState>,   // Current state of the state machine.
+    pub cwnd: WatchedValue<u32>, // Congestion window: Max number of bytes that may be in flight.
+    pub pacing_gain: Cell<f64>,  // Factor applied to the bandwidth estimate to get the pacing rate.
+    pub cwnd_gain: Cell<f64>,    // Factor applied to the BDP estimate to get the target cwnd.
+
+    // Path Model
+    pub bw_samples: RefCell<VecDeque<(u64, f64)>>, // Delivery rate samples of recent rounds, in bytes per second.
+    pub min_rtt: Cell<Option<Duration>>,           // Estimate of the round-trip propagation time.
+    pub min_rtt_stamp: Cell<Option<Instant>>,      // The moment at which min_rtt was last refreshed, once ACKs arrive.
+
+    // Round Trip Counting
+    pub sent: Cell<u64>,                   // Total number of bytes sent.
+    pub delivered: Cell<u64>,              // Total number of bytes acknowledged.
+    pub round_count: Cell<u64>,            // Number of round trips so far.
+    pub round_end: Cell<u64>,              // The current round ends once more than this many bytes are acknowledged.
+    pub round_start_delivered: Cell<u64>,  // Number of bytes acknowledged when the current round started.
+    pub round_rtt: Cell<Option<Duration>>, // Latest RTT sample of the current round.
+
+    // Startup State
+    pub filled_pipe: Cell<bool>,  // Whether the bottleneck bandwidth has been found.
+    pub full_bw: Cell<f64>,       // The bandwidth estimate at which startup last grew substantially.
+    pub full_bw_count: Cell<u32>, // Number of rounds since startup last grew substantially.
+
+    // Probe Bandwidth State
+    pub cycle_index: Cell<usize>, // Phase of the pacing gain cycle.
+
+    // Probe RTT State
+    pub probe_rtt_done_stamp: Cell<Option<Instant>>, // The moment at which probing the RTT may end.
+    pub probe_rtt_round_done: Cell<bool>,            // Whether a full round has gone by while probing the RTT.
+    pub prior_cwnd: Cell<u32>,                       // The value of cwnd before probing the RTT.
+
+    // Fast Retransmit State
+    pub duplicate_ack_count: Cell<u32>, // The number of consecutive duplicate ACKs we've received.
+    pub dupack_threshold: u32,          // The number of duplicate ACKs that trigger a fast retransmit.
+    pub fast_retransmit_now: WatchedValue<bool>, // Flag to cause the retransmitter to retransmit a segment now.
+}
+
+impl<RT: NetworkRuntime> CongestionControl<RT> for Bbr {
+    fn new(mss: usize, _seq_no: SeqNumber, options: Option<Options>) -> Box<dyn CongestionControl<RT>> {
+        let mss: u32 = mss.try_into().unwrap();
+        let options: Options = options.unwrap_or_default();
+        let dupack_threshold: u32 = options
+            .get_int("dupack_threshold")
+            .map(|t| t.try_into().unwrap())
+            .unwrap_or(DEFAULT_DUPACK_THRESHOLD);
+
+        Box::new(Self {
+            mss,
+            state: Cell::new(BbrState::Startup),
+            cwnd: WatchedValue::new(Self::INITIAL_CWND_SEGMENTS * mss),
+            pacing_gain: Cell::new(Self::HIGH_GAIN),
+            cwnd_gain: Cell::new(Self::HIGH_GAIN),
+
+            bw_samples: RefCell::new(VecDeque::new()),
+            min_rtt: Cell::new(None),
+            min_rtt_stamp: Cell::new(None),
+
+            sent: Cell::new(0),
+            delivered: Cell::new(0),
+            round_count: Cell::new(0),
+            round_end: Cell::new(0),
+            round_start_delivered: Cell::new(0),
+            round_rtt: Cell::new(None),
+
+            filled_pipe: Cell::new(false),
+            full_bw: Cell::new(0.),
+            full_bw_count: Cell::new(0),
+
+            cycle_index: Cell::new(0),
+
+            probe_rtt_done_stamp: Cell::new(None),
+            probe_rtt_round_done: Cell::new(false),
+            prior_cwnd: Cell::new(0),
+
+            duplicate_ack_count: Cell::new(0),
+            dupack_threshold,
+            fast_retransmit_now: WatchedValue::new(false),
+        })
+    }
+
+    fn get_phase(&self) -> CongestionPhase {
+        CongestionPhase::Bbr(self.state.get())
+    }
+}
+
+impl Bbr {
+    // Number of rounds over which the maximum delivery rate is taken.
+    const BW_FILTER_ROUNDS: u64 = 10;
+    // Number of rounds without the bandwidth estimate growing past FULL_BW_THRESHOLD, after which startup has filled
+    // the pipe.
+    const FULL_BW_ROUNDS: u32 = 3;
+    // Growth of the bandwidth estimate that shows that startup has not filled the pipe yet.
+    const FULL_BW_THRESHOLD: f64 = 1.25;
+    // Gain that doubles the sending rate every round trip during startup (2 / ln(2)).
+    const HIGH_GAIN: f64 = 2.885;
+    // Initial cwnd, in segments (RFC 6928).
+    const INITIAL_CWND_SEGMENTS: u32 = 10;
+    // Minimum cwnd, in segments.
+    const MIN_CWND_SEGMENTS: u32 = 4;
+    // How long the round-trip propagation time estimate stays valid without being refreshed.
+    pub const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+    // Pacing gains of the phases of the probe bandwidth cycle, each lasting one round trip.
+    const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1., 1., 1., 1., 1., 1.];
+    // Gain applied to the BDP estimate to get the target cwnd while probing bandwidth.
+    const PROBE_BW_CWND_GAIN: f64 = 2.;
+    // Minimum time spent probing the round-trip propagation time.
+    pub const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+
+    // Gets the bottleneck bandwidth estimate, in bytes per second.
+    fn bandwidth(&self) -> Option<f64> {
+        self.bw_samples.borrow().iter().map(|(_, bw)| *bw).reduce(f64::max)
+    }
+
+    // Gets the bandwidth-delay product estimate, in bytes.
+    fn bdp(&self) -> Option<f64> {
+        Some(self.bandwidth()? * self.min_rtt.get()?.as_secs_f64())
+    }
+
+    fn bytes_in_flight(&self) -> u64 {
+        self.sent.get().saturating_sub(self.delivered.get())
+    }
+
+    fn min_cwnd(&self) -> u32 {
+        Self::MIN_CWND_SEGMENTS * self.mss
+    }
+
+    // Takes a delivery rate sample at the end of each round, returning whether a round has just ended.  A round ends
+    // with the first ACK for data sent after it started, so the sample covers the data acknowledged before that ACK.
+    fn update_round(&self, bytes_acknowledged: u32, rtt: Option<Duration>) -> bool {
+        let delivered: u64 = self.delivered.get() + bytes_acknowledged as u64;
+        let prior_delivered: u64 = self.delivered.get();
+        self.delivered.set(delivered);
+        if delivered <= self.round_end.get() {
+            if rtt.is_some() {
+                self.round_rtt.set(rtt);
+            }
+            return false;
+        }
+        let round_count: u64 = self.round_count.get() + 1;
+        self.round_count.set(round_count);
+        self.round_end.set(self.sent.get());
+
+        let round_delivered: u64 = prior_delivered - self.round_start_delivered.get();
+        if let Some(round_rtt) = self.round_rtt.get().or_else(|| self.min_rtt.get()) {
+            if round_delivered > 0 {
+                let bw: f64 = round_delivered as f64 / round_rtt.as_secs_f64();
+                let mut bw_samples = self.bw_samples.borrow_mut();
+                bw_samples.push_back((round_count, bw));
+                while let Some((round, _)) = bw_samples.front() {
+                    if round + Self::BW_FILTER_ROUNDS > round_count {
+                        break;
+                    }
+                    bw_samples.pop_front();
+                }
+            }
+        }
+        self.round_start_delivered.set(prior_delivered);
+        self.round_rtt.set(rtt);
+        true
+    }
+
+    fn update_min_rtt(&self, rtt: Option<Duration>, now: Instant) {
+        // The filter starts with the first ACK, as the runtime clock is not at hand before that.
+        let min_rtt_stamp: Instant = self.min_rtt_stamp.get().unwrap_or(now);
+        self.min_rtt_stamp.set(Some(min_rtt_stamp));
+        let expired: bool = now.saturating_duration_since(min_rtt_stamp) > Self::MIN_RTT_FILTER_LEN;
+        if let Some(rtt) = rtt {
+            if expired || self.min_rtt.get().map_or(true, |min_rtt| rtt <= min_rtt) {
+                self.min_rtt.set(Some(rtt));
+                self.min_rtt_stamp.set(Some(now));
+            }
+        }
+
+        // Probe the round-trip propagation time if it has not been seen for a while.
+        if expired && self.state.get() != BbrState::ProbeRtt {
+            self.state.set(BbrState::ProbeRtt);
+            self.pacing_gain.set(1.);
+            self.cwnd_gain.set(1.);
+            self.prior_cwnd.set(self.cwnd.get());
+            self.probe_rtt_done_stamp.set(None);
+        }
+    }
+
+    fn enter_probe_bw(&self) {
+        self.state.set(BbrState::ProbeBw);
+        self.cwnd_gain.set(Self::PROBE_BW_CWND_GAIN);
+        self.cycle_index.set(0);
+        self.pacing_gain.set(Self::PACING_GAIN_CYCLE[0]);
+    }
+
+    fn update_state(&self, round_ended: bool, now: Instant) {
+        match self.state.get() {
+            BbrState::Startup if round_ended => {
+                // The pipe is full once the bandwidth estimate stops growing substantially.
+                let bw: f64 = self.bandwidth().unwrap_or(0.);
+                if bw >= self.full_bw.get() * Self::FULL_BW_THRESHOLD {
+                    self.full_bw.set(bw);
+                    self.full_bw_count.set(0);
+                } else {
+                    self.full_bw_count.set(self.full_bw_count.get() + 1);
+                    if self.full_bw_count.get() >= Self::FULL_BW_ROUNDS {
+                        self.filled_pipe.set(true);
+                        self.state.set(BbrState::Drain);
+                        self.pacing_gain.set(1. / Self::HIGH_GAIN);
+                    }
+                }
+            },
+            BbrState::Drain => {
+                if let Some(bdp) = self.bdp() {
+                    if self.bytes_in_flight() as f64 <= bdp {
+                        self.enter_probe_bw();
+                    }
+                }
+            },
+            BbrState::ProbeBw if round_ended => {
+                let cycle_index: usize = (self.cycle_index.get() + 1) % Self::PACING_GAIN_CYCLE.len();
+                self.cycle_index.set(cycle_index);
+                self.pacing_gain.set(Self::PACING_GAIN_CYCLE[cycle_index]);
+            },
+            BbrState::ProbeRtt => match self.probe_rtt_done_stamp.get() {
+                // Wait for the data in flight to drop to the minimum cwnd before starting the clock.
+                None if self.bytes_in_flight() <= self.min_cwnd() as u64 => {
+                    self.probe_rtt_done_stamp.set(Some(now + Self::PROBE_RTT_DURATION));
+                    self.probe_rtt_round_done.set(false);
+                    self.round_end.set(self.sent.get());
+                },
+                Some(done_stamp) => {
+                    if round_ended {
+                        self.probe_rtt_round_done.set(true);
+                    }
+                    if self.probe_rtt_round_done.get() && now >= done_stamp {
+                        self.min_rtt_stamp.set(Some(now));
+                        self.cwnd.set(max(self.cwnd.get(), self.prior_cwnd.get()));
+                        if self.filled_pipe.get() {
+                            self.enter_probe_bw();
+                        } else {
+                            self.state.set(BbrState::Startup);
+                            self.pacing_gain.set(Self::HIGH_GAIN);
+                            self.cwnd_gain.set(Self::HIGH_GAIN);
+                        }
+                    }
+                },
+                None => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn update_cwnd(&self, bytes_acknowledged: u32) {
+        let cwnd: u32 = if self.state.get() == BbrState::ProbeRtt {
+            self.min_cwnd()
+        } else {
+            let cwnd: u32 = self.cwnd.get().saturating_add(bytes_acknowledged);
+            match self.bdp() {
+                // Once the pipe is full, keep cwnd at its target.  Before that, let it grow with every ACK.
+                Some(bdp) if self.filled_pipe.get() => cwnd.min((bdp * self.cwnd_gain.get()) as u32),
+                _ => cwnd,
+            }
+        };
+        let cwnd: u32 = max(cwnd, self.min_cwnd());
+        if cwnd != self.cwnd.get() {
+            self.cwnd.set(cwnd);
+        }
+    }
+}
+
+impl<RT: NetworkRuntime> SlowStartCongestionAvoidance<RT> for Bbr {
+    fn get_cwnd(&self) -> u32 {
+        self.cwnd.get()
+    }
+
+    fn watch_cwnd(&self) -> (u32, WatchFuture<'_, u32>) {
+        self.cwnd.watch()
+    }
+
+    fn get_pacing_rate(&self) -> Option<f64> {
+        Some(self.bandwidth()? * self.pacing_gain.get())
+    }
+
+    fn on_send(&self, _rto: Duration, num_sent_bytes: u32, _now: Instant) {
+        self.sent.set(self.sent.get() + num_sent_bytes as u64);
+    }
+
+    fn on_ack(&self, bytes_acknowledged: u32, rtt: Option<Duration>, now: Instant) {
+        self.duplicate_ack_count.set(0);
+
+        let round_ended: bool = self.update_round(bytes_acknowledged, rtt);
+        self.update_min_rtt(rtt, now);
+        self.update_state(round_ended, now);
+        self.update_cwnd(bytes_acknowledged);
+    }
+
+    fn on_loss(&self, is_rto: bool) {
+        // Loss is not a signal of congestion to BBR.  A retransmission timeout, however, means that our model of the
+        // path is off, so we start over from the minimum window and let it grow back as ACKs arrive.
+        if is_rto {
+            self.cwnd.set(self.min_cwnd());
+        }
+    }
+
+    fn on_takeover(&self, cwnd: u32, _ssthresh: u32) {
+        // Startup grows the window from where the connection is, rather than from the initial window.
+        self.cwnd.set(max(cwnd, self.min_cwnd()));
+    }
+}
+
+impl<RT: NetworkRuntime> FastRetransmitRecovery<RT> for Bbr {
+    fn get_duplicate_ack_count(&self) -> u32 {
+        self.duplicate_ack_count.get()
+    }
+
+    fn get_retransmit_now_flag(&self) -> bool {
+        self.fast_retransmit_now.get()
+    }
+
+    fn watch_retransmit_now_flag(&self) -> (bool, WatchFuture<'_, bool>) {
+        self.fast_retransmit_now.watch()
+    }
+
+    fn on_fast_retransmit(&self) {
+        self.fast_retransmit_now.set_without_notify(false);
+    }
+
+    fn on_dup_ack(&self) {
+        let duplicate_ack_count: u32 = self.duplicate_ack_count.get() + 1;
+        self.duplicate_ack_count.set(duplicate_ack_count);
+        if duplicate_ack_count == self.dupack_threshold {
+            self.fast_retransmit_now.set(true);
+        }
+    }
+}
+
+impl<RT: NetworkRuntime> LimitedTransmit<RT> for Bbr {}
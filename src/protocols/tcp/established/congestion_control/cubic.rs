@@ -11,12 +11,16 @@
 
 use super::{
     CongestionControl,
+    CongestionPhase,
     FastRetransmitRecovery,
     LimitedTransmit,
     Options,
     SlowStartCongestionAvoidance,
 };
-use crate::protocols::tcp::SeqNumber;
+use crate::protocols::tcp::{
+    config::DEFAULT_DUPACK_THRESHOLD,
+    SeqNumber,
+};
 use ::runtime::{
     network::NetworkRuntime,
     watched::{
@@ -38,6 +42,27 @@ use ::std::{
     },
 };
 
+/// State of Forward RTO-Recovery (F-RTO), which detects spurious retransmission timeouts (RFC 5682).
+#[derive(Clone, Copy, Debug)]
+pub enum Frto {
+    /// No retransmission timeout is being checked.
+    Inactive,
+    /// Waiting for the first ACK after a retransmission timeout.
+    FirstAck { recover: SeqNumber, prior: CongestionState },
+    /// Waiting for the second ACK after a retransmission timeout.
+    SecondAck { prior: CongestionState },
+}
+
+/// Congestion state before a retransmission timeout, which is restored if the timeout turns out to be spurious.
+#[derive(Clone, Copy, Debug)]
+pub struct CongestionState {
+    cwnd: u32,
+    ssthresh: u32,
+    w_max: u32,
+    last_congestion_was_rto: bool,
+    retransmitted_packets_in_flight: u32,
+}
+
 #[derive(Debug)]
 pub struct Cubic {
     pub mss: u32, // Just for convenience, otherwise we have `as u32` or `.try_into().unwrap()` scattered everywhere...
@@ -46,7 +71,7 @@ pub struct Cubic {
     pub cwnd: WatchedValue<u32>, // Congestion window: Max number of bytes that may be in flight ot prevent congestion.
     pub fast_convergence: bool, // Should we employ the fast convergence algorithm (Only recommended if there are multiple CUBIC streams on the same network, in which case we'll cede capacity to new ones faster).
     pub initial_cwnd: u32,      // The initial value of cwnd, which gets used if the connection ever resets.
-    pub last_send_time: Cell<Instant>, // The moment at which we last sent data.
+    pub last_send_time: Cell<Option<Instant>>, // The moment at which we last sent data, if we have sent any.
     pub last_congestion_was_rto: Cell<bool>, // A flag for whether the last congestion event was detected by RTO.
     pub retransmitted_packets_in_flight: Cell<u32>, // A flag for if there is currently a retransmitted packet in flight.
     pub rtt_at_last_send: Cell<Duration>,           // The RTT at the moment we last sent data.
@@ -55,12 +80,23 @@ pub struct Cubic {
 
     // Fast Recovery / Fast Retransmit State
     pub duplicate_ack_count: Cell<u32>, // The number of consecutive duplicate ACKs we've received.
+    pub dupack_threshold: u32,          // The number of duplicate ACKs that trigger a fast retransmit.
     pub fast_retransmit_now: WatchedValue<bool>, // Flag to cause the retransmitter to retransmit a segment now.
     pub in_fast_recovery: Cell<bool>,   // Are we currently in the `fast recovery` algorithm.
     pub prev_ack_seq_no: Cell<SeqNumber>, // The previous highest ACK sequence number.
     pub recover: Cell<SeqNumber>, // If we receive dup ACKs with sequence numbers greater than this we'll attempt fast recovery.
 
     pub limited_transmit_cwnd_increase: WatchedValue<u32>, // The amount by which cwnd should be increased due to the limited transit algorithm.
+
+    // Forward RTO-Recovery State
+    pub frto: Cell<Frto>, // Where we are in checking whether the last retransmission timeout was spurious.
+
+    // Congestion Window Validation State (RFC 7661)
+    pub cwv_period: Option<Duration>, // The non-validated period (NVP), if congestion window validation is enabled.
+    pub pipe_ack: Cell<Option<u32>>,  // The number of bytes acknowledged in the last complete round trip, if measured.
+    pub pipe_ack_accumulator: Cell<u32>, // The number of bytes acknowledged so far in the current round trip.
+    pub pipe_ack_round_end: Cell<Option<SeqNumber>>, // The ACK sequence number that completes the current round trip.
+    pub nvp_start: Cell<Option<Instant>>, // When we last entered the non-validated phase, or reduced cwnd within it.
 }
 
 impl<RT: NetworkRuntime> CongestionControl<RT> for Cubic {
@@ -75,6 +111,15 @@ impl<RT: NetworkRuntime> CongestionControl<RT> for Cubic {
 
         let options: Options = options.unwrap_or_default();
         let fast_convergence: bool = options.get_bool("fast_convergence").unwrap_or(true);
+        // Thresholds that do not fit or that would retransmit without any duplicate ACK fall back to the default.
+        let dupack_threshold: u32 = options
+            .get_int("dupack_threshold")
+            .and_then(|t| u32::try_from(t).ok())
+            .filter(|t| *t > 0)
+            .unwrap_or(DEFAULT_DUPACK_THRESHOLD);
+        let cwv_period: Option<Duration> = options
+            .get_int("cwnd_validation_period_ms")
+            .map(|ms| Duration::from_millis(ms.try_into().unwrap()));
 
         Box::new(Self {
             mss,
@@ -83,7 +128,7 @@ impl<RT: NetworkRuntime> CongestionControl<RT> for Cubic {
             cwnd: WatchedValue::new(initial_cwnd),
             fast_convergence,
             initial_cwnd,
-            last_send_time: Cell::new(Instant::now()),
+            last_send_time: Cell::new(None),
             retransmitted_packets_in_flight: Cell::new(0),
             rtt_at_last_send: Cell::new(Duration::new(1, 0)), // The default RTT is 1 sec.
             ssthresh: Cell::new(u32::MAX), // According to RFC5681 ssthresh should be initialised 'arbitrarily high'.
@@ -95,17 +140,35 @@ impl<RT: NetworkRuntime> CongestionControl<RT> for Cubic {
             recover: Cell::new(seq_no), // Recover set to initial send sequence number according to RFC6582.
             prev_ack_seq_no: Cell::new(seq_no), // RFC6582 doesn't specify the initial value, but this seems sensible.
             duplicate_ack_count: Cell::new(0),
+            dupack_threshold,
 
             limited_transmit_cwnd_increase: WatchedValue::new(0),
+
+            frto: Cell::new(Frto::Inactive),
+
+            cwv_period,
+            pipe_ack: Cell::new(None),
+            pipe_ack_accumulator: Cell::new(0),
+            pipe_ack_round_end: Cell::new(None),
+            nvp_start: Cell::new(None),
         })
     }
+
+    fn get_phase(&self) -> CongestionPhase {
+        if self.in_fast_recovery.get() {
+            CongestionPhase::FastRecovery
+        } else if self.cwnd.get() < self.ssthresh.get() {
+            CongestionPhase::SlowStart
+        } else {
+            CongestionPhase::CongestionAvoidance
+        }
+    }
 }
 
 impl Cubic {
     const BETA_CUBIC: f32 = 0.7;
     // Cubic const parameters.
     const C: f32 = 0.4;
-    const DUP_ACK_THRESHOLD: u32 = 3;
 
     fn fast_convergence(&self) {
         // The fast convergence algorithm assumes that w_max and cwnd are stored in units of mss, so we do this
@@ -122,7 +185,7 @@ impl Cubic {
     fn increment_dup_ack_count(&self) -> u32 {
         let duplicate_ack_count: u32 = self.duplicate_ack_count.get() + 1;
         self.duplicate_ack_count.set(duplicate_ack_count);
-        if duplicate_ack_count < Self::DUP_ACK_THRESHOLD {
+        if duplicate_ack_count < self.dupack_threshold {
             self.limited_transmit_cwnd_increase.modify(|ltci| ltci + self.mss);
         }
         duplicate_ack_count
@@ -138,7 +201,7 @@ impl Cubic {
         let ack_covers_recover: bool = ack_seq_no - SeqNumber::from(1) > self.recover.get();
         let retransmitted_packet_dropped_heuristic: bool = cwnd > self.mss && ack_seq_no_diff <= 4 * self.mss;
 
-        if duplicate_ack_count == Self::DUP_ACK_THRESHOLD
+        if duplicate_ack_count == self.dupack_threshold
             && (ack_covers_recover || retransmitted_packet_dropped_heuristic)
         {
             // Check against recover specified in RFC6582.
@@ -156,7 +219,7 @@ impl Cubic {
             self.fast_retransmit_now.set(true);
             // We don't reset ca_start here even though cwnd has been shrunk because we aren't going
             // straight back into congestion avoidance.
-        } else if duplicate_ack_count > Self::DUP_ACK_THRESHOLD || self.in_fast_recovery.get() {
+        } else if duplicate_ack_count > self.dupack_threshold || self.in_fast_recovery.get() {
             self.cwnd.modify(|c| c + self.mss);
         }
     }
@@ -217,7 +280,10 @@ impl Cubic {
         let cwnd: u32 = self.cwnd.get();
         let ssthresh: u32 = self.ssthresh.get();
 
-        if cwnd < ssthresh {
+        if self.is_non_validated() {
+            // An application-limited connection has not shown that the path can take a larger window, so we do not
+            // grow it any further.
+        } else if cwnd < ssthresh {
             // Slow start.
             self.cwnd.modify(|c| c + min(bytes_acknowledged, mss));
         } else {
@@ -268,6 +334,116 @@ impl Cubic {
         self.last_congestion_was_rto.set(true);
     }
 
+    fn congestion_state(&self) -> CongestionState {
+        CongestionState {
+            cwnd: self.cwnd.get(),
+            ssthresh: self.ssthresh.get(),
+            w_max: self.w_max.get(),
+            last_congestion_was_rto: self.last_congestion_was_rto.get(),
+            retransmitted_packets_in_flight: self.retransmitted_packets_in_flight.get(),
+        }
+    }
+
+    fn on_rto_frto(&self, send_next: SeqNumber) {
+        match self.frto.get() {
+            // Start checking whether this timeout is spurious, once we know what to restore if it is.
+            Frto::Inactive => self.frto.set(Frto::FirstAck {
+                recover: send_next,
+                prior: self.congestion_state(),
+            }),
+            // The timer expired again before we could tell, so fall back to conventional RTO recovery.
+            Frto::FirstAck { .. } | Frto::SecondAck { .. } => self.frto.set(Frto::Inactive),
+        }
+    }
+
+    fn on_ack_received_frto(&self, send_unacked: SeqNumber, ack_seq_no: SeqNumber) {
+        let acks_new_data: bool = ack_seq_no > send_unacked;
+        match self.frto.get() {
+            Frto::Inactive => (),
+            Frto::FirstAck { recover, prior } => {
+                // RFC 5682, section 2.1, step 2: an ACK that advances the window, but does not acknowledge everything
+                // that was outstanding when the timer expired, may have been triggered by either the original
+                // transmission or the retransmission. We let new data go out and look at the next ACK.
+                if acks_new_data && ack_seq_no < recover {
+                    self.frto.set(Frto::SecondAck { prior });
+                } else {
+                    self.frto.set(Frto::Inactive);
+                }
+            },
+            Frto::SecondAck { prior } => {
+                // RFC 5682, section 2.1, step 3: a second ACK that advances the window acknowledges data that was not
+                // retransmitted, so the timeout was spurious. Undo the congestion response to it (RFC 4015).
+                if acks_new_data {
+                    self.cwnd.set(max(self.cwnd.get(), prior.cwnd));
+                    self.ssthresh.set(prior.ssthresh);
+                    self.w_max.set(prior.w_max);
+                    self.last_congestion_was_rto.set(prior.last_congestion_was_rto);
+                    self.retransmitted_packets_in_flight
+                        .set(prior.retransmitted_packets_in_flight);
+                }
+                self.frto.set(Frto::Inactive);
+            },
+        }
+    }
+
+    // Whether we are in the non-validated phase of RFC 7661, i.e. we used less than half of cwnd in the last round trip.
+    fn is_non_validated(&self) -> bool {
+        match (self.cwv_period, self.pipe_ack.get()) {
+            (Some(_), Some(pipe_ack)) => pipe_ack < self.cwnd.get() / 2,
+            _ => false,
+        }
+    }
+
+    fn on_ack_received_cwv(&self, send_next: SeqNumber, bytes_acknowledged: u32, ack_seq_no: SeqNumber, now: Instant) {
+        if self.cwv_period.is_none() {
+            return;
+        }
+
+        // Measure pipeACK, the number of bytes acknowledged over a round trip. A round trip starts with the first ACK
+        // after we have sent new data, and ends once the data that was outstanding at that time is acknowledged.
+        let round_end: SeqNumber = match self.pipe_ack_round_end.get() {
+            Some(round_end) => round_end,
+            None => {
+                self.pipe_ack_accumulator.set(0);
+                send_next
+            },
+        };
+        let pipe_ack_accumulator: u32 = self.pipe_ack_accumulator.get() + bytes_acknowledged;
+        if ack_seq_no < round_end {
+            self.pipe_ack_accumulator.set(pipe_ack_accumulator);
+            self.pipe_ack_round_end.set(Some(round_end));
+            return;
+        }
+        self.pipe_ack.set(Some(pipe_ack_accumulator));
+        self.pipe_ack_accumulator.set(0);
+        self.pipe_ack_round_end
+            .set(if send_next > ack_seq_no { Some(send_next) } else { None });
+
+        self.validate_cwnd(now);
+    }
+
+    // Reduces cwnd if we have been in the non-validated phase for longer than the NVP (RFC 7661, section 4.4.3).
+    fn validate_cwnd(&self, now: Instant) {
+        let cwv_period: Duration = match self.cwv_period {
+            Some(cwv_period) => cwv_period,
+            None => return,
+        };
+        if !self.is_non_validated() {
+            self.nvp_start.set(None);
+            return;
+        }
+        let nvp_start: Instant = self.nvp_start.get().unwrap_or(now);
+        if now.duration_since(nvp_start) >= cwv_period {
+            let cwnd: u32 = self.cwnd.get();
+            self.ssthresh.set(max(self.ssthresh.get(), 3 * cwnd / 4));
+            self.cwnd.set(max(cwnd / 2, self.initial_cwnd));
+            // The reduction repeats every NVP for as long as the connection remains application-limited.
+            self.nvp_start.set(Some(now));
+        } else {
+            self.nvp_start.set(Some(nvp_start));
+        }
+    }
+
     fn on_rto_fast_recovery(&self, send_unacked: SeqNumber) {
         // Exit fast recovery/retransmit
         self.recover.set(send_unacked);
@@ -284,24 +460,38 @@ impl<RT: NetworkRuntime> SlowStartCongestionAvoidance<RT> for Cubic {
         self.cwnd.watch()
     }
 
-    fn on_cwnd_check_before_send(&self) {
-        let long_time_since_send: bool =
-            Instant::now().duration_since(self.last_send_time.get()) > self.rtt_at_last_send.get();
+    fn get_ssthresh(&self) -> Option<u32> {
+        Some(self.ssthresh.get())
+    }
+
+    fn on_cwnd_check_before_send(&self, now: Instant) {
+        let long_time_since_send: bool = match self.last_send_time.get() {
+            Some(last_send_time) => now.saturating_duration_since(last_send_time) > self.rtt_at_last_send.get(),
+            None => false,
+        };
         if long_time_since_send {
             let restart_window: u32 = min(self.initial_cwnd, self.cwnd.get());
             self.cwnd.set(restart_window);
             self.limited_transmit_cwnd_increase.set_without_notify(0);
         }
+        self.validate_cwnd(now);
     }
 
-    fn on_send(&self, rto: Duration, num_bytes_sent: u32) {
-        self.last_send_time.set(Instant::now());
+    fn on_send(&self, rto: Duration, num_bytes_sent: u32, now: Instant) {
+        self.last_send_time.set(Some(now));
         self.rtt_at_last_send.set(rto);
         self.limited_transmit_cwnd_increase
             .set_without_notify(self.limited_transmit_cwnd_increase.get().saturating_sub(num_bytes_sent));
     }
 
-    fn on_ack_received(&self, rto: Duration, send_unacked: SeqNumber, send_next: SeqNumber, ack_seq_no: SeqNumber) {
+    fn on_ack_received(
+        &self,
+        rto: Duration,
+        send_unacked: SeqNumber,
+        send_next: SeqNumber,
+        ack_seq_no: SeqNumber,
+        now: Instant,
+    ) {
         let bytes_acknowledged: u32 = (ack_seq_no - send_unacked).into();
         if bytes_acknowledged == 0 {
             // ACK is a duplicate
@@ -321,14 +511,31 @@ impl<RT: NetworkRuntime> SlowStartCongestionAvoidance<RT> for Cubic {
             }
             // Used to handle dup ACKs after timeout.
             self.prev_ack_seq_no.set(ack_seq_no);
+
+            self.on_ack_received_cwv(send_next, bytes_acknowledged, ack_seq_no, now);
         }
+
+        // Check whether a recent retransmission timeout was spurious.
+        self.on_ack_received_frto(send_unacked, ack_seq_no);
     }
 
-    fn on_rto(&self, send_unacked: SeqNumber) {
-        // Handle timeout for any of the algorithms we could currently be using.
+    fn on_rto(&self, send_unacked: SeqNumber, send_next: SeqNumber) {
+        // Handle timeout for any of the algorithms we could currently be using. F-RTO goes first, so that it records
+        // the congestion state before the others reduce it.
+        self.on_rto_frto(send_next);
         self.on_rto_ss_ca();
         self.on_rto_fast_recovery(send_unacked);
     }
+
+    fn on_takeover(&self, cwnd: u32, ssthresh: u32) {
+        self.cwnd.set(max(cwnd, self.mss));
+        self.ssthresh.set(ssthresh);
+        // Start a congestion avoidance epoch at the window we were handed, which becomes the plateau of the cubic
+        // function. There was no congestion event to recover from, so K is zero, as it is after an RTO.
+        self.w_max.set(self.cwnd.get());
+        self.last_congestion_was_rto.set(true);
+        self.ca_start.set(Instant::now());
+    }
 }
 
 impl<RT: NetworkRuntime> FastRetransmitRecovery<RT> for Cubic {
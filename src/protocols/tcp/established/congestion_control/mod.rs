@@ -1,6 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+mod bbr;
 mod cubic;
 mod none;
 mod options;
@@ -12,10 +13,17 @@ use ::runtime::{
 };
 use ::std::{
     fmt::Debug,
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 pub use self::{
+    bbr::{
+        Bbr,
+        BbrState,
+    },
     cubic::Cubic,
     none::None,
     options::{
@@ -24,6 +32,21 @@ pub use self::{
     },
 };
 
+/// Phase of a congestion control algorithm.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CongestionPhase {
+    /// The algorithm does not limit the sending rate, so it has no phases.
+    Uncontrolled,
+    /// The congestion window grows by up to a segment for every segment acknowledged.
+    SlowStart,
+    /// The congestion window grows by about a segment per round trip, or along the cubic function of CUBIC.
+    CongestionAvoidance,
+    /// Lost segments are being retransmitted after duplicate ACKs.
+    FastRecovery,
+    /// Phase of the BBR state machine.
+    Bbr(BbrState),
+}
+
 pub trait SlowStartCongestionAvoidance<RT: NetworkRuntime> {
     fn get_cwnd(&self) -> u32 {
         u32::MAX
@@ -32,17 +55,47 @@ pub trait SlowStartCongestionAvoidance<RT: NetworkRuntime> {
         (u32::MAX, WatchFuture::Pending)
     }
 
-    // Called immediately before the cwnd check is performed before data is sent.
-    fn on_cwnd_check_before_send(&self) {}
+    // Slow start threshold, for algorithms that have one.
+    fn get_ssthresh(&self) -> Option<u32> {
+        None
+    }
+
+    // Rate at which to pace data out, in bytes per second, for algorithms that estimate it.  Only used on connections
+    // that are configured to pace.
+    fn get_pacing_rate(&self) -> Option<f64> {
+        None
+    }
+
+    // Called immediately before the cwnd check is performed before data is sent, with the current time of the runtime.
+    fn on_cwnd_check_before_send(&self, _now: Instant) {}
 
-    fn on_ack_received(&self, _rto: Duration, _send_unacked: SeqNumber, _send_next: SeqNumber, _ack_seq_no: SeqNumber) {
+    // Called once an ACK has been received, with the current time of the runtime.
+    fn on_ack_received(
+        &self,
+        _rto: Duration,
+        _send_unacked: SeqNumber,
+        _send_next: SeqNumber,
+        _ack_seq_no: SeqNumber,
+        _now: Instant,
+    ) {
     }
 
+    // Called once an ACK that acknowledges new data has been processed, along with the round-trip time sampled from it,
+    // if any, and the current time of the runtime.
+    fn on_ack(&self, _bytes_acknowledged: u32, _rtt: Option<Duration>, _now: Instant) {}
+
+    // Called immediately before a segment deemed lost is retransmitted, either after RTO or on fast retransmit.
+    fn on_loss(&self, _is_rto: bool) {}
+
     // Called immediately before retransmit after RTO.
-    fn on_rto(&self, _send_unacked: SeqNumber) {}
+    fn on_rto(&self, _send_unacked: SeqNumber, _send_next: SeqNumber) {}
+
+    // Called immediately before a segment is sent for the 1st time, with the current time of the runtime.
+    fn on_send(&self, _rto: Duration, _num_sent_bytes: u32, _now: Instant) {}
 
-    // Called immediately before a segment is sent for the 1st time.
-    fn on_send(&self, _rto: Duration, _num_sent_bytes: u32) {}
+    // Called when the algorithm takes over an established connection from another one, with the congestion window and
+    // slow start threshold that the connection had reached.
+    fn on_takeover(&self, _cwnd: u32, _ssthresh: u32) {}
 }
 
 pub trait FastRetransmitRecovery<RT: NetworkRuntime>
@@ -61,6 +114,9 @@ where
     }
 
     fn on_fast_retransmit(&self) {}
+
+    // Called once a duplicate ACK has been processed.
+    fn on_dup_ack(&self) {}
 }
 
 pub trait LimitedTransmit<RT: NetworkRuntime>
@@ -81,6 +137,11 @@ pub trait CongestionControl<RT: NetworkRuntime>:
     fn new(mss: usize, seq_no: SeqNumber, options: Option<options::Options>) -> Box<dyn CongestionControl<RT>>
     where
         Self: Sized;
+
+    // Phase that the algorithm is currently in, for debugging purposes.
+    fn get_phase(&self) -> CongestionPhase {
+        CongestionPhase::Uncontrolled
+    }
 }
 
 pub type CongestionControlConstructor<T> =
@@ -5,29 +5,48 @@ use super::{
     congestion_control::{
         self,
         CongestionControlConstructor,
+        CongestionPhase,
     },
     rto::RtoCalculator,
     sender::{
         Sender,
         UnackedSegment,
     },
+    token_bucket::TokenBucket,
 };
-use crate::protocols::{
-    arp::ArpPeer,
-    ethernet2::{
-        EtherType2,
-        Ethernet2Header,
-    },
-    ip::IpProtocol,
-    ipv4::Ipv4Header,
-    tcp::{
-        segment::{
-            TcpHeader,
-            TcpSegment,
+use crate::{
+    collections::RingBuffer,
+    protocols::{
+        arp::ArpPeer,
+        ethernet2::{
+            EtherType2,
+            Ethernet2Header,
+        },
+        ip::IpProtocol,
+        ipv4::Ipv4Header,
+        tcp::{
+            config::{
+                TcpConfigExt,
+                MIN_ACK_DELAY,
+            },
+            constants::MSL,
+            loopback::{
+                self,
+                LoopbackSender,
+            },
+            segment::{
+                TcpHeader,
+                TcpOptions2,
+                TcpSegment,
+            },
+            SeqNumber,
         },
-        SeqNumber,
     },
 };
+use ::libc::{
+    EAGAIN,
+    ENOTCONN,
+};
 use ::runtime::{
     fail::Fail,
     memory::{
@@ -47,8 +66,10 @@ use ::runtime::{
 use ::std::{
     cell::{
         Cell,
+        Ref,
         RefCell,
     },
+    cmp,
     collections::VecDeque,
     convert::TryInto,
     net::SocketAddrV4,
@@ -64,17 +85,14 @@ use ::std::{
     },
 };
 
-// ToDo: Review this value (and its purpose).  It (2048 segments) of 8 KB jumbo packets would limit the unread data to
-// just 16 MB.  If we don't want to lie, that is also about the max window size we should ever advertise.  Whereas TCP
-// with the window scale option allows for window sizes of up to 1 GB.  This value appears to exist more because of the
-// mechanism used to manage the receive queue (a VecDeque) than anything else.
-const RECV_QUEUE_SZ: usize = 2048;
-
-// ToDo: Review this value (and its purpose).  It (16 segments) seems awfully small (would make fast retransmit less
-// useful), and this mechanism isn't the best way to protect ourselves against deliberate out-of-order segment attacks.
-// Ideally, we'd limit out-of-order data to that which (along with the unread data) will fit in the receive window.
+// ToDo: Review this value (and its purpose).  It caps the number of ranges of out-of-order data that we keep track of.
+// Out-of-order data is already limited to that which (along with the unread data) will fit in the receive window, so
+// this only guards against peers that scatter data across the window on purpose.
 const MAX_OUT_OF_ORDER: usize = 16;
 
+// Fraction of the smoothed round-trip time that ACKs are held back for, once it has been measured.
+const ACK_DELAY_RTT_DIVISOR: u32 = 4;
+
 // TCP Connection State.
 // Note: This ControlBlock structure is only used after we've reached the ESTABLISHED state, so states LISTEN,
 // SYN_RCVD, and SYN_SENT aren't included here.
@@ -90,6 +108,24 @@ pub enum State {
     Closed,
 }
 
+// Number of states that a connection goes through once it is established.
+const NUM_STATES: usize = State::Closed as usize + 1;
+
+// Reason why a connection entered the CLOSED state.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CloseReason {
+    // Our peer reset the connection.
+    PeerReset,
+    // We gave up on the connection, e.g. because our peer violated the protocol.
+    LocalAbort,
+    // Our peer stopped acknowledging data, or the connection was idle for too long.
+    Timeout,
+    // Our peer did not acknowledge data within the user timeout (RFC 5482).
+    UserTimeout,
+    // Both sides closed the connection in an orderly fashion.
+    Graceful,
+}
+
 // ToDo: Consider incorporating this directly into ControlBlock.
 struct Receiver {
     //
@@ -112,32 +148,324 @@ struct Receiver {
     // Sequence number of the next byte of data (or FIN) that we expect to receive.  In RFC 793 terms, this is RCV.NXT.
     pub receive_next: Cell<SeqNumber>,
 
+    // Size of the receive buffer, which bounds the unread data along with the out-of-order data after it.
+    receive_buffer_size: u32,
+
     // Receive queue.  Contains in-order received (and acknowledged) data ready for the application to read.
     recv_queue: RefCell<VecDeque<Buffer>>,
+
+    // Reassembly area for out-of-order data.  Data is written at its distance from RCV.NXT, which is where the ring
+    // buffer's head is kept, until the data before it arrives.  This is sized to the receive buffer, which bounds our
+    // receive window, so any data that we accept fits.
+    out_of_order: RefCell<RingBuffer>,
+
+    // Sequence number ranges of the data in the reassembly area, as [start, end) pairs.  These are sorted, and
+    // ranges that overlap or touch are merged.
+    out_of_order_ranges: RefCell<VecDeque<(SeqNumber, SeqNumber)>>,
+
+    // Whether we have received (in-order) a FIN from our peer, after which no more data is going to arrive.
+    pub fin_received: Cell<bool>,
+
+    // Sequence numbers that follow the data of in-order segments with the PSH flag set, in ascending order.  These are
+    // only recorded if the user asked for push boundaries.
+    push_boundaries: RefCell<VecDeque<SeqNumber>>,
 }
 
 impl Receiver {
-    pub fn new(reader_next: SeqNumber, receive_next: SeqNumber) -> Self {
+    pub fn new(reader_next: SeqNumber, receive_next: SeqNumber, receive_buffer_size: u32) -> Self {
         Self {
             reader_next: Cell::new(reader_next),
             receive_next: Cell::new(receive_next),
-            recv_queue: RefCell::new(VecDeque::with_capacity(RECV_QUEUE_SZ)),
+            receive_buffer_size,
+            recv_queue: RefCell::new(VecDeque::new()),
+            out_of_order: RefCell::new(RingBuffer::new(receive_buffer_size as usize)),
+            out_of_order_ranges: RefCell::new(VecDeque::new()),
+            fin_received: Cell::new(false),
+            push_boundaries: RefCell::new(VecDeque::new()),
         }
     }
 
-    pub fn pop(&self) -> Option<Buffer> {
-        let buf: Buffer = self.recv_queue.borrow_mut().pop_front()?;
+    pub fn is_empty(&self) -> bool {
+        self.recv_queue.borrow().is_empty()
+    }
+
+    /// Gets the number of bytes of data that are ready to be read.
+    pub fn len(&self) -> usize {
+        let bytes_unread: u32 = (self.receive_next.get() - self.reader_next.get()).into();
+        bytes_unread as usize - self.fin_received.get() as usize
+    }
+
+    /// Pops the received data of the next segment, along with that of the segments after it until at least `min_len`
+    /// bytes are popped, if there are that many.
+    pub fn pop(&self, min_len: usize) -> Option<Buffer> {
+        let mut len: usize = 0;
+        for segment in self.recv_queue.borrow().iter() {
+            len += segment.len();
+            if len >= min_len {
+                break;
+            }
+        }
+
+        self.pop_bytes(len)
+    }
+
+    /// Pops received data up to the next push boundary. If no boundary is known for the queued data, this pops the
+    /// data of the next segment like [Self::pop].
+    pub fn pop_to_boundary(&self) -> Option<Buffer> {
+        let reader_next: SeqNumber = self.reader_next.get();
+        let boundary: SeqNumber = {
+            let mut push_boundaries = self.push_boundaries.borrow_mut();
+            // Forget about boundaries in data that has been read without regard to them.
+            while push_boundaries.front().map_or(false, |b| *b <= reader_next) {
+                push_boundaries.pop_front();
+            }
+            match push_boundaries.pop_front() {
+                Some(boundary) => boundary,
+                None => return self.pop(1),
+            }
+        };
+
+        self.pop_bytes(u32::from(boundary - reader_next) as usize)
+    }
+
+    /// Pops up to `len` bytes of received data, splitting or gathering buffers as needed. Data that lies within the
+    /// first buffer is handed out without copying it.
+    fn pop_bytes(&self, len: usize) -> Option<Buffer> {
+        let mut recv_queue = self.recv_queue.borrow_mut();
+        let front: &mut Buffer = recv_queue.front_mut()?;
+        let buf: Buffer = if front.len() >= len {
+            // The data lies within the first buffer, so hand out the part of it that is wanted.
+            let mut buf: Buffer = front.clone();
+            let front_len: usize = front.len();
+            front.adjust(len);
+            buf.trim(front_len - len);
+            if front.is_empty() {
+                recv_queue.pop_front();
+            }
+            buf
+        } else {
+            // The data spans several buffers, so gather them into a single one.
+            let mut bytes: Vec<u8> = Vec::with_capacity(len);
+            while bytes.len() < len {
+                let segment: &mut Buffer = match recv_queue.front_mut() {
+                    Some(segment) => segment,
+                    None => break,
+                };
+                let n: usize = cmp::min(len - bytes.len(), segment.len());
+                bytes.extend_from_slice(&segment[..n]);
+                if n == segment.len() {
+                    recv_queue.pop_front();
+                } else {
+                    segment.adjust(n);
+                }
+            }
+            Buffer::Heap(DataBuffer::from_slice(&bytes))
+        };
         self.reader_next
             .set(self.reader_next.get() + SeqNumber::from(buf.len() as u32));
 
         Some(buf)
     }
 
-    pub fn push(&self, buf: Buffer) {
-        let buf_len: u32 = buf.len() as u32;
+    /// Records that a segment with the PSH flag set ends right before `boundary`.
+    pub fn push_boundary(&self, boundary: SeqNumber) {
+        self.push_boundaries.borrow_mut().push_back(boundary);
+    }
+
+    /// Copies data from the front of the receive queue into `buf`, without consuming it. Returns the number of bytes
+    /// copied.
+    pub fn peek(&self, buf: &mut [u8]) -> usize {
+        let mut nbytes: usize = 0;
+        for segment in self.recv_queue.borrow().iter() {
+            if nbytes == buf.len() {
+                break;
+            }
+            let len: usize = cmp::min(buf.len() - nbytes, segment.len());
+            buf[nbytes..(nbytes + len)].copy_from_slice(&segment[..len]);
+            nbytes += len;
+        }
+
+        nbytes
+    }
+
+    /// Copies data from the front of the receive queue into `buf`, consuming it. Data that does not fit is left in
+    /// the queue. Returns the number of bytes copied.
+    pub fn pop_into(&self, buf: &mut [u8]) -> usize {
+        let mut recv_queue = self.recv_queue.borrow_mut();
+        let mut nbytes: usize = 0;
+        while nbytes < buf.len() {
+            let segment: &mut Buffer = match recv_queue.front_mut() {
+                Some(segment) => segment,
+                None => break,
+            };
+            let len: usize = cmp::min(buf.len() - nbytes, segment.len());
+            buf[nbytes..(nbytes + len)].copy_from_slice(&segment[..len]);
+            nbytes += len;
+            if len == segment.len() {
+                recv_queue.pop_front();
+            } else {
+                segment.adjust(len);
+            }
+        }
+        self.reader_next
+            .set(self.reader_next.get() + SeqNumber::from(nbytes as u32));
+
+        nbytes
+    }
+
+    // Gets the number of bytes that the receive buffer has room for after the unread data.
+    fn available(&self) -> usize {
+        self.receive_buffer_size as usize - self.len()
+    }
+
+    /// Queues in-order data that starts at RCV.NXT. Data that overruns the receive buffer is dropped.
+    pub fn push(&self, mut buf: Buffer) {
+        let available: usize = self.available();
+        if buf.len() > available {
+            warn!(
+                "Dropping {} bytes that overrun the receive buffer",
+                buf.len() - available
+            );
+            buf.trim(buf.len() - available);
+        }
+        let buf_len: usize = buf.len();
+        if buf_len == 0 {
+            return;
+        }
         self.recv_queue.borrow_mut().push_back(buf);
         self.receive_next
             .set(self.receive_next.get() + SeqNumber::from(buf_len as u32));
+
+        // Keep the head of the reassembly area at RCV.NXT.
+        if !self.out_of_order_ranges.borrow().is_empty() {
+            let mut out_of_order = self.out_of_order.borrow_mut();
+            out_of_order.commit(buf_len);
+            out_of_order.consume(buf_len);
+        }
+    }
+
+    /// Writes data that starts at `seg_start`, after RCV.NXT, into the reassembly area, until the data before it
+    /// arrives. Data that overruns the receive buffer is dropped.
+    pub fn push_out_of_order(&self, seg_start: SeqNumber, buf: Buffer) {
+        let offset: usize = u32::from(seg_start - self.receive_next.get()) as usize;
+        let len: usize = cmp::min(buf.len(), self.available().saturating_sub(offset));
+        if len == 0 {
+            return;
+        }
+        let nbytes: usize = self.out_of_order.borrow_mut().write_at(offset, &buf[..len]);
+        debug_assert_eq!(nbytes, len);
+        let mut start: SeqNumber = seg_start;
+        let mut end: SeqNumber = seg_start + SeqNumber::from(len as u32);
+
+        // Merge the new range with the ranges that it overlaps or touches, and insert it in order.
+        let mut ranges = self.out_of_order_ranges.borrow_mut();
+        let mut index: usize = 0;
+        while index < ranges.len() {
+            let (range_start, range_end): (SeqNumber, SeqNumber) = ranges[index];
+            if range_end < start {
+                index += 1;
+            } else if end < range_start {
+                break;
+            } else {
+                if range_start < start {
+                    start = range_start;
+                }
+                if range_end > end {
+                    end = range_end;
+                }
+                ranges.remove(index);
+            }
+        }
+        ranges.insert(index, (start, end));
+
+        // If we now track too many ranges, forget the later ones.
+        // ToDo: The reassembly area is already limited (in size) by our receive window, while the below check
+        // imposes a limit on the number of ranges.  Do we need this?  Presumably for attack mitigation?
+        while ranges.len() > MAX_OUT_OF_ORDER {
+            ranges.pop_back();
+        }
+    }
+
+    /// Queues the data in the reassembly area that the data before it has arrived for, and advances RCV.NXT over it.
+    /// Returns true if any data was queued.
+    pub fn recover_out_of_order(&self) -> bool {
+        let mut ranges = self.out_of_order_ranges.borrow_mut();
+        let mut out_of_order = self.out_of_order.borrow_mut();
+        let mut recovered: bool = false;
+        while let Some((start, end)) = ranges.front().copied() {
+            let receive_next: SeqNumber = self.receive_next.get();
+            if start > receive_next {
+                // Since the ranges are sorted, we can stop when the next one is not in sequence.
+                break;
+            }
+            ranges.pop_front();
+            if end <= receive_next {
+                // This data has been received in order since.
+                continue;
+            }
+
+            // The head of the reassembly area is at RCV.NXT, so the rest of the range starts right there.
+            let len: usize = u32::from(end - receive_next) as usize;
+            debug!("Recovering {} bytes of out-of-order data at {}", len, receive_next);
+            let nbytes: usize = out_of_order.commit(len);
+            debug_assert_eq!(nbytes, len);
+            let buf: DataBuffer = match out_of_order.as_slices() {
+                (front, []) => DataBuffer::from_slice(front),
+                (front, back) => DataBuffer::from_slice(&[front, back].concat()),
+            };
+            out_of_order.consume(len);
+            self.recv_queue.borrow_mut().push_back(Buffer::Heap(buf));
+            self.receive_next.set(end);
+            recovered = true;
+        }
+
+        recovered
+    }
+}
+
+/// Connection parameters that were negotiated in the three-way handshake.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectionParams {
+    /// Maximum segment size that we use when sending.
+    pub mss: usize,
+    /// Maximum segment size that our peer advertised, or the fallback one if it did not advertise any.
+    pub remote_mss: usize,
+    /// Whether window scaling was agreed. If not, both scale factors are zero.
+    pub window_scaling: bool,
+    /// Scale factor of the window that we advertise.
+    pub local_window_scale: u8,
+    /// Scale factor of the window that our peer advertises.
+    pub remote_window_scale: u8,
+    /// Whether selective acknowledgements were agreed. We never offer them, so they are never agreed.
+    pub sack_permitted: bool,
+    /// Whether timestamps were agreed (RFC 7323), in which case every segment carries them.
+    pub timestamps: bool,
+    /// Whether Explicit Congestion Notification was agreed (RFC 3168).
+    pub ecn: bool,
+}
+
+/// Statistics of a connection, which accumulate over its lifetime.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ConnectionStats {
+    /// Number of segments carrying data that arrived ahead of RCV.NXT.
+    pub out_of_order_segments: u64,
+    /// Largest distance ahead of RCV.NXT at which a segment arrived (in bytes).
+    pub max_reorder_distance: u32,
+}
+
+/// Time that a connection has spent in each state, for diagnostics.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StateDurations {
+    /// Time from the first SYN to the connection being established, which it spent in SYN-SENT or SYN-RECEIVED.
+    pub handshake: Duration,
+    /// Time spent in each of the states that a connection goes through once it is established, indexed by state.
+    durations: [Duration; NUM_STATES],
+}
+
+impl StateDurations {
+    /// Gets the time spent in a state.
+    pub fn get(&self, state: State) -> Duration {
+        self.durations[state as usize]
     }
 }
 
@@ -153,12 +481,24 @@ pub struct ControlBlock<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>
     // this along with other remote IP information (such as routing, path MTU, etc).
     arp: Rc<ArpPeer<RT>>,
 
+    // Queue of segments that we address to ourselves.
+    loopback: LoopbackSender,
+
     // Send-side state information.  ToDo: Consider incorporating this directly into ControlBlock.
     sender: Sender,
 
     // TCP Connection State.
     state: Cell<State>,
 
+    // Time at which we entered the current state.
+    state_entered: Cell<Instant>,
+
+    // Time spent in the states that we have left.
+    state_durations: Cell<StateDurations>,
+
+    // Round-trip time of the handshake, from our SYN to its SYN+ACK, if we opened this connection actively.
+    handshake_rtt: Cell<Option<Duration>>,
+
     ack_delay_timeout: Duration,
 
     ack_deadline: WatchedValue<Option<Instant>>,
@@ -176,11 +516,8 @@ pub struct ControlBlock<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>
 
     waker: RefCell<Option<Waker>>,
 
-    // Queue of out-of-order segments.  This is where we hold onto data that we've received (because it was within our
-    // receive window) but can't yet present to the user because we're missing some other data that comes between this
-    // and what we've already presented to the user.
-    //
-    out_of_order: RefCell<VecDeque<(SeqNumber, Buffer)>>,
+    // Tasks waiting for all pushed data to be acknowledged.
+    flush_wakers: RefCell<Vec<Waker>>,
 
     // The sequence number of the FIN, if we received it out-of-order.
     // Note: This could just be a boolean to remember if we got a FIN; the sequence number is for checking correctness.
@@ -192,9 +529,18 @@ pub struct ControlBlock<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>
     // Whether the user has called close.
     pub user_is_done_sending: Cell<bool>,
 
-    // Congestion control trait implementation we're currently using.
+    // Congestion control trait implementation we're currently using.  The user may replace it mid-connection.
     // ToDo: Consider switching this to a static implementation to avoid V-table call overhead.
-    cc: Box<dyn congestion_control::CongestionControl<RT>>,
+    cc: RefCell<Box<dyn congestion_control::CongestionControl<RT>>>,
+
+    // Options that the congestion control algorithm was created with, which are handed to any that replaces it.
+    cc_options: congestion_control::Options,
+
+    // Copies of the congestion control state that the background tasks wait on.  They cannot wait on the algorithm
+    // itself, as it may be replaced while they do.
+    cwnd: WatchedValue<u32>,
+    limited_transmit_cwnd_increase: WatchedValue<u32>,
+    retransmit_now: WatchedValue<bool>,
 
     // Current retransmission timer expiration time.
     // ToDo: Consider storing this directly in the RtoCalculator.
@@ -202,6 +548,42 @@ pub struct ControlBlock<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>
 
     // Retransmission Timeout (RTO) calculator.
     rto: RefCell<RtoCalculator>,
+
+    // Number of consecutive retransmission timeouts, since new data was last acknowledged.
+    retransmit_count: Cell<usize>,
+
+    // Configuration extensions that were in effect when this connection was established.
+    config: TcpConfigExt,
+
+    // Time at which we leave TIME-WAIT and enter CLOSED.
+    time_wait_deadline: WatchedValue<Option<Instant>>,
+
+    // Parameters that were negotiated when this connection was established.
+    negotiated: ConnectionParams,
+
+    // Limits the rate at which we send data, if configured.
+    rate_limiter: Option<RefCell<TokenBucket>>,
+
+    // Last non-fatal error on this connection (e.g. from an ICMP message), which the user has not yet taken.
+    soft_error: RefCell<Option<Fail>>,
+
+    // Reason why this connection was closed, which the user has not yet cleared.
+    close_reason: Cell<Option<CloseReason>>,
+
+    // Statistics of this connection.
+    stats: Cell<ConnectionStats>,
+
+    // Time at which we last sent or received data on this connection.
+    last_activity: Cell<Instant>,
+
+    // Time to live of the last datagram that we received on this connection, if any.
+    received_ttl: Cell<Option<u8>>,
+
+    // Most recent timestamp of our peer, which we echo in every segment that we send if timestamps were agreed.
+    ts_recent: Cell<u32>,
+
+    // Time at which the handshake started, from which our timestamp clock ticks.
+    ts_started: Instant,
 }
 
 //==============================================================================
@@ -212,6 +594,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         remote: SocketAddrV4,
         rt: RT,
         arp: ArpPeer<RT>,
+        loopback: LoopbackSender,
         receiver_seq_no: SeqNumber,
         ack_delay_timeout: Duration,
         receiver_window_size: u32,
@@ -222,30 +605,122 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         sender_mss: usize,
         cc_constructor: CongestionControlConstructor<RT>,
         congestion_control_options: Option<congestion_control::Options>,
+        config: TcpConfigExt,
+        negotiated: ConnectionParams,
+        handshake_started: Instant,
     ) -> Self {
+        let now: Instant = rt.now();
+        let state_durations: StateDurations = StateDurations {
+            handshake: now.saturating_duration_since(handshake_started),
+            ..Default::default()
+        };
         let sender = Sender::new(sender_seq_no, sender_window_size, sender_window_scale, sender_mss);
+        let rate_limiter: Option<RefCell<TokenBucket>> = config
+            .get_rate_limit()
+            .map(|(rate, burst)| RefCell::new(TokenBucket::new(rate, burst, now)));
+        // The duplicate ACK threshold and congestion window validation are configured along with the rest of the peer,
+        // so we hand them over to the congestion control algorithm along with its other options.
+        let mut congestion_control_options: congestion_control::Options =
+            congestion_control_options.unwrap_or_default();
+        congestion_control_options.insert_int("dupack_threshold".to_string(), config.get_dupack_threshold() as i64);
+        if let Some(period) = config.get_cwnd_validation() {
+            congestion_control_options.insert_int("cwnd_validation_period_ms".to_string(), period.as_millis() as i64);
+        }
+        let cc: Box<dyn congestion_control::CongestionControl<RT>> =
+            cc_constructor(sender_mss, sender_seq_no, Some(congestion_control_options.clone()));
         Self {
             local,
             remote,
             rt: Rc::new(rt),
             arp: Rc::new(arp),
+            loopback,
             sender: sender,
             state: Cell::new(State::Established),
+            state_entered: Cell::new(now),
+            state_durations: Cell::new(state_durations),
+            handshake_rtt: Cell::new(None),
             ack_delay_timeout,
             ack_deadline: WatchedValue::new(None),
             receive_buffer_size: receiver_window_size,
             window_scale: receiver_window_scale,
             waker: RefCell::new(None),
-            out_of_order: RefCell::new(VecDeque::new()),
+            flush_wakers: RefCell::new(Vec::new()),
             out_of_order_fin: Cell::new(Option::None),
-            receiver: Receiver::new(receiver_seq_no, receiver_seq_no),
+            receiver: Receiver::new(receiver_seq_no, receiver_seq_no, receiver_window_size),
             user_is_done_sending: Cell::new(false),
-            cc: cc_constructor(sender_mss, sender_seq_no, congestion_control_options),
+            cwnd: WatchedValue::new(cc.get_cwnd()),
+            limited_transmit_cwnd_increase: WatchedValue::new(cc.get_limited_transmit_cwnd_increase()),
+            retransmit_now: WatchedValue::new(cc.get_retransmit_now_flag()),
+            cc: RefCell::new(cc),
+            cc_options: congestion_control_options,
             retransmit_deadline: WatchedValue::new(None),
-            rto: RefCell::new(RtoCalculator::new()),
+            rto: RefCell::new(RtoCalculator::new(config.get_min_rto())),
+            retransmit_count: Cell::new(0),
+            config,
+            time_wait_deadline: WatchedValue::new(None),
+            negotiated,
+            rate_limiter,
+            soft_error: RefCell::new(None),
+            close_reason: Cell::new(None),
+            stats: Cell::new(ConnectionStats::default()),
+            last_activity: Cell::new(now),
+            received_ttl: Cell::new(None),
+            ts_recent: Cell::new(0),
+            ts_started: handshake_started,
         }
     }
 
+    /// Builds a connection in the ESTABLISHED state without a handshake, so that its send and receive logic can be
+    /// tested in isolation. `local_seq_no` is the next sequence number that we send and `remote_seq_no` is the next one
+    /// that we expect to receive. Neither side scales its window, and segments are sized to the fallback MSS.
+    #[cfg(test)]
+    pub fn for_test(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        rt: RT,
+        arp: ArpPeer<RT>,
+        local_seq_no: SeqNumber,
+        remote_seq_no: SeqNumber,
+        local_window_size: u16,
+        remote_window_size: u16,
+    ) -> Self {
+        use crate::protocols::tcp::constants::FALLBACK_MSS;
+
+        let ack_delay_timeout: Duration = rt.tcp_options().get_ack_delay_timeout();
+        let now: Instant = rt.now();
+        // Segments addressed to ourselves are not delivered, as there is no TCP peer to receive them.
+        let (loopback, _): (LoopbackSender, _) = ::futures::channel::mpsc::unbounded();
+        Self::new(
+            local,
+            remote,
+            rt,
+            arp,
+            loopback,
+            remote_seq_no,
+            ack_delay_timeout,
+            local_window_size as u32,
+            0,
+            local_seq_no,
+            remote_window_size as u32,
+            0,
+            FALLBACK_MSS,
+            congestion_control::None::new,
+            None,
+            TcpConfigExt::default(),
+            ConnectionParams {
+                mss: FALLBACK_MSS,
+                remote_mss: FALLBACK_MSS,
+                window_scaling: false,
+                local_window_scale: 0,
+                remote_window_scale: 0,
+                sack_permitted: false,
+                timestamps: false,
+                ecn: false,
+            },
+            now,
+        )
+    }
+
     pub fn get_local(&self) -> SocketAddrV4 {
         self.local
     }
@@ -267,40 +742,92 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         self.sender.send(buf, self)
     }
 
+    pub fn send_sga(&self, sga: Vec<Buffer>) -> Result<(), Fail> {
+        self.sender.send_sga(sga, self)
+    }
+
     pub fn congestion_control_watch_retransmit_now_flag(&self) -> (bool, WatchFuture<bool>) {
-        self.cc.watch_retransmit_now_flag()
+        self.retransmit_now.watch()
+    }
+
+    pub fn congestion_control_get_phase(&self) -> CongestionPhase {
+        self.cc.borrow().get_phase()
     }
 
     pub fn congestion_control_on_fast_retransmit(&self) {
-        self.cc.on_fast_retransmit()
+        self.cc.borrow().on_fast_retransmit();
+        self.congestion_control_sync();
     }
 
-    pub fn congestion_control_on_rto(&self, send_unacknowledged: SeqNumber) {
-        self.cc.on_rto(send_unacknowledged)
+    pub fn congestion_control_on_rto(&self, send_unacknowledged: SeqNumber, send_next: SeqNumber) {
+        self.cc.borrow().on_rto(send_unacknowledged, send_next);
+        self.congestion_control_sync();
+    }
+
+    pub fn congestion_control_on_loss(&self, is_rto: bool) {
+        self.cc.borrow().on_loss(is_rto);
+        self.congestion_control_sync();
     }
 
     pub fn congestion_control_on_send(&self, rto: Duration, num_sent_bytes: u32) {
-        self.cc.on_send(rto, num_sent_bytes)
+        self.cc.borrow().on_send(rto, num_sent_bytes, self.rt.now());
+        self.congestion_control_sync();
     }
 
     pub fn congestion_control_on_cwnd_check_before_send(&self) {
-        self.cc.on_cwnd_check_before_send()
+        self.cc.borrow().on_cwnd_check_before_send(self.rt.now());
+        self.congestion_control_sync();
     }
 
     pub fn congestion_control_get_cwnd(&self) -> u32 {
-        self.cc.get_cwnd()
+        self.cc.borrow().get_cwnd()
     }
 
     pub fn congestion_control_watch_cwnd(&self) -> (u32, WatchFuture<u32>) {
-        self.cc.watch_cwnd()
+        self.cwnd.watch()
     }
 
     pub fn congestion_control_get_limited_transmit_cwnd_increase(&self) -> u32 {
-        self.cc.get_limited_transmit_cwnd_increase()
+        self.cc.borrow().get_limited_transmit_cwnd_increase()
     }
 
     pub fn congestion_control_watch_limited_transmit_cwnd_increase(&self) -> (u32, WatchFuture<u32>) {
-        self.cc.watch_limited_transmit_cwnd_increase()
+        self.limited_transmit_cwnd_increase.watch()
+    }
+
+    // Replaces the congestion control algorithm of this connection.  The new algorithm carries on from the congestion
+    // window that the connection has reached, unless the old one did not limit it.  If the old algorithm has no slow
+    // start threshold, that window becomes the threshold, as the old algorithm already probed the path to get there.
+    pub fn set_congestion_control(&self, cc_constructor: CongestionControlConstructor<RT>) {
+        let (send_unacked, _): (SeqNumber, _) = self.sender.get_send_unacked();
+        let cc: Box<dyn congestion_control::CongestionControl<RT>> =
+            cc_constructor(self.sender.get_mss(), send_unacked, Some(self.cc_options.clone()));
+        {
+            let old_cc: Ref<Box<dyn congestion_control::CongestionControl<RT>>> = self.cc.borrow();
+            if old_cc.get_phase() != CongestionPhase::Uncontrolled {
+                let cwnd: u32 = old_cc.get_cwnd();
+                cc.on_takeover(cwnd, old_cc.get_ssthresh().unwrap_or(cwnd));
+            }
+        }
+        *self.cc.borrow_mut() = cc;
+        self.congestion_control_sync();
+    }
+
+    // Copies the congestion control state that the background tasks wait on, after the algorithm may have changed it.
+    fn congestion_control_sync(&self) {
+        let cc: Ref<Box<dyn congestion_control::CongestionControl<RT>>> = self.cc.borrow();
+        let cwnd: u32 = cc.get_cwnd();
+        if self.cwnd.get() != cwnd {
+            self.cwnd.set(cwnd);
+        }
+        let ltci: u32 = cc.get_limited_transmit_cwnd_increase();
+        if self.limited_transmit_cwnd_increase.get() != ltci {
+            self.limited_transmit_cwnd_increase.set(ltci);
+        }
+        let retransmit_now: bool = cc.get_retransmit_now_flag();
+        if self.retransmit_now.get() != retransmit_now {
+            self.retransmit_now.set(retransmit_now);
+        }
     }
 
     pub fn get_mss(&self) -> usize {
@@ -323,6 +850,31 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         self.sender.get_send_next()
     }
 
+    // Oldest unacknowledged sequence number (SND.UNA in RFC 793 terms).
+    pub fn snd_una(&self) -> SeqNumber {
+        self.sender.get_send_unacked().0
+    }
+
+    // Next sequence number to be sent (SND.NXT).
+    pub fn snd_nxt(&self) -> SeqNumber {
+        self.sender.get_send_next().0
+    }
+
+    // Send window that our peer last advertised, in bytes (SND.WND).
+    pub fn snd_wnd(&self) -> u32 {
+        self.sender.get_send_window().0
+    }
+
+    // Next sequence number that we expect to receive (RCV.NXT).
+    pub fn rcv_nxt(&self) -> SeqNumber {
+        self.receiver.receive_next.get()
+    }
+
+    // Receive window that we offer, in bytes (RCV.WND).
+    pub fn rcv_wnd(&self) -> u32 {
+        self.get_receive_window_size()
+    }
+
     pub fn modify_send_next(&self, f: impl FnOnce(SeqNumber) -> SeqNumber) {
         self.sender.modify_send_next(f)
     }
@@ -332,9 +884,21 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
     }
 
     pub fn set_retransmit_deadline(&self, when: Option<Instant>) {
+        // The retransmission timer never runs past the user timeout, so that the retransmitter gets to enforce it.
+        let when: Option<Instant> = match (when, self.get_user_timeout_deadline()) {
+            (Some(when), Some(deadline)) => Some(cmp::min(when, deadline)),
+            _ => when,
+        };
         self.retransmit_deadline.set(when);
     }
 
+    // Get the time by which our peer must acknowledge the oldest data that we have outstanding, if a user timeout
+    // (RFC 5482) is configured.
+    pub fn get_user_timeout_deadline(&self) -> Option<Instant> {
+        let user_timeout: Duration = self.config.get_user_timeout()?;
+        Some(self.sender.get_oldest_unacked_tx()? + user_timeout)
+    }
+
     pub fn watch_retransmit_deadline(&self) -> (Option<Instant>, WatchFuture<Option<Instant>>) {
         self.retransmit_deadline.watch()
     }
@@ -347,6 +911,10 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         self.sender.push_unacked_segment(segment)
     }
 
+    pub fn peek_unacked_segment(&self, seq_no: SeqNumber) -> Option<(SeqNumber, Buffer)> {
+        self.sender.peek_unacked_segment(seq_no)
+    }
+
     pub fn rto_add_sample(&self, rtt: Duration) {
         self.rto.borrow_mut().add_sample(rtt)
     }
@@ -359,6 +927,173 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         self.rto.borrow_mut().record_failure()
     }
 
+    // Count a retransmission timeout, returning the number of consecutive timeouts so far.
+    pub fn record_retransmit_timeout(&self) -> usize {
+        let count: usize = self.retransmit_count.get() + 1;
+        self.retransmit_count.set(count);
+        count
+    }
+
+    // Give up on this connection without further ado.
+    pub fn abort(&self, reason: CloseReason) {
+        self.enter_closed(reason);
+        self.retransmit_deadline.set(None);
+        self.ack_deadline.set(None);
+        self.time_wait_deadline.set(None);
+
+        // ToDo: Return all outstanding user Receive and Send requests with "timed out" responses.
+        // ToDo: Delete the ControlBlock.
+    }
+
+    pub fn get_state(&self) -> State {
+        self.state.get()
+    }
+
+    // Move to another state, accounting for the time spent in the current one.
+    fn set_state(&self, state: State) {
+        let now: Instant = self.rt.now();
+        let mut state_durations: StateDurations = self.state_durations.get();
+        state_durations.durations[self.state.get() as usize] += now.saturating_duration_since(self.state_entered.get());
+        self.state_durations.set(state_durations);
+        self.state_entered.set(now);
+        self.state.set(state);
+    }
+
+    // Get the time that this connection has spent in each state so far, including the current one.
+    pub fn get_state_durations(&self) -> StateDurations {
+        let mut state_durations: StateDurations = self.state_durations.get();
+        state_durations.durations[self.state.get() as usize] +=
+            self.rt.now().saturating_duration_since(self.state_entered.get());
+        state_durations
+    }
+
+    // Get the round-trip time of the handshake, if we opened this connection actively.
+    pub fn get_handshake_rtt(&self) -> Option<Duration> {
+        self.handshake_rtt.get()
+    }
+
+    pub fn set_handshake_rtt(&self, rtt: Duration) {
+        self.handshake_rtt.set(Some(rtt));
+    }
+
+    /// Sets the most recent timestamp of our peer, as seen in the handshake.
+    pub fn set_ts_recent(&self, ts_recent: u32) {
+        self.ts_recent.set(ts_recent);
+    }
+
+    // Enter CLOSED, recording why, unless this connection is closed already.
+    fn enter_closed(&self, reason: CloseReason) {
+        if self.state.get() != State::Closed {
+            self.set_state(State::Closed);
+            self.close_reason.set(Some(reason));
+            // Data that is still unacknowledged never will be.
+            self.wake_flushes();
+        }
+    }
+
+    // Get the reason why this connection was closed, if it is.
+    pub fn get_close_reason(&self) -> Option<CloseReason> {
+        self.close_reason.get()
+    }
+
+    // Take the reason why this connection was closed, if it is, clearing it.
+    pub fn take_close_reason(&self) -> Option<CloseReason> {
+        self.close_reason.take()
+    }
+
+    // Record a non-fatal error, which replaces any earlier one that the user has not yet taken.
+    pub fn record_soft_error(&self, error: Fail) {
+        *self.soft_error.borrow_mut() = Some(error);
+    }
+
+    // Take the last non-fatal error on this connection, if any, clearing it.
+    pub fn take_error(&self) -> Option<Fail> {
+        self.soft_error.borrow_mut().take()
+    }
+
+    // Enter TIME-WAIT, start the time-wait timer, and turn off the other timers.
+    fn enter_time_wait(&self, now: Instant) {
+        self.set_state(State::TimeWait);
+        self.retransmit_deadline.set(None);
+        self.ack_deadline.set(None);
+        self.time_wait_deadline.set(Some(now + 2 * MSL));
+    }
+
+    pub fn watch_time_wait_deadline(&self) -> (Option<Instant>, WatchFuture<Option<Instant>>) {
+        self.time_wait_deadline.watch()
+    }
+
+    // The time-wait timer expired, so this connection can be safely closed.
+    pub fn on_time_wait_timeout(&self) {
+        self.time_wait_deadline.set(None);
+        if self.state.get() == State::TimeWait {
+            self.enter_closed(CloseReason::Graceful);
+
+            // ToDo: Delete the ControlBlock.
+        }
+    }
+
+    // Get the time at which we last sent or received data on this connection.
+    pub fn get_last_activity(&self) -> Instant {
+        self.last_activity.get()
+    }
+
+    // Get the time to live of the last datagram that we received on this connection.
+    pub fn get_received_ttl(&self) -> Option<u8> {
+        self.received_ttl.get()
+    }
+
+    pub fn set_received_ttl(&self, ttl: u8) {
+        self.received_ttl.set(Some(ttl));
+    }
+
+    pub fn get_negotiated(&self) -> ConnectionParams {
+        self.negotiated
+    }
+
+    pub fn get_stats(&self) -> ConnectionStats {
+        self.stats.get()
+    }
+
+    pub fn get_config(&self) -> &TcpConfigExt {
+        &self.config
+    }
+
+    // Computes how long to hold back an ACK.  This is a fraction of the smoothed round-trip time, so that ACKs are timely
+    // on fast paths, bounded below by MIN_ACK_DELAY and above by the configured delayed ACK timeout.  Until the
+    // round-trip time has been measured, the configured timeout is used as is.
+    fn ack_delay(&self) -> Duration {
+        let rto: Ref<RtoCalculator> = self.rto.borrow();
+        if !rto.has_sample() {
+            return self.ack_delay_timeout;
+        }
+        let ack_delay: Duration = cmp::max(rto.srtt() / ACK_DELAY_RTT_DIVISOR, MIN_ACK_DELAY);
+        cmp::min(ack_delay, self.ack_delay_timeout)
+    }
+
+    // Computes how long to wait after sending `num_bytes` so that a full `window` is spread over one round-trip time, or
+    // that data goes out at the pacing rate of the congestion control algorithm, if it has one.
+    pub fn pacing_interval(&self, num_bytes: u32, window: u32) -> Duration {
+        if let Some(pacing_rate) = self.cc.borrow().get_pacing_rate() {
+            return Duration::from_secs_f64(num_bytes as f64 / pacing_rate);
+        }
+        let srtt: Duration = self.rto.borrow().srtt();
+        srtt.mul_f64(num_bytes as f64 / window as f64)
+    }
+
+    // Computes how long to wait before sending `num_bytes`, so as not to exceed the configured rate limit.
+    pub fn rate_limit_delay(&self, num_bytes: usize) -> Option<Duration> {
+        let rate_limiter: &RefCell<TokenBucket> = self.rate_limiter.as_ref()?;
+        rate_limiter.borrow_mut().delay(num_bytes, self.rt.now())
+    }
+
+    // Accounts `num_bytes` that we have sent against the configured rate limit.
+    pub fn rate_limit_consume(&self, num_bytes: usize) {
+        if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+            rate_limiter.borrow_mut().consume(num_bytes, self.rt.now());
+        }
+    }
+
     pub fn unsent_top_size(&self) -> Option<usize> {
         self.sender.top_size_unsent()
     }
@@ -386,6 +1121,21 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         // ToDo: We're probably getting "now" here in order to get a timestamp as close as possible to when we received
         // the packet.  However, this is wasteful if we don't take a path below that actually uses it.  Review this.
         let now: Instant = self.rt.now();
+        if !data.is_empty() {
+            self.last_activity.set(now);
+        }
+
+        // RFC 7323, section 4.3: we echo the timestamp of the latest segment that does not start beyond what we have
+        // acknowledged, unless it is older than the one that we echo already.
+        if self.negotiated.timestamps {
+            if let Some((timestamp, _)) = header.timestamp_option() {
+                if header.seq_num <= self.receiver.receive_next.get()
+                    && timestamp.wrapping_sub(self.ts_recent.get()) as i32 >= 0
+                {
+                    self.ts_recent.set(timestamp);
+                }
+            }
+        }
 
         // Check to see if the segment is acceptable sequence-wise (i.e. contains some data that fits within the receive
         // window, or is a non-data segment with a sequence number that falls within the window).  Unacceptable segments
@@ -442,6 +1192,11 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
                     // This is an entirely duplicate (i.e. old) segment.  ACK (if not RST) and drop.
                     //
                     if !header.rst {
+                        // A retransmitted FIN means that our peer did not get our ACK for it.  In TIME-WAIT, we also
+                        // restart the 2 MSL time-wait timeout, so that we stay around to ACK further retransmissions.
+                        if header.fin && self.state.get() == State::TimeWait {
+                            self.enter_time_wait(now);
+                        }
                         self.send_ack();
                     }
                     return;
@@ -477,8 +1232,11 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
 
         // The start of the segment is in the window.
         // Check that the end of the segment is in the window, and trim it down if it is not.
+        // Note that a segment may have been trimmed at the front above, in case it overlaps both edges of the window.
         //
+        let mut trimmed_beyond_window: bool = false;
         if seg_len > 0 && seg_end >= after_receive_window {
+            trimmed_beyond_window = true;
             let mut excess: u32 = u32::from(seg_end - after_receive_window);
             excess += 1;
             // ToDo: If we end up (after receive handling rewrite is complete) not needing seg_end and seg_len after
@@ -513,7 +1271,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
                     // ToDo: Flush all segment queues.
 
                     // Enter Closed state.
-                    self.state.set(State::Closed);
+                    self.enter_closed(CloseReason::PeerReset);
 
                     // ToDo: Delete the ControlBlock.
                     return;
@@ -522,7 +1280,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
                 // Closing states.
                 State::Closing | State::LastAck | State::TimeWait => {
                     // Enter Closed state.
-                    self.state.set(State::Closed);
+                    self.enter_closed(CloseReason::PeerReset);
 
                     // ToDo: Delete the ControlBlock.
                     return;
@@ -548,7 +1306,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
             // ToDo: Flush all segment queues.
 
             // Enter Closed state.
-            self.state.set(State::Closed);
+            self.enter_closed(CloseReason::LocalAbort);
 
             // ToDo: Delete the ControlBlock.
             return;
@@ -577,12 +1335,14 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         // ToDo: Restructure this call into congestion control to either integrate it directly or make it more fine-
         // grained.  It currently duplicates the new/duplicate ack check itself internally, which is inefficient.
         // We should either make separate calls for each case or integrate those cases directly.
-        self.cc.on_ack_received(
+        self.cc.borrow().on_ack_received(
             self.rto.borrow().estimate(),
             send_unacknowledged,
             send_next,
             header.ack_num,
+            self.rt.now(),
         );
+        self.congestion_control_sync();
 
         if send_unacknowledged < header.ack_num {
             if header.ack_num <= send_next {
@@ -590,14 +1350,21 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
                 let bytes_acknowledged: u32 = (header.ack_num - send_unacknowledged).into();
 
                 // Remove the now acknowledged data from the unacknowledged queue.
-                self.sender.remove_acknowledged_data(self, bytes_acknowledged, now);
+                let rtt: Option<Duration> = self.sender.remove_acknowledged_data(self, header.ack_num, now);
+
+                // Our peer is making progress, so start counting retransmission timeouts afresh.
+                self.retransmit_count.set(0);
 
                 // Update SND.UNA to SEG.ACK.
                 self.sender.send_unacked.set(header.ack_num);
+                self.wake_flushes();
 
                 // Update our send window (SND.WND).
                 self.sender.update_send_window(header);
 
+                self.cc.borrow().on_ack(bytes_acknowledged, rtt, now);
+                self.congestion_control_sync();
+
                 if header.ack_num == send_next {
                     // This segment acknowledges everything we've sent so far (i.e. nothing is currently outstanding).
 
@@ -609,18 +1376,18 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
                         State::Established => (), // Common case.  Nothing more to do.
                         State::FinWait1 => {
                             // Our FIN is now ACK'd, so enter FIN-WAIT-2.
-                            self.state.set(State::FinWait2);
+                            self.set_state(State::FinWait2);
                         },
                         State::Closing => {
                             // Our FIN is now ACK'd, so enter TIME-WAIT.
-                            self.state.set(State::TimeWait);
+                            self.enter_time_wait(now);
                         },
                         State::LastAck => {
                             // Our FIN is now ACK'd, so this connection can be safely closed.  In LAST-ACK state we
                             // were just waiting for all of our sent data (including FIN) to be ACK'd, so now that it
                             // is, we can delete our state (we maintained it in case we needed to retransmit something,
                             // but we had already sent everything we're ever going to send (incl. FIN) at least once).
-                            self.state.set(State::Closed);
+                            self.enter_closed(CloseReason::Graceful);
 
                             // ToDo: Delete the ControlBlock.
                         },
@@ -644,6 +1411,19 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         } else {
             // Duplicate ACK (doesn't acknowledge anything new).  We can mostly ignore this, except for fast-retransmit.
             // ToDo: Implement fast-retransmit.  In which case, we'd increment our dup-ack counter here.
+
+            // It may still update our send window (SND.WND), as our peer sends a window update without any new data
+            // to acknowledge when its application reads data.  Missing such an update would leave us stalled until
+            // our next window probe.
+            if header.ack_num == send_unacknowledged {
+                self.sender.update_send_window(header);
+
+                // Only ACKs that arrive while we have data outstanding count as duplicates (RFC 5681 Section 2).
+                if send_unacknowledged != send_next {
+                    self.cc.borrow().on_dup_ack();
+                    self.congestion_control_sync();
+                }
+            }
         }
 
         // ToDo: Check the URG bit.  If we decide to support this, how should we do it?
@@ -663,11 +1443,11 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
                         if header.fin {
                             seg_len -= 1;
                             self.store_out_of_order_fin(seg_end);
-                            seg_end = seg_end - SeqNumber::from(1);
                         }
                         debug_assert_eq!(seg_len, data.len() as u32);
                         if seg_len > 0 {
-                            self.store_out_of_order_segment(seg_start, seg_end, data);
+                            self.record_reordering(seg_start - receive_next);
+                            self.receiver.push_out_of_order(seg_start, data);
                         }
                         // Sending an ACK here is only a "MAY" according to the RFCs, but helpful for fast retransmit.
                         self.send_ack();
@@ -685,6 +1465,10 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
             match self.state.get() {
                 State::Established | State::FinWait1 | State::FinWait2 => {
                     // We can only legitimately receive data in ESTABLISHED, FIN-WAIT-1, and FIN-WAIT-2.
+                    if header.psh && self.config.get_push_boundaries() {
+                        self.receiver
+                            .push_boundary(seg_start + SeqNumber::from(data.len() as u32));
+                    }
                     header.fin |= self.receive_data(seg_start, data);
                     should_schedule_ack = true;
                 },
@@ -701,25 +1485,31 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
             self.receiver
                 .receive_next
                 .set(self.receiver.receive_next.get() + SeqNumber::from(1));
+            self.receiver.fin_received.set(true);
+
+            // Wake up anyone waiting on the receive queue, so they can find out that no more data is coming.
+            if let Some(w) = self.waker.borrow_mut().take() {
+                w.wake()
+            }
 
             match self.state.get() {
-                State::Established => self.state.set(State::CloseWait),
+                State::Established => self.set_state(State::CloseWait),
                 State::FinWait1 => {
                     // RFC 793 has a benign logic flaw.  It says "If our FIN has been ACKed (perhaps in this segment),
                     // then enter TIME-WAIT, start the time-wait timer, turn off the other timers;".  But if our FIN
                     // has been ACK'd, we'd be in FIN-WAIT-2 here as a result of processing that ACK (see ACK handling
                     // above) and will enter TIME-WAIT in the FIN-WAIT-2 case below.  So we can skip that clause and go
                     // straight to "otherwise enter the CLOSING state".
-                    self.state.set(State::Closing);
+                    self.set_state(State::Closing);
                 },
                 State::FinWait2 => {
                     // Enter TIME-WAIT.
-                    self.state.set(State::TimeWait);
-                    // ToDo: Start the time-wait timer and turn off the other timers.
+                    self.enter_time_wait(now);
                 },
                 State::CloseWait | State::Closing | State::LastAck => (), // Remain in current state.
                 State::TimeWait => {
-                    // ToDo: Remain in TIME-WAIT.  Restart the 2 MSL time-wait timeout.
+                    // Remain in TIME-WAIT, but restart the 2 MSL time-wait timeout.
+                    self.enter_time_wait(now);
                 },
                 state => panic!("Bad TCP state {:?}", state), // Should never happen.
             }
@@ -730,13 +1520,30 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
             return;
         }
 
+        // If we dropped the end of this segment for not fitting in our receive window, tell our peer right away how much
+        // of it we took, so that it does not have to wait for its retransmission timer to send the rest.
+        if trimmed_beyond_window {
+            self.send_ack();
+            return;
+        }
+
         // Check if we need to ACK soon.
         if should_schedule_ack {
             // We should ACK this segment, preferably via piggybacking on a response.
             // ToDo: Consider replacing the delayed ACK timer with a simple flag.
             if self.ack_deadline.get().is_none() {
                 // Start the delayed ACK timer to ensure an ACK gets sent soon even if no piggyback opportunity occurs.
-                self.ack_deadline.set(Some(now + self.ack_delay_timeout));
+                //
+                // A PSH-terminated segment completes a message, and its sender may hold back its next sub-MSS segment
+                // until this one is acknowledged (Nagle's algorithm).  If we also delayed our ACK, each side would be
+                // waiting on the other until the delayed ACK timer expires.  So we ACK such segments as soon as the
+                // background acknowledger runs, which still leaves room to piggyback on an immediate response.
+                let ack_delay: Duration = if header.psh {
+                    Duration::from_secs(0)
+                } else {
+                    self.ack_delay()
+                };
+                self.ack_deadline.set(Some(now + ack_delay));
             } else {
                 // We already owe our peer an ACK (the timer was already running), so cancel the timer and ACK now.
                 self.ack_deadline.set(None);
@@ -805,26 +1612,46 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
 
     /// Transmit this message to our connected peer.
     ///
-    pub fn emit(&self, header: TcpHeader, data: Buffer, remote_link_addr: MacAddress) {
+    pub fn emit(&self, mut header: TcpHeader, data: Buffer, remote_link_addr: MacAddress) {
         debug!("Sending {} bytes + {:?}", data.len(), header);
 
         // This routine should only ever be called to send TCP segments that contain a valid ACK value.
         debug_assert!(header.ack);
 
         let sent_fin: bool = header.fin;
+        if !data.is_empty() {
+            self.last_activity.set(self.rt.now());
+        }
 
         // Prepare description of TCP segment to send.
         // ToDo: Change this to call lower levels to fill in their header information, handle routing, ARPing, etc.
+        let ipv4_hdr: Ipv4Header = Ipv4Header::new(self.local.ip().clone(), self.remote.ip().clone(), IpProtocol::TCP);
+        if self.negotiated.timestamps {
+            header.push_option(TcpOptions2::timestamp(
+                self.ts_started,
+                self.rt.now(),
+                self.ts_recent.get(),
+            ));
+        }
+        if let Some(key) = self.config.get_md5_key(self.remote.ip()) {
+            header.sign_md5(&ipv4_hdr, &data[..], key);
+        }
         let segment = TcpSegment {
-            ethernet2_hdr: Ethernet2Header::new(remote_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4),
-            ipv4_hdr: Ipv4Header::new(self.local.ip().clone(), self.remote.ip().clone(), IpProtocol::TCP),
+            ethernet2_hdr: Ethernet2Header::new(remote_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4)
+                .with_vlan_tag(self.arp.vlan_tag()),
+            ipv4_hdr,
             tcp_hdr: header,
             data,
             tx_checksum_offload: self.rt.tcp_options().get_tx_checksum_offload(),
         };
 
-        // Call the runtime to send the segment.
-        self.rt.transmit(segment);
+        // Call the runtime to send the segment, unless it is addressed to ourselves.
+        loopback::transmit(
+            self.rt.as_ref(),
+            &self.loopback,
+            self.config.get_transmit_hook(),
+            segment,
+        );
 
         // Post-send operations follow.
         // Review: We perform these after the send, in order to keep send latency as low as possible.
@@ -836,9 +1663,9 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         if sent_fin {
             match self.state.get() {
                 // Active close.
-                State::Established => self.state.set(State::FinWait1),
+                State::Established => self.set_state(State::FinWait1),
                 // Passive close.
-                State::CloseWait => self.state.set(State::LastAck),
+                State::CloseWait => self.set_state(State::LastAck),
                 // We can legitimately retransmit the FIN in these states.  And we stay there until the FIN is ACK'd.
                 State::FinWait1 | State::LastAck => {},
                 // We shouldn't be sending a FIN from any other state.
@@ -847,8 +1674,20 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         }
     }
 
+    /// Gets the maximum segment size that our peer advertised.
     pub fn remote_mss(&self) -> usize {
-        self.sender.remote_mss()
+        self.negotiated.remote_mss
+    }
+
+    /// Gets the maximum segment size that we advertised to our peer.
+    pub fn local_mss(&self) -> usize {
+        self.config.get_advertised_mss(&self.rt.tcp_options())
+    }
+
+    /// Gets the largest amount of data that we send in a segment, which is the maximum segment size of our peer once it
+    /// is clamped to our own limits.
+    pub fn effective_send_mss(&self) -> usize {
+        self.sender.get_mss()
     }
 
     pub fn get_ack_deadline(&self) -> (Option<Instant>, WatchFuture<Option<Instant>>) {
@@ -878,129 +1717,143 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         hdr_window_size
     }
 
+    /// Pops the next buffer of received data. Once our peer has closed its side of the connection and all data
+    /// received before that has been read, this returns an empty buffer to signal the end of the stream. Received
+    /// data never comes in empty buffers, so an empty buffer is always the end of the stream.
     pub fn poll_recv(&self, ctx: &mut Context) -> Poll<Result<Buffer, Fail>> {
-        // ToDo: Need to add a way to indicate that the other side closed (i.e. that we've received a FIN).
-        // Should we do this via a zero-sized buffer?  Same as with the unsent and unacked queues on the send side?
-        //
         // This code was checking for an empty receive queue by comparing sequence numbers, as in:
         //  if self.receiver.reader_next.get() == self.receiver.receive_next.get() {
         // But that will think data is available to be read once we've received a FIN, because FINs consume sequence
         // number space.  Now we call is_empty() on the receive queue instead.
-        if self.receiver.recv_queue.borrow().is_empty() {
+        if self.receiver.is_empty() {
+            if self.receiver.fin_received.get() {
+                return Poll::Ready(Ok(Buffer::Heap(DataBuffer::empty())));
+            }
+            *self.waker.borrow_mut() = Some(ctx.waker().clone());
+            return Poll::Pending;
+        }
+        if self.below_recv_lowat() {
             *self.waker.borrow_mut() = Some(ctx.waker().clone());
             return Poll::Pending;
         }
 
-        let segment: Buffer = self
-            .receiver
-            .pop()
-            .expect("poll_recv failed to pop data from receive queue");
+        let window_size: u32 = self.get_receive_window_size();
+        let segment: Option<Buffer> = if self.config.get_push_boundaries() {
+            self.receiver.pop_to_boundary()
+        } else {
+            self.receiver.pop(self.get_recv_lowat())
+        };
+        let segment: Buffer = segment.expect("poll_recv failed to pop data from receive queue");
+        self.send_window_update(window_size);
 
         Poll::Ready(Ok(segment))
     }
 
-    // This routine remembers that we have received an out-of-order FIN.
-    //
-    pub fn store_out_of_order_fin(&self, fin: SeqNumber) {
-        self.out_of_order_fin.set(Some(fin));
+    /// Completes once all data that was pushed has been sent and acknowledged by our peer, or fails if the connection
+    /// is closed before that.
+    pub fn poll_flush(&self, ctx: &mut Context) -> Poll<Result<(), Fail>> {
+        if self.sender.is_flushed() {
+            return Poll::Ready(Ok(()));
+        }
+        if self.state.get() == State::Closed {
+            return Poll::Ready(Err(Fail::new(ENOTCONN, "connection closed")));
+        }
+        let mut flush_wakers = self.flush_wakers.borrow_mut();
+        if !flush_wakers.iter().any(|w| w.will_wake(ctx.waker())) {
+            flush_wakers.push(ctx.waker().clone());
+        }
+        Poll::Pending
     }
 
-    // This routine takes an incoming TCP segment and adds it to the out-of-order receive queue.
-    // If the new segment had a FIN it has been removed prior to this routine being called.
-    // Note: Since this is not the "fast path", this is written for clarity over efficiency.
-    //
-    pub fn store_out_of_order_segment(&self, mut new_start: SeqNumber, mut new_end: SeqNumber, mut buf: Buffer) {
-        let mut out_of_order = self.out_of_order.borrow_mut();
-        let mut action_index: usize = out_of_order.len();
-        let mut another_pass_neeeded: bool = true;
+    // Wake all tasks that are waiting for pushed data to be acknowledged, so that they check again.
+    fn wake_flushes(&self) {
+        for w in self.flush_wakers.borrow_mut().drain(..) {
+            w.wake()
+        }
+    }
 
-        while another_pass_neeeded {
-            another_pass_neeeded = false;
+    /// Like [Self::poll_recv], but copies received data into `buf` rather than handing out the buffers it arrived in.
+    /// The end of the stream is signaled by reading zero bytes into a non-empty `buf`.
+    pub fn poll_recv_into(&self, buf: &mut [u8], ctx: &mut Context) -> Poll<Result<usize, Fail>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
 
-            // Find the new segment's place in the out-of-order store.
-            // The out-of-order store is sorted by starting sequence number, and contains no duplicate data.
-            action_index = out_of_order.len();
-            for index in 0..out_of_order.len() {
-                let stored_segment: &(SeqNumber, Buffer) = &out_of_order[index];
+        if self.receiver.is_empty() {
+            if self.receiver.fin_received.get() {
+                return Poll::Ready(Ok(0));
+            }
+            *self.waker.borrow_mut() = Some(ctx.waker().clone());
+            return Poll::Pending;
+        }
+        if self.below_recv_lowat() {
+            *self.waker.borrow_mut() = Some(ctx.waker().clone());
+            return Poll::Pending;
+        }
 
-                // Properties of the segment stored at this index.
-                let stored_start: SeqNumber = stored_segment.0;
-                let stored_len: u32 = stored_segment.1.len() as u32;
-                debug_assert_ne!(stored_len, 0);
-                let stored_end: SeqNumber = stored_start + SeqNumber::from(stored_len - 1);
+        let window_size: u32 = self.get_receive_window_size();
+        let nbytes: usize = self.receiver.pop_into(buf);
+        self.send_window_update(window_size);
 
-                //
-                // The new data segment has six possibilites when compared to an existing out-of-order segment:
-                //
-                //                                |<- out-of-order segment ->|
-                //
-                // |<- new before->|    |<- new front overlap ->|    |<- new end overlap ->|    |<- new after ->|
-                //                                   |<- new duplicate ->|
-                //                            |<- new completely encompassing ->|
-                //
-                if new_start < stored_start {
-                    // The new segment starts before the start of this out-of-order segment.
-                    if new_end < stored_start {
-                        // The new segment comes completely before this out-of-order segment.
-                        // Since the out-of-order store is sorted, we don't need to check for overlap with any more.
-                        action_index = index;
-                        break;
-                    }
-                    // The end of the new segment overlaps with the start of this out-of-order segment.
-                    if stored_end < new_end {
-                        // The new segment ends after the end of this out-of-order segment.  In other words, the new
-                        // segment completely encompasses the out-of-order segment.
-
-                        // Set flags to remove the currently stored segment and re-run the insertion loop, as the
-                        // new segment may completely encompass even more segments.
-                        another_pass_neeeded = true;
-                        action_index = index;
-                        break;
-                    }
-                    // We have some data overlap between the new segment and the front of the out-of-order segment.
-                    // Trim the end of the new segment and stop checking for out-of-order overlap.
-                    let excess: u32 = u32::from(new_end - stored_start) + 1;
-                    new_end = new_end - SeqNumber::from(excess);
-                    buf.trim(excess as usize);
-                    break;
-                } else {
-                    // The new segment starts at or after the start of this out-of-order segment.
-                    // This is the stored_start <= new_start case.
-                    if new_end <= stored_end {
-                        // And the new segment ends at or before this out-of-order segment.
-                        // The new segment's data is a complete duplicate of this out-of-order segment's data.
-                        // Just drop the new segment.
-                        return;
-                    }
-                    if stored_end < new_start {
-                        // The new segment comes entirely after this out-of-order segment.
-                        // Continue to check the next out-of-order segment for potential overlap.
-                        continue;
-                    }
-                    // We have some data overlap between the new segment and the end of the out-of-order segment.
-                    // Adjust the beginning of the new segment and continue on to check the next out-of-order segment.
-                    let duplicate: u32 = u32::from(stored_end - new_start);
-                    new_start = new_start + SeqNumber::from(duplicate);
-                    buf.adjust(duplicate as usize);
-                    continue;
-                }
-            }
+        Poll::Ready(Ok(nbytes))
+    }
+
+    // Get the least amount of received data that completes a receive.  This never exceeds the receive buffer, as more
+    // data than that would never arrive.
+    fn get_recv_lowat(&self) -> usize {
+        cmp::min(self.config.get_recv_lowat(), self.receive_buffer_size as usize)
+    }
+
+    // Check if receives should keep waiting for data, as there is less than the low-water mark and more is going to
+    // arrive.
+    fn below_recv_lowat(&self) -> bool {
+        self.receiver.len() < self.get_recv_lowat() && !self.receiver.fin_received.get()
+    }
+
+    // Tells our peer right away that reading data has reopened a receive window that was (nearly) closed, given the
+    // window size before the read.  Otherwise our peer would stall until the next ACK or window probe.  Following the
+    // receiver side of SWS avoidance (RFC 1122 Section 4.2.3.3), the window needs to grow to at least the smaller of one
+    // MSS and half of the receive buffer.
+    fn send_window_update(&self, window_size: u32) {
+        if self.receiver.fin_received.get() {
+            return;
+        }
+        let threshold: u32 = cmp::min(self.get_mss() as u32, self.receive_buffer_size / 2);
+        if window_size < threshold && self.get_receive_window_size() >= threshold {
+            self.send_ack();
+        }
+    }
 
-            if another_pass_neeeded {
-                // The new segment completely encompassed an existing segment, which we will now remove.
-                out_of_order.remove(action_index);
+    /// Copies received data into `buf` without consuming it, so that it is returned again by the next receive.
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize, Fail> {
+        if self.receiver.is_empty() {
+            if self.receiver.fin_received.get() {
+                return Ok(0);
             }
+            return Err(Fail::new(EAGAIN, "no data available"));
         }
 
-        // Insert the new segment into the correct position.
-        out_of_order.insert(action_index, (new_start, buf));
+        Ok(self.receiver.peek(buf))
+    }
 
-        // If the out-of-order store now contains too many entries, delete the later entries.
-        // ToDo: The out-of-order store is already limited (in size) by our receive window, while the below check
-        // imposes a limit on the number of entries.  Do we need this?  Presumably for attack mitigation?
-        while out_of_order.len() > MAX_OUT_OF_ORDER {
-            out_of_order.pop_back();
-        }
+    /// Checks if our peer has closed its side of the connection and all data that it sent has been read, so that
+    /// receives only return the end of the stream. Our side of the connection may still be open.
+    pub fn is_read_closed(&self) -> bool {
+        self.receiver.fin_received.get() && self.receiver.is_empty()
+    }
+
+    // Account a segment carrying data that arrived `distance` bytes ahead of RCV.NXT.
+    fn record_reordering(&self, distance: SeqNumber) {
+        let mut stats: ConnectionStats = self.stats.get();
+        stats.out_of_order_segments += 1;
+        stats.max_reorder_distance = stats.max_reorder_distance.max(distance.into());
+        self.stats.set(stats);
+    }
+
+    // This routine remembers that we have received an out-of-order FIN.
+    //
+    pub fn store_out_of_order_fin(&self, fin: SeqNumber) {
+        self.out_of_order_fin.set(Some(fin));
     }
 
     // This routine takes an incoming in-order TCP segment and adds the data to the user's receive queue.  If the new
@@ -1012,36 +1865,16 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
     // Returns true if a previously out-of-order segment containing a FIN has now been received.
     //
     pub fn receive_data(&self, seg_start: SeqNumber, buf: Buffer) -> bool {
-        let recv_next: SeqNumber = self.receiver.receive_next.get();
-
         // This routine should only be called with in-order segment data.
-        debug_assert_eq!(seg_start, recv_next);
+        debug_assert_eq!(seg_start, self.receiver.receive_next.get());
 
         // Push the new segment data onto the end of the receive queue.
-        let mut recv_next: SeqNumber = recv_next + SeqNumber::from(buf.len() as u32);
         self.receiver.push(buf);
 
         // Okay, we've successfully received some new data.  Check if any of the formerly out-of-order data waiting in
-        // the out-of-order queue is now in-order.  If so, we can move it to the receive queue.
-        let mut added_out_of_order: bool = false;
-        let mut out_of_order = self.out_of_order.borrow_mut();
-        while !out_of_order.is_empty() {
-            if let Some(stored_entry) = out_of_order.front() {
-                if stored_entry.0 == recv_next {
-                    // Move this entry's buffer from the out-of-order store to the receive queue.
-                    // This data is now considered to be "received" by TCP, and included in our RCV.NXT calculation.
-                    debug!("Recovering out-of-order packet at {}", recv_next);
-                    if let Some(temp) = out_of_order.pop_front() {
-                        recv_next = recv_next + SeqNumber::from(temp.1.len() as u32);
-                        self.receiver.push(temp.1);
-                        added_out_of_order = true;
-                    }
-                } else {
-                    // Since our out-of-order list is sorted, we can stop when the next segment is not in sequence.
-                    break;
-                }
-            }
-        }
+        // the reassembly area is now in-order.  If so, we can move it to the receive queue.
+        // This data is now considered to be "received" by TCP, and included in our RCV.NXT calculation.
+        let added_out_of_order: bool = self.receiver.recover_out_of_order();
 
         // ToDo: Review recent change to update control block copy of recv_next upon each push to the receiver.
         // When receiving a retransmitted segment that fills a "hole" in the receive space, thus allowing a number
@@ -1052,7 +1885,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         // self.receive_next.set(recv_next);
 
         // This appears to be checking if something is waiting on the receive queue, and if so, wakes that thing up.
-        // Note: unlike updating receive_next (see above comment) we only do this once (i.e. outside the while loop).
+        // Note: unlike updating receive_next (see above comment) we only do this once, however much data was recovered.
         // ToDo: Verify that this is the right place and time to do this.
         if let Some(w) = self.waker.borrow_mut().take() {
             w.wake()
@@ -1063,7 +1896,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ControlBlock<RT> {
         if added_out_of_order {
             match self.out_of_order_fin.get() {
                 Some(fin) => {
-                    debug_assert_eq!(fin, recv_next);
+                    debug_assert_eq!(fin, self.receiver.receive_next.get());
                     return true;
                 },
                 _ => (),
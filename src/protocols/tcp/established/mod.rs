@@ -6,16 +6,34 @@ pub mod congestion_control;
 mod ctrlblk;
 mod rto;
 mod sender;
+mod stream;
+mod token_bucket;
 
-pub use self::ctrlblk::{
-    ControlBlock,
-    State,
+pub use self::{
+    ctrlblk::{
+        CloseReason,
+        ConnectionParams,
+        ConnectionStats,
+        ControlBlock,
+        State,
+        StateDurations,
+    },
+    stream::TcpStream,
 };
 
-use self::background::background;
+use self::{
+    background::background,
+    congestion_control::{
+        CongestionControlConstructor,
+        CongestionPhase,
+    },
+};
 use crate::{
     futures::FutureOperation,
-    protocols::tcp::segment::TcpHeader,
+    protocols::tcp::{
+        segment::TcpHeader,
+        SeqNumber,
+    },
 };
 use ::futures::{
     channel::mpsc,
@@ -39,12 +57,13 @@ use ::std::{
     time::Duration,
 };
 
+#[derive(Clone)]
 pub struct EstablishedSocket<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     pub cb: Rc<ControlBlock<RT>>,
     /// The background co-routines handles various tasks, such as retransmission and acknowledging.
     /// We annotate it as unused because the compiler believes that it is never called which is not the case.
     #[allow(unused)]
-    background: SchedulerHandle,
+    background: Rc<SchedulerHandle>,
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> EstablishedSocket<RT> {
@@ -54,7 +73,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> EstablishedSocket<
         let handle: SchedulerHandle = cb.rt().spawn(FutureOperation::Background::<RT>(future.boxed_local()));
         Self {
             cb: cb.clone(),
-            background: handle,
+            background: Rc::new(handle),
         }
     }
 
@@ -66,23 +85,113 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> EstablishedSocket<
         self.cb.send(buf)
     }
 
+    /// Sends the elements of a scatter-gather array without copying them.
+    pub fn send_sga(&self, sga: Vec<Buffer>) -> Result<(), Fail> {
+        self.cb.send_sga(sga)
+    }
+
+    pub fn poll_flush(&self, ctx: &mut Context) -> Poll<Result<(), Fail>> {
+        self.cb.poll_flush(ctx)
+    }
+
     pub fn poll_recv(&self, ctx: &mut Context) -> Poll<Result<Buffer, Fail>> {
         self.cb.poll_recv(ctx)
     }
 
+    pub fn poll_recv_into(&self, buf: &mut [u8], ctx: &mut Context) -> Poll<Result<usize, Fail>> {
+        self.cb.poll_recv_into(buf, ctx)
+    }
+
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize, Fail> {
+        self.cb.peek(buf)
+    }
+
     pub fn close(&self) -> Result<(), Fail> {
         self.cb.close()
     }
 
+    /// Checks if our peer has closed its side of this connection and all data that it sent has been read.
+    pub fn is_read_closed(&self) -> bool {
+        self.cb.is_read_closed()
+    }
+
+    /// Gets the maximum segment size that our peer advertised.
     pub fn remote_mss(&self) -> usize {
         self.cb.remote_mss()
     }
 
+    /// Gets the maximum segment size that we advertised to our peer.
+    pub fn local_mss(&self) -> usize {
+        self.cb.local_mss()
+    }
+
+    /// Gets the largest amount of data that we send in a segment.
+    pub fn effective_send_mss(&self) -> usize {
+        self.cb.effective_send_mss()
+    }
+
     pub fn current_rto(&self) -> Duration {
         self.cb.rto_estimate()
     }
 
+    /// Gets the phase that the congestion control algorithm of this connection is in.
+    pub fn cc_phase(&self) -> CongestionPhase {
+        self.cb.congestion_control_get_phase()
+    }
+
+    /// Replaces the congestion control algorithm of this connection.
+    pub fn set_congestion_control(&self, constructor: CongestionControlConstructor<RT>) {
+        self.cb.set_congestion_control(constructor)
+    }
+
+    pub fn negotiated(&self) -> ConnectionParams {
+        self.cb.get_negotiated()
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.cb.get_stats()
+    }
+
     pub fn endpoints(&self) -> (SocketAddrV4, SocketAddrV4) {
         (self.cb.get_local(), self.cb.get_remote())
     }
+
+    pub fn state(&self) -> State {
+        self.cb.get_state()
+    }
+
+    pub fn send_next(&self) -> SeqNumber {
+        self.cb.get_send_next().0
+    }
+
+    /// Gets the reason why this connection was closed, if it is.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.cb.get_close_reason()
+    }
+
+    /// Takes the reason why this connection was closed, if it is, clearing it.
+    pub fn take_close_reason(&self) -> Option<CloseReason> {
+        self.cb.take_close_reason()
+    }
+
+    /// Gets the time from our SYN to its SYN+ACK, if we opened this connection actively. This is measured once, unlike
+    /// the smoothed round-trip time.
+    pub fn handshake_rtt(&self) -> Option<Duration> {
+        self.cb.get_handshake_rtt()
+    }
+
+    /// Gets the time that this connection has spent in each state so far.
+    pub fn state_durations(&self) -> StateDurations {
+        self.cb.get_state_durations()
+    }
+
+    /// Takes the last non-fatal error on this connection, if any, like `SO_ERROR`.
+    pub fn take_error(&self) -> Option<Fail> {
+        self.cb.take_error()
+    }
+
+    /// Records a non-fatal error on this connection, which does not fail it.
+    pub fn record_soft_error(&self, error: Fail) {
+        self.cb.record_soft_error(error)
+    }
 }
@@ -13,16 +13,19 @@ pub struct RtoCalculator {
     srtt: f64,
     rttvar: f64,
     rto: f64,
+    /// Lower bound of the retransmission timeout (in seconds).
+    lbound: f64,
 
     received_sample: bool,
 }
 
 impl RtoCalculator {
-    pub fn new() -> Self {
+    pub fn new(min_rto: Duration) -> Self {
         Self {
             srtt: 1.0,
             rttvar: 0.0,
             rto: 1.0,
+            lbound: FloatDuration::from(min_rto).as_seconds(),
 
             received_sample: false,
         }
@@ -54,9 +57,8 @@ impl RtoCalculator {
 
     fn update_rto(&mut self, new_rto: f64) {
         const UBOUND_SEC: f64 = 60.0f64;
-        const LBOUND_SEC: f64 = 0.100f64;
-        self.rto = match (new_rto.partial_cmp(&LBOUND_SEC), new_rto.partial_cmp(&UBOUND_SEC)) {
-            (Some(cmp::Ordering::Less), _) => LBOUND_SEC,
+        self.rto = match (new_rto.partial_cmp(&self.lbound), new_rto.partial_cmp(&UBOUND_SEC)) {
+            (Some(cmp::Ordering::Less), _) => self.lbound,
             (_, Some(cmp::Ordering::Greater)) => UBOUND_SEC,
             (None, _) | (_, None) => panic!("NaN RTO: {:?}", new_rto),
             _ => new_rto,
@@ -70,4 +72,13 @@ impl RtoCalculator {
     pub fn estimate(&self) -> Duration {
         FloatDuration::seconds(self.rto).to_std().unwrap()
     }
+
+    /// Checks if a round-trip time has been measured, so that the smoothed round-trip time is not just a guess.
+    pub fn has_sample(&self) -> bool {
+        self.received_sample
+    }
+
+    pub fn srtt(&self) -> Duration {
+        FloatDuration::seconds(self.srtt).to_std().unwrap()
+    }
 }
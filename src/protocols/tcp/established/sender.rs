@@ -25,6 +25,7 @@ use ::std::{
         Cell,
         RefCell,
     },
+    cmp,
     collections::VecDeque,
     convert::TryInto,
     fmt,
@@ -42,6 +43,84 @@ pub struct UnackedSegment {
     pub bytes: Buffer,
     // Set to `None` on retransmission to implement Karn's algorithm.
     pub initial_tx: Option<Instant>,
+    // Time at which this segment was first sent.  Unlike `initial_tx`, this is kept across retransmissions.
+    pub first_tx: Instant,
+}
+
+// Retransmission queue.  Holds the segments that we have sent but our peer has not acknowledged yet, in sequence number
+// order.  Sequence numbers wrap around, so they cannot be used as keys of a sorted map.  Instead, segments are looked up
+// by their offset from the oldest one, which does increase along the queue.
+//
+struct RetransmitQueue {
+    // Segments along with the sequence number of their first byte.
+    segments: VecDeque<(SeqNumber, UnackedSegment)>,
+    // Sequence number that follows the newest segment.
+    next_seq_no: SeqNumber,
+}
+
+impl RetransmitQueue {
+    fn new(seq_no: SeqNumber) -> Self {
+        Self {
+            segments: VecDeque::new(),
+            next_seq_no: seq_no,
+        }
+    }
+
+    // Get the amount of sequence space that a segment occupies.  The end-of-send marker carries no data, but occupies
+    // one sequence number for our FIN.
+    fn seq_len(segment: &UnackedSegment) -> u32 {
+        cmp::max(segment.bytes.len(), 1) as u32
+    }
+
+    // Add a segment that was sent right after the newest one.
+    fn push(&mut self, segment: UnackedSegment) {
+        let seq_no: SeqNumber = self.next_seq_no;
+        self.next_seq_no = seq_no + SeqNumber::from(Self::seq_len(&segment));
+        self.segments.push_back((seq_no, segment));
+    }
+
+    // Remove the oldest segment.
+    fn pop(&mut self) -> Option<UnackedSegment> {
+        let (_, segment) = self.segments.pop_front()?;
+        Some(segment)
+    }
+
+    // Find the segment that covers a sequence number, along with the sequence number of its first byte.
+    fn find_mut(&mut self, seq_no: SeqNumber) -> Option<(SeqNumber, &mut UnackedSegment)> {
+        let first: SeqNumber = self.segments.front()?.0;
+        let offset: u32 = (seq_no - first).into();
+        // Sequence numbers before the oldest segment have an offset past all segments, so they are not found either.
+        let index: usize = self
+            .segments
+            .partition_point(|(start, segment)| u32::from(*start - first) + Self::seq_len(segment) <= offset);
+        let (start, segment) = self.segments.get_mut(index)?;
+        Some((*start, segment))
+    }
+
+    // Remove the data acknowledged by a cumulative ACK.  A segment that is only partly acknowledged is trimmed down to
+    // its unacknowledged data.  Every segment that is at least partly acknowledged is first handed to `on_acked`.
+    fn remove_acknowledged(&mut self, ack_num: SeqNumber, mut on_acked: impl FnMut(&UnackedSegment)) {
+        debug_assert!(ack_num <= self.next_seq_no);
+        while let Some((start, segment)) = self.segments.front_mut() {
+            if ack_num <= *start {
+                break;
+            }
+            on_acked(segment);
+
+            let bytes_acknowledged: u32 = (ack_num - *start).into();
+            if bytes_acknowledged < Self::seq_len(segment) {
+                // Only some of the data in this segment has been acked.  Remove just the acked amount, and leave this
+                // segment on the queue.
+                segment.bytes.adjust(bytes_acknowledged as usize);
+                segment.initial_tx = None;
+                *start = ack_num;
+                break;
+            }
+
+            // ToDo: Mark the send operation associated with this buffer as complete, so the user can reuse the buffer.
+            self.segments.pop_front();
+        }
+    }
 }
 
 /// Hard limit for unsent queue.
@@ -69,7 +148,7 @@ pub struct Sender {
     pub send_unacked: WatchedValue<SeqNumber>,
 
     // Queue of unacknowledged sent data.  RFC 793 calls this the "retransmission queue".
-    unacked_queue: RefCell<VecDeque<UnackedSegment>>,
+    unacked_queue: RefCell<RetransmitQueue>,
 
     // Sequence Number of the next data to be sent.  In RFC 793 terms, this is SND.NXT.
     send_next: WatchedValue<SeqNumber>,
@@ -110,7 +189,7 @@ impl Sender {
     pub fn new(seq_no: SeqNumber, send_window: u32, window_scale: u8, mss: usize) -> Self {
         Self {
             send_unacked: WatchedValue::new(seq_no),
-            unacked_queue: RefCell::new(VecDeque::new()),
+            unacked_queue: RefCell::new(RetransmitQueue::new(seq_no)),
             send_next: WatchedValue::new(seq_no),
             unsent_queue: RefCell::new(VecDeque::new()),
             unsent_seq_no: WatchedValue::new(seq_no),
@@ -149,11 +228,28 @@ impl Sender {
     }
 
     pub fn pop_unacked_segment(&self) -> Option<UnackedSegment> {
-        self.unacked_queue.borrow_mut().pop_front()
+        self.unacked_queue.borrow_mut().pop()
     }
 
     pub fn push_unacked_segment(&self, segment: UnackedSegment) {
-        self.unacked_queue.borrow_mut().push_back(segment)
+        self.unacked_queue.borrow_mut().push(segment)
+    }
+
+    // Get the time at which the oldest unacknowledged segment was first sent, if there is any.
+    pub fn get_oldest_unacked_tx(&self) -> Option<Instant> {
+        let unacked_queue = self.unacked_queue.borrow();
+        let (_, segment): &(SeqNumber, UnackedSegment) = unacked_queue.segments.front()?;
+        Some(segment.first_tx)
+    }
+
+    // Get the unacknowledged segment that covers a sequence number, along with the sequence number of its first byte,
+    // so it can be retransmitted.  The segment stays on the unacknowledged queue until it is acknowledged, but it is no
+    // longer used for RTT estimation (Karn's algorithm).
+    pub fn peek_unacked_segment(&self, seq_no: SeqNumber) -> Option<(SeqNumber, Buffer)> {
+        let mut unacked_queue = self.unacked_queue.borrow_mut();
+        let (start, segment): (SeqNumber, &mut UnackedSegment) = unacked_queue.find_mut(seq_no)?;
+        segment.initial_tx = None;
+        Some((start, segment.bytes.clone()))
     }
 
     // This is the main TCP send routine.
@@ -197,8 +293,12 @@ impl Sender {
         // it on the unsent queue and that's it.
         //
 
-        // Check for unsent data.
-        if self.unsent_queue.borrow().is_empty() {
+        // Check for unsent data.  When pacing or rate limiting, all data goes through the background sender, so that it
+        // can be spaced out.
+        if self.unsent_queue.borrow().is_empty()
+            && !cb.get_config().get_pacing()
+            && cb.get_config().get_rate_limit().is_none()
+        {
             // No unsent data queued up, so we can try to send this new buffer immediately.
 
             // Calculate amount of data in flight (SND.NXT - SND.UNA).
@@ -206,7 +306,6 @@ impl Sender {
             let send_next: SeqNumber = self.send_next.get();
             let sent_data: u32 = (send_next - send_unacknowledged).into();
 
-            // ToDo: What limits buffer len to MSS?
             let in_flight_after_send: u32 = sent_data + buf_len;
 
             // Before we get cwnd for the check, we prompt it to shrink it if the connection has been idle.
@@ -218,16 +317,23 @@ impl Sender {
 
             let win_sz: u32 = self.send_window.get();
 
-            if win_sz > 0 && win_sz >= in_flight_after_send && effective_cwnd >= in_flight_after_send {
+            // A buffer larger than the MSS is left for the background sender to split into segments.
+            if win_sz > 0
+                && win_sz >= in_flight_after_send
+                && effective_cwnd >= in_flight_after_send
+                && buf_len as usize <= cb.get_mss()
+            {
                 if let Some(remote_link_addr) = cb.arp().try_query(cb.get_remote().ip().clone()) {
                     // This hook is primarily intended to record the last time we sent data, so we can later tell if
                     // the connection has been idle.
                     let rto: Duration = cb.rto_estimate();
-                    cb.congestion_control_on_send(rto, sent_data);
+                    cb.congestion_control_on_send(rto, buf_len);
 
                     // Prepare the segment and send it.
                     let mut header: TcpHeader = cb.tcp_header();
                     header.seq_num = send_next;
+                    // This segment carries the whole buffer, so it ends the message.
+                    header.psh = buf_len != 0;
                     if buf_len == 0 {
                         // This buffer is the end-of-send marker.
                         // Set FIN and adjust sequence number consumption accordingly.
@@ -247,8 +353,9 @@ impl Sender {
                     let unacked_segment = UnackedSegment {
                         bytes: buf,
                         initial_tx: Some(cb.rt().now()),
+                        first_tx: cb.rt().now(),
                     };
-                    self.unacked_queue.borrow_mut().push_back(unacked_segment);
+                    self.unacked_queue.borrow_mut().push(unacked_segment);
 
                     // Start the retransmission timer if it isn't already running.
                     if cb.get_retransmit_deadline().is_none() {
@@ -277,50 +384,68 @@ impl Sender {
         Ok(())
     }
 
-    // Remove acknowledged data from the unacknowledged (a.k.a. retransmission) queue.
+    // Sends the elements of a scatter-gather array as one message, without copying them into a contiguous buffer.  The
+    // elements are queued for the background sender, which cuts segments straight out of them, so that both the
+    // segments that it emits and the copies that it keeps on the retransmission queue reference the memory of the
+    // application until it is acknowledged.  Segments do not span elements, and only the last one is pushed.
     //
-    pub fn remove_acknowledged_data<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
+    pub fn send_sga<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
         &self,
+        sga: Vec<Buffer>,
         cb: &ControlBlock<RT>,
-        bytes_acknowledged: u32,
-        now: Instant,
-    ) {
-        let mut bytes_remaining: usize = bytes_acknowledged as usize;
-
-        while bytes_remaining != 0 {
-            if let Some(segment) = self.unacked_queue.borrow_mut().front_mut() {
-                // Add sample for RTO if we have an initial transmit time.
-                // Note that in the case of repacketization, an ack for the first byte is enough for the time sample.
-                // ToDo: TCP timestamp support.
-                if let Some(initial_tx) = segment.initial_tx {
-                    cb.rto_add_sample(now - initial_tx);
-                }
+    ) -> Result<(), Fail> {
+        if cb.user_is_done_sending.get() {
+            return Err(Fail::new(EINVAL, "Connection is closing"));
+        }
 
-                if segment.bytes.len() > bytes_remaining {
-                    // Only some of the data in this segment has been acked.  Remove just the acked amount.
-                    segment.bytes.adjust(bytes_remaining);
-                    segment.initial_tx = None;
+        // An empty buffer on the unsent queue is the end-of-send marker, so empty elements may not be queued.
+        if sga.is_empty() || sga.iter().any(|buf| buf.is_empty()) {
+            return Err(Fail::new(EINVAL, "zero-length buffer"));
+        }
+        let sga_len: u32 = sga
+            .iter()
+            .map(|buf| buf.len())
+            .sum::<usize>()
+            .try_into()
+            .map_err(|_| Fail::new(EINVAL, "buffer too large"))?;
 
-                    // Leave this segment on the unacknowledged queue.
-                    break;
-                }
+        // Too fast.
+        if self.unsent_queue.borrow().len() > UNSENT_QUEUE_CUTOFF {
+            return Err(Fail::new(EBUSY, "too many packets to send"));
+        }
 
-                if segment.bytes.len() == 0 {
-                    // This buffer is the end-of-send marker.  So we should only have one byte of acknowledged sequence
-                    // space remaining (corresponding to our FIN).
-                    debug_assert_eq!(bytes_remaining, 1);
-                    bytes_remaining = 0;
-                }
+        trace!("Queueing scatter-gather send for background processing");
+        self.unsent_queue.borrow_mut().extend(sga);
+        self.unsent_seq_no.modify(|s| s + SeqNumber::from(sga_len));
+
+        Ok(())
+    }
 
-                bytes_remaining -= segment.bytes.len();
-            } else {
-                debug_assert!(false); // Shouldn't have bytes_remaining with no segments remaining in unacked_queue.
+    // Remove acknowledged data from the unacknowledged (a.k.a. retransmission) queue.  Returns the most recent
+    // round-trip time sample taken from the acknowledged segments, if any.
+    //
+    pub fn remove_acknowledged_data<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static>(
+        &self,
+        cb: &ControlBlock<RT>,
+        ack_num: SeqNumber,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut rtt: Option<Duration> = None;
+        self.unacked_queue.borrow_mut().remove_acknowledged(ack_num, |segment| {
+            // Add sample for RTO if we have an initial transmit time.
+            // Note that in the case of repacketization, an ack for the first byte is enough for the time sample.
+            // ToDo: TCP timestamp support.
+            if let Some(initial_tx) = segment.initial_tx {
+                cb.rto_add_sample(now - initial_tx);
+                rtt = Some(now - initial_tx);
             }
+        });
+        rtt
+    }
 
-            // Remove this segment from the unacknowledged queue.
-            // ToDo: Mark the send operation associated with this buffer as complete, so the user can reuse the buffer.
-            self.unacked_queue.borrow_mut().pop_front();
-        }
+    // Check whether all data that the user pushed has been sent and acknowledged.
+    pub fn is_flushed(&self) -> bool {
+        self.send_unacked.get() == self.unsent_seq_no.get()
     }
 
     pub fn pop_one_unsent_byte(&self) -> Option<Buffer> {
@@ -368,8 +493,12 @@ impl Sender {
             || (self.send_window_last_update_seq.get() == header.seq_num
                 && self.send_window_last_update_ack.get() <= header.ack_num)
         {
-            // Update our send window.
-            self.send_window.set((header.window_size as u32) << self.window_scale);
+            // Update our send window.  Only wake up those watching it on an actual change, as our peer repeats its
+            // window in every ACK, including those that answer window probes.
+            let send_window: u32 = (header.window_size as u32) << self.window_scale;
+            if self.send_window.get() != send_window {
+                self.send_window.set(send_window);
+            }
             self.send_window_last_update_seq.set(header.seq_num);
             self.send_window_last_update_ack.set(header.ack_num);
         }
@@ -381,8 +510,102 @@ impl Sender {
             self.window_scale
         );
     }
+}
 
-    pub fn remote_mss(&self) -> usize {
-        self.mss
+// Unit tests for the retransmission queue.
+#[cfg(test)]
+mod tests {
+    use super::{
+        RetransmitQueue,
+        UnackedSegment,
+    };
+    use crate::protocols::tcp::SeqNumber;
+    use ::runtime::memory::{
+        Buffer,
+        DataBuffer,
+    };
+    use ::std::time::Instant;
+
+    // Build a retransmission queue holding data segments of the given sizes, followed by our FIN.
+    fn build_queue(seq_no: SeqNumber, sizes: &[usize]) -> RetransmitQueue {
+        let mut queue: RetransmitQueue = RetransmitQueue::new(seq_no);
+        for size in sizes {
+            queue.push(UnackedSegment {
+                bytes: Buffer::Heap(DataBuffer::new(*size).unwrap()),
+                initial_tx: Some(Instant::now()),
+                first_tx: Instant::now(),
+            });
+        }
+        queue.push(UnackedSegment {
+            bytes: Buffer::Heap(DataBuffer::empty()),
+            initial_tx: Some(Instant::now()),
+            first_tx: Instant::now(),
+        });
+        queue
+    }
+
+    // Test that cumulative ACKs remove the acknowledged prefix of the queue.
+    #[test]
+    fn remove_acknowledged() {
+        // Start right before the sequence space wraps around.
+        let seq_no: SeqNumber = SeqNumber::from(u32::MAX - 50);
+        let mut queue: RetransmitQueue = build_queue(seq_no, &[100, 100, 100]);
+
+        // An ACK for data that has already been acknowledged removes nothing.
+        let mut num_acked: usize = 0;
+        queue.remove_acknowledged(seq_no, |_| num_acked += 1);
+        assert_eq!(num_acked, 0);
+        assert_eq!(queue.segments.len(), 4);
+
+        // Acknowledge the first segment and half of the second one.
+        let ack_num: SeqNumber = seq_no + SeqNumber::from(150);
+        queue.remove_acknowledged(ack_num, |_| num_acked += 1);
+        assert_eq!(num_acked, 2);
+        assert_eq!(queue.segments.len(), 3);
+        let (start, segment) = queue.segments.front().unwrap();
+        assert_eq!(*start, ack_num);
+        assert_eq!(segment.bytes.len(), 50);
+        assert!(segment.initial_tx.is_none());
+
+        // Acknowledge the remaining data, but not our FIN.
+        let ack_num: SeqNumber = seq_no + SeqNumber::from(300);
+        queue.remove_acknowledged(ack_num, |_| ());
+        assert_eq!(queue.segments.len(), 1);
+        assert_eq!(queue.segments.front().unwrap().0, ack_num);
+
+        // Acknowledge our FIN.
+        queue.remove_acknowledged(ack_num + SeqNumber::from(1), |_| ());
+        assert!(queue.segments.is_empty());
+    }
+
+    // Test that segments are found by any of the sequence numbers they cover.
+    #[test]
+    fn find_segment() {
+        let seq_no: SeqNumber = SeqNumber::from(u32::MAX - 50);
+        let mut queue: RetransmitQueue = build_queue(seq_no, &[100, 200, 1]);
+
+        for (offset, expected) in [
+            (0, 0),
+            (99, 0),
+            (100, 100),
+            (250, 100),
+            (299, 100),
+            (300, 300),
+            (301, 301),
+        ] {
+            let (start, _) = queue.find_mut(seq_no + SeqNumber::from(offset)).unwrap();
+            assert_eq!(start, seq_no + SeqNumber::from(expected));
+        }
+
+        // Sequence numbers outside of the queue are not found.
+        assert!(queue.find_mut(seq_no - SeqNumber::from(1)).is_none());
+        assert!(queue.find_mut(seq_no + SeqNumber::from(302)).is_none());
+
+        // Once partly acknowledged, a segment is found from the first unacknowledged byte on.
+        queue.remove_acknowledged(seq_no + SeqNumber::from(150), |_| ());
+        assert!(queue.find_mut(seq_no + SeqNumber::from(149)).is_none());
+        let (start, segment) = queue.find_mut(seq_no + SeqNumber::from(200)).unwrap();
+        assert_eq!(start, seq_no + SeqNumber::from(150));
+        assert_eq!(segment.bytes.len(), 150);
     }
 }
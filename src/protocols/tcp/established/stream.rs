@@ -0,0 +1,104 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    EstablishedSocket,
+    State,
+};
+use ::futures::io::{
+    AsyncRead,
+    AsyncWrite,
+};
+use ::runtime::{
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
+    network::NetworkRuntime,
+    task::SchedulerRuntime,
+};
+use ::std::{
+    cmp,
+    io,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+//==============================================================================
+// Structures
+//==============================================================================
+
+/// Adapter that exposes an established connection through the [AsyncRead] and [AsyncWrite] traits.
+pub struct TcpStream<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
+    socket: EstablishedSocket<RT>,
+    /// Whether or not we have already requested a graceful shutdown.
+    closed: bool,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpStream<RT> {
+    pub fn new(socket: EstablishedSocket<RT>) -> Self {
+        Self { socket, closed: false }
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> AsyncRead for TcpStream<RT> {
+    fn poll_read(self: Pin<&mut Self>, ctx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let self_ = self.get_mut();
+        match self_.socket.poll_recv_into(buf, ctx) {
+            Poll::Ready(Ok(nbytes)) => Poll::Ready(Ok(nbytes)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(io::Error::from_raw_os_error(e.errno))),
+            // A connection that was shut down without a FIN from our peer is not going to receive any more data either.
+            Poll::Pending if self_.socket.state() == State::Closed => Poll::Ready(Ok(0)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> AsyncWrite for TcpStream<RT> {
+    fn poll_write(self: Pin<&mut Self>, _ctx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let self_ = self.get_mut();
+        // Hand data over one segment at a time, as the send path does not split buffers into segments.
+        let nbytes: usize = cmp::min(buf.len(), self_.socket.effective_send_mss());
+        if nbytes == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let segment: Buffer = Buffer::Heap(DataBuffer::from_slice(&buf[..nbytes]));
+        match self_.socket.send(segment) {
+            Ok(()) => Poll::Ready(Ok(nbytes)),
+            Err(e) => Poll::Ready(Err(io::Error::from_raw_os_error(e.errno))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
+        let self_ = self.get_mut();
+        self_
+            .socket
+            .poll_flush(ctx)
+            .map_err(|e| io::Error::from_raw_os_error(e.errno))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<io::Result<()>> {
+        let self_ = self.get_mut();
+        if !self_.closed {
+            if let Err(e) = self_.socket.close() {
+                return Poll::Ready(Err(io::Error::from_raw_os_error(e.errno)));
+            }
+            self_.closed = true;
+        }
+        self_
+            .socket
+            .poll_flush(ctx)
+            .map_err(|e| io::Error::from_raw_os_error(e.errno))
+    }
+}
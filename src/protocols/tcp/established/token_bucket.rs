@@ -0,0 +1,63 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use std::{
+    cmp,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+// Tokens are kept in billionths of a byte, so that refilling over any number of nanoseconds is exact.
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+// Token bucket that limits the rate at which bytes are sent.
+#[derive(Debug)]
+pub struct TokenBucket {
+    // Rate at which tokens are added, in bytes per second.
+    rate: u128,
+    // Maximum number of tokens.
+    capacity: u128,
+    // Number of tokens currently available.
+    tokens: u128,
+    // Time at which tokens were last added.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    // Creates a full token bucket, which lets `burst` bytes go out at once and `rate` bytes per second after that.
+    pub fn new(rate: usize, burst: usize, now: Instant) -> Self {
+        let capacity: u128 = burst as u128 * NANOS_PER_SEC;
+        Self {
+            rate: rate as u128,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed: u128 = now.saturating_duration_since(self.last_refill).as_nanos();
+        self.tokens = cmp::min(self.capacity, self.tokens + elapsed * self.rate);
+        self.last_refill = cmp::max(self.last_refill, now);
+    }
+
+    // Gets how long to wait until `num_bytes` may be sent, if they cannot be sent right away. Sends larger than the
+    // burst size only wait for a full bucket.
+    pub fn delay(&mut self, num_bytes: usize, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        let needed: u128 = cmp::min(self.capacity, num_bytes as u128 * NANOS_PER_SEC);
+        if self.tokens >= needed {
+            return None;
+        }
+        let nanos: u128 = (needed - self.tokens + self.rate - 1) / self.rate;
+        Some(Duration::from_nanos(nanos as u64))
+    }
+
+    // Takes tokens for `num_bytes` that were sent.
+    pub fn consume(&mut self, num_bytes: usize, now: Instant) {
+        self.refill(now);
+        self.tokens = self.tokens.saturating_sub(num_bytes as u128 * NANOS_PER_SEC);
+    }
+}
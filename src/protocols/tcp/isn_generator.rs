@@ -23,6 +23,11 @@ impl IsnGenerator {
         }
     }
 
+    #[cfg(test)]
+    pub fn get_nonce(&self) -> u32 {
+        self.nonce
+    }
+
     #[cfg(test)]
     pub fn generate(&mut self, _local: &SocketAddrV4, _remote: &SocketAddrV4) -> SeqNumber {
         SeqNumber::from(0)
@@ -45,3 +50,12 @@ impl IsnGenerator {
         isn
     }
 }
+
+/// Makes sure that an initial sequence number lies past the final sequence number of a previous incarnation of the
+/// same connection (if any), so that old duplicate segments cannot be mistaken for new ones.
+pub fn isn_after(isn: SeqNumber, last_seq_num: Option<SeqNumber>) -> SeqNumber {
+    match last_seq_num {
+        Some(last_seq_num) if isn <= last_seq_num => last_seq_num + SeqNumber::from(1),
+        _ => isn,
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//==============================================================================
+// Imports
+//==============================================================================
+
+use super::{
+    config::TcpTransmitHook,
+    segment::TcpSegment,
+};
+use ::futures::channel::mpsc;
+use ::runtime::network::NetworkRuntime;
+
+//==============================================================================
+// Types
+//==============================================================================
+
+/// Sending half of the queue of segments that we address to ourselves, which the TCP peer delivers in memory.
+pub type LoopbackSender = mpsc::UnboundedSender<TcpSegment>;
+
+/// Receiving half of the queue of segments that we address to ourselves.
+pub type LoopbackReceiver = mpsc::UnboundedReceiver<TcpSegment>;
+
+//==============================================================================
+// Standalone Functions
+//==============================================================================
+
+/// Sends a segment. Segments that are addressed to our own address skip the runtime, and thus the serialization and
+/// parsing of their headers, and are queued for the TCP peer to receive them instead. The transmit hook, if any, sees
+/// the segment first.
+pub fn transmit<RT: NetworkRuntime>(
+    rt: &RT,
+    loopback: &LoopbackSender,
+    hook: Option<&TcpTransmitHook>,
+    mut segment: TcpSegment,
+) {
+    if let Some(hook) = hook {
+        if !hook(&mut segment) {
+            debug!("Dropping segment: discarded by the transmit hook");
+            return;
+        }
+    }
+    if segment.ipv4_hdr.get_dest_addr() != rt.local_ipv4_addr() {
+        rt.transmit(segment);
+        return;
+    }
+    if loopback.unbounded_send(segment).is_err() {
+        warn!("Dropping loopback segment: TCP peer is gone");
+    }
+}
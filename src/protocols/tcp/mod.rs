@@ -2,9 +2,11 @@
 // Licensed under the MIT license.
 
 mod active_open;
+pub mod config;
 pub mod constants;
 mod established;
 mod isn_generator;
+mod loopback;
 pub mod operations;
 mod passive_open;
 pub mod peer;
@@ -15,7 +17,25 @@ mod sequence_number;
 mod tests;
 
 pub use self::{
-    established::congestion_control,
+    config::{
+        SocketProfile,
+        TcpConfigExt,
+        TcpTransmitHook,
+    },
+    established::{
+        congestion_control,
+        CloseReason,
+        ConnectionParams,
+        ConnectionStats,
+        State,
+        StateDurations,
+        TcpStream,
+    },
+    passive_open::{
+        AcceptCallback,
+        AcceptFilter,
+        AcceptQueueStats,
+    },
     peer::TcpPeer,
     segment::{
         MAX_TCP_HEADER_SIZE,
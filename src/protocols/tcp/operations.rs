@@ -31,6 +31,7 @@ pub enum TcpOperation<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     Connect(FutureResult<ConnectFuture<RT>>),
     Pop(FutureResult<PopFuture<RT>>),
     Push(FutureResult<PushFuture>),
+    Flush(FutureResult<FlushFuture<RT>>),
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> From<AcceptFuture<RT>> for TcpOperation<RT> {
@@ -51,6 +52,12 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> From<PushFuture> f
     }
 }
 
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> From<FlushFuture<RT>> for TcpOperation<RT> {
+    fn from(f: FlushFuture<RT>) -> Self {
+        TcpOperation::Flush(FutureResult::new(f, None))
+    }
+}
+
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> From<PopFuture<RT>> for TcpOperation<RT> {
     fn from(f: PopFuture<RT>) -> Self {
         TcpOperation::Pop(FutureResult::new(f, None))
@@ -66,6 +73,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Future for TcpOper
             TcpOperation::Connect(ref mut f) => Future::poll(Pin::new(f), ctx),
             TcpOperation::Push(ref mut f) => Future::poll(Pin::new(f), ctx),
             TcpOperation::Pop(ref mut f) => Future::poll(Pin::new(f), ctx),
+            TcpOperation::Flush(ref mut f) => Future::poll(Pin::new(f), ctx),
         }
     }
 }
@@ -103,6 +111,16 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpOperation<RT> {
                 done: Some(Err(e)),
             }) => (future.fd, None, OperationResult::Failed(e)),
 
+            // Flush operation.
+            TcpOperation::Flush(FutureResult {
+                future,
+                done: Some(Ok(())),
+            }) => (future.fd, None, OperationResult::Flush),
+            TcpOperation::Flush(FutureResult {
+                future,
+                done: Some(Err(e)),
+            }) => (future.fd, None, OperationResult::Failed(e)),
+
             // Pop Operation.
             TcpOperation::Pop(FutureResult {
                 future,
@@ -222,3 +240,27 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Future for PopFutu
         peer.poll_recv(self_.fd, ctx)
     }
 }
+
+/// Flush Operation Descriptor, which completes once all data that was pushed to a connection has been acknowledged.
+pub struct FlushFuture<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
+    pub fd: QDesc,
+    pub inner: Rc<RefCell<Inner<RT>>>,
+}
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> fmt::Debug for FlushFuture<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FlushFuture({:?})", self.fd)
+    }
+}
+
+impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Future for FlushFuture<RT> {
+    type Output = Result<(), Fail>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let self_ = self.get_mut();
+        let peer = TcpPeer {
+            inner: self_.inner.clone(),
+        };
+        peer.poll_flush(self_.fd, ctx)
+    }
+}
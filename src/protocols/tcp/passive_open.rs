@@ -2,9 +2,21 @@
 // Licensed under the MIT license.
 
 use super::{
-    constants::FALLBACK_MSS,
-    established::ControlBlock,
-    isn_generator::IsnGenerator,
+    config::TcpConfigExt,
+    constants::MAX_WINDOW_SCALE,
+    established::{
+        ConnectionParams,
+        ControlBlock,
+        State,
+    },
+    isn_generator::{
+        isn_after,
+        IsnGenerator,
+    },
+    loopback::{
+        self,
+        LoopbackSender,
+    },
 };
 use crate::{
     futures::FutureOperation,
@@ -17,10 +29,7 @@ use crate::{
         ip::IpProtocol,
         ipv4::Ipv4Header,
         tcp::{
-            established::{
-                congestion_control,
-                congestion_control::CongestionControl,
-            },
+            established::congestion_control::CongestionControlConstructor,
             segment::{
                 TcpHeader,
                 TcpOptions2,
@@ -30,10 +39,19 @@ use crate::{
         },
     },
 };
-use ::futures::FutureExt;
+use ::futures::{
+    future,
+    stream::{
+        self,
+        Stream,
+    },
+    FutureExt,
+};
 use ::libc::{
     EBADMSG,
     ECONNREFUSED,
+    ECONNRESET,
+    EHOSTUNREACH,
     ETIMEDOUT,
 };
 use ::runtime::{
@@ -48,12 +66,12 @@ use ::runtime::{
 };
 use ::std::{
     cell::RefCell,
+    cmp,
     collections::{
         HashMap,
         HashSet,
         VecDeque,
     },
-    convert::TryInto,
     future::Future,
     net::SocketAddrV4,
     rc::Rc,
@@ -62,15 +80,44 @@ use ::std::{
         Poll,
         Waker,
     },
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
+/// Predicate that tells whether a listening socket accepts connection requests from a given remote endpoint.
+pub type AcceptFilter = Rc<dyn Fn(&SocketAddrV4) -> bool>;
+
+/// Callback that a listening socket hands each connection to as soon as it is established, instead of queueing it to
+/// be accepted.
+pub type AcceptCallback<RT> = Rc<dyn Fn(ControlBlock<RT>)>;
+
+/// Occupancy of the backlog of a listening socket.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AcceptQueueStats {
+    /// Number of established connections that are waiting to be accepted.
+    pub ready: usize,
+    /// Number of connections whose handshake is in progress.
+    pub inflight: usize,
+    /// Number of connection requests that were refused because the backlog was full, over the lifetime of the socket.
+    pub refused: u64,
+}
+
 struct InflightAccept {
     local_isn: SeqNumber,
     remote_isn: SeqNumber,
     header_window_size: u16,
     remote_window_scale: Option<u8>,
     mss: usize,
+    remote_mss: usize,
+    ecn: bool,
+    /// Timestamp of the SYN, if timestamps were agreed (RFC 7323).
+    ts_recent: Option<u32>,
+    /// Time at which the SYN arrived.
+    syn_received: Instant,
+    /// Data that the SYN carried, which is queued until the connection is established (see RFC 793, Section 3.9).
+    syn_data: Buffer,
 
     #[allow(unused)]
     handle: SchedulerHandle,
@@ -79,21 +126,24 @@ struct InflightAccept {
 struct ReadySockets<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     ready: VecDeque<Result<ControlBlock<RT>, Fail>>,
     endpoints: HashSet<SocketAddrV4>,
-    waker: Option<Waker>,
+    /// Wakers of all pending accepts, which are woken whenever a connection is ready.
+    wakers: Vec<Waker>,
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ReadySockets<RT> {
     fn push_ok(&mut self, cb: ControlBlock<RT>) {
         assert!(self.endpoints.insert(cb.get_remote()));
         self.ready.push_back(Ok(cb));
-        if let Some(w) = self.waker.take() {
-            w.wake()
-        }
+        self.wake();
     }
 
     fn push_err(&mut self, err: Fail) {
         self.ready.push_back(Err(err));
-        if let Some(w) = self.waker.take() {
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        for w in self.wakers.drain(..) {
             w.wake()
         }
     }
@@ -102,7 +152,9 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> ReadySockets<RT> {
         let r = match self.ready.pop_front() {
             Some(r) => r,
             None => {
-                self.waker.replace(ctx.waker().clone());
+                if !self.wakers.iter().any(|w| w.will_wake(ctx.waker())) {
+                    self.wakers.push(ctx.waker().clone());
+                }
                 return Poll::Pending;
             },
         };
@@ -123,18 +175,33 @@ pub struct PassiveSocket<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static
 
     max_backlog: usize,
     isn_generator: IsnGenerator,
+    accept_filter: Option<AcceptFilter>,
+    accept_callback: Option<AcceptCallback<RT>>,
+    refused: u64,
 
     local: SocketAddrV4,
     rt: RT,
     arp: ArpPeer<RT>,
+    loopback: LoopbackSender,
+    config: TcpConfigExt,
+    congestion_control: CongestionControlConstructor<RT>,
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT> {
-    pub fn new(local: SocketAddrV4, max_backlog: usize, rt: RT, arp: ArpPeer<RT>, nonce: u32) -> Self {
+    pub fn new(
+        local: SocketAddrV4,
+        max_backlog: usize,
+        rt: RT,
+        arp: ArpPeer<RT>,
+        loopback: LoopbackSender,
+        config: TcpConfigExt,
+        congestion_control: CongestionControlConstructor<RT>,
+        nonce: u32,
+    ) -> Self {
         let ready = ReadySockets {
             ready: VecDeque::new(),
             endpoints: HashSet::new(),
-            waker: None,
+            wakers: Vec::new(),
         };
         let ready = Rc::new(RefCell::new(ready));
         Self {
@@ -142,18 +209,74 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
             ready,
             max_backlog,
             isn_generator: IsnGenerator::new(nonce),
+            accept_filter: None,
+            accept_callback: None,
+            refused: 0,
             local,
             rt,
             arp,
+            loopback,
+            config,
+            congestion_control,
+        }
+    }
+
+    /// Installs a predicate that connection requests must satisfy to be accepted. Requests from other remote endpoints
+    /// are dropped before any state is allocated for them. If `None`, all requests are accepted.
+    pub fn set_accept_filter(&mut self, filter: Option<AcceptFilter>) {
+        self.accept_filter = filter;
+    }
+
+    /// Installs a callback that established connections are handed to, rather than waiting to be accepted. The callback
+    /// runs while the TCP peer handles the segment that completes the handshake, so it must not call back into the
+    /// peer. If `None`, connections wait to be accepted.
+    pub fn set_accept_callback(&mut self, callback: Option<AcceptCallback<RT>>) {
+        self.accept_callback = callback;
+    }
+
+    /// Gets the occupancy of the backlog.
+    pub fn stats(&self) -> AcceptQueueStats {
+        AcceptQueueStats {
+            ready: self.ready.borrow().len(),
+            inflight: self.inflight.len(),
+            refused: self.refused,
         }
     }
 
+    /// Checks if a connection from a remote endpoint is being accepted on this socket, i.e. its handshake is in progress
+    /// or it waits to be accepted.
+    pub fn is_accepting(&self, remote: &SocketAddrV4) -> bool {
+        self.inflight.contains_key(remote) || self.ready.borrow().endpoints.contains(remote)
+    }
+
     pub fn poll_accept(&mut self, ctx: &mut Context) -> Poll<Result<ControlBlock<RT>, Fail>> {
         self.ready.borrow_mut().poll(ctx)
     }
 
-    pub fn receive(&mut self, ip_header: &Ipv4Header, header: &TcpHeader) -> Result<(), Fail> {
+    /// Waits for a connection to be accepted. This is an asynchronous counterpart of [Self::poll_accept].
+    pub async fn accept(&mut self) -> Result<ControlBlock<RT>, Fail> {
+        future::poll_fn(|ctx| self.poll_accept(ctx)).await
+    }
+
+    /// Returns a stream of the connections that are accepted on this socket, in the order in which they are
+    /// established. The stream never ends, so that servers can loop over it.
+    pub fn incoming(&mut self) -> impl Stream<Item = Result<ControlBlock<RT>, Fail>> + '_ {
+        stream::poll_fn(move |ctx| self.poll_accept(ctx).map(Some))
+    }
+
+    /// Handles an incoming segment. If a previous connection with the same remote endpoint closed recently,
+    /// `last_seq_num` is the final sequence number that we used on it.
+    pub fn receive(
+        &mut self,
+        ip_header: &Ipv4Header,
+        header: &TcpHeader,
+        data: Buffer,
+        last_seq_num: Option<SeqNumber>,
+    ) -> Result<(), Fail> {
         let remote = SocketAddrV4::new(ip_header.get_src_addr(), header.src_port);
+        // When listening on the wildcard address, the connection is to whichever local address the segment is for.
+        let local = SocketAddrV4::new(ip_header.get_dest_addr(), self.local.port());
+        debug_assert!(self.local.ip().is_unspecified() || *self.local.ip() == *local.ip());
         if self.ready.borrow().endpoints.contains(&remote) {
             // TODO: What should we do if a packet shows up for a connection that hasn't been `accept`ed yet?
             return Ok(());
@@ -161,36 +284,69 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
         let inflight_len = self.inflight.len();
 
         // If the packet is for an inflight connection, route it there.
-        if self.inflight.contains_key(&remote) {
+        if let Some(&InflightAccept {
+            local_isn,
+            remote_isn,
+            header_window_size,
+            remote_window_scale,
+            mss,
+            remote_mss,
+            ecn,
+            ts_recent,
+            syn_received,
+            ..
+        }) = self.inflight.get(&remote)
+        {
+            // RFC 793: a reset in SYN-RECEIVED sends a passively opened connection back to LISTEN.
+            if header.rst {
+                warn!("Connection request from {} was reset", remote);
+                self.inflight.remove(&remote);
+                return Err(Fail::new(ECONNRESET, "connection reset"));
+            }
+            // Our SYN+ACK may have been lost, in which case our peer retransmits its SYN. Restart the handshake, which
+            // sends a new SYN+ACK right away rather than when our own retransmission timer expires.
+            if header.syn && !header.ack {
+                if header.seq_num != remote_isn {
+                    return Err(Fail::new(EBADMSG, "invalid SYN seq num"));
+                }
+                debug!("Received SYN retransmission: {:?}", header);
+                let future = Self::background(
+                    local_isn,
+                    remote_isn,
+                    local,
+                    remote,
+                    remote_window_scale.is_some(),
+                    ecn,
+                    ts_recent,
+                    syn_received,
+                    self.rt.clone(),
+                    self.arp.clone(),
+                    self.loopback.clone(),
+                    self.config.clone(),
+                    self.ready.clone(),
+                );
+                let handle: SchedulerHandle = self.rt.spawn(FutureOperation::Background::<RT>(future.boxed_local()));
+                if let Some(accept) = self.inflight.get_mut(&remote) {
+                    accept.handle = handle;
+                }
+                return Ok(());
+            }
             if !header.ack {
                 return Err(Fail::new(EBADMSG, "expeting ACK"));
             }
             debug!("Received ACK: {:?}", header);
-            let &InflightAccept {
-                local_isn,
-                remote_isn,
-                header_window_size,
-                remote_window_scale,
-                mss,
-                ..
-            } = self.inflight.get(&remote).unwrap();
             if header.ack_num != local_isn + SeqNumber::from(1) {
                 return Err(Fail::new(EBADMSG, "invalid SYN+ACK seq num"));
             }
 
             let tcp_options = self.rt.tcp_options();
+            let window_scaling: bool = remote_window_scale.is_some();
             let (local_window_scale, remote_window_scale) = match remote_window_scale {
                 Some(w) => (tcp_options.get_window_scale() as u32, w),
                 None => (0, 0),
             };
-            let remote_window_size = (header_window_size)
-                .checked_shl(remote_window_scale as u32)
-                .expect("TODO: Window size overflow")
-                .try_into()
-                .expect("TODO: Window size overflow");
-            let local_window_size = (tcp_options.get_receive_window_size() as u32)
-                .checked_shl(local_window_scale as u32)
-                .expect("TODO: Window size overflow");
+            let remote_window_size: u32 = (header_window_size as u32) << remote_window_scale;
+            let local_window_size: u32 = self.config.get_receive_buffer_size(&tcp_options, local_window_scale);
             info!(
                 "Window sizes: local {}, remote {}",
                 local_window_size, remote_window_size
@@ -200,24 +356,70 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
                 local_window_scale, remote_window_scale
             );
 
-            self.inflight.remove(&remote);
+            let syn_data: Buffer = self
+                .inflight
+                .remove(&remote)
+                .map(|accept| accept.syn_data)
+                .expect("inflight connection vanished");
             let cb = ControlBlock::new(
-                self.local,
+                local,
                 remote,
                 self.rt.clone(),
                 self.arp.clone(),
+                self.loopback.clone(),
                 remote_isn + SeqNumber::from(1),
-                self.rt.tcp_options().get_ack_delay_timeout(),
+                self.config.get_ack_delay_timeout(&self.rt.tcp_options()),
                 local_window_size,
                 local_window_scale,
                 local_isn + SeqNumber::from(1),
                 remote_window_size,
                 remote_window_scale,
                 mss,
-                congestion_control::None::new,
+                self.congestion_control,
                 None,
+                self.config.clone(),
+                ConnectionParams {
+                    mss,
+                    remote_mss,
+                    window_scaling,
+                    local_window_scale: local_window_scale as u8,
+                    remote_window_scale,
+                    sack_permitted: false,
+                    timestamps: ts_recent.is_some(),
+                    ecn,
+                },
+                syn_received,
             );
-            self.ready.borrow_mut().push_ok(cb);
+            if let Some(ts_recent) = ts_recent {
+                cb.set_ts_recent(header.timestamp_option().map_or(ts_recent, |(timestamp, _)| timestamp));
+            }
+            // Data that was queued from the SYN comes first. It is handed over as if it had arrived right after the
+            // SYN, so that the connection acknowledges it and trims whatever does not fit in its receive window.
+            if !syn_data.is_empty() {
+                let mut syn_header: TcpHeader = header.clone();
+                syn_header.seq_num = remote_isn + SeqNumber::from(1);
+                syn_header.fin = false;
+                cb.receive(&mut syn_header, syn_data);
+            }
+            // The final ACK of the handshake may carry data (or a FIN) as well, which must be readable as soon as the
+            // connection is accepted.
+            if !data.is_empty() || header.fin {
+                cb.receive(&mut header.clone(), data);
+            }
+            match cb.get_state() {
+                // The callback is invoked last, once the state of this socket is consistent again.
+                State::Established | State::CloseWait => match self.accept_callback {
+                    Some(ref callback) => callback(cb),
+                    None => self.ready.borrow_mut().push_ok(cb),
+                },
+                state => {
+                    warn!(
+                        "Dropping connection from {}: it is {:?} after the handshake",
+                        remote, state
+                    );
+                    return Err(Fail::new(ECONNRESET, "connection reset"));
+                },
+            }
             return Ok(());
         }
 
@@ -225,47 +427,94 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
         if !header.syn || header.ack || header.rst {
             return Err(Fail::new(EBADMSG, "invalid flags"));
         }
+        // Refuse connection requests from endpoints that cannot exist, including our own (i.e. a self-connect).
+        if remote.port() == 0 || remote.ip().is_broadcast() || remote == local {
+            warn!("Dropping SYN: invalid source endpoint {}", remote);
+            return Err(Fail::new(EBADMSG, "invalid source endpoint"));
+        }
         debug!("Received SYN: {:?}", header);
+        if let Some(ref filter) = self.accept_filter {
+            if !filter(&remote) {
+                warn!("Dropping SYN: {} is not allowed by the accept filter", remote);
+                return Err(Fail::new(ECONNREFUSED, "connection refused by accept filter"));
+            }
+        }
         if inflight_len + self.ready.borrow().len() >= self.max_backlog {
             // TODO: Should we send a RST here?
+            self.refused += 1;
             return Err(Fail::new(ECONNREFUSED, "connection refused"));
         }
-        let local_isn = self.isn_generator.generate(&self.local, &remote);
-        let remote_isn = header.seq_num;
-        let future = Self::background(
-            local_isn,
-            remote_isn,
-            self.local,
-            remote,
-            self.rt.clone(),
-            self.arp.clone(),
-            self.ready.clone(),
-        );
-        let handle: SchedulerHandle = self.rt.spawn(FutureOperation::Background::<RT>(future.boxed_local()));
-
         let mut remote_window_scale = None;
-        let mut mss = FALLBACK_MSS;
+        let mut mss: usize = self.config.get_fallback_mss();
         for option in header.iter_options() {
             match option {
                 TcpOptions2::WindowScale(w) => {
                     info!("Received window scale: {:?}", w);
-                    remote_window_scale = Some(*w);
+                    // RFC 7323: a shift count larger than the maximum is treated as the maximum.
+                    if *w > MAX_WINDOW_SCALE {
+                        warn!("Clamping window scale {} to {}", w, MAX_WINDOW_SCALE);
+                    }
+                    remote_window_scale = Some(cmp::min(*w, MAX_WINDOW_SCALE));
                 },
                 TcpOptions2::MaximumSegmentSize(m) => {
                     info!("Received advertised MSS: {}", m);
+                    if (*m as usize) < self.config.get_min_mss() {
+                        warn!("Raising advertised MSS {} to {}", m, self.config.get_min_mss());
+                    }
                     mss = *m as usize;
                 },
                 _ => continue,
             }
         }
+        let remote_mss: usize = mss;
+        // RFC 7323: we agree to use timestamps by sending them in our SYN+ACK, which we may only do if the SYN has them.
+        let ts_recent: Option<u32> = match self.config.get_timestamps() {
+            true => header.timestamp_option().map(|(timestamp, _)| timestamp),
+            false => None,
+        };
+        let mss: usize = self.config.get_send_mss(remote_mss, ts_recent.is_some());
+        // RFC 3168: an ECN-setup SYN has both ECE and CWR set.
+        let ecn: bool = self.config.get_ecn() && header.ece && header.cwr;
+
+        let local_isn = isn_after(self.isn_generator.generate(&local, &remote), last_seq_num);
+        let remote_isn = header.seq_num;
+        let syn_received: Instant = self.rt.now();
+        let future = Self::background(
+            local_isn,
+            remote_isn,
+            local,
+            remote,
+            remote_window_scale.is_some(),
+            ecn,
+            ts_recent,
+            syn_received,
+            self.rt.clone(),
+            self.arp.clone(),
+            self.loopback.clone(),
+            self.config.clone(),
+            self.ready.clone(),
+        );
+        let handle: SchedulerHandle = self.rt.spawn(FutureOperation::Background::<RT>(future.boxed_local()));
+
         let accept = InflightAccept {
             local_isn,
             remote_isn,
             header_window_size: header.window_size,
             remote_window_scale,
             mss,
+            remote_mss,
+            ecn,
+            ts_recent,
+            syn_received,
+            syn_data: data,
             handle,
         };
+        if !accept.syn_data.is_empty() {
+            debug!(
+                "Queueing {} bytes of SYN data until the connection is established",
+                accept.syn_data.len()
+            );
+        }
         self.inflight.insert(remote, accept);
         Ok(())
     }
@@ -275,8 +524,14 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
         remote_isn: SeqNumber,
         local: SocketAddrV4,
         remote: SocketAddrV4,
+        window_scaling: bool,
+        ecn: bool,
+        ts_recent: Option<u32>,
+        syn_received: Instant,
         rt: RT,
         arp: ArpPeer<RT>,
+        loopback: LoopbackSender,
+        config: TcpConfigExt,
         ready: Rc<RefCell<ReadySockets<RT>>>,
     ) -> impl Future<Output = ()> {
         let tcp_options = rt.tcp_options();
@@ -284,9 +539,15 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
         let handshake_timeout: Duration = tcp_options.get_handshake_timeout();
 
         async move {
+            // Whether or not we have been able to resolve the remote link address at least once. This is used to tell
+            // apart failures in which the remote host is unreachable from those in which it simply did not respond.
+            let mut resolved_link_addr: bool = false;
             for _ in 0..handshake_retries {
                 let remote_link_addr = match arp.query(remote.ip().clone()).await {
-                    Ok(r) => r,
+                    Ok(r) => {
+                        resolved_link_addr = true;
+                        r
+                    },
                     Err(e) => {
                         warn!("ARP query failed: {:?}", e);
                         continue;
@@ -297,27 +558,47 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> PassiveSocket<RT>
                 tcp_hdr.seq_num = local_isn;
                 tcp_hdr.ack = true;
                 tcp_hdr.ack_num = remote_isn + SeqNumber::from(1);
-                tcp_hdr.window_size = tcp_options.get_receive_window_size();
+                tcp_hdr.window_size = config.get_handshake_window_size(&tcp_options);
+                // RFC 3168: we agree to use ECN by setting ECE (but not CWR) in our SYN+ACK.
+                tcp_hdr.ece = ecn;
 
-                let mss = tcp_options.get_advertised_mss() as u16;
+                let mss = config.get_advertised_mss(&tcp_options) as u16;
                 tcp_hdr.push_option(TcpOptions2::MaximumSegmentSize(mss));
                 info!("Advertising MSS: {}", mss);
 
-                tcp_hdr.push_option(TcpOptions2::WindowScale(tcp_options.get_window_scale()));
-                info!("Advertising window scale: {}", tcp_options.get_window_scale());
+                // RFC 7323: we may only send a window scale option in our SYN+ACK if there was one in the SYN.
+                if window_scaling {
+                    tcp_hdr.push_option(TcpOptions2::WindowScale(tcp_options.get_window_scale()));
+                    info!("Advertising window scale: {}", tcp_options.get_window_scale());
+                }
+
+                if let Some(ts_recent) = ts_recent {
+                    tcp_hdr.push_option(TcpOptions2::timestamp(syn_received, rt.now(), ts_recent));
+                }
+
+                let ipv4_hdr: Ipv4Header = Ipv4Header::new(local.ip().clone(), remote.ip().clone(), IpProtocol::TCP);
+                if let Some(key) = config.get_md5_key(remote.ip()) {
+                    tcp_hdr.sign_md5(&ipv4_hdr, &[], key);
+                }
 
                 debug!("Sending SYN+ACK: {:?}", tcp_hdr);
                 let segment = TcpSegment {
-                    ethernet2_hdr: Ethernet2Header::new(remote_link_addr, rt.local_link_addr(), EtherType2::Ipv4),
-                    ipv4_hdr: Ipv4Header::new(local.ip().clone(), remote.ip().clone(), IpProtocol::TCP),
+                    ethernet2_hdr: Ethernet2Header::new(remote_link_addr, rt.local_link_addr(), EtherType2::Ipv4)
+                        .with_vlan_tag(arp.vlan_tag()),
+                    ipv4_hdr,
                     tcp_hdr,
                     data: Buffer::Heap(DataBuffer::empty()),
                     tx_checksum_offload: tcp_options.get_rx_checksum_offload(),
                 };
-                rt.transmit(segment);
+                loopback::transmit(&rt, &loopback, config.get_transmit_hook(), segment);
                 rt.wait(handshake_timeout).await;
             }
-            ready.borrow_mut().push_err(Fail::new(ETIMEDOUT, "handshake timeout"));
+            let error: Fail = if resolved_link_addr {
+                Fail::new(ETIMEDOUT, "handshake timeout")
+            } else {
+                Fail::new(EHOSTUNREACH, "ARP resolution failed")
+            };
+            ready.borrow_mut().push_err(error);
         }
     }
 }
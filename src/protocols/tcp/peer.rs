@@ -7,40 +7,80 @@
 
 use super::{
     active_open::ActiveOpenSocket,
-    established::EstablishedSocket,
-    isn_generator::IsnGenerator,
-    passive_open::PassiveSocket,
-};
-use crate::protocols::{
-    arp::ArpPeer,
-    ethernet2::{
-        EtherType2,
-        Ethernet2Header,
+    config::TcpConfigExt,
+    established::{
+        CloseReason,
+        ConnectionParams,
+        ConnectionStats,
+        EstablishedSocket,
+        State,
+        StateDurations,
+        TcpStream,
+    },
+    isn_generator::{
+        isn_after,
+        IsnGenerator,
+    },
+    loopback::{
+        self,
+        LoopbackReceiver,
+        LoopbackSender,
     },
-    ip::{
-        EphemeralPorts,
-        IpProtocol,
+    passive_open::{
+        AcceptCallback,
+        AcceptFilter,
+        AcceptQueueStats,
+        PassiveSocket,
     },
-    ipv4::Ipv4Header,
-    tcp::{
-        established::ControlBlock,
-        operations::{
-            AcceptFuture,
-            ConnectFuture,
-            PopFuture,
-            PushFuture,
+};
+use crate::{
+    futures::FutureOperation,
+    protocols::{
+        arp::ArpPeer,
+        ethernet2::{
+            EtherType2,
+            Ethernet2Header,
+        },
+        ip::{
+            EphemeralPorts,
+            IpProtocol,
         },
-        segment::{
-            TcpHeader,
-            TcpSegment,
+        ipv4::Ipv4Header,
+        tcp::{
+            constants::MSL,
+            established::{
+                congestion_control::{
+                    self,
+                    CongestionControl,
+                    CongestionControlConstructor,
+                    CongestionPhase,
+                },
+                ControlBlock,
+            },
+            operations::{
+                AcceptFuture,
+                ConnectFuture,
+                FlushFuture,
+                PopFuture,
+                PushFuture,
+            },
+            segment::{
+                TcpHeader,
+                TcpSegment,
+            },
+            SeqNumber,
         },
-        SeqNumber,
     },
 };
-use ::futures::channel::mpsc;
+use ::futures::{
+    channel::mpsc,
+    FutureExt,
+    StreamExt,
+};
 use ::libc::{
     EAGAIN,
     EBADF,
+    EBADMSG,
     EBUSY,
     EINPROGRESS,
     EINVAL,
@@ -60,22 +100,33 @@ use ::runtime::{
         DataBuffer,
     },
     network::NetworkRuntime,
+    scheduler::SchedulerHandle,
     task::SchedulerRuntime,
     QDesc,
 };
 use ::std::{
     cell::{
+        Ref,
         RefCell,
         RefMut,
     },
     collections::HashMap,
-    net::SocketAddrV4,
-    rc::Rc,
+    net::{
+        Ipv4Addr,
+        SocketAddrV4,
+    },
+    rc::{
+        Rc,
+        Weak,
+    },
     task::{
         Context,
         Poll,
     },
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 #[cfg(feature = "profiler")]
@@ -108,15 +159,27 @@ pub struct Inner<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     connecting: HashMap<(SocketAddrV4, SocketAddrV4), ActiveOpenSocket<RT>>,
     established: HashMap<(SocketAddrV4, SocketAddrV4), EstablishedSocket<RT>>,
 
+    // Final sequence numbers of recently closed connections, along with the time at which we forgot the connection.
+    // New connections on the same 4-tuple pick their initial sequence number past these, for up to one MSL.
+    closed: HashMap<(SocketAddrV4, SocketAddrV4), (SeqNumber, Instant)>,
+
     rt: RT,
+    // Address that connections from sockets which are not bound to a specific one come from, instead of ours.
+    source_ipv4_addr: Option<Ipv4Addr>,
     arp: ArpPeer<RT>,
+    loopback: LoopbackSender,
     rng: Rc<RefCell<SmallRng>>,
+    config: TcpConfigExt,
+    congestion_control: CongestionControlConstructor<RT>,
 
     dead_socket_tx: mpsc::UnboundedSender<QDesc>,
 }
 
 pub struct TcpPeer<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     pub(super) inner: Rc<RefCell<Inner<RT>>>,
+    // Delivers the segments that we address to ourselves.
+    #[allow(unused)]
+    loopback_handle: SchedulerHandle,
 }
 
 //==============================================================================
@@ -126,8 +189,52 @@ pub struct TcpPeer<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
     pub fn new(rt: RT, arp: ArpPeer<RT>, rng_seed: [u8; 32]) -> Self {
         let (tx, rx) = mpsc::unbounded();
-        let inner = Rc::new(RefCell::new(Inner::new(rt.clone(), arp, rng_seed, tx, rx)));
-        Self { inner }
+        let (loopback_tx, loopback_rx) = mpsc::unbounded();
+        let inner = Rc::new(RefCell::new(Inner::new(rt.clone(), arp, loopback_tx, rng_seed, tx, rx)));
+        let future = Self::loopback(Rc::downgrade(&inner), loopback_rx);
+        let loopback_handle: SchedulerHandle = rt.spawn(FutureOperation::Background::<RT>(future.boxed_local()));
+        Self { inner, loopback_handle }
+    }
+
+    /// Receives the segments that we address to ourselves. These skip the runtime altogether, so they are neither
+    /// serialized nor parsed.
+    async fn loopback(inner: Weak<RefCell<Inner<RT>>>, mut loopback_rx: LoopbackReceiver) {
+        while let Some(segment) = loopback_rx.next().await {
+            let inner: Rc<RefCell<Inner<RT>>> = match inner.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let TcpSegment {
+                ipv4_hdr,
+                tcp_hdr,
+                data,
+                ..
+            } = segment;
+            if let Err(e) = inner.borrow_mut().receive_segment(&ipv4_hdr, tcp_hdr, data) {
+                warn!("Dropped loopback segment: {:?}", e);
+            }
+        }
+    }
+
+    /// Installs configuration extensions. These apply to sockets that are subsequently connected or listening.
+    pub fn set_config(&self, config: TcpConfigExt) {
+        self.inner.borrow_mut().config = config;
+    }
+
+    /// Returns the installed configuration extensions.
+    pub fn get_config(&self) -> TcpConfigExt {
+        self.inner.borrow().config.clone()
+    }
+
+    /// Installs the congestion control algorithm of sockets that are subsequently connected or listening.
+    pub fn set_congestion_control(&self, constructor: CongestionControlConstructor<RT>) {
+        self.inner.borrow_mut().congestion_control = constructor;
+    }
+
+    /// Sets the address that connections from sockets which are not bound to a specific one come from. If `None`, which
+    /// is the default, they come from the address of the runtime.
+    pub fn set_source_ipv4_addr(&self, addr: Option<Ipv4Addr>) {
+        self.inner.borrow_mut().source_ipv4_addr = addr;
     }
 
     /// Opens a TCP socket.
@@ -235,12 +342,60 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         }
 
         let nonce: u32 = inner.rng.borrow_mut().gen();
-        let socket = PassiveSocket::new(local, backlog, inner.rt.clone(), inner.arp.clone(), nonce);
+        let socket = PassiveSocket::new(
+            local,
+            backlog,
+            inner.rt.clone(),
+            inner.arp.clone(),
+            inner.loopback.clone(),
+            inner.config.clone(),
+            inner.congestion_control,
+            nonce,
+        );
         assert!(inner.passive.insert(local, socket).is_none());
         inner.sockets.insert(qd, Socket::Listening { local });
         Ok(())
     }
 
+    /// Installs a predicate that connection requests to a listening socket must satisfy to be accepted.
+    pub fn set_accept_filter(&self, qd: QDesc, filter: Option<AcceptFilter>) -> Result<(), Fail> {
+        let mut inner: RefMut<Inner<RT>> = self.inner.borrow_mut();
+        let local: SocketAddrV4 = match inner.sockets.get(&qd) {
+            Some(Socket::Listening { local }) => *local,
+            Some(..) => return Err(Fail::new(libc::EINVAL, "socket is not listening")),
+            None => return Err(Fail::new(EBADF, "invalid queue descriptor")),
+        };
+        let passive: &mut PassiveSocket<RT> = inner.passive.get_mut(&local).expect("sockets/local inconsistency");
+        passive.set_accept_filter(filter);
+        Ok(())
+    }
+
+    /// Installs a callback that connections established by a listening socket are handed to, rather than waiting to be
+    /// accepted. The callback must not call back into this peer.
+    pub fn set_accept_callback(&self, qd: QDesc, callback: Option<AcceptCallback<RT>>) -> Result<(), Fail> {
+        let mut inner: RefMut<Inner<RT>> = self.inner.borrow_mut();
+        let local: SocketAddrV4 = match inner.sockets.get(&qd) {
+            Some(Socket::Listening { local }) => *local,
+            Some(..) => return Err(Fail::new(libc::EINVAL, "socket is not listening")),
+            None => return Err(Fail::new(EBADF, "invalid queue descriptor")),
+        };
+        let passive: &mut PassiveSocket<RT> = inner.passive.get_mut(&local).expect("sockets/local inconsistency");
+        passive.set_accept_callback(callback);
+        Ok(())
+    }
+
+    /// Gets the occupancy of the backlog of a listening socket.
+    pub fn accept_queue_stats(&self, qd: QDesc) -> Result<AcceptQueueStats, Fail> {
+        let inner: Ref<Inner<RT>> = self.inner.borrow();
+        let local: &SocketAddrV4 = match inner.sockets.get(&qd) {
+            Some(Socket::Listening { local }) => local,
+            Some(..) => return Err(Fail::new(libc::EINVAL, "socket is not listening")),
+            None => return Err(Fail::new(EBADF, "invalid queue descriptor")),
+        };
+        let passive: &PassiveSocket<RT> = inner.passive.get(local).expect("sockets/local inconsistency");
+        Ok(passive.stats())
+    }
+
     /// Accepts an incoming connection.
     pub fn do_accept(&self, qd: QDesc, new_qd: QDesc) -> AcceptFuture<RT> {
         AcceptFuture::new(qd, new_qd, self.inner.clone())
@@ -287,21 +442,40 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
     pub fn connect(&self, qd: QDesc, remote: SocketAddrV4) -> Result<ConnectFuture<RT>, Fail> {
         let mut inner: RefMut<Inner<RT>> = self.inner.borrow_mut();
 
+        // Refuse to connect to endpoints that cannot exist.
+        if remote.port() == 0 || remote.ip().is_broadcast() {
+            return Err(Fail::new(libc::EBADMSG, "invalid remote endpoint"));
+        }
+
         // Get local address bound to socket.
-        let local: SocketAddrV4 = match inner.sockets.get_mut(&qd) {
+        let local: SocketAddrV4 = match inner.sockets.get(&qd) {
             // Handle unbound socket.
             Some(Socket::Inactive { local: None }) => {
                 // TODO: we should free this when closing.
                 let local_port: u16 = inner.ephemeral_ports.alloc_any()?;
-                SocketAddrV4::new(inner.rt.local_ipv4_addr(), local_port)
+                SocketAddrV4::new(inner.source_ipv4_addr(), local_port)
+            },
+            // Handle bound socket. Connections from a socket that is bound to the wildcard address come from ours.
+            Some(Socket::Inactive { local: Some(local) }) if local.ip().is_unspecified() => {
+                SocketAddrV4::new(inner.source_ipv4_addr(), local.port())
             },
-            // Handle bound socket.
             Some(Socket::Inactive { local: Some(local) }) => *local,
             Some(Socket::Connecting { local: _, remote: _ }) => Err(Fail::new(libc::EALREADY, "socket is connecting"))?,
             Some(Socket::Established { local: _, remote: _ }) => Err(Fail::new(libc::EISCONN, "socket is connected"))?,
             _ => Err(Fail::new(libc::EBADF, "invalid queue descriptor"))?,
         };
 
+        // A connection to our own endpoint would connect the socket to itself.
+        if local == remote {
+            return Err(Fail::new(libc::EBADMSG, "connection to own endpoint"));
+        }
+
+        // Check if the 4-tuple is taken, either by a previous connection that is still around or by one that is being
+        // opened. The latter may be a connection that a socket listening on our local endpoint is accepting.
+        if !inner.reap_closed(&(local, remote)) || inner.is_opening(&(local, remote)) {
+            return Err(Fail::new(libc::EADDRINUSE, "connection already exists"));
+        }
+
         // Update socket state.
         match inner.sockets.get_mut(&qd) {
             Some(socket) => {
@@ -315,9 +489,18 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         };
 
         // Create active socket.
-        let local_isn: SeqNumber = inner.isn_generator.generate(&local, &remote);
-        let socket: ActiveOpenSocket<RT> =
-            ActiveOpenSocket::new(local_isn, local, remote, inner.rt.clone(), inner.arp.clone());
+        let last_seq_num: Option<SeqNumber> = inner.last_seq_num(&(local, remote));
+        let local_isn: SeqNumber = isn_after(inner.isn_generator.generate(&local, &remote), last_seq_num);
+        let socket: ActiveOpenSocket<RT> = ActiveOpenSocket::new(
+            local_isn,
+            local,
+            remote,
+            inner.rt.clone(),
+            inner.arp.clone(),
+            inner.loopback.clone(),
+            inner.config.clone(),
+            inner.congestion_control,
+        );
 
         // Insert socket in connecting table.
         if inner.connecting.insert((local, remote), socket).is_some() {
@@ -347,6 +530,64 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         }
     }
 
+    pub fn poll_flush(&self, fd: QDesc, ctx: &mut Context) -> Poll<Result<(), Fail>> {
+        let inner = self.inner.borrow_mut();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Poll::Ready(Err(Fail::new(ENOTCONN, "connection not established"))),
+            None => return Poll::Ready(Err(Fail::new(EBADF, "bad queue descriptor"))),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => s.poll_flush(ctx),
+            None => Poll::Ready(Err(Fail::new(ENOTCONN, "connection not established"))),
+        }
+    }
+
+    /// Reads received data into `buf`, returning the number of bytes read. Data that does not fit remains buffered.
+    pub fn poll_recv_into(&self, fd: QDesc, buf: &mut [u8], ctx: &mut Context) -> Poll<Result<usize, Fail>> {
+        let inner = self.inner.borrow_mut();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(Socket::Connecting { .. }) => return Poll::Ready(Err(Fail::new(EINPROGRESS, "socket connecting"))),
+            Some(Socket::Inactive { .. }) => return Poll::Ready(Err(Fail::new(EBADF, "socket inactive"))),
+            Some(Socket::Listening { .. }) => return Poll::Ready(Err(Fail::new(ENOTCONN, "socket listening"))),
+            None => return Poll::Ready(Err(Fail::new(EBADF, "bad queue descriptor"))),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => s.poll_recv_into(buf, ctx),
+            None => Poll::Ready(Err(Fail::new(ENOTCONN, "connection not established"))),
+        }
+    }
+
+    /// Copies received data into `buf` without consuming it, like `recv()` with `MSG_PEEK`.
+    pub fn peek(&self, fd: QDesc, buf: &mut [u8]) -> Result<usize, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => s.peek(buf),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Checks if our peer has closed its side of a connection and all data that it sent has been read, so that receives
+    /// only return the end of the stream. Our side of the connection may still be open for sending.
+    pub fn is_read_closed(&self, fd: QDesc) -> Result<bool, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.is_read_closed()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
     pub fn push(&self, fd: QDesc, buf: Buffer) -> PushFuture {
         let err = match self.send(fd, buf) {
             Ok(()) => None,
@@ -355,6 +596,24 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         PushFuture { fd, err }
     }
 
+    /// Pushes the elements of a scatter-gather array as one message. Their data is sent straight from the buffers of
+    /// the application, which are referenced until they are acknowledged, rather than copied into a contiguous buffer.
+    pub fn push_sga(&self, fd: QDesc, sga: Vec<Buffer>) -> PushFuture {
+        let err = match self.send_sga(fd, sga) {
+            Ok(()) => None,
+            Err(e) => Some(e),
+        };
+        PushFuture { fd, err }
+    }
+
+    /// Waits until all data that was pushed to a socket has been acknowledged by our peer.
+    pub fn flush(&self, fd: QDesc) -> FlushFuture<RT> {
+        FlushFuture {
+            fd,
+            inner: self.inner.clone(),
+        }
+    }
+
     pub fn pop(&self, fd: QDesc) -> PopFuture<RT> {
         PopFuture {
             fd,
@@ -375,6 +634,19 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         }
     }
 
+    fn send_sga(&self, fd: QDesc, sga: Vec<Buffer>) -> Result<(), Fail> {
+        let inner = self.inner.borrow_mut();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => s.send_sga(sga),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
     /// Closes a TCP socket.
     pub fn do_close(&self, qd: QDesc) -> Result<(), Fail> {
         let mut inner: RefMut<Inner<RT>> = self.inner.borrow_mut();
@@ -395,6 +667,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         Ok(())
     }
 
+    /// Gets the maximum segment size that the peer of a connection advertised.
     pub fn remote_mss(&self, fd: QDesc) -> Result<usize, Fail> {
         let inner = self.inner.borrow();
         let key = match inner.sockets.get(&fd) {
@@ -408,6 +681,62 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         }
     }
 
+    /// Gets the maximum segment size that we advertised on a connection.
+    pub fn local_mss(&self, fd: QDesc) -> Result<usize, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.local_mss()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the largest amount of data that we send in a segment on a connection.
+    pub fn effective_send_mss(&self, fd: QDesc) -> Result<usize, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.effective_send_mss()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the connection parameters that were negotiated in the three-way handshake.
+    pub fn negotiated(&self, fd: QDesc) -> Result<ConnectionParams, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.negotiated()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the statistics of a connection.
+    pub fn stats(&self, fd: QDesc) -> Result<ConnectionStats, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.stats()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
     pub fn current_rto(&self, fd: QDesc) -> Result<Duration, Fail> {
         let inner = self.inner.borrow();
         let key = match inner.sockets.get(&fd) {
@@ -421,6 +750,68 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
         }
     }
 
+    /// Gets the phase that the congestion control algorithm of a connection is in.
+    pub fn cc_phase(&self, fd: QDesc) -> Result<CongestionPhase, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.cc_phase()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Replaces the congestion control algorithm of an established connection, like `TCP_CONGESTION` does. The new
+    /// algorithm carries on from the congestion window that the connection has reached.
+    pub fn set_socket_congestion_control(
+        &self,
+        fd: QDesc,
+        constructor: CongestionControlConstructor<RT>,
+    ) -> Result<(), Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => {
+                s.set_congestion_control(constructor);
+                Ok(())
+            },
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the time to live of the last datagram received on a connection, if any.
+    pub fn received_ttl(&self, fd: QDesc) -> Result<Option<u8>, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.cb.get_received_ttl()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the local endpoint of a socket, which is unspecified if the socket is not bound.
+    pub fn local_endpoint(&self, fd: QDesc) -> Result<SocketAddrV4, Fail> {
+        match self.inner.borrow().sockets.get(&fd) {
+            Some(Socket::Inactive { local: None }) => Ok(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+            Some(Socket::Inactive { local: Some(local) })
+            | Some(Socket::Listening { local })
+            | Some(Socket::Connecting { local, .. })
+            | Some(Socket::Established { local, .. }) => Ok(*local),
+            None => Err(Fail::new(EBADF, "bad queue descriptor")),
+        }
+    }
+
     pub fn endpoints(&self, fd: QDesc) -> Result<(SocketAddrV4, SocketAddrV4), Fail> {
         let inner = self.inner.borrow();
         let key = match inner.sockets.get(&fd) {
@@ -433,12 +824,106 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> TcpPeer<RT> {
             None => Err(Fail::new(ENOTCONN, "connection not established")),
         }
     }
+
+    /// Wraps an established connection into a [TcpStream], which implements the `futures` I/O traits.
+    pub fn stream(&self, fd: QDesc) -> Result<TcpStream<RT>, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(s) => Ok(TcpStream::new(s.clone())),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the reason why the connection with the given endpoints was closed, if it is. This remains available after
+    /// the socket is closed, until the connection is forgotten.
+    pub fn close_reason(&self, endpoints: (SocketAddrV4, SocketAddrV4)) -> Option<CloseReason> {
+        self.inner.borrow().established.get(&endpoints)?.close_reason()
+    }
+
+    /// Takes the reason why the connection with the given endpoints was closed, if it is. Taking it clears it.
+    pub fn take_close_reason(&self, endpoints: (SocketAddrV4, SocketAddrV4)) -> Option<CloseReason> {
+        self.inner.borrow().established.get(&endpoints)?.take_close_reason()
+    }
+
+    /// Gets the time from our SYN to its SYN+ACK on a connection, if we opened it actively, like the time `connect`
+    /// took once the link address of the remote host was resolved.
+    pub fn handshake_rtt(&self, fd: QDesc) -> Result<Option<Duration>, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.handshake_rtt()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Gets the time that the connection with the given endpoints has spent in each state so far. This remains
+    /// available after the socket is closed, until the connection is forgotten.
+    pub fn state_durations(&self, endpoints: (SocketAddrV4, SocketAddrV4)) -> Option<StateDurations> {
+        Some(self.inner.borrow().established.get(&endpoints)?.state_durations())
+    }
+
+    /// Takes the last non-fatal error on a connection, if any, like `SO_ERROR`. Taking it clears it.
+    pub fn take_error(&self, fd: QDesc) -> Result<Option<Fail>, Fail> {
+        let inner = self.inner.borrow();
+        let key = match inner.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => return Err(Fail::new(ENOTCONN, "connection not established")),
+            None => return Err(Fail::new(EBADF, "bad queue descriptor")),
+        };
+        match inner.established.get(&key) {
+            Some(ref s) => Ok(s.take_error()),
+            None => Err(Fail::new(ENOTCONN, "connection not established")),
+        }
+    }
+
+    /// Handles an ICMP error message about a segment that we sent. On an established connection, this is a soft error
+    /// that the user may take, rather than a reason to give up on the connection.
+    pub fn receive_icmp_error(&self, local: SocketAddrV4, remote: SocketAddrV4, cause: Fail) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(s) = inner.established.get(&(local, remote)) {
+            debug!("ICMP error on connection {:?}: {:?}", (local, remote), cause);
+            s.record_soft_error(cause);
+        } else if let Some(s) = inner.connecting.get_mut(&(local, remote)) {
+            debug!("ICMP error on connecting connection {:?}: {:?}", (local, remote), cause);
+            s.receive_icmp_error(cause);
+        } else {
+            debug!("Ignoring ICMP error for {:?}: {:?}", (local, remote), cause);
+        }
+    }
+
+    /// Gets the state of the connection with the given endpoints, which remains available after the socket is closed.
+    #[cfg(test)]
+    pub fn connection_state(&self, endpoints: (SocketAddrV4, SocketAddrV4)) -> Option<State> {
+        self.inner.borrow().established.get(&endpoints).map(|s| s.state())
+    }
+
+    /// Gets the nonce that the initial sequence numbers of active opens are derived from.
+    #[cfg(test)]
+    pub fn isn_nonce(&self) -> u32 {
+        self.inner.borrow().isn_generator.get_nonce()
+    }
+
+    /// Gets the control block of the connection with the given endpoints, to inspect its state.
+    #[cfg(test)]
+    pub fn control_block(&self, endpoints: (SocketAddrV4, SocketAddrV4)) -> Option<Rc<ControlBlock<RT>>> {
+        self.inner.borrow().established.get(&endpoints).map(|s| s.cb.clone())
+    }
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Inner<RT> {
     fn new(
         rt: RT,
         arp: ArpPeer<RT>,
+        loopback: LoopbackSender,
         rng_seed: [u8; 32],
         dead_socket_tx: mpsc::UnboundedSender<QDesc>,
         _dead_socket_rx: mpsc::UnboundedReceiver<QDesc>,
@@ -453,27 +938,61 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Inner<RT> {
             passive: HashMap::new(),
             connecting: HashMap::new(),
             established: HashMap::new(),
+            closed: HashMap::new(),
             rt,
+            source_ipv4_addr: None,
             arp,
+            loopback,
             rng: Rc::new(RefCell::new(rng)),
+            config: TcpConfigExt::default(),
+            congestion_control: congestion_control::None::new,
             dead_socket_tx,
         }
     }
 
+    /// Gets the address that connections from sockets which are not bound to a specific one come from.
+    fn source_ipv4_addr(&self) -> Ipv4Addr {
+        self.source_ipv4_addr.unwrap_or(self.rt.local_ipv4_addr())
+    }
+
     fn receive(&mut self, ip_hdr: &Ipv4Header, buf: Buffer) -> Result<(), Fail> {
         let tcp_options = self.rt.tcp_options();
-        let (mut tcp_hdr, data) = TcpHeader::parse(ip_hdr, buf, tcp_options.get_rx_checksum_offload())?;
+        let (tcp_hdr, data) = TcpHeader::parse(ip_hdr, buf, tcp_options.get_rx_checksum_offload())?;
         debug!("TCP received {:?}", tcp_hdr);
-        let local = SocketAddrV4::new(ip_hdr.get_dest_addr(), tcp_hdr.dst_port);
         let remote = SocketAddrV4::new(ip_hdr.get_src_addr(), tcp_hdr.src_port);
 
         if remote.ip().is_broadcast() || remote.ip().is_multicast() || remote.ip().is_unspecified() {
             return Err(Fail::new(EINVAL, "invalid address type"));
         }
+        // RFC 2385: segments exchanged with a host that shares a key with us must carry a valid signature, and those
+        // exchanged with any other host must not carry one.
+        match self.config.get_md5_key(remote.ip()) {
+            Some(md5_key) if !tcp_hdr.verify_md5(ip_hdr, &data[..], md5_key) => {
+                warn!("Dropping segment from {}: TCP MD5 signature mismatch", remote);
+                return Err(Fail::new(EBADMSG, "TCP MD5 signature mismatch"));
+            },
+            None if tcp_hdr.md5_option().is_some() => {
+                warn!("Dropping segment from {}: unexpected TCP MD5 signature", remote);
+                return Err(Fail::new(EBADMSG, "unexpected TCP MD5 signature"));
+            },
+            _ => (),
+        }
+        self.receive_segment(ip_hdr, tcp_hdr, data)
+    }
+
+    /// Routes a segment to the connection or listening socket that it is for.
+    fn receive_segment(&mut self, ip_hdr: &Ipv4Header, mut tcp_hdr: TcpHeader, data: Buffer) -> Result<(), Fail> {
+        let local = SocketAddrV4::new(ip_hdr.get_dest_addr(), tcp_hdr.dst_port);
+        let remote = SocketAddrV4::new(ip_hdr.get_src_addr(), tcp_hdr.src_port);
         let key = (local, remote);
 
+        // A SYN for a connection that is already closed attempts to open a new incarnation of it.
+        if tcp_hdr.syn && !tcp_hdr.ack {
+            self.reap_closed(&key);
+        }
         if let Some(s) = self.established.get(&key) {
             debug!("Routing to established connection: {:?}", key);
+            s.cb.set_received_ttl(ip_hdr.get_ttl());
             s.receive(&mut tcp_hdr, data);
             return Ok(());
         }
@@ -482,19 +1001,75 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Inner<RT> {
             s.receive(&tcp_hdr);
             return Ok(());
         }
-        let (local, _) = key;
-        if let Some(s) = self.passive.get_mut(&local) {
-            debug!("Routing to passive connection: {:?}", local);
-            return s.receive(ip_hdr, &tcp_hdr);
+        let passive_key: SocketAddrV4 = self.passive_key(&local);
+        if let Some(s) = self.passive.get_mut(&passive_key) {
+            debug!("Routing to passive connection: {:?}", passive_key);
+            let last_seq_num: Option<SeqNumber> = self.last_seq_num(&key);
+            return s.receive(ip_hdr, &tcp_hdr, data, last_seq_num);
         }
 
-        // The packet isn't for an open port; send a RST segment.
+        // The packet isn't for an open port; send a RST segment, unless it is one itself.
+        if tcp_hdr.rst {
+            return Ok(());
+        }
         debug!("Sending RST for {:?}, {:?}", local, remote);
-        self.send_rst(&local, &remote)?;
+        self.send_rst(&local, &remote, &tcp_hdr, data.len())?;
         Ok(())
     }
 
-    fn send_rst(&mut self, local: &SocketAddrV4, remote: &SocketAddrV4) -> Result<(), Fail> {
+    /// Gets the key of the listening socket that segments for a local endpoint are routed to. A socket that listens on
+    /// the specific local address takes precedence over one that listens on the wildcard.
+    fn passive_key(&self, local: &SocketAddrV4) -> SocketAddrV4 {
+        match self.passive.contains_key(local) {
+            true => *local,
+            false => SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, local.port()),
+        }
+    }
+
+    /// Checks if a connection on the given 4-tuple is being opened, either actively or by a listening socket.
+    fn is_opening(&self, key: &(SocketAddrV4, SocketAddrV4)) -> bool {
+        let (local, remote) = key;
+        self.connecting.contains_key(key)
+            || self
+                .passive
+                .get(&self.passive_key(local))
+                .map_or(false, |s| s.is_accepting(remote))
+    }
+
+    /// Forgets about a connection if it is closed, remembering the final sequence number that we used on it. Returns
+    /// `true` if there is no connection left on the given 4-tuple.
+    fn reap_closed(&mut self, key: &(SocketAddrV4, SocketAddrV4)) -> bool {
+        let now: Instant = self.rt.now();
+        self.closed.retain(|_, (_, when)| now < *when + MSL);
+        match self.established.get(key) {
+            Some(s) if s.state() == State::Closed => {
+                let last_seq_num: SeqNumber = s.send_next() - SeqNumber::from(1);
+                self.established.remove(key);
+                self.closed.insert(*key, (last_seq_num, now));
+                true
+            },
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    /// Gets the final sequence number that we used on a recently closed connection with the given 4-tuple.
+    fn last_seq_num(&self, key: &(SocketAddrV4, SocketAddrV4)) -> Option<SeqNumber> {
+        let now: Instant = self.rt.now();
+        match self.closed.get(key) {
+            Some(&(last_seq_num, when)) if now < when + MSL => Some(last_seq_num),
+            _ => None,
+        }
+    }
+
+    /// Sends a RST in reply to a segment with the given header and amount of data, which is not for any connection.
+    fn send_rst(
+        &mut self,
+        local: &SocketAddrV4,
+        remote: &SocketAddrV4,
+        header: &TcpHeader,
+        data_len: usize,
+    ) -> Result<(), Fail> {
         // TODO: Make this work pending on ARP resolution if needed.
         let remote_link_addr = self
             .arp
@@ -503,15 +1078,30 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Inner<RT> {
 
         let mut tcp_hdr = TcpHeader::new(local.port(), remote.port());
         tcp_hdr.rst = true;
+        // RFC 793, section 3.4: the RST takes its sequence number from the ACK field of the offending segment, if it has
+        // one. Otherwise, it acknowledges the segment, so that the sender finds it acceptable (e.g. in SYN-SENT).
+        if header.ack {
+            tcp_hdr.seq_num = header.ack_num;
+        } else {
+            let seg_len: u32 = data_len as u32 + header.syn as u32 + header.fin as u32;
+            tcp_hdr.ack = true;
+            tcp_hdr.ack_num = header.seq_num + SeqNumber::from(seg_len);
+        }
+
+        let ipv4_hdr: Ipv4Header = Ipv4Header::new(local.ip().clone(), remote.ip().clone(), IpProtocol::TCP);
+        if let Some(key) = self.config.get_md5_key(remote.ip()) {
+            tcp_hdr.sign_md5(&ipv4_hdr, &[], key);
+        }
 
         let segment = TcpSegment {
-            ethernet2_hdr: Ethernet2Header::new(remote_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4),
-            ipv4_hdr: Ipv4Header::new(local.ip().clone(), remote.ip().clone(), IpProtocol::TCP),
+            ethernet2_hdr: Ethernet2Header::new(remote_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4)
+                .with_vlan_tag(self.arp.vlan_tag()),
+            ipv4_hdr,
             tcp_hdr,
             data: Buffer::Heap(DataBuffer::empty()),
             tx_checksum_offload: self.rt.tcp_options().get_rx_checksum_offload(),
         };
-        self.rt.transmit(segment);
+        loopback::transmit(&self.rt, &self.loopback, self.config.get_transmit_hook(), segment);
 
         Ok(())
     }
@@ -2,7 +2,11 @@
 // Licensed under the MIT license.
 
 use crate::protocols::{
-    ethernet2::Ethernet2Header,
+    ethernet2::{
+        pad_body,
+        padding_size,
+        Ethernet2Header,
+    },
     ip::IpProtocol,
     ipv4::Ipv4Header,
     tcp::SeqNumber,
@@ -20,7 +24,11 @@ use ::runtime::{
 };
 use ::std::{
     convert::TryInto,
-    io::Cursor,
+    io::{
+        Cursor,
+        Read,
+    },
+    time::Instant,
 };
 
 pub const MIN_TCP_HEADER_SIZE: usize = 20;
@@ -42,7 +50,7 @@ impl PacketBuf for TcpSegment {
     }
 
     fn body_size(&self) -> usize {
-        self.data.len()
+        self.data.len() + padding_size(self.header_size() + self.data.len())
     }
 
     fn write_header(&self, buf: &mut [u8]) {
@@ -69,7 +77,11 @@ impl PacketBuf for TcpSegment {
     }
 
     fn take_body(self) -> Option<Buffer> {
-        Some(self.data)
+        // Pad short frames to the minimum Ethernet frame size.
+        match padding_size(self.header_size() + self.data.len()) {
+            0 => Some(self.data),
+            padding => Some(pad_body(&self.data[..], padding)),
+        }
     }
 }
 
@@ -93,9 +105,20 @@ pub enum TcpOptions2 {
         sender_timestamp: u32,
         echo_timestamp: u32,
     },
+    /// MD5 digest that authenticates the segment (RFC 2385).
+    Md5Signature([u8; 16]),
 }
 
 impl TcpOptions2 {
+    /// Builds a timestamps option (RFC 7323) that echoes `echo_timestamp`. Our timestamp clock ticks once per
+    /// millisecond from `started`, the start of the connection.
+    pub fn timestamp(started: Instant, now: Instant, echo_timestamp: u32) -> Self {
+        TcpOptions2::Timestamp {
+            sender_timestamp: now.saturating_duration_since(started).as_millis() as u32,
+            echo_timestamp,
+        }
+    }
+
     fn compute_size(&self) -> usize {
         use TcpOptions2::*;
         match self {
@@ -105,6 +128,7 @@ impl TcpOptions2 {
             SelectiveAcknowlegementPermitted => 2,
             SelectiveAcknowlegement { num_sacks, .. } => 2 + 8 * num_sacks,
             Timestamp { .. } => 10,
+            Md5Signature(..) => 18,
         }
     }
 
@@ -151,11 +175,17 @@ impl TcpOptions2 {
                 NetworkEndian::write_u32(&mut buf[6..10], *echo_timestamp);
                 10
             },
+            Md5Signature(digest) => {
+                buf[0] = 19;
+                buf[1] = 18;
+                buf[2..18].copy_from_slice(digest);
+                18
+            },
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TcpHeader {
     pub src_port: u16,
     pub dst_port: u16,
@@ -226,6 +256,9 @@ impl TcpHeader {
         if data_offset > MAX_TCP_HEADER_SIZE {
             return Err(Fail::new(EBADMSG, "TCP data offset too large"));
         }
+        if (buf[12] & 0x0e) != 0 {
+            return Err(Fail::new(EBADMSG, "TCP reserved bits set"));
+        }
         let (hdr_buf, data_buf) = buf[..].split_at(data_offset);
 
         let src_port = NetworkEndian::read_u16(&hdr_buf[0..2]);
@@ -255,6 +288,10 @@ impl TcpHeader {
         }
 
         let urgent_pointer = NetworkEndian::read_u16(&hdr_buf[18..20]);
+        // The urgent pointer is an offset from the sequence number of the segment, so it may not point past its data.
+        if urg && urgent_pointer as usize > data_buf.len() {
+            return Err(Fail::new(EBADMSG, "TCP urgent pointer past end of data"));
+        }
 
         let mut num_options = 0;
         let mut option_list = [TcpOptions2::NoOperation; MAX_TCP_OPTIONS];
@@ -317,6 +354,15 @@ impl TcpHeader {
                             echo_timestamp,
                         }
                     },
+                    19 => {
+                        let option_length = option_rdr.read_u8()?;
+                        if option_length != 18 {
+                            return Err(Fail::new(EBADMSG, "TCP MD5 signature size was not 18"));
+                        }
+                        let mut digest: [u8; 16] = [0; 16];
+                        option_rdr.read_exact(&mut digest)?;
+                        TcpOptions2::Md5Signature(digest)
+                    },
                     _ => return Err(Fail::new(EBADMSG, "invalid TCP option")),
                 };
                 if num_options >= option_list.len() {
@@ -353,6 +399,34 @@ impl TcpHeader {
 
     pub fn serialize(&self, buf: &mut [u8], ipv4_hdr: &Ipv4Header, data: &[u8], tx_checksum_offload: bool) {
         let fixed_buf: &mut [u8; MIN_TCP_HEADER_SIZE] = (&mut buf[..MIN_TCP_HEADER_SIZE]).try_into().unwrap();
+        self.serialize_fixed(fixed_buf);
+
+        let mut cur_pos = MIN_TCP_HEADER_SIZE;
+        for i in 0..self.num_options {
+            let bytes_written = self.option_list[i].serialize(&mut buf[cur_pos..]);
+            cur_pos += bytes_written;
+        }
+        // Write out an "End of options list" if we had options.
+        if self.num_options > 0 {
+            buf[cur_pos] = 0;
+            cur_pos += 1;
+        }
+        // Zero out the remainder of padding in the header.
+        for byte in &mut buf[cur_pos..] {
+            *byte = 0;
+        }
+
+        // Alright, we've fully filled out the header, time to compute the checksum.
+        if !tx_checksum_offload {
+            let checksum = tcp_checksum(ipv4_hdr, &buf[..], data);
+            NetworkEndian::write_u16(&mut buf[16..18], checksum);
+        } else {
+            NetworkEndian::write_u16(&mut buf[16..18], 0u16);
+        }
+    }
+
+    /// Serializes the part of this header that precedes the options. The checksum is left as zero.
+    fn serialize_fixed(&self, fixed_buf: &mut [u8; MIN_TCP_HEADER_SIZE]) {
         NetworkEndian::write_u16(&mut fixed_buf[0..2], self.src_port.into());
         NetworkEndian::write_u16(&mut fixed_buf[2..4], self.dst_port.into());
         NetworkEndian::write_u32(&mut fixed_buf[4..8], self.seq_num.into());
@@ -390,32 +464,10 @@ impl TcpHeader {
 
         NetworkEndian::write_u16(&mut fixed_buf[14..16], self.window_size);
 
-        // Write the checksum (bytes 16..18) later.
+        // The checksum (bytes 16..18) is computed over the whole segment.
+        NetworkEndian::write_u16(&mut fixed_buf[16..18], 0u16);
 
         NetworkEndian::write_u16(&mut fixed_buf[18..20], self.urgent_pointer);
-
-        let mut cur_pos = MIN_TCP_HEADER_SIZE;
-        for i in 0..self.num_options {
-            let bytes_written = self.option_list[i].serialize(&mut buf[cur_pos..]);
-            cur_pos += bytes_written;
-        }
-        // Write out an "End of options list" if we had options.
-        if self.num_options > 0 {
-            buf[cur_pos] = 0;
-            cur_pos += 1;
-        }
-        // Zero out the remainder of padding in the header.
-        for byte in &mut buf[cur_pos..] {
-            *byte = 0;
-        }
-
-        // Alright, we've fully filled out the header, time to compute the checksum.
-        if !tx_checksum_offload {
-            let checksum = tcp_checksum(ipv4_hdr, &buf[..], data);
-            NetworkEndian::write_u16(&mut buf[16..18], checksum);
-        } else {
-            NetworkEndian::write_u16(&mut buf[16..18], 0u16);
-        }
     }
 
     // TODO: Review the use of usize here (and everywhere in inetstack, really).
@@ -442,6 +494,87 @@ impl TcpHeader {
         self.option_list[self.num_options] = option;
         self.num_options += 1;
     }
+
+    /// Gets the maximum segment size that this header advertises, if any.
+    pub fn mss_option(&self) -> Option<u16> {
+        self.iter_options().find_map(|option| match option {
+            TcpOptions2::MaximumSegmentSize(mss) => Some(*mss),
+            _ => None,
+        })
+    }
+
+    /// Gets the window scale that this header advertises, if any.
+    pub fn window_scale_option(&self) -> Option<u8> {
+        self.iter_options().find_map(|option| match option {
+            TcpOptions2::WindowScale(window_scale) => Some(*window_scale),
+            _ => None,
+        })
+    }
+
+    /// Checks if this header offers selective acknowledgements.
+    pub fn sack_permitted(&self) -> bool {
+        self.iter_options()
+            .any(|option| matches!(option, TcpOptions2::SelectiveAcknowlegementPermitted))
+    }
+
+    /// Gets the timestamp and the echoed timestamp of this header, if it carries the timestamps option.
+    pub fn timestamp_option(&self) -> Option<(u32, u32)> {
+        self.iter_options().find_map(|option| match option {
+            TcpOptions2::Timestamp {
+                sender_timestamp,
+                echo_timestamp,
+            } => Some((*sender_timestamp, *echo_timestamp)),
+            _ => None,
+        })
+    }
+
+    /// Gets the MD5 signature of this header, if any.
+    pub fn md5_option(&self) -> Option<[u8; 16]> {
+        self.iter_options().find_map(|option| match option {
+            TcpOptions2::Md5Signature(digest) => Some(*digest),
+            _ => None,
+        })
+    }
+
+    /// Signs this header with an MD5 signature option (RFC 2385) for the given IPv4 header, payload and shared key.
+    /// The signature covers the header, so it must be the last change to the header before it is sent.
+    pub fn sign_md5(&mut self, ipv4_hdr: &Ipv4Header, data: &[u8], key: &[u8]) {
+        self.push_option(TcpOptions2::Md5Signature([0; 16]));
+        let digest: [u8; 16] = self.compute_md5(ipv4_hdr, self.compute_size(), data, key);
+        self.option_list[self.num_options - 1] = TcpOptions2::Md5Signature(digest);
+    }
+
+    /// Checks the MD5 signature option (RFC 2385) of a received header against the given shared key. The IPv4 header
+    /// and payload are the ones that the header was received with. Returns `false` if the header is not signed.
+    pub fn verify_md5(&self, ipv4_hdr: &Ipv4Header, data: &[u8], key: &[u8]) -> bool {
+        // The options that we received may have been padded differently than we would, so take the header size from
+        // the datagram.
+        let segment_len: usize = (ipv4_hdr.get_total_length() as usize).saturating_sub(ipv4_hdr.compute_size());
+        let header_len: usize = segment_len.saturating_sub(data.len());
+        match self.md5_option() {
+            Some(digest) => digest == self.compute_md5(ipv4_hdr, header_len, data, key),
+            None => false,
+        }
+    }
+
+    /// Computes the MD5 digest of a segment with this header and the given size, as per RFC 2385, section 2.0.
+    fn compute_md5(&self, ipv4_hdr: &Ipv4Header, header_len: usize, data: &[u8], key: &[u8]) -> [u8; 16] {
+        let mut fixed_buf: [u8; MIN_TCP_HEADER_SIZE] = [0; MIN_TCP_HEADER_SIZE];
+        self.serialize_fixed(&mut fixed_buf);
+        fixed_buf[12] = ((header_len / 4) as u8) << 4 | (fixed_buf[12] & 1);
+
+        let mut context: md5::Context = md5::Context::new();
+        // Pseudo-header.
+        context.consume(ipv4_hdr.get_src_addr().octets());
+        context.consume(ipv4_hdr.get_dest_addr().octets());
+        context.consume([0, IpProtocol::TCP as u8]);
+        context.consume(((header_len + data.len()) as u16).to_be_bytes());
+        // Header without options, then payload and key.
+        context.consume(fixed_buf);
+        context.consume(data);
+        context.consume(key);
+        context.compute().0
+    }
 }
 
 fn tcp_checksum(ipv4_header: &Ipv4Header, header: &[u8], data: &[u8]) -> u16 {
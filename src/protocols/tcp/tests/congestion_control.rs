@@ -0,0 +1,644 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    protocols::tcp::{
+        config::DEFAULT_DUPACK_THRESHOLD,
+        congestion_control::{
+            Bbr,
+            BbrState,
+            CongestionControl,
+            CongestionPhase,
+            Cubic,
+            FastRetransmitRecovery,
+            LimitedTransmit,
+            Options,
+            SlowStartCongestionAvoidance,
+        },
+        operations::PushFuture,
+        tests::{
+            established::cook_buffer,
+            setup::{
+                advance_clock,
+                connection_setup,
+            },
+        },
+        SeqNumber,
+        TcpConfigExt,
+    },
+    test_helpers::{
+        self,
+        Engine,
+        TestRuntime,
+    },
+};
+use ::futures::task::noop_waker_ref;
+use ::libc::EINVAL;
+use ::runtime::{
+    memory::Buffer,
+    QDesc,
+};
+use ::std::{
+    cell::RefCell,
+    future::Future,
+    net::SocketAddrV4,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+//=============================================================================
+
+/// Congestion control event.
+#[derive(Debug, Eq, PartialEq)]
+enum Event {
+    Send(u32),
+    Ack(u32),
+    DupAck,
+    Loss(bool),
+}
+
+thread_local! {
+    /// Events that the recording congestion control of the running test was notified of.
+    static EVENTS: RefCell<Vec<Event>> = RefCell::new(Vec::new());
+}
+
+/// Congestion control that records the events it is notified of.
+#[derive(Debug)]
+struct Recorder;
+
+impl Recorder {
+    fn record(event: Event) {
+        EVENTS.with(|events| events.borrow_mut().push(event));
+    }
+}
+
+impl CongestionControl<TestRuntime> for Recorder {
+    fn new(_mss: usize, _seq_no: SeqNumber, _options: Option<Options>) -> Box<dyn CongestionControl<TestRuntime>> {
+        Box::new(Recorder)
+    }
+}
+
+impl SlowStartCongestionAvoidance<TestRuntime> for Recorder {
+    fn on_send(&self, _rto: Duration, num_sent_bytes: u32, _now: Instant) {
+        Self::record(Event::Send(num_sent_bytes));
+    }
+
+    fn on_ack(&self, bytes_acknowledged: u32, _rtt: Option<Duration>, _now: Instant) {
+        Self::record(Event::Ack(bytes_acknowledged));
+    }
+
+    fn on_loss(&self, is_rto: bool) {
+        Self::record(Event::Loss(is_rto));
+    }
+}
+
+impl FastRetransmitRecovery<TestRuntime> for Recorder {
+    fn on_dup_ack(&self) {
+        Self::record(Event::DupAck);
+    }
+}
+
+impl LimitedTransmit<TestRuntime> for Recorder {}
+
+//=============================================================================
+
+/// Tests if fast retransmit waits for the configured number of duplicate ACKs.
+#[test]
+fn test_dupack_threshold() {
+    let mss: usize = 536;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let send_next: SeqNumber = seq_no + SeqNumber::from(4 * mss as u32);
+    let rto: Duration = Duration::from_secs(1);
+    let now: Instant = Instant::now();
+
+    let config: TcpConfigExt = TcpConfigExt::default().dupack_threshold(Some(5)).unwrap();
+    let mut options: Options = Options::default();
+    options.insert_int("dupack_threshold".to_string(), config.get_dupack_threshold() as i64);
+    let cc: Box<dyn CongestionControl<TestRuntime>> =
+        <Cubic as CongestionControl<TestRuntime>>::new(mss, seq_no, Some(options));
+
+    // No fast retransmit before the fifth duplicate ACK.
+    for _ in 0..4 {
+        cc.on_ack_received(rto, seq_no, send_next, seq_no, now);
+        assert!(!cc.get_retransmit_now_flag());
+    }
+
+    // The fifth duplicate ACK triggers a fast retransmit.
+    cc.on_ack_received(rto, seq_no, send_next, seq_no, now);
+    assert_eq!(cc.get_duplicate_ack_count(), 5);
+    assert!(cc.get_retransmit_now_flag());
+}
+
+/// Tests if duplicate ACK thresholds that cannot trigger a fast retransmit are rejected, and if Cubic falls back to the
+/// default threshold when it is given one anyway.
+#[test]
+fn test_dupack_threshold_out_of_range() {
+    match TcpConfigExt::default().dupack_threshold(Some(0)) {
+        Err(e) => assert_eq!(e.errno, EINVAL),
+        Ok(_) => panic!("threshold 0 should have been rejected"),
+    }
+    assert!(TcpConfigExt::default().dupack_threshold(Some(1)).is_ok());
+
+    let mss: usize = 536;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let send_next: SeqNumber = seq_no + SeqNumber::from(4 * mss as u32);
+    let rto: Duration = Duration::from_secs(1);
+    let now: Instant = Instant::now();
+
+    for threshold in [0, -1, i64::from(u32::MAX) + 1] {
+        let mut options: Options = Options::default();
+        options.insert_int("dupack_threshold".to_string(), threshold);
+        let cc: Box<dyn CongestionControl<TestRuntime>> =
+            <Cubic as CongestionControl<TestRuntime>>::new(mss, seq_no, Some(options));
+
+        // Only the default number of duplicate ACKs triggers a fast retransmit.
+        for _ in 1..DEFAULT_DUPACK_THRESHOLD {
+            cc.on_ack_received(rto, seq_no, send_next, seq_no, now);
+            assert!(!cc.get_retransmit_now_flag());
+        }
+        cc.on_ack_received(rto, seq_no, send_next, seq_no, now);
+        assert!(cc.get_retransmit_now_flag());
+    }
+}
+
+/// Tests if the reported phase goes from slow start to fast recovery on a loss, and then to congestion avoidance.
+#[test]
+fn test_cubic_phase() {
+    let mss: usize = 536;
+    let mss32: u32 = mss as u32;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let send_next: SeqNumber = seq_no + SeqNumber::from(8 * mss32);
+    let rto: Duration = Duration::from_secs(1);
+    let now: Instant = Instant::now();
+    let cc: Box<dyn CongestionControl<TestRuntime>> = <Cubic as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+    assert_eq!(cc.get_phase(), CongestionPhase::SlowStart);
+
+    // The first segment is acknowledged, which grows the congestion window.
+    let send_unacked: SeqNumber = seq_no + SeqNumber::from(mss32);
+    cc.on_ack_received(rto, seq_no, send_next, send_unacked, now);
+    assert_eq!(cc.get_phase(), CongestionPhase::SlowStart);
+
+    // The second segment is lost, so the following ones are acknowledged with duplicate ACKs.
+    for _ in 0..3 {
+        cc.on_ack_received(rto, send_unacked, send_next, send_unacked, now);
+    }
+    assert_eq!(cc.get_phase(), CongestionPhase::FastRecovery);
+
+    // The retransmission fills the hole, and everything is acknowledged, including a segment sent during recovery.
+    let send_next: SeqNumber = send_next + SeqNumber::from(mss32);
+    cc.on_ack_received(rto, send_unacked, send_next, send_next, now);
+    assert_eq!(cc.get_phase(), CongestionPhase::CongestionAvoidance);
+
+    // Other algorithms report their own phases.
+    let bbr: Box<dyn CongestionControl<TestRuntime>> = <Bbr as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+    assert_eq!(bbr.get_phase(), CongestionPhase::Bbr(BbrState::Startup));
+}
+
+//=============================================================================
+
+/// Tests if the congestion window is restored when the ACKs after a retransmission timeout show that the original
+/// transmissions were delivered.
+#[test]
+fn test_spurious_rto() {
+    let mss: usize = 536;
+    let mss32: u32 = mss as u32;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let rto: Duration = Duration::from_secs(1);
+    let now: Instant = Instant::now();
+    let cc: Box<dyn CongestionControl<TestRuntime>> = <Cubic as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+
+    // Four segments are in flight when a delay spike makes the retransmission timer expire.
+    let send_next: SeqNumber = seq_no + SeqNumber::from(4 * mss32);
+    let cwnd: u32 = cc.get_cwnd();
+    cc.on_rto(seq_no, send_next);
+    assert_eq!(cc.get_cwnd(), mss32);
+
+    // The delayed ACKs for the original segments then arrive.
+    let mut send_unacked: SeqNumber = seq_no;
+    for _ in 0..2 {
+        let ack_seq_no: SeqNumber = send_unacked + SeqNumber::from(mss32);
+        cc.on_ack_received(rto, send_unacked, send_next, ack_seq_no, now);
+        send_unacked = ack_seq_no;
+    }
+    assert_eq!(cc.get_cwnd(), cwnd);
+}
+
+/// Tests if the congestion window stays reduced when the ACKs after a retransmission timeout show that segments were
+/// lost.
+#[test]
+fn test_genuine_rto() {
+    let mss: usize = 536;
+    let mss32: u32 = mss as u32;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let rto: Duration = Duration::from_secs(1);
+    let now: Instant = Instant::now();
+    let cc: Box<dyn CongestionControl<TestRuntime>> = <Cubic as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+
+    // Four segments are in flight when the retransmission timer expires.
+    let send_next: SeqNumber = seq_no + SeqNumber::from(4 * mss32);
+    let cwnd: u32 = cc.get_cwnd();
+    cc.on_rto(seq_no, send_next);
+
+    // The retransmission is acknowledged, but the following segment was lost too.
+    let send_unacked: SeqNumber = seq_no + SeqNumber::from(mss32);
+    cc.on_ack_received(rto, seq_no, send_next, send_unacked, now);
+    cc.on_ack_received(rto, send_unacked, send_next, send_unacked, now);
+    assert!(cc.get_cwnd() < cwnd);
+}
+
+/// Sends `num_bytes` in segments of at most `mss` bytes, and has them acknowledged one segment at a time at time `now`.
+/// Returns the next sequence number to send.
+fn send_round(
+    cc: &dyn CongestionControl<TestRuntime>,
+    mss: u32,
+    rto: Duration,
+    now: Instant,
+    seq_no: SeqNumber,
+    num_bytes: u32,
+) -> SeqNumber {
+    let send_next: SeqNumber = seq_no + SeqNumber::from(num_bytes);
+    let mut send_unacked: SeqNumber = seq_no;
+    while send_unacked != send_next {
+        let segment_len: u32 = (send_next - send_unacked).into();
+        cc.on_send(rto, segment_len.min(mss), now);
+        send_unacked = send_unacked + SeqNumber::from(segment_len.min(mss));
+    }
+    let mut send_unacked: SeqNumber = seq_no;
+    while send_unacked != send_next {
+        let segment_len: u32 = (send_next - send_unacked).into();
+        let ack_seq_no: SeqNumber = send_unacked + SeqNumber::from(segment_len.min(mss));
+        cc.on_ack_received(rto, send_unacked, send_next, ack_seq_no, now);
+        send_unacked = ack_seq_no;
+    }
+    send_next
+}
+
+/// Tests if Cubic restarts from its initial window after being idle for longer than the retransmission timeout, as
+/// measured by the clock that it is given rather than the wall clock.
+#[test]
+fn test_slow_start_restart() {
+    let mss: usize = 536;
+    let mss32: u32 = mss as u32;
+    let rto: Duration = Duration::from_secs(1);
+    let cc: Box<dyn CongestionControl<TestRuntime>> =
+        <Cubic as CongestionControl<TestRuntime>>::new(mss, SeqNumber::from(1), None);
+    let initial_cwnd: u32 = cc.get_cwnd();
+
+    // Open up the window.
+    let mut now: Instant = Instant::now();
+    let mut seq_no: SeqNumber = SeqNumber::from(1);
+    for _ in 0..3 {
+        seq_no = send_round(cc.as_ref(), mss32, rto, now, seq_no, cc.get_cwnd());
+        now += Duration::from_millis(10);
+    }
+    let cwnd: u32 = cc.get_cwnd();
+    assert!(cwnd > initial_cwnd);
+
+    // A short pause leaves the window alone.
+    cc.on_cwnd_check_before_send(now + rto / 2);
+    assert_eq!(cc.get_cwnd(), cwnd);
+
+    // A pause longer than the retransmission timeout does not, even though no wall-clock time has passed.
+    cc.on_cwnd_check_before_send(now + 2 * rto);
+    assert_eq!(cc.get_cwnd(), initial_cwnd);
+}
+
+/// Tests if the congestion window of a connection that turns application-limited decays towards what it uses once the
+/// non-validated period has passed, so that a later burst does not go out at the stale window (RFC 7661).
+#[test]
+fn test_cwnd_validation() {
+    let mss: usize = 536;
+    let mss32: u32 = mss as u32;
+    let rto: Duration = Duration::from_secs(1);
+    let rtt: Duration = Duration::from_millis(10);
+    let cwv_period: Duration = Duration::from_millis(100);
+    let mut options: Options = Options::default();
+    options.insert_int("cwnd_validation_period_ms".to_string(), cwv_period.as_millis() as i64);
+    let validated: Box<dyn CongestionControl<TestRuntime>> =
+        <Cubic as CongestionControl<TestRuntime>>::new(mss, SeqNumber::from(1), Some(options));
+    let unvalidated: Box<dyn CongestionControl<TestRuntime>> =
+        <Cubic as CongestionControl<TestRuntime>>::new(mss, SeqNumber::from(1), None);
+    let initial_cwnd: u32 = validated.get_cwnd();
+
+    for cc in [&validated, &unvalidated] {
+        // Slow start opens up the window while the connection fills it, one round trip at a time.
+        let mut now: Instant = Instant::now();
+        let mut seq_no: SeqNumber = SeqNumber::from(1);
+        for _ in 0..3 {
+            seq_no = send_round(cc.as_ref(), mss32, rto, now, seq_no, cc.get_cwnd());
+            now += rtt;
+        }
+        assert_eq!(cc.get_cwnd(), 8 * initial_cwnd);
+
+        // The application then sends a single segment per round trip. The window is left alone until it has been
+        // underused for a whole non-validated period.
+        let app_limited_start: Instant = now;
+        while now + rtt < app_limited_start + cwv_period {
+            seq_no = send_round(cc.as_ref(), mss32, rto, now, seq_no, mss32);
+            now += rtt;
+        }
+        cc.on_cwnd_check_before_send(now);
+        assert!(cc.get_cwnd() >= 8 * initial_cwnd);
+
+        // From then on, it is halved once per non-validated period.
+        while now < app_limited_start + 5 * cwv_period {
+            seq_no = send_round(cc.as_ref(), mss32, rto, now, seq_no, mss32);
+            now += rtt;
+        }
+
+        // Right before a burst, the sender checks the window.
+        cc.on_cwnd_check_before_send(now);
+    }
+
+    // Once validated, the window no longer allows for more than what the connection started with, whereas the stale
+    // window would let a burst out at eight times that.
+    assert_eq!(validated.get_cwnd(), initial_cwnd);
+    assert!(unvalidated.get_cwnd() >= 8 * initial_cwnd);
+}
+
+/// Tests if CUBIC carries on from the congestion window of an algorithm that it takes over from, growing it along the
+/// cubic function with that window as its plateau.
+#[test]
+fn test_cubic_takeover() {
+    let mss: usize = 1460;
+    let mss32: u32 = mss as u32;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let rto: Duration = Duration::from_secs(1);
+    let now: Instant = Instant::now();
+    let bbr: Box<dyn CongestionControl<TestRuntime>> = <Bbr as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+    let cc: Box<dyn CongestionControl<TestRuntime>> = <Cubic as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+
+    // BBR has no slow start threshold, so the window that it reached becomes the threshold.
+    let cwnd: u32 = bbr.get_cwnd();
+    assert_eq!(bbr.get_ssthresh(), None);
+    cc.on_takeover(cwnd, cwnd);
+    assert_eq!(cc.get_cwnd(), cwnd);
+    assert_eq!(cc.get_phase(), CongestionPhase::CongestionAvoidance);
+
+    // Each ACK grows the window towards W_cubic(t + RTT) = C * (t + RTT - K)^3 + W_max, where W_max is the window that
+    // was handed over, K is zero, and t is about zero.
+    send_round(cc.as_ref(), mss32, rto, now, seq_no, cwnd);
+    let w_max: f32 = cwnd as f32 / mss as f32;
+    let w_cubic: f32 = 0.4 * rto.as_secs_f32().powi(3) + w_max;
+    let mut expected: u32 = cwnd;
+    for _ in 0..(cwnd / mss32) {
+        let segments: f32 = expected as f32 / mss as f32;
+        expected += ((w_cubic - segments) / segments * mss as f32) as u32;
+    }
+    assert!(cc.get_cwnd() > cwnd);
+    assert!(
+        cc.get_cwnd().abs_diff(expected) <= cwnd / mss32,
+        "{} != {}",
+        cc.get_cwnd(),
+        expected
+    );
+}
+
+//=============================================================================
+
+/// Tests if the sender notifies congestion control of sends, ACKs, duplicate ACKs, and losses as they happen.
+#[test]
+fn test_congestion_control_hooks() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    client
+        .ipv4
+        .tcp
+        .set_congestion_control(<Recorder as CongestionControl<TestRuntime>>::new);
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+    let mss32: u32 = mss as u32;
+    EVENTS.with(|events| events.borrow_mut().clear());
+
+    // Send two segments, the first of which gets lost.
+    for _ in 0..2 {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+    client.rt().poll_scheduler();
+    client.rt().pop_frame();
+    let bytes: Buffer = client.rt().pop_frame();
+
+    // The server answers the out-of-order segment with a duplicate ACK.
+    server.receive(bytes).unwrap();
+    let bytes: Buffer = server.rt().pop_frame();
+    client.receive(bytes).unwrap();
+
+    // The retransmission timer expires, and the lost segment is retransmitted.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    client.rt().poll_scheduler();
+    let bytes: Buffer = client.rt().pop_frame();
+
+    // The server acknowledges both segments.
+    server.receive(bytes).unwrap();
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    server.rt().poll_scheduler();
+    let bytes: Buffer = server.rt().pop_frame();
+    client.receive(bytes).unwrap();
+
+    EVENTS.with(|events| {
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                Event::Send(mss32),
+                Event::Send(mss32),
+                Event::DupAck,
+                Event::Loss(true),
+                Event::Ack(2 * mss32),
+            ]
+        )
+    });
+}
+
+//=============================================================================
+
+/// Tests if the delivery rate of BBR converges towards the bandwidth of the bottleneck link, without building up a
+/// standing queue. The link is simulated one round trip at a time: data beyond its bandwidth-delay product waits in
+/// the queue of the bottleneck, which adds to the round-trip time of all data in flight.
+#[test]
+fn test_bbr_converges_to_bandwidth() {
+    let mss: usize = 1460;
+    let mss32: u32 = mss as u32;
+    let seq_no: SeqNumber = SeqNumber::from(1);
+    let rto: Duration = Duration::from_secs(1);
+    let cc: Box<dyn CongestionControl<TestRuntime>> = <Bbr as CongestionControl<TestRuntime>>::new(mss, seq_no, None);
+
+    // A 10 Mbit/s link with a round-trip propagation time of 50 ms.
+    let bandwidth: f64 = 1_250_000.0;
+    let min_rtt: Duration = Duration::from_millis(50);
+    let bdp: f64 = bandwidth * min_rtt.as_secs_f64();
+
+    let mut now: Instant = Instant::now();
+    let mut delivery_rates: Vec<f64> = Vec::new();
+    for _ in 0..64 {
+        // Fill the congestion window.
+        let num_segments: u32 = cc.get_cwnd() / mss32;
+        for _ in 0..num_segments {
+            cc.on_send(rto, mss32, now);
+        }
+
+        // All of it is delivered within one round trip, which the queue at the bottleneck stretches.
+        let in_flight: f64 = (num_segments * mss32) as f64;
+        let rtt: Duration = min_rtt + Duration::from_secs_f64((in_flight - bdp).max(0.) / bandwidth);
+        for _ in 0..num_segments {
+            cc.on_ack(mss32, Some(rtt), now);
+        }
+        delivery_rates.push(in_flight / rtt.as_secs_f64());
+        now += rtt;
+    }
+
+    // Once the bandwidth of the link is found, BBR keeps it busy.
+    for delivery_rate in &delivery_rates[32..] {
+        assert!(*delivery_rate >= 0.9 * bandwidth, "{} bytes/s", delivery_rate);
+    }
+
+    // It paces data out at about the bandwidth of the link, give or take the gain of the current probing phase.
+    let pacing_rate: f64 = cc.get_pacing_rate().unwrap();
+    assert!(
+        pacing_rate >= 0.7 * bandwidth && pacing_rate <= 1.4 * bandwidth,
+        "{} bytes/s",
+        pacing_rate
+    );
+
+    // And keeps no more than about two bandwidth-delay products in flight, which bounds the queue at the bottleneck.
+    assert!((cc.get_cwnd() as f64) <= 2.1 * bdp);
+}
+
+/// Sends a whole congestion window of BBR at time `now`, and has all of it acknowledged with round-trip time `rtt`.
+fn bbr_round(cc: &dyn CongestionControl<TestRuntime>, mss: u32, rto: Duration, rtt: Duration, now: Instant) {
+    let num_segments: u32 = cc.get_cwnd() / mss;
+    for _ in 0..num_segments {
+        cc.on_send(rto, mss, now);
+    }
+    for _ in 0..num_segments {
+        cc.on_ack(mss, Some(rtt), now);
+    }
+}
+
+/// Tests if BBR probes the round-trip propagation time once its estimate has not been refreshed for a while, as
+/// measured by the clock that it is given, and goes back to its previous state after probing.
+#[test]
+fn test_bbr_probe_rtt() {
+    let mss: usize = 1460;
+    let mss32: u32 = mss as u32;
+    let rto: Duration = Duration::from_secs(1);
+    let cc: Box<dyn CongestionControl<TestRuntime>> =
+        <Bbr as CongestionControl<TestRuntime>>::new(mss, SeqNumber::from(1), None);
+
+    // The first round sets the estimate, which later rounds do not refresh, as their round-trip time is higher.
+    let rtt: Duration = Duration::from_millis(60);
+    let start: Instant = Instant::now();
+    bbr_round(cc.as_ref(), mss32, rto, Duration::from_millis(50), start);
+    let mut now: Instant = start + Bbr::MIN_RTT_FILTER_LEN;
+    bbr_round(cc.as_ref(), mss32, rto, rtt, now);
+    assert_eq!(cc.get_phase(), CongestionPhase::Bbr(BbrState::Startup));
+
+    // Once the estimate is older than the filter, BBR cuts the window to probe the round-trip time.
+    now += rtt;
+    bbr_round(cc.as_ref(), mss32, rto, rtt, now);
+    assert_eq!(cc.get_phase(), CongestionPhase::Bbr(BbrState::ProbeRtt));
+    assert_eq!(cc.get_cwnd(), 4 * mss32);
+
+    // It probes for a while, and then picks up where it left off.
+    let probe_start: Instant = now;
+    while cc.get_phase() == CongestionPhase::Bbr(BbrState::ProbeRtt) {
+        assert!(now < probe_start + 2 * Bbr::PROBE_RTT_DURATION);
+        now += rtt;
+        bbr_round(cc.as_ref(), mss32, rto, rtt, now);
+    }
+    assert!(now >= probe_start + Bbr::PROBE_RTT_DURATION);
+    assert_eq!(cc.get_phase(), CongestionPhase::Bbr(BbrState::Startup));
+}
+
+//=============================================================================
+
+/// Tests if the congestion control algorithm of an established connection can be replaced, and if the connection then
+/// carries on under the new one.
+#[test]
+fn test_set_socket_congestion_control() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    client
+        .ipv4
+        .tcp
+        .set_congestion_control(<Bbr as CongestionControl<TestRuntime>>::new);
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+    assert_eq!(
+        client.ipv4.tcp.cc_phase(client_fd).unwrap(),
+        CongestionPhase::Bbr(BbrState::Startup)
+    );
+
+    // Switch the connection over to CUBIC, which carries on from the window that BBR reached.
+    client
+        .ipv4
+        .tcp
+        .set_socket_congestion_control(client_fd, <Cubic as CongestionControl<TestRuntime>>::new)
+        .unwrap();
+    assert_eq!(
+        client.ipv4.tcp.cc_phase(client_fd).unwrap(),
+        CongestionPhase::CongestionAvoidance
+    );
+
+    // Data still flows, and is acknowledged.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    client.rt().poll_scheduler();
+    let bytes: Buffer = client.rt().pop_frame();
+    server.receive(bytes).unwrap();
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    server.rt().poll_scheduler();
+    let bytes: Buffer = server.rt().pop_frame();
+    client.receive(bytes).unwrap();
+    assert_eq!(
+        client.ipv4.tcp.cc_phase(client_fd).unwrap(),
+        CongestionPhase::CongestionAvoidance
+    );
+
+    // Sockets that are not connected have no congestion control algorithm to replace.
+    let unconnected_fd: QDesc = client.tcp_socket().unwrap();
+    assert_eq!(
+        client
+            .ipv4
+            .tcp
+            .set_socket_congestion_control(unconnected_fd, <Cubic as CongestionControl<TestRuntime>>::new)
+            .unwrap_err()
+            .errno,
+        libc::ENOTCONN
+    );
+}
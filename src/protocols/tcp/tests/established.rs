@@ -2,17 +2,63 @@
 // Licensed under the MIT license.
 
 use crate::{
-    protocols::tcp::{
-        operations::PushFuture,
-        tests::{
-            check_packet_data,
-            check_packet_pure_ack,
-            setup::{
-                advance_clock,
-                connection_setup,
+    protocols::{
+        ethernet2::{
+            EtherType2,
+            Ethernet2Header,
+            ETHERNET2_HEADER_SIZE,
+            MIN_FRAME_SIZE,
+        },
+        icmpv4::{
+            Icmpv4Header,
+            Icmpv4Message,
+            Icmpv4Type2,
+        },
+        ip::IpProtocol,
+        ipv4::{
+            Ipv4Header,
+            IPV4_HEADER_DEFAULT_SIZE,
+        },
+        tcp::{
+            config::{
+                LOW_LATENCY_MIN_RTO,
+                MAX_ACK_DELAY,
+            },
+            constants::{
+                MIN_MSS,
+                MSL,
             },
+            established::ControlBlock,
+            operations::{
+                FlushFuture,
+                PushFuture,
+            },
+            segment::{
+                TcpHeader,
+                TcpSegment,
+            },
+            tests::{
+                check_packet_data,
+                check_packet_pure_ack,
+                setup::{
+                    advance_clock,
+                    connection_setup,
+                    extract_headers,
+                    serialize_segment,
+                    FlagWaker,
+                },
+            },
+            CloseReason,
+            ConnectionStats,
+            SeqNumber,
+            SocketProfile,
+            State,
+            StateDurations,
+            TcpConfigExt,
+            TcpStream,
+            TcpTransmitHook,
+            MIN_TCP_HEADER_SIZE,
         },
-        SeqNumber,
     },
     test_helpers::{
         self,
@@ -20,7 +66,25 @@ use crate::{
         TestRuntime,
     },
 };
-use ::futures::task::noop_waker_ref;
+use ::futures::{
+    future::LocalBoxFuture,
+    io::{
+        self,
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+        Cursor,
+    },
+    task::{
+        noop_waker_ref,
+        waker,
+    },
+    FutureExt,
+};
+use ::libc::{
+    EINVAL,
+    ENOBUFS,
+};
 use ::rand;
 use ::runtime::{
     memory::{
@@ -31,21 +95,34 @@ use ::runtime::{
     QDesc,
 };
 use ::std::{
+    cell::RefCell,
     collections::VecDeque,
     future::Future,
     net::SocketAddrV4,
     pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
     task::{
         Context,
         Poll,
+        Waker,
+    },
+    time::{
+        Duration,
+        Instant,
     },
-    time::Instant,
 };
 
 //=============================================================================
 
 /// Cooks a buffer.
-fn cook_buffer(size: usize, stamp: Option<u8>) -> Buffer {
+pub fn cook_buffer(size: usize, stamp: Option<u8>) -> Buffer {
     let mut buf: DataBuffer = DataBuffer::new(size).unwrap();
     for i in 0..size {
         buf[i] = stamp.unwrap_or(i as u8);
@@ -438,6 +515,152 @@ pub fn test_send_recv_with_delay() {
 
 //=============================================================================
 
+/// Tests that, with pacing enabled, segments within a window are spread out in time rather than sent back to back.
+#[test]
+pub fn test_send_paced() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    client.ipv4.tcp.set_config(TcpConfigExt::default().pacing(true));
+    let window_scale: u8 = client.rt().tcp_options().get_window_scale();
+    let max_window_size: u32 = (client.rt().tcp_options().get_receive_window_size() as u32)
+        .checked_shl(window_scale as u32)
+        .unwrap();
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Queue up a few small segments, which all fit in the window.
+    let bufsize: u32 = 64;
+    let nsegments: u32 = 3;
+    for _ in 0..nsegments {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(bufsize as usize, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+
+    // Segments should leave one at a time, each one only after some time has elapsed. The step is well below the
+    // retransmission timeout, so none of them is retransmitted.
+    let step: Duration = Duration::from_millis(100);
+    for i in 0..nsegments {
+        client.rt().poll_scheduler();
+        let bytes: Buffer = client.rt().pop_frame();
+        check_packet_data(
+            bytes,
+            test_helpers::ALICE_MAC,
+            test_helpers::BOB_MAC,
+            test_helpers::ALICE_IPV4,
+            test_helpers::BOB_IPV4,
+            max_window_size as u16,
+            SeqNumber::from(1 + i * bufsize),
+            None,
+        );
+        client.rt().poll_scheduler();
+        assert!(client.rt().pop_frame_unchecked().is_none());
+
+        now += step;
+        client.rt().advance_clock(now);
+    }
+}
+
+//=============================================================================
+
+/// Sends a small message and checks that its receiver acknowledges it without the clock being advanced.
+fn send_recv_prompt_ack(
+    ctx: &mut Context,
+    receiver: &mut Engine<TestRuntime>,
+    sender: &mut Engine<TestRuntime>,
+    receiver_fd: QDesc,
+    sender_fd: QDesc,
+    bufsize: u32,
+    ack_num: SeqNumber,
+) {
+    // Push a small message.
+    let mut push_future: PushFuture = sender.tcp_push(sender_fd, cook_buffer(bufsize as usize, None));
+    match Future::poll(Pin::new(&mut push_future), ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let bytes: Buffer = sender.rt().pop_frame();
+    let (_, _, tcp_header) = extract_headers(bytes.clone());
+    assert!(tcp_header.psh);
+
+    // Pop the message.
+    recv_data(ctx, receiver, sender, receiver_fd, bytes);
+
+    // The ACK should go out right away.
+    receiver.rt().poll_scheduler();
+    let bytes: Buffer = receiver.rt().pop_frame();
+    check_packet_pure_ack(
+        bytes.clone(),
+        receiver.rt().local_link_addr(),
+        sender.rt().local_link_addr(),
+        receiver.rt().local_ipv4_addr(),
+        sender.rt().local_ipv4_addr(),
+        ack_num,
+    );
+    sender.receive(bytes).unwrap();
+}
+
+//=============================================================================
+
+/// Tests a ping-pong of small messages. Each message is PSH-terminated, so its receiver should acknowledge it without
+/// waiting for the delayed ACK timer. Otherwise, this would deadlock with a sender that holds back small segments
+/// while it has unacknowledged data.
+#[test]
+pub fn test_send_recv_ping_pong() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // The clock is not advanced from here on, so the delayed ACK timer never expires.
+    let bufsize: u32 = 16;
+    for i in 0..4 {
+        let ack_num: SeqNumber = SeqNumber::from(1 + (i + 1) * bufsize);
+        send_recv_prompt_ack(
+            &mut ctx,
+            &mut server,
+            &mut client,
+            server_fd,
+            client_fd,
+            bufsize,
+            ack_num,
+        );
+        send_recv_prompt_ack(
+            &mut ctx,
+            &mut client,
+            &mut server,
+            client_fd,
+            server_fd,
+            bufsize,
+            ack_num,
+        );
+    }
+}
+
+//=============================================================================
+
 #[test]
 fn test_connect_disconnect() {
     let mut ctx = Context::from_waker(noop_waker_ref());
@@ -456,3 +679,2437 @@ fn test_connect_disconnect() {
 
     connection_hangup(&mut ctx, &mut now, &mut server, &mut client, server_fd, client_fd);
 }
+
+//=============================================================================
+
+/// Tests if both peers reach TIME-WAIT and then CLOSED when they close simultaneously.
+#[test]
+fn test_simultaneous_close() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let window_scale: u8 = client.rt().tcp_options().get_window_scale();
+    let max_window_size: u32 = (client.rt().tcp_options().get_receive_window_size() as u32)
+        .checked_shl(window_scale as u32)
+        .unwrap();
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Both peers send some data, which crosses on the wire.
+    let bufsize: usize = 64;
+    let client_buf: Buffer = cook_buffer(bufsize, Some(0xa));
+    let server_buf: Buffer = cook_buffer(bufsize, Some(0xb));
+    let (client_data, _): (Buffer, usize) = send_data(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        client_fd,
+        max_window_size as u16,
+        SeqNumber::from(1),
+        None,
+        client_buf.clone(),
+    );
+    let (server_data, _): (Buffer, usize) = send_data(
+        &mut ctx,
+        &mut now,
+        &mut client,
+        &mut server,
+        server_fd,
+        max_window_size as u16,
+        SeqNumber::from(1),
+        None,
+        server_buf.clone(),
+    );
+    let mut server_pop_future = server.tcp_pop(server_fd);
+    server.receive(client_data).unwrap();
+    match Future::poll(Pin::new(&mut server_pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) if buf[..] == client_buf[..] => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let mut client_pop_future = client.tcp_pop(client_fd);
+    client.receive(server_data).unwrap();
+    match Future::poll(Pin::new(&mut client_pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) if buf[..] == server_buf[..] => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // Both peers close right away, before hearing from each other.
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+    server.tcp_close(server_fd).expect("server tcp_close returned error");
+    let client_fin: Buffer = client.rt().pop_frame();
+    let server_fin: Buffer = server.rt().pop_frame();
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::FinWait1)
+    );
+    assert_eq!(
+        server.ipv4.tcp.connection_state(server_endpoints),
+        Some(State::FinWait1)
+    );
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Each peer receives a FIN that does not acknowledge its own FIN.
+    server.receive(client_fin).unwrap();
+    client.receive(server_fin).unwrap();
+    assert_eq!(client.ipv4.tcp.connection_state(client_endpoints), Some(State::Closing));
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closing));
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Each peer receives the acknowledgement of its FIN.
+    let server_ack: Buffer = server.rt().pop_frame();
+    let client_ack: Buffer = client.rt().pop_frame();
+    server.receive(client_ack).unwrap();
+    client.receive(server_ack).unwrap();
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::TimeWait)
+    );
+    assert_eq!(
+        server.ipv4.tcp.connection_state(server_endpoints),
+        Some(State::TimeWait)
+    );
+
+    // Both peers leave TIME-WAIT once the time-wait timer expires.
+    for _ in 0..(2 * MSL).as_secs() {
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    }
+    client.rt().poll_scheduler();
+    server.rt().poll_scheduler();
+    assert_eq!(client.ipv4.tcp.connection_state(client_endpoints), Some(State::Closed));
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+    assert!(client.rt().pop_frame_unchecked().is_none());
+    assert!(server.rt().pop_frame_unchecked().is_none());
+}
+
+//=============================================================================
+
+/// Tests if a lost FIN is retransmitted and the close still completes.
+#[test]
+fn test_fin_retransmission() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Send FIN: Client -> Server, which gets lost.
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+    client.rt().poll_scheduler();
+    let (_, _, fin_header) = extract_headers(client.rt().pop_frame());
+    assert!(fin_header.fin);
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::FinWait1)
+    );
+
+    // The FIN should be retransmitted once the retransmission timer expires.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    client.rt().poll_scheduler();
+    let bytes: Buffer = client.rt().pop_frame();
+    let (_, _, tcp_header) = extract_headers(bytes.clone());
+    assert!(tcp_header.fin);
+    assert_eq!(tcp_header.seq_num, fin_header.seq_num);
+
+    // ACK FIN: Server -> Client
+    server.receive(bytes).expect("server receive returned error");
+    server.rt().poll_scheduler();
+    let bytes: Buffer = server.rt().pop_frame();
+    client.receive(bytes).expect("client receive (of ACK) returned error");
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::FinWait2)
+    );
+
+    // Send FIN: Server -> Client
+    server.tcp_close(server_fd).expect("server tcp_close returned error");
+    server.rt().poll_scheduler();
+    let bytes: Buffer = server.rt().pop_frame();
+
+    // ACK FIN: Client -> Server
+    client.receive(bytes).expect("client receive (of FIN) returned error");
+    client.rt().poll_scheduler();
+    let bytes: Buffer = client.rt().pop_frame();
+    server.receive(bytes).expect("server receive (of ACK) returned error");
+
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::TimeWait)
+    );
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+}
+
+//=============================================================================
+
+/// Tests if a connection is aborted once the retransmission limit for an unacknowledged FIN is exceeded.
+#[test]
+fn test_fin_retransmission_limit() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let max_retransmits: usize = 2;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    client
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().max_retransmits(Some(max_retransmits)));
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Send FIN: Client -> Server, which always gets lost.
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+    client.rt().poll_scheduler();
+    let mut nfins: usize = 0;
+
+    // The retransmission timeout doubles on every attempt, so this is more than enough time to exceed the limit.
+    for _ in 0..(1 << (max_retransmits + 2)) {
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            let (_, _, tcp_header) = extract_headers(bytes);
+            assert!(tcp_header.fin);
+            nfins += 1;
+        }
+        advance_clock(None, Some(&mut client), &mut now);
+        client.rt().poll_scheduler();
+    }
+
+    assert_eq!(nfins, 1 + max_retransmits);
+    assert_eq!(client.ipv4.tcp.connection_state(client_endpoints), Some(State::Closed));
+}
+
+//=============================================================================
+
+/// Tests if retransmitted FINs are ACKed again, without disturbing the closing connection.
+#[test]
+fn test_duplicate_fin() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Send FIN: Client -> Server, whose ACK gets lost.
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+    let client_fin: Buffer = client.rt().pop_frame();
+    server
+        .receive(client_fin.clone())
+        .expect("server receive (of FIN) returned error");
+    let (_, _, ack_header) = extract_headers(server.rt().pop_frame());
+    assert_eq!(
+        server.ipv4.tcp.connection_state(server_endpoints),
+        Some(State::CloseWait)
+    );
+
+    // Retransmitted FIN: Client -> Server, in CLOSE-WAIT.
+    server
+        .receive(client_fin.clone())
+        .expect("server receive (of duplicate FIN) returned error");
+    let bytes: Buffer = server.rt().pop_frame();
+    let (_, _, tcp_header) = extract_headers(bytes.clone());
+    assert_eq!(tcp_header.ack_num, ack_header.ack_num);
+    assert_eq!(
+        server.ipv4.tcp.connection_state(server_endpoints),
+        Some(State::CloseWait)
+    );
+    client.receive(bytes).expect("client receive (of ACK) returned error");
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::FinWait2)
+    );
+
+    // Send FIN: Server -> Client
+    server.tcp_close(server_fd).expect("server tcp_close returned error");
+    let server_fin: Buffer = server.rt().pop_frame();
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::LastAck));
+
+    // Retransmitted FIN: Client -> Server, in LAST-ACK.
+    server
+        .receive(client_fin)
+        .expect("server receive (of duplicate FIN) returned error");
+    let (_, _, tcp_header) = extract_headers(server.rt().pop_frame());
+    assert_eq!(tcp_header.ack_num, ack_header.ack_num);
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::LastAck));
+
+    // ACK FIN: Client -> Server, which gets lost.
+    client
+        .receive(server_fin.clone())
+        .expect("client receive (of FIN) returned error");
+    let (_, _, ack_header) = extract_headers(client.rt().pop_frame());
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::TimeWait)
+    );
+
+    // Retransmitted FIN: Server -> Client, in TIME-WAIT.
+    client
+        .receive(server_fin)
+        .expect("client receive (of duplicate FIN) returned error");
+    let bytes: Buffer = client.rt().pop_frame();
+    let (_, _, tcp_header) = extract_headers(bytes.clone());
+    assert_eq!(tcp_header.ack_num, ack_header.ack_num);
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::TimeWait)
+    );
+    server.receive(bytes).expect("server receive (of ACK) returned error");
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+}
+
+//=============================================================================
+
+/// Tests if the data carried by a FIN is delivered to the application before the end of the stream.
+#[test]
+fn test_fin_with_data() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let mut server_stream: TcpStream<TestRuntime> = server.ipv4.tcp.stream(server_fd).unwrap();
+
+    // Send data: Client -> Server, and set the FIN bit on the segment that carries it.
+    let data: Vec<u8> = (0..100).collect();
+    let mut push_future: PushFuture = client.tcp_push(client_fd, Buffer::Heap(DataBuffer::from_slice(&data)));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let (eth2_header, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) =
+        extract_headers(client.rt().pop_frame());
+    let seq_num: SeqNumber = tcp_header.seq_num;
+    let segment: TcpSegment = TcpSegment {
+        ethernet2_hdr: eth2_header,
+        ipv4_hdr: ipv4_header,
+        tcp_hdr: TcpHeader {
+            fin: true,
+            ..tcp_header
+        },
+        data: Buffer::Heap(DataBuffer::from_slice(&data)),
+        tx_checksum_offload: false,
+    };
+    server
+        .receive(serialize_segment(segment))
+        .expect("server receive (of FIN with data) returned error");
+    assert_eq!(
+        server.ipv4.tcp.connection_state(server_endpoints),
+        Some(State::CloseWait)
+    );
+
+    // The server acknowledges both the data and the FIN.
+    let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(server.rt().pop_frame());
+    assert_eq!(ack_header.ack_num, seq_num + SeqNumber::from(data.len() as u32 + 1));
+
+    // The application reads the data, and then the end of the stream.
+    let mut buf: [u8; 200] = [0; 200];
+    match Pin::new(&mut server_stream).poll_read(&mut ctx, &mut buf) {
+        Poll::Ready(Ok(nbytes)) if buf[..nbytes] == data[..] => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    match Pin::new(&mut server_stream).poll_read(&mut ctx, &mut buf) {
+        Poll::Ready(Ok(0)) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+//=============================================================================
+
+/// Tests if the end of the stream is signaled once all data sent before a FIN has been read.
+#[test]
+fn test_recv_eof() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Send data and then FIN: Client -> Server.
+    let data: Vec<u8> = (0..100).collect();
+    let mut push_future: PushFuture = client.tcp_push(client_fd, Buffer::Heap(DataBuffer::from_slice(&data)));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let data_segment: Buffer = client.rt().pop_frame();
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+    let fin_segment: Buffer = client.rt().pop_frame();
+
+    // Nothing has arrived yet, so there is no end of stream either.
+    let mut pop_future = server.tcp_pop(server_fd);
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+
+    server.receive(data_segment).unwrap();
+    server.receive(fin_segment).unwrap();
+
+    // The reader drains the data first.
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) if buf[..] == data[..] => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // The end of the stream is signaled by an empty buffer, for as long as the reader keeps asking.
+    for _ in 0..2 {
+        let mut pop_future = server.tcp_pop(server_fd);
+        match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+            Poll::Ready(Ok(buf)) if buf.is_empty() => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+    let mut buf: [u8; 40] = [0; 40];
+    match server.ipv4.tcp.poll_recv_into(server_fd, &mut buf, &mut ctx) {
+        Poll::Ready(Ok(0)) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    assert_eq!(server.ipv4.tcp.peek(server_fd, &mut buf).unwrap(), 0);
+}
+
+//=============================================================================
+
+/// Tests if data can be copied between the two ends of a connection through their stream adapters.
+#[test]
+fn test_stream_copy() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let mut server_stream: TcpStream<TestRuntime> = server.ipv4.tcp.stream(server_fd).unwrap();
+    let mut client_stream: TcpStream<TestRuntime> = client.ipv4.tcp.stream(client_fd).unwrap();
+
+    // The stream spans several segments.
+    let data: Vec<u8> = cook_buffer(4 * client.tcp_mss(client_fd).unwrap() + 7, None).to_vec();
+    let mut reader: Cursor<Vec<u8>> = Cursor::new(data.clone());
+    let mut received: Vec<u8> = Vec::new();
+
+    // The client writes the whole stream and closes its side of the connection, the server reads until end of stream.
+    let mut send_future: LocalBoxFuture<'_, io::Result<()>> = async {
+        io::copy(&mut reader, &mut client_stream).await?;
+        client_stream.close().await
+    }
+    .boxed_local();
+    let mut recv_future: LocalBoxFuture<'_, io::Result<u64>> =
+        io::copy(&mut server_stream, &mut received).boxed_local();
+
+    let mut sent: Option<io::Result<()>> = None;
+    let mut recvd: Option<io::Result<u64>> = None;
+    for _ in 0..32 {
+        if sent.is_none() {
+            if let Poll::Ready(result) = Future::poll(send_future.as_mut(), &mut ctx) {
+                sent = Some(result);
+            }
+        }
+        if recvd.is_none() {
+            if let Poll::Ready(result) = Future::poll(recv_future.as_mut(), &mut ctx) {
+                recvd = Some(result);
+            }
+        }
+        if sent.is_some() && recvd.is_some() {
+            break;
+        }
+
+        // Exchange whatever the peers have to say to each other.
+        client.rt().poll_scheduler();
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            server.receive(bytes).unwrap();
+        }
+        server.rt().poll_scheduler();
+        while let Some(bytes) = server.rt().pop_frame_unchecked() {
+            client.receive(bytes).unwrap();
+        }
+    }
+    drop(send_future);
+    drop(recv_future);
+
+    assert!(matches!(sent, Some(Ok(()))));
+    assert_eq!(recvd.unwrap().unwrap(), data.len() as u64);
+    assert_eq!(received, data);
+}
+
+//=============================================================================
+
+/// Tests if a stream can be read into caller-provided buffers that are smaller than the received data.
+#[test]
+fn test_recv_into() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Send a 100-byte stream in two segments.
+    let data: Vec<u8> = (0..100).collect();
+    for chunk in data.chunks(50) {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, Buffer::Heap(DataBuffer::from_slice(chunk)));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        let bytes: Buffer = client.rt().pop_frame();
+        server.receive(bytes).unwrap();
+    }
+
+    // Read the stream into 40-byte slices.
+    let mut received: Vec<u8> = Vec::new();
+    for expected in [40, 40, 20] {
+        let mut buf: [u8; 40] = [0; 40];
+        let nbytes: usize = match server.ipv4.tcp.poll_recv_into(server_fd, &mut buf, &mut ctx) {
+            Poll::Ready(Ok(nbytes)) => nbytes,
+            _ => panic!("poll_recv_into should have returned data"),
+        };
+        assert_eq!(nbytes, expected);
+        received.extend_from_slice(&buf[..nbytes]);
+    }
+    assert_eq!(received, data);
+
+    // The whole stream has been read.
+    let mut buf: [u8; 40] = [0; 40];
+    assert!(server
+        .ipv4
+        .tcp
+        .poll_recv_into(server_fd, &mut buf, &mut ctx)
+        .is_pending());
+}
+
+//=============================================================================
+
+/// Tests if peeking at received data leaves it in place for the next read.
+#[test]
+fn test_peek() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Nothing to peek at yet.
+    let mut peeked: [u8; 8] = [0; 8];
+    assert!(server.ipv4.tcp.peek(server_fd, &mut peeked).is_err());
+
+    // Send data.
+    let data: Buffer = cook_buffer(32, None);
+    let mut push_future: PushFuture = client.tcp_push(client_fd, data.clone());
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let bytes: Buffer = client.rt().pop_frame();
+    server.receive(bytes).unwrap();
+
+    // Peek at the first few bytes, twice.
+    for _ in 0..2 {
+        assert_eq!(server.ipv4.tcp.peek(server_fd, &mut peeked).unwrap(), peeked.len());
+        assert_eq!(peeked[..], data[..peeked.len()]);
+    }
+
+    // Read the data, which should start with the bytes that we peeked at.
+    let mut pop_future = server.tcp_pop(server_fd);
+    let received: Buffer = match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(received)) => received,
+        _ => panic!("pop should have returned data"),
+    };
+    assert_eq!(received[..peeked.len()], peeked[..]);
+    assert_eq!(received[..], data[..]);
+}
+
+//=============================================================================
+
+/// Tests if a rate-limited connection does not send faster than the configured rate.
+#[test]
+fn test_send_rate_limited() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mss: usize = client.rt().tcp_options().get_advertised_mss();
+    let rate: usize = 2 * mss;
+    let burst: usize = mss;
+    client
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().rate_limit(Some((rate, burst))).unwrap());
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+
+    // Push a buffer that takes several seconds to send at the configured rate.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(16 * mss, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // Count the bytes that arrive at the server as the clock advances.
+    let step: Duration = Duration::from_millis(100);
+    let start: Instant = now;
+    let mut received: usize = 0;
+    for _ in 0..50 {
+        client.rt().poll_scheduler();
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            server.receive(bytes).unwrap();
+        }
+        server.rt().poll_scheduler();
+        while let Some(bytes) = server.rt().pop_frame_unchecked() {
+            client.receive(bytes).unwrap();
+        }
+        loop {
+            let mut pop_future = server.tcp_pop(server_fd);
+            match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+                Poll::Ready(Ok(buf)) => received += buf.len(),
+                _ => break,
+            }
+        }
+
+        // The burst goes out at once, the rest at no more than the configured rate.
+        let elapsed: f64 = (now - start).as_secs_f64();
+        assert!(received as f64 <= burst as f64 + rate as f64 * elapsed);
+
+        now += step;
+        client.rt().advance_clock(now);
+        server.rt().advance_clock(now);
+    }
+
+    // Data kept flowing at about the configured rate.
+    let elapsed: f64 = (now - start).as_secs_f64();
+    assert!(received as f64 >= rate as f64 * elapsed - 2.0 * mss as f64);
+}
+
+/// Tests if rate limits that would never let any data out are rejected.
+#[test]
+fn test_rate_limit_out_of_range() {
+    for (rate, burst) in [(0, 1), (1, 0), (0, 0)] {
+        match TcpConfigExt::default().rate_limit(Some((rate, burst))) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("rate {} and burst {} should have been rejected", rate, burst),
+        }
+    }
+    assert!(TcpConfigExt::default().rate_limit(Some((1, 1))).is_ok());
+}
+
+//=============================================================================
+
+/// Sends more data than fits in the receive buffer of the server, which has the given size. Returns the number of
+/// bytes that the server buffered.
+fn fill_recv_buffer(recv_buffer_size: u32) -> usize {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .recv_buffer_size(Some(recv_buffer_size))
+            .unwrap(),
+    );
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let window_scale: u8 = server.ipv4.tcp.negotiated(server_fd).unwrap().local_window_scale;
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+
+    // Push more data than the server is able to buffer.
+    for _ in 0..(recv_buffer_size as usize / mss + 2) {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+    client.rt().poll_scheduler();
+    while let Some(bytes) = client.rt().pop_frame_unchecked() {
+        server.receive(bytes).unwrap();
+    }
+
+    // The server advertises whatever room is left in its receive buffer.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    server.rt().poll_scheduler();
+    let mut ack: Option<Buffer> = None;
+    while let Some(bytes) = server.rt().pop_frame_unchecked() {
+        ack = Some(bytes);
+    }
+    let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(ack.unwrap());
+    let window_size: u32 = (ack_header.window_size as u32) << window_scale;
+
+    // Count the bytes that the server buffered.
+    let mut buffered: usize = 0;
+    loop {
+        let mut pop_future = server.tcp_pop(server_fd);
+        match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+            Poll::Ready(Ok(buf)) => buffered += buf.len(),
+            _ => break,
+        }
+    }
+    assert!(buffered <= recv_buffer_size as usize);
+    assert_eq!(
+        window_size,
+        ((recv_buffer_size - buffered as u32) >> window_scale) << window_scale
+    );
+
+    buffered
+}
+
+/// Tests if the receive buffer size bounds how much data a connection buffers.
+#[test]
+fn test_recv_buffer_size() {
+    let small: usize = fill_recv_buffer(2048);
+    let large: usize = fill_recv_buffer(16384);
+    assert!(small > 0);
+    assert!(large > small);
+}
+
+/// Tests if receive buffers that cannot hold a segment of the smallest supported size are rejected.
+#[test]
+fn test_recv_buffer_size_out_of_range() {
+    for size in [0, MIN_MSS as u32 - 1] {
+        match TcpConfigExt::default().recv_buffer_size(Some(size)) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("receive buffer size {} should have been rejected", size),
+        }
+    }
+    assert!(TcpConfigExt::default().recv_buffer_size(Some(MIN_MSS as u32)).is_ok());
+}
+
+//=============================================================================
+
+/// Tests if a window update is sent right away when reading data reopens a closed receive window.
+#[test]
+fn test_window_update() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let recv_buffer_size: u32 = 2048;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .recv_buffer_size(Some(recv_buffer_size))
+            .unwrap(),
+    );
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let window_scale: u8 = server.ipv4.tcp.negotiated(server_fd).unwrap().local_window_scale;
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+
+    // Fill the receive window of the server.
+    for _ in 0..(recv_buffer_size as usize / mss + 2) {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+    client.rt().poll_scheduler();
+    while let Some(bytes) = client.rt().pop_frame_unchecked() {
+        server.receive(bytes).unwrap();
+    }
+    while server.rt().pop_frame_unchecked().is_some() {}
+
+    // Reading the first segment reopens the window, which the server announces without waiting for the clock.
+    let mut pop_future = server.tcp_pop(server_fd);
+    let nbytes: usize = match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) => buf.len(),
+        _ => panic!("pop should have returned data"),
+    };
+    let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(server.rt().pop_frame());
+    assert!(ack_header.ack);
+    assert!((ack_header.window_size as u32) << window_scale >= nbytes as u32);
+
+    // Once the window is open, reading more data does not send any further updates.
+    let mut pop_future = server.tcp_pop(server_fd);
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_ready());
+    assert!(server.rt().pop_frame_unchecked().is_none());
+}
+
+//=============================================================================
+
+/// Tests if a sender that missed a window update recovers by probing the window.
+#[test]
+fn test_lost_window_update() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    // The receive buffer of the server holds exactly two segments, so that they close its window.
+    let recv_buffer_size: u32 = 2 * client.rt().tcp_options().get_advertised_mss() as u32;
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .recv_buffer_size(Some(recv_buffer_size))
+            .unwrap(),
+    );
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+
+    // Push more data than the server is able to buffer.
+    let num_bytes: usize = 3 * mss;
+    for _ in 0..(num_bytes / mss) {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+    client.rt().poll_scheduler();
+    while let Some(bytes) = client.rt().pop_frame_unchecked() {
+        server.receive(bytes).unwrap();
+    }
+
+    // The server acknowledges the data that filled its window, which closes the window of the client.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    server.rt().poll_scheduler();
+    while let Some(bytes) = server.rt().pop_frame_unchecked() {
+        client.receive(bytes).unwrap();
+    }
+
+    // The client probes the closed window, which the server cannot accept yet.
+    client.rt().poll_scheduler();
+    let probe: Buffer = client.rt().pop_frame();
+    let (_, _, probe_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(probe.clone());
+    server.receive(probe).unwrap();
+    while let Some(bytes) = server.rt().pop_frame_unchecked() {
+        client.receive(bytes).unwrap();
+    }
+
+    // The server application drains the buffer, but the window update that reopens the window gets lost.
+    let mut received: usize = 0;
+    loop {
+        let mut pop_future = server.tcp_pop(server_fd);
+        match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+            Poll::Ready(Ok(buf)) => received += buf.len(),
+            _ => break,
+        }
+    }
+    assert!(received > 0);
+    server.rt().pop_frame();
+    client.rt().poll_scheduler();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+
+    // The client keeps probing, which lets it find out that the window has reopened.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    client.rt().poll_scheduler();
+    let probe: Buffer = client.rt().pop_frame();
+    let (_, _, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(probe.clone());
+    assert_eq!(tcp_header.seq_num, probe_header.seq_num);
+    server.receive(probe).unwrap();
+
+    // The client then resumes sending the rest of the data.
+    for _ in 0..16 {
+        if received == num_bytes {
+            break;
+        }
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+        server.rt().poll_scheduler();
+        while let Some(bytes) = server.rt().pop_frame_unchecked() {
+            client.receive(bytes).unwrap();
+        }
+        client.rt().poll_scheduler();
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            server.receive(bytes).unwrap();
+        }
+        loop {
+            let mut pop_future = server.tcp_pop(server_fd);
+            match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+                Poll::Ready(Ok(buf)) => received += buf.len(),
+                _ => break,
+            }
+        }
+    }
+    assert_eq!(received, num_bytes);
+}
+
+//=============================================================================
+
+/// Tests if an ICMP error about an established connection is kept as a soft error, which is taken exactly once.
+#[test]
+fn test_take_soft_error() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    assert!(client.ipv4.tcp.take_error(client_fd).unwrap().is_none());
+
+    // The client sends a segment, which gets answered with a source quench that quotes its start.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let (_, datagram): (Ethernet2Header, Buffer) = Ethernet2Header::parse(client.rt().pop_frame()).unwrap();
+    let quoted: Buffer = Buffer::Heap(DataBuffer::from_slice(&datagram[..28]));
+    server.rt().transmit(Icmpv4Message::with_data(
+        Ethernet2Header::new(test_helpers::ALICE_MAC, test_helpers::BOB_MAC, EtherType2::Ipv4),
+        Ipv4Header::new(test_helpers::BOB_IPV4, test_helpers::ALICE_IPV4, IpProtocol::ICMPv4),
+        Icmpv4Header::new(Icmpv4Type2::SourceQuench, 0),
+        quoted,
+    ));
+    client.receive(server.rt().pop_frame()).unwrap();
+
+    // The error is reported once, without failing the connection.
+    match client.ipv4.tcp.take_error(client_fd) {
+        Ok(Some(error)) if error.errno == ENOBUFS => (),
+        r => panic!("take_error should have returned the source quench, got {:?}", r),
+    }
+    assert!(client.ipv4.tcp.take_error(client_fd).unwrap().is_none());
+    let endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+    assert_eq!(client.ipv4.tcp.connection_state(endpoints), Some(State::Established));
+}
+
+//=============================================================================
+
+/// Tests if the sequence numbers of both ends of a connection advance as data is sent and acknowledged.
+#[test]
+fn test_sequence_number_accessors() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_cb: Rc<ControlBlock<TestRuntime>> = server
+        .ipv4
+        .tcp
+        .control_block(server.ipv4.tcp.endpoints(server_fd).unwrap())
+        .unwrap();
+    let client_cb: Rc<ControlBlock<TestRuntime>> = client
+        .ipv4
+        .tcp
+        .control_block(client.ipv4.tcp.endpoints(client_fd).unwrap())
+        .unwrap();
+
+    // Nothing is in flight after the handshake.
+    let snd_una: SeqNumber = client_cb.snd_una();
+    let rcv_wnd: u32 = server_cb.rcv_wnd();
+    assert_eq!(client_cb.snd_nxt(), snd_una);
+    assert_eq!(server_cb.rcv_nxt(), snd_una);
+    assert!(client_cb.snd_wnd() > 0);
+
+    // Sending data advances SND.NXT by its length, but not SND.UNA.
+    let num_bytes: usize = 32;
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(num_bytes, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    assert_eq!(client_cb.snd_nxt(), snd_una + SeqNumber::from(num_bytes as u32));
+    assert_eq!(client_cb.snd_una(), snd_una);
+
+    // Receiving it advances RCV.NXT, and the unread data takes up part of the receive window.
+    server.receive(client.rt().pop_frame()).unwrap();
+    assert_eq!(server_cb.rcv_nxt(), client_cb.snd_nxt());
+    assert_eq!(server_cb.rcv_wnd(), rcv_wnd - num_bytes as u32);
+
+    // Once the data is acknowledged, SND.UNA catches up with SND.NXT.
+    recv_pure_ack(&mut now, &mut server, &mut client, client_cb.snd_nxt());
+    assert_eq!(client_cb.snd_una(), client_cb.snd_nxt());
+}
+
+//=============================================================================
+
+/// Tests if segments that arrive out of order are accounted in the statistics of a connection.
+#[test]
+fn test_reordering_stats() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    assert_eq!(server.ipv4.tcp.stats(server_fd).unwrap(), ConnectionStats::default());
+
+    // The client sends three segments.
+    let num_bytes: usize = 32;
+    let mut segments: Vec<Buffer> = Vec::new();
+    for _ in 0..3 {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(num_bytes, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        segments.push(client.rt().pop_frame());
+    }
+
+    // They arrive in reverse order, so the last one arrives two segments ahead of RCV.NXT and the second one one
+    // segment ahead.
+    for bytes in segments.into_iter().rev() {
+        server.receive(bytes).unwrap();
+    }
+    let stats: ConnectionStats = server.ipv4.tcp.stats(server_fd).unwrap();
+    assert_eq!(stats.out_of_order_segments, 2);
+    assert_eq!(stats.max_reorder_distance, 2 * num_bytes as u32);
+
+    // The sender saw nothing out of order.
+    assert_eq!(client.ipv4.tcp.stats(client_fd).unwrap(), ConnectionStats::default());
+}
+
+//=============================================================================
+
+/// Tests if segments that arrive out of order are reassembled, and read back in order once the gap before them is
+/// filled.
+#[test]
+fn test_out_of_order_reassembly() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_cb: Rc<ControlBlock<TestRuntime>> = server
+        .ipv4
+        .tcp
+        .control_block(server.ipv4.tcp.endpoints(server_fd).unwrap())
+        .unwrap();
+    let rcv_nxt: SeqNumber = server_cb.rcv_nxt();
+
+    // The client sends four segments, each filled with its own stamp.
+    let num_bytes: usize = 32;
+    let mut segments: Vec<Buffer> = Vec::new();
+    for stamp in 1..=4 {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(num_bytes, Some(stamp)));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        segments.push(client.rt().pop_frame());
+    }
+
+    // All but the first one arrive, out of order and with a duplicate. None of them can be read yet.
+    for index in [1, 3, 2, 3] {
+        server.receive(segments[index].clone()).unwrap();
+    }
+    assert_eq!(server_cb.rcv_nxt(), rcv_nxt);
+    let mut buf: [u8; 128] = [0; 128];
+    assert!(server
+        .ipv4
+        .tcp
+        .poll_recv_into(server_fd, &mut buf, &mut ctx)
+        .is_pending());
+
+    // The first one fills the gap, so all of the data is received.
+    server.receive(segments[0].clone()).unwrap();
+    assert_eq!(server_cb.rcv_nxt(), rcv_nxt + SeqNumber::from(4 * num_bytes as u32));
+
+    // It is read back in order.
+    match server.ipv4.tcp.poll_recv_into(server_fd, &mut buf, &mut ctx) {
+        Poll::Ready(Ok(nbytes)) => assert_eq!(nbytes, 4 * num_bytes),
+        _ => panic!("poll_recv_into should have returned data"),
+    }
+    for (index, chunk) in buf.chunks(num_bytes).enumerate() {
+        assert!(chunk.iter().all(|b| *b == index as u8 + 1));
+    }
+}
+
+//=============================================================================
+
+/// Tests if received data is split at push boundaries when the receiver asks for them.
+#[test]
+fn test_push_boundaries() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().push_boundaries(true));
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // The client sends two messages. The first one is carried by two segments, of which only the last one has the PSH
+    // flag set.
+    let messages: [(usize, u8); 3] = [(16, 1), (16, 1), (24, 2)];
+    for (i, (num_bytes, stamp)) in messages.iter().enumerate() {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(*num_bytes, Some(*stamp)));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        let (eth2_header, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) =
+            extract_headers(client.rt().pop_frame());
+        assert!(tcp_header.psh);
+        let segment: TcpSegment = TcpSegment {
+            ethernet2_hdr: eth2_header,
+            ipv4_hdr: ipv4_header,
+            tcp_hdr: TcpHeader {
+                psh: i != 0,
+                ..tcp_header
+            },
+            data: cook_buffer(*num_bytes, Some(*stamp)),
+            tx_checksum_offload: false,
+        };
+        server.receive(serialize_segment(segment)).unwrap();
+    }
+
+    // The server reads each message on its own.
+    for (num_bytes, stamp) in [(32, 1), (24, 2)] {
+        let mut pop_future = server.tcp_pop(server_fd);
+        match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+            Poll::Ready(Ok(buf)) if buf.len() == num_bytes && buf.iter().all(|b| *b == stamp) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+}
+
+//=============================================================================
+
+/// Tests if a connection on which no data is sent or received is aborted once the idle timeout expires.
+#[test]
+fn test_idle_timeout() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let idle_timeout: Duration = Duration::from_secs(5);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().idle_timeout(Some(idle_timeout)).unwrap());
+
+    let (server_fd, _): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+
+    // The connection survives until the deadline.
+    for _ in 0..(idle_timeout.as_secs() - 1) {
+        advance_clock(Some(&mut server), None, &mut now);
+        server.rt().poll_scheduler();
+    }
+    assert_eq!(
+        server.ipv4.tcp.connection_state(server_endpoints),
+        Some(State::Established)
+    );
+
+    // Once the deadline passes without any data, the connection is torn down.
+    advance_clock(Some(&mut server), None, &mut now);
+    server.rt().poll_scheduler();
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+}
+
+/// Tests if an idle timeout that would abort connections right away is rejected.
+#[test]
+fn test_idle_timeout_out_of_range() {
+    match TcpConfigExt::default().idle_timeout(Some(Duration::ZERO)) {
+        Err(e) => assert_eq!(e.errno, EINVAL),
+        Ok(_) => panic!("zero idle timeout should have been rejected"),
+    }
+    assert!(TcpConfigExt::default()
+        .idle_timeout(Some(Duration::from_millis(1)))
+        .is_ok());
+}
+
+//=============================================================================
+
+/// Tests if a connection is aborted once its data has gone unacknowledged for longer than the user timeout, even though
+/// retransmissions remain.
+#[test]
+fn test_user_timeout() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let user_timeout: Duration = Duration::from_secs(4);
+    let max_retransmits: usize = 15;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    client.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .max_retransmits(Some(max_retransmits))
+            .user_timeout(Some(user_timeout))
+            .unwrap(),
+    );
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Send data: Client -> Server, which always gets lost.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let mut nsegments: usize = 0;
+
+    // The connection survives until the deadline.
+    for _ in 0..user_timeout.as_secs() {
+        while client.rt().pop_frame_unchecked().is_some() {
+            nsegments += 1;
+        }
+        assert_eq!(
+            client.ipv4.tcp.connection_state(client_endpoints),
+            Some(State::Established)
+        );
+        advance_clock(None, Some(&mut client), &mut now);
+        client.rt().poll_scheduler();
+    }
+
+    // At the deadline, the connection is aborted long before running out of retransmissions.
+    assert_eq!(client.ipv4.tcp.connection_state(client_endpoints), Some(State::Closed));
+    assert!(nsegments < 1 + max_retransmits);
+}
+
+/// Tests if a user timeout that would abort connections as soon as they send data is rejected.
+#[test]
+fn test_user_timeout_out_of_range() {
+    match TcpConfigExt::default().user_timeout(Some(Duration::ZERO)) {
+        Err(e) => assert_eq!(e.errno, EINVAL),
+        Ok(_) => panic!("zero user timeout should have been rejected"),
+    }
+    assert!(TcpConfigExt::default()
+        .user_timeout(Some(Duration::from_millis(1)))
+        .is_ok());
+}
+
+//=============================================================================
+
+/// Tests if a graceful close sends all data that is still queued before its FIN.
+#[test]
+fn test_close_drains_send_queue() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let recv_buffer_size: u32 = 4096;
+
+    // Setup peers. The server has a small receive buffer, so that the client has to queue most of its data.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .recv_buffer_size(Some(recv_buffer_size))
+            .unwrap(),
+    );
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+    let first_seq_num: SeqNumber = client
+        .ipv4
+        .tcp
+        .control_block(client_endpoints)
+        .unwrap()
+        .get_send_next()
+        .0;
+
+    // Queue more data than the server can take at once, then close right away.
+    let num_bytes: usize = 4 * recv_buffer_size as usize;
+    for _ in 0..(num_bytes / mss) {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+    let num_bytes: usize = (num_bytes / mss) * mss;
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+
+    // Let the server read data as it arrives, until the client runs out of things to send.
+    let mut received: usize = 0;
+    let mut fin_seq_num: Option<SeqNumber> = None;
+    for _ in 0..100 {
+        client.rt().poll_scheduler();
+        let mut idle: bool = true;
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            let (_, _, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+            if tcp_header.fin {
+                fin_seq_num = Some(tcp_header.seq_num);
+            }
+            server.receive(bytes).unwrap();
+            idle = false;
+        }
+        loop {
+            let mut pop_future = server.tcp_pop(server_fd);
+            match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+                Poll::Ready(Ok(buf)) if !buf.is_empty() => received += buf.len(),
+                _ => break,
+            }
+        }
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+        server.rt().poll_scheduler();
+        while let Some(bytes) = server.rt().pop_frame_unchecked() {
+            client.receive(bytes).unwrap();
+            idle = false;
+        }
+        if idle && fin_seq_num.is_some() {
+            break;
+        }
+    }
+
+    // All queued data went out ahead of the FIN, and the server acknowledged both.
+    assert_eq!(received, num_bytes);
+    assert_eq!(fin_seq_num, Some(first_seq_num + SeqNumber::from(num_bytes as u32)));
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::FinWait2)
+    );
+}
+
+//=============================================================================
+
+/// Tests if a segment that overlaps both edges of the receive window is trimmed down to the new data that fits in it.
+#[test]
+fn test_segment_overlapping_window_edges() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let recv_buffer_size: u32 = 2048;
+    let overlap: usize = 10;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .recv_buffer_size(Some(recv_buffer_size))
+            .unwrap(),
+    );
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+
+    // Send data: Client -> Server, which the server reads right away.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(100, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let bytes: Buffer = client.rt().pop_frame();
+    let (eth2_header, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) =
+        extract_headers(bytes.clone());
+    server.receive(bytes).unwrap();
+    let mut pop_future = server.tcp_pop(server_fd);
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) if buf.len() == 100 => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    while server.rt().pop_frame_unchecked().is_some() {}
+    let cb: Rc<ControlBlock<TestRuntime>> = server.ipv4.tcp.control_block(server_endpoints).unwrap();
+    let receive_next: SeqNumber = cb.rcv_nxt();
+    let window: usize = cb.rcv_wnd() as usize;
+
+    // Send a segment that starts before RCV.NXT and ends after RCV.NXT + RCV.WND.
+    let data: Vec<u8> = (0..(overlap + window + overlap)).map(|i| i as u8).collect();
+    let segment: TcpSegment = TcpSegment {
+        ethernet2_hdr: eth2_header,
+        ipv4_hdr: ipv4_header,
+        tcp_hdr: TcpHeader {
+            seq_num: receive_next - SeqNumber::from(overlap as u32),
+            ..tcp_header
+        },
+        data: Buffer::Heap(DataBuffer::from_slice(&data)),
+        tx_checksum_offload: false,
+    };
+    server.receive(serialize_segment(segment)).unwrap();
+
+    // The server acknowledges the whole window right away.
+    let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(server.rt().pop_frame());
+    assert_eq!(ack_header.ack_num, receive_next + SeqNumber::from(window as u32));
+
+    // Exactly the data that fits in the window was accepted.
+    let mut received: Vec<u8> = Vec::new();
+    loop {
+        let mut pop_future = server.tcp_pop(server_fd);
+        match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+            Poll::Ready(Ok(buf)) => received.extend_from_slice(&buf[..]),
+            _ => break,
+        }
+    }
+    assert_eq!(received[..], data[overlap..(overlap + window)]);
+}
+
+/// Tests if keep-alive probes, with or without a garbage byte, are answered with a duplicate ACK that leaves the
+/// sequence state of the connection untouched.
+#[test]
+fn test_keepalive_probe() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+
+    // Send data: Client -> Server, which the server reads right away.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(100, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let data_bytes: Buffer = client.rt().pop_frame();
+    server.receive(data_bytes.clone()).unwrap();
+    let mut pop_future = server.tcp_pop(server_fd);
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) if buf.len() == 100 => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    while server.rt().pop_frame_unchecked().is_some() {}
+    let cb: Rc<ControlBlock<TestRuntime>> = server.ipv4.tcp.control_block(server_endpoints).unwrap();
+    let receive_next: SeqNumber = cb.rcv_nxt();
+    let send_next: SeqNumber = cb.get_send_next().0;
+
+    for probe_data in [&[][..], &[0][..]] {
+        // Send a keep-alive probe, which repeats the last sequence number that the server has received.
+        let (eth2_header, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) =
+            extract_headers(data_bytes.clone());
+        let segment: TcpSegment = TcpSegment {
+            ethernet2_hdr: eth2_header,
+            ipv4_hdr: ipv4_header,
+            tcp_hdr: TcpHeader {
+                seq_num: receive_next - SeqNumber::from(1),
+                ack_num: send_next,
+                ..tcp_header
+            },
+            data: Buffer::Heap(DataBuffer::from_slice(probe_data)),
+            tx_checksum_offload: false,
+        };
+        server.receive(serialize_segment(segment)).unwrap();
+
+        // The server answers right away with a duplicate ACK.
+        let bytes: Buffer = server.rt().pop_frame();
+        let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+        assert_eq!(ack_header.seq_num, send_next);
+        check_packet_pure_ack(
+            bytes,
+            test_helpers::BOB_MAC,
+            test_helpers::ALICE_MAC,
+            test_helpers::BOB_IPV4,
+            test_helpers::ALICE_IPV4,
+            receive_next,
+        );
+        assert!(server.rt().pop_frame_unchecked().is_none());
+
+        // Nothing has advanced, and no data is delivered.
+        assert_eq!(cb.rcv_nxt(), receive_next);
+        assert_eq!(cb.get_send_next().0, send_next);
+        let mut pop_future = server.tcp_pop(server_fd);
+        assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+    }
+}
+
+/// Tests if a pure ACK sent on a connection is sized for its headers alone, and padded to the minimum frame size.
+#[test]
+fn test_pure_ack_frame_size() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Send data: Client -> Server.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    server.receive(client.rt().pop_frame()).unwrap();
+
+    // Let the delayed ACK go out.
+    advance_clock(Some(&mut server), None, &mut now);
+    server.rt().poll_scheduler();
+    let bytes: Buffer = server.rt().pop_frame();
+
+    // The frame is padded with zeros, which the IPv4 length leaves out.
+    let headers_size: usize = ETHERNET2_HEADER_SIZE + IPV4_HEADER_DEFAULT_SIZE + MIN_TCP_HEADER_SIZE;
+    assert_eq!(bytes.len(), MIN_FRAME_SIZE);
+    assert!(bytes[headers_size..].iter().all(|b| *b == 0));
+    let (_, eth2_payload): (Ethernet2Header, Buffer) = Ethernet2Header::parse(bytes).unwrap();
+    let (ipv4_header, ipv4_payload): (Ipv4Header, Buffer) = Ipv4Header::parse(eth2_payload).unwrap();
+    assert_eq!(
+        ipv4_header.get_total_length() as usize,
+        IPV4_HEADER_DEFAULT_SIZE + MIN_TCP_HEADER_SIZE
+    );
+    let (tcp_header, tcp_payload): (TcpHeader, Buffer) = TcpHeader::parse(&ipv4_header, ipv4_payload, false).unwrap();
+    assert!(tcp_header.ack);
+    assert!(tcp_payload.is_empty());
+}
+
+/// Tests if a minimum retransmission timeout that would let every segment be retransmitted right away is rejected.
+#[test]
+fn test_min_rto_out_of_range() {
+    match TcpConfigExt::default().min_rto(Some(Duration::ZERO)) {
+        Err(e) => assert_eq!(e.errno, EINVAL),
+        Ok(_) => panic!("zero minimum RTO should have been rejected"),
+    }
+    assert!(TcpConfigExt::default().min_rto(Some(Duration::from_millis(1))).is_ok());
+}
+
+/// Tests if the low-latency profile acknowledges segments without delay and bounds the retransmission timeout by its
+/// aggressive floor.
+#[test]
+fn test_low_latency_profile() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let config: TcpConfigExt = TcpConfigExt::default().profile(SocketProfile::LowLatency);
+    assert_eq!(config.get_min_rto(), LOW_LATENCY_MIN_RTO);
+    server.ipv4.tcp.set_config(config.clone());
+    client.ipv4.tcp.set_config(config);
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Send two small buffers back to back: Client -> Server. Neither is held back waiting for an ACK, but that is the
+    // case for every profile.
+    let mut frames: Vec<Buffer> = Vec::new();
+    for _ in 0..2 {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        frames.push(client.rt().pop_frame());
+    }
+
+    for bytes in frames {
+        // Clear the PSH flag, so that the ACK would otherwise be delayed.
+        let (eth2_header, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+        let segment: TcpSegment = TcpSegment {
+            ethernet2_hdr: eth2_header,
+            ipv4_hdr: ipv4_header,
+            tcp_hdr: TcpHeader {
+                psh: false,
+                ..tcp_header
+            },
+            data: cook_buffer(32, None),
+            tx_checksum_offload: false,
+        };
+        server.receive(serialize_segment(segment)).unwrap();
+
+        // The server acknowledges the segment without the clock being advanced.
+        server.rt().poll_scheduler();
+        let ack: Buffer = server.rt().pop_frame();
+        let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(ack.clone());
+        assert!(ack_header.ack);
+        client.receive(ack).unwrap();
+    }
+
+    // The round-trip time is zero, so the retransmission timeout sits at its floor.
+    let cb: Rc<ControlBlock<TestRuntime>> = client.ipv4.tcp.control_block(client_endpoints).unwrap();
+    assert_eq!(cb.rto_estimate(), LOW_LATENCY_MIN_RTO);
+}
+
+/// Tests if a segment is sized to the window of the receiver when that window is smaller than the MSS.
+#[test]
+fn test_send_quantum_limited_by_window() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let recv_buffer_size: u32 = MIN_MSS as u32;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .recv_buffer_size(Some(recv_buffer_size))
+            .unwrap(),
+    );
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+    let cb: Rc<ControlBlock<TestRuntime>> = client.ipv4.tcp.control_block(client_endpoints).unwrap();
+    let mss: usize = client.tcp_mss(client_fd).unwrap();
+    let window: usize = cb.snd_wnd() as usize;
+    assert!(window > 0 && window < mss);
+
+    // Send a full-sized buffer: Client -> Server.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    client.rt().poll_scheduler();
+
+    // The segment carries as much as fits in the window.
+    let (_, eth2_payload): (Ethernet2Header, Buffer) = Ethernet2Header::parse(client.rt().pop_frame()).unwrap();
+    let (ipv4_header, ipv4_payload): (Ipv4Header, Buffer) = Ipv4Header::parse(eth2_payload).unwrap();
+    let (_, tcp_payload): (TcpHeader, Buffer) = TcpHeader::parse(&ipv4_header, ipv4_payload, false).unwrap();
+    assert_eq!(tcp_payload.len(), window);
+    assert!(client.rt().pop_frame_unchecked().is_none());
+}
+
+//=============================================================================
+
+/// Tests if a connection that our peer resets records that as the reason why it was closed.
+#[test]
+fn test_close_reason_peer_reset() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    assert_eq!(server.ipv4.tcp.close_reason(server_endpoints), None);
+
+    // The client resets the connection, with a RST that starts where its next segment would.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let (ethernet2_hdr, ipv4_hdr, mut tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) =
+        extract_headers(client.rt().pop_frame());
+    tcp_hdr.rst = true;
+    tcp_hdr.psh = false;
+    server
+        .receive(serialize_segment(TcpSegment {
+            ethernet2_hdr,
+            ipv4_hdr,
+            tcp_hdr,
+            data: Buffer::Heap(DataBuffer::empty()),
+            tx_checksum_offload: false,
+        }))
+        .unwrap();
+
+    // The reason is kept until it is taken.
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+    assert_eq!(
+        server.ipv4.tcp.close_reason(server_endpoints),
+        Some(CloseReason::PeerReset)
+    );
+    assert_eq!(
+        server.ipv4.tcp.take_close_reason(server_endpoints),
+        Some(CloseReason::PeerReset)
+    );
+    assert_eq!(server.ipv4.tcp.close_reason(server_endpoints), None);
+}
+
+//=============================================================================
+
+/// Tests if a connection that both peers close in an orderly fashion records that as the reason why it was closed.
+#[test]
+fn test_close_reason_graceful() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    connection_hangup(&mut ctx, &mut now, &mut server, &mut client, server_fd, client_fd);
+
+    // The server closed last, so it is done with the connection, while the client waits in TIME-WAIT.
+    assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+    assert_eq!(
+        server.ipv4.tcp.close_reason(server_endpoints),
+        Some(CloseReason::Graceful)
+    );
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::TimeWait)
+    );
+    assert_eq!(client.ipv4.tcp.close_reason(client_endpoints), None);
+
+    // The client is done once the time-wait timer expires.
+    for _ in 0..(2 * MSL).as_secs() {
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    }
+    client.rt().poll_scheduler();
+    assert_eq!(
+        client.ipv4.tcp.close_reason(client_endpoints),
+        Some(CloseReason::Graceful)
+    );
+}
+
+//=============================================================================
+
+/// Tests if a receive waits for data to reach the low-water mark, rather than completing with whatever has arrived.
+#[test]
+fn test_recv_lowat() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The server waits for at least 10 bytes.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().recv_lowat(Some(10)).unwrap());
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // The first 5 bytes are not enough to complete the receive.
+    let mut pop_future = server.tcp_pop(server_fd);
+    for (num_bytes, stamp) in [(5, 1), (6, 2)] {
+        assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(num_bytes, Some(stamp)));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        server.receive(client.rt().pop_frame()).unwrap();
+    }
+
+    // Once 6 more bytes arrive, the receive completes with all of them.
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) => assert_eq!(buf[..], [1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2]),
+        _ => panic!("pop should have returned data"),
+    }
+}
+
+/// Tests if a low-water mark that no receive could fall short of is rejected.
+#[test]
+fn test_recv_lowat_out_of_range() {
+    match TcpConfigExt::default().recv_lowat(Some(0)) {
+        Err(e) => assert_eq!(e.errno, EINVAL),
+        Ok(_) => panic!("zero low-water mark should have been rejected"),
+    }
+    assert!(TcpConfigExt::default().recv_lowat(Some(1)).is_ok());
+}
+
+//=============================================================================
+
+/// Tests if reading is reported as closed once our peer half-closes the connection and its data is drained, while
+/// sending still works.
+#[test]
+fn test_read_closed_after_half_close() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Send data and then FIN: Client -> Server.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    server.receive(client.rt().pop_frame()).unwrap();
+    client.tcp_close(client_fd).expect("client tcp_close returned error");
+    server.receive(client.rt().pop_frame()).unwrap();
+
+    // Reading is closed only once the data is drained.
+    assert!(!server.ipv4.tcp.is_read_closed(server_fd).unwrap());
+    let mut pop_future = server.tcp_pop(server_fd);
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok(buf)) if buf.len() == 32 => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    assert!(server.ipv4.tcp.is_read_closed(server_fd).unwrap());
+    server.rt().poll_scheduler();
+    while server.rt().pop_frame_unchecked().is_some() {}
+
+    // The server still sends data, which the client accepts.
+    let mut push_future: PushFuture = server.tcp_push(server_fd, cook_buffer(16, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let bytes: Buffer = server.rt().pop_frame();
+    let (_, _, data_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    assert!(!data_header.fin);
+    client.receive(bytes).unwrap();
+    let cb: Rc<ControlBlock<TestRuntime>> = client.ipv4.tcp.control_block(client_endpoints).unwrap();
+    assert_eq!(cb.rcv_nxt(), data_header.seq_num + SeqNumber::from(16));
+}
+
+/// Receives a segment that carries `data` with its PSH flag cleared, so that the ACK for it is delayed.
+fn receive_unpushed(receiver: &mut Engine<TestRuntime>, bytes: Buffer, data: Buffer) {
+    let (eth2_header, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    let segment: TcpSegment = TcpSegment {
+        ethernet2_hdr: eth2_header,
+        ipv4_hdr: ipv4_header,
+        tcp_hdr: TcpHeader {
+            psh: false,
+            ..tcp_header
+        },
+        data,
+        tx_checksum_offload: false,
+    };
+    receiver.receive(serialize_segment(segment)).unwrap();
+}
+
+/// Tests if the delayed ACK timeout scales down to a fraction of the round-trip time on a low-latency link.
+#[test]
+fn test_ack_delay_follows_rtt() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let rtt: Duration = Duration::from_millis(8);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().ack_delay(Some(MAX_ACK_DELAY)));
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let cb: Rc<ControlBlock<TestRuntime>> = server.ipv4.tcp.control_block(server_endpoints).unwrap();
+
+    // Until the round-trip time is measured, the server holds back its ACK for as long as it is configured to.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    receive_unpushed(&mut server, client.rt().pop_frame(), cook_buffer(32, None));
+    assert_eq!(cb.get_ack_deadline().0, Some(now + MAX_ACK_DELAY));
+    now += MAX_ACK_DELAY;
+    server.rt().advance_clock(now);
+    client.rt().advance_clock(now);
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+
+    // Send data: Server -> Client. The client acknowledges it right away, and the ACK takes a round-trip time to arrive.
+    let mut push_future: PushFuture = server.tcp_push(server_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    client.receive(server.rt().pop_frame()).unwrap();
+    client.rt().poll_scheduler();
+    let ack: Buffer = client.rt().pop_frame();
+    now += rtt;
+    server.rt().advance_clock(now);
+    client.rt().advance_clock(now);
+    server.receive(ack).unwrap();
+
+    // Now the server holds back its ACK for a fraction of the round-trip time.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    receive_unpushed(&mut server, client.rt().pop_frame(), cook_buffer(32, None));
+    assert_eq!(cb.get_ack_deadline().0, Some(now + rtt / 4));
+}
+
+/// Tests if a control block that is built in the ESTABLISHED state, without a handshake, exchanges data.
+#[test]
+fn test_control_block_for_test() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let local: SocketAddrV4 = SocketAddrV4::new(test_helpers::ALICE_IPV4, 49152);
+    let remote: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, 80);
+    let local_seq_no: SeqNumber = SeqNumber::from(1000);
+    let remote_seq_no: SeqNumber = SeqNumber::from(5000);
+    let window_size: u16 = 1024;
+
+    let mut alice: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let cb: ControlBlock<TestRuntime> = ControlBlock::for_test(
+        local,
+        remote,
+        alice.rt().clone(),
+        alice.arp.clone(),
+        local_seq_no,
+        remote_seq_no,
+        window_size,
+        window_size,
+    );
+    assert_eq!(cb.get_state(), State::Established);
+
+    // Send data: Local -> Remote.
+    cb.send(cook_buffer(32, None)).unwrap();
+    let bufsize: usize = check_packet_data(
+        alice.rt().pop_frame(),
+        test_helpers::ALICE_MAC,
+        test_helpers::BOB_MAC,
+        test_helpers::ALICE_IPV4,
+        test_helpers::BOB_IPV4,
+        window_size,
+        local_seq_no,
+        Some(remote_seq_no),
+    );
+    assert_eq!(bufsize, 32);
+
+    // Receive data that acknowledges ours: Remote -> Local.
+    let mut header: TcpHeader = TcpHeader::new(remote.port(), local.port());
+    header.seq_num = remote_seq_no;
+    header.ack = true;
+    header.ack_num = local_seq_no + SeqNumber::from(32);
+    header.window_size = window_size;
+    cb.receive(&mut header, cook_buffer(16, None));
+    assert_eq!(cb.get_send_unacked().0, local_seq_no + SeqNumber::from(32));
+    assert_eq!(cb.rcv_nxt(), remote_seq_no + SeqNumber::from(16));
+    match cb.poll_recv(&mut ctx) {
+        Poll::Ready(Ok(buf)) => assert_eq!(buf.len(), 16),
+        _ => panic!("received data should be ready"),
+    }
+}
+
+/// Tests if an ACK that is owed is piggybacked on data that is sent before the delayed ACK timer expires, rather than
+/// being sent on its own as well.
+#[test]
+fn test_ack_piggybacked_on_data() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let cb: Rc<ControlBlock<TestRuntime>> = server.ipv4.tcp.control_block(server_endpoints).unwrap();
+
+    // Send data: Client -> Server. The server owes an ACK for it.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let bytes: Buffer = client.rt().pop_frame();
+    let (_, _, client_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    receive_unpushed(&mut server, bytes, cook_buffer(32, None));
+    assert!(cb.get_ack_deadline().0.is_some());
+
+    // Send data: Server -> Client. The data segment carries the ACK.
+    let mut push_future: PushFuture = server.tcp_push(server_fd, cook_buffer(16, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let (_, _, data_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(server.rt().pop_frame());
+    assert!(data_header.ack);
+    assert_eq!(data_header.ack_num, client_header.seq_num + SeqNumber::from(32));
+    assert_eq!(cb.get_ack_deadline().0, None);
+
+    // No pure ACK follows once the delayed ACK timeout has passed.
+    now += MAX_ACK_DELAY;
+    server.rt().advance_clock(now);
+    server.rt().poll_scheduler();
+    assert!(server.rt().pop_frame_unchecked().is_none());
+}
+
+/// Tests if the transmit hook rewrites segments before they are sent.
+#[test]
+fn test_transmit_hook_rewrites_segment() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let rewritten_port: u16 = 4242;
+
+    // Setup peers. The client rewrites the source port of segments that carry data.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let hook: TcpTransmitHook = Rc::new(move |segment: &mut TcpSegment| {
+        if !segment.data.is_empty() {
+            segment.tcp_hdr.src_port = rewritten_port;
+        }
+        true
+    });
+    client
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().transmit_hook(Some(hook)));
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let client_port: u16 = client.ipv4.tcp.endpoints(client_fd).unwrap().0.port();
+
+    // Send data: Client -> Server.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // The emitted frame carries the rewritten port.
+    let (_, _, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(client.rt().pop_frame());
+    assert_ne!(client_port, rewritten_port);
+    assert_eq!(tcp_header.src_port, rewritten_port);
+}
+
+/// Tests if a connection records how long its handshake took and how long it spent in each state afterwards.
+#[test]
+fn test_state_durations() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. Each step of the handshake takes one second.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Both peers measured the handshake from their first SYN until they established the connection.
+    let durations: StateDurations = client.ipv4.tcp.state_durations(client_endpoints).unwrap();
+    assert_eq!(durations.handshake, Duration::from_secs(2));
+    let durations: StateDurations = server.ipv4.tcp.state_durations(server_endpoints).unwrap();
+    assert_eq!(durations.handshake, Duration::from_secs(2));
+
+    // Stay established for a while, then close the connection on the client.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    client.tcp_close(client_fd).unwrap();
+    client.rt().poll_scheduler();
+    assert_eq!(
+        client.ipv4.tcp.connection_state(client_endpoints),
+        Some(State::FinWait1)
+    );
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // The time spent in each state is accounted for, including the current one.
+    let durations: StateDurations = client.ipv4.tcp.state_durations(client_endpoints).unwrap();
+    assert_eq!(durations.get(State::Established), Duration::from_secs(1));
+    assert_eq!(durations.get(State::FinWait1), Duration::from_secs(1));
+    assert_eq!(durations.get(State::CloseWait), Duration::ZERO);
+}
+
+/// Tests if the elements of a scatter-gather array are sent as one message, straight from the memory of the application.
+#[test]
+fn test_push_sga_zero_copy() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The client records where the payload of each data segment that it sends lies in memory.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let sent: Rc<RefCell<Vec<(usize, usize, bool)>>> = Rc::new(RefCell::new(Vec::new()));
+    let sent_: Rc<RefCell<Vec<(usize, usize, bool)>>> = sent.clone();
+    let hook: TcpTransmitHook = Rc::new(move |segment: &mut TcpSegment| {
+        if !segment.data.is_empty() {
+            let ptr: usize = segment.data.as_ptr() as usize;
+            sent_.borrow_mut().push((ptr, segment.data.len(), segment.tcp_hdr.psh));
+        }
+        true
+    });
+    client
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().transmit_hook(Some(hook)));
+
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+    let mss: usize = client.ipv4.tcp.effective_send_mss(client_fd).unwrap();
+
+    // Push a scatter-gather array whose middle element spans more than one segment.
+    let sga: Vec<Buffer> = vec![
+        cook_buffer(100, Some(1)),
+        cook_buffer(mss + 50, None),
+        cook_buffer(30, Some(3)),
+    ];
+    let mut push_future: PushFuture = client.ipv4.tcp.push_sga(client_fd, sga.clone());
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    client.rt().poll_scheduler();
+
+    // The payload that goes on the wire is the concatenation of the elements.
+    let mut payload: Vec<u8> = Vec::new();
+    while let Some(frame) = client.rt().pop_frame_unchecked() {
+        let (_, ipv4_payload): (Ethernet2Header, Buffer) = Ethernet2Header::parse(frame).unwrap();
+        let (ipv4_header, tcp_payload): (Ipv4Header, Buffer) = Ipv4Header::parse(ipv4_payload).unwrap();
+        let (_, data): (TcpHeader, Buffer) = TcpHeader::parse(&ipv4_header, tcp_payload, false).unwrap();
+        payload.extend_from_slice(&data[..]);
+    }
+    let expected: Vec<u8> = sga.iter().flat_map(|buf| buf[..].to_vec()).collect();
+    assert_eq!(payload, expected);
+
+    // Each segment was cut straight out of one of the elements, and only the last one is pushed.
+    let sent: Vec<(usize, usize, bool)> = sent.borrow().clone();
+    assert_eq!(sent.len(), 4);
+    for (i, (ptr, len, psh)) in sent.iter().enumerate() {
+        assert!(sga.iter().any(|buf| {
+            let start: usize = buf.as_ptr() as usize;
+            start <= *ptr && ptr + len <= start + buf.len()
+        }));
+        assert_eq!(*psh, i == sent.len() - 1);
+    }
+}
+
+/// Tests if a flush completes only once our peer has acknowledged all data that was pushed.
+#[test]
+fn test_flush_waits_for_ack() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Nothing was pushed yet, so there is nothing to wait for.
+    let mut flush_future: FlushFuture<TestRuntime> = client.ipv4.tcp.flush(client_fd);
+    match Future::poll(Pin::new(&mut flush_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // Send two segments: Client -> Server.
+    let mut frames: Vec<(Buffer, TcpHeader)> = Vec::new();
+    for size in [32, 16] {
+        let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(size, None));
+        match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+        let bytes: Buffer = client.rt().pop_frame();
+        let (_, _, header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+        frames.push((bytes, header));
+    }
+    let end: SeqNumber = frames[1].1.seq_num + SeqNumber::from(16);
+
+    // The flush waits for both segments to be acknowledged.
+    let mut flush_future: FlushFuture<TestRuntime> = client.ipv4.tcp.flush(client_fd);
+    assert!(Future::poll(Pin::new(&mut flush_future), &mut ctx).is_pending());
+
+    for (i, (bytes, header)) in frames.into_iter().enumerate() {
+        // The server acknowledges one segment at a time.
+        server.receive(bytes).unwrap();
+        now += MAX_ACK_DELAY;
+        server.rt().advance_clock(now);
+        server.rt().poll_scheduler();
+        let ack: Buffer = server.rt().pop_frame();
+        let (_, _, ack_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(ack.clone());
+        assert_eq!(
+            ack_header.ack_num,
+            header.seq_num + SeqNumber::from(if i == 0 { 32 } else { 16 })
+        );
+        client.receive(ack).unwrap();
+
+        // The flush only completes once the ACK covers all of the pushed data.
+        match Future::poll(Pin::new(&mut flush_future), &mut ctx) {
+            Poll::Ready(Ok(())) if ack_header.ack_num == end => Ok(()),
+            Poll::Pending if ack_header.ack_num != end => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+    }
+}
+
+/// Tests if concurrent flushes, each polled by a task of its own, are all woken up and completed by the ACK that covers
+/// the pushed data.
+#[test]
+fn test_concurrent_flushes() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let (_, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Send data: Client -> Server.
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(32, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    let bytes: Buffer = client.rt().pop_frame();
+
+    // Issue two flushes, one through the socket and one through a stream that wraps it.
+    let flags: Vec<Arc<FlagWaker>> = (0..2).map(|_| Arc::new(FlagWaker(AtomicBool::new(false)))).collect();
+    let wakers: Vec<Waker> = flags.iter().map(|flag| waker(flag.clone())).collect();
+    let mut flush_future: FlushFuture<TestRuntime> = client.ipv4.tcp.flush(client_fd);
+    let mut stream: TcpStream<TestRuntime> = client.ipv4.tcp.stream(client_fd).unwrap();
+    assert!(Future::poll(Pin::new(&mut flush_future), &mut Context::from_waker(&wakers[0])).is_pending());
+    assert!(Pin::new(&mut stream)
+        .poll_flush(&mut Context::from_waker(&wakers[1]))
+        .is_pending());
+
+    // The server acknowledges the data.
+    server.receive(bytes).unwrap();
+    now += MAX_ACK_DELAY;
+    server.rt().advance_clock(now);
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+
+    // Both flushes were woken up, and both of them complete.
+    assert!(flags.iter().all(|flag| flag.0.load(Ordering::SeqCst)));
+    match Future::poll(Pin::new(&mut flush_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    match Pin::new(&mut stream).poll_flush(&mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
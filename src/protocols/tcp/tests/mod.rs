@@ -1,7 +1,9 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+pub mod congestion_control;
 pub mod established;
+pub mod segment;
 pub mod setup;
 
 use crate::protocols::{
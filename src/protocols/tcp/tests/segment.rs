@@ -0,0 +1,229 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    protocols::{
+        ethernet2::{
+            EtherType2,
+            Ethernet2Header,
+        },
+        ip::IpProtocol,
+        ipv4::Ipv4Header,
+        tcp::{
+            segment::{
+                TcpHeader,
+                TcpOptions2,
+                TcpSegment,
+                MIN_TCP_HEADER_SIZE,
+            },
+            tests::setup::serialize_segment,
+        },
+    },
+    test_helpers,
+};
+use ::libc::EBADMSG;
+use ::runtime::{
+    fail::Fail,
+    memory::{
+        Buffer,
+        DataBuffer,
+    },
+};
+
+//=============================================================================
+
+/// Parses raw bytes as a TCP segment, with checksum validation disabled.
+fn parse_bytes(bytes: &[u8]) -> Result<(TcpHeader, Buffer), Fail> {
+    let ipv4_header: Ipv4Header = Ipv4Header::new(test_helpers::ALICE_IPV4, test_helpers::BOB_IPV4, IpProtocol::TCP);
+    TcpHeader::parse(&ipv4_header, Buffer::Heap(DataBuffer::from_slice(bytes)), true)
+}
+
+/// Builds the bytes of a minimal TCP header with the given data offset (in 32-bit words).
+fn cook_header(data_offset: u8) -> [u8; MIN_TCP_HEADER_SIZE] {
+    let mut bytes: [u8; MIN_TCP_HEADER_SIZE] = [0; MIN_TCP_HEADER_SIZE];
+    bytes[0..2].copy_from_slice(&80u16.to_be_bytes());
+    bytes[2..4].copy_from_slice(&8080u16.to_be_bytes());
+    bytes[12] = data_offset << 4;
+    // ACK flag.
+    bytes[13] = 1 << 4;
+    bytes
+}
+
+//=============================================================================
+
+/// Tests if the MSS option is found among the options of a header.
+#[test]
+fn test_mss_option() {
+    let mut header: TcpHeader = TcpHeader::new(80, 8080);
+    assert_eq!(header.mss_option(), None);
+
+    header.push_option(TcpOptions2::NoOperation);
+    header.push_option(TcpOptions2::MaximumSegmentSize(1460));
+    assert_eq!(header.mss_option(), Some(1460));
+    assert_eq!(header.window_scale_option(), None);
+    assert!(!header.sack_permitted());
+}
+
+/// Tests if the window scale option is found among the options of a header.
+#[test]
+fn test_window_scale_option() {
+    let mut header: TcpHeader = TcpHeader::new(80, 8080);
+    assert_eq!(header.window_scale_option(), None);
+
+    header.push_option(TcpOptions2::MaximumSegmentSize(536));
+    header.push_option(TcpOptions2::WindowScale(7));
+    assert_eq!(header.window_scale_option(), Some(7));
+    assert_eq!(header.mss_option(), Some(536));
+    assert!(!header.sack_permitted());
+}
+
+/// Tests if the SACK-permitted option is found among the options of a header.
+#[test]
+fn test_sack_permitted_option() {
+    let mut header: TcpHeader = TcpHeader::new(80, 8080);
+    assert!(!header.sack_permitted());
+
+    header.push_option(TcpOptions2::SelectiveAcknowlegementPermitted);
+    assert!(header.sack_permitted());
+    assert_eq!(header.mss_option(), None);
+    assert_eq!(header.window_scale_option(), None);
+}
+
+/// Tests if a well-formed header is accepted.
+#[test]
+fn test_parse_valid() {
+    let bytes: [u8; MIN_TCP_HEADER_SIZE] = cook_header(5);
+    let (header, data): (TcpHeader, Buffer) = parse_bytes(&bytes).unwrap();
+    assert_eq!(header.src_port, 80);
+    assert_eq!(header.dst_port, 8080);
+    assert!(header.ack);
+    assert_eq!(data.len(), 0);
+}
+
+/// Tests if a header whose data offset is smaller than the minimum header size is rejected.
+#[test]
+fn test_parse_data_offset_too_small() {
+    let bytes: [u8; MIN_TCP_HEADER_SIZE] = cook_header(4);
+    match parse_bytes(&bytes) {
+        Err(error) if error.errno == EBADMSG => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+/// Tests if a header whose data offset goes past the end of the segment is rejected.
+#[test]
+fn test_parse_data_offset_past_end() {
+    let bytes: [u8; MIN_TCP_HEADER_SIZE] = cook_header(6);
+    match parse_bytes(&bytes) {
+        Err(error) if error.errno == EBADMSG => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+/// Tests if a header that sets a reserved bit is rejected.
+#[test]
+fn test_parse_reserved_bit() {
+    let mut bytes: [u8; MIN_TCP_HEADER_SIZE] = cook_header(5);
+    bytes[12] |= 1 << 1;
+    match parse_bytes(&bytes) {
+        Err(error) if error.errno == EBADMSG => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+/// Tests if a URG segment is only accepted if its urgent pointer stays within its data.
+#[test]
+fn test_parse_urgent_pointer() {
+    let mut bytes: Vec<u8> = cook_header(5).to_vec();
+    bytes.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+    // URG flag.
+    bytes[13] |= 1 << 5;
+
+    // The urgent pointer may point right past the end of the data.
+    bytes[18..20].copy_from_slice(&4u16.to_be_bytes());
+    let (header, data): (TcpHeader, Buffer) = parse_bytes(&bytes).unwrap();
+    assert!(header.urg);
+    assert_eq!(header.urgent_pointer, 4);
+    assert_eq!(data.len(), 4);
+
+    // But no further.
+    bytes[18..20].copy_from_slice(&5u16.to_be_bytes());
+    match parse_bytes(&bytes) {
+        Err(error) if error.errno == EBADMSG => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // The urgent pointer is ignored if the URG flag is not set.
+    bytes[13] &= !(1 << 5);
+    parse_bytes(&bytes).unwrap();
+}
+
+/// Tests if the checksum of a segment is only verified when receive checksum offload is disabled.
+#[test]
+fn test_parse_checksum() {
+    let ipv4_header: Ipv4Header = Ipv4Header::new(test_helpers::ALICE_IPV4, test_helpers::BOB_IPV4, IpProtocol::TCP);
+    let mut header: TcpHeader = TcpHeader::new(80, 8080);
+    header.ack = true;
+    let data: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+    let header_size: usize = header.compute_size();
+    let mut bytes: Vec<u8> = vec![0; header_size + data.len()];
+    header.serialize(&mut bytes[..header_size], &ipv4_header, &data, false);
+    bytes[header_size..].copy_from_slice(&data);
+
+    // A segment with a valid checksum is accepted.
+    let buf: Buffer = Buffer::Heap(DataBuffer::from_slice(&bytes));
+    let (_, payload): (TcpHeader, Buffer) = TcpHeader::parse(&ipv4_header, buf, false).unwrap();
+    assert_eq!(&payload[..], &data[..]);
+
+    // A segment with a bad checksum is dropped when offload is disabled.
+    bytes[16] ^= 0xff;
+    let buf: Buffer = Buffer::Heap(DataBuffer::from_slice(&bytes));
+    match TcpHeader::parse(&ipv4_header, buf, false) {
+        Err(error) if error.errno == EBADMSG => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // A segment with a bad checksum is accepted when offload is enabled.
+    let buf: Buffer = Buffer::Heap(DataBuffer::from_slice(&bytes));
+    let (_, payload): (TcpHeader, Buffer) = TcpHeader::parse(&ipv4_header, buf, true).unwrap();
+    assert_eq!(&payload[..], &data[..]);
+}
+
+/// Tests if a signed segment is authenticated with the key that signed it, and only with that key.
+#[test]
+fn test_md5_signature() {
+    let key: &[u8] = b"secret";
+    let data: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+    let ipv4_hdr: Ipv4Header = Ipv4Header::new(test_helpers::ALICE_IPV4, test_helpers::BOB_IPV4, IpProtocol::TCP);
+    let mut tcp_hdr: TcpHeader = TcpHeader::new(80, 8080);
+    tcp_hdr.ack = true;
+    tcp_hdr.push_option(TcpOptions2::MaximumSegmentSize(1460));
+    tcp_hdr.sign_md5(&ipv4_hdr, &data, key);
+    assert!(tcp_hdr.md5_option().is_some());
+
+    let segment: TcpSegment = TcpSegment {
+        ethernet2_hdr: Ethernet2Header::new(test_helpers::BOB_MAC, test_helpers::ALICE_MAC, EtherType2::Ipv4),
+        ipv4_hdr,
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::from_slice(&data)),
+        tx_checksum_offload: false,
+    };
+    let bytes: Buffer = serialize_segment(segment);
+    let (_, ipv4_payload): (Ethernet2Header, Buffer) = Ethernet2Header::parse(bytes).unwrap();
+    let (ipv4_hdr, tcp_payload): (Ipv4Header, Buffer) = Ipv4Header::parse(ipv4_payload).unwrap();
+    let (tcp_hdr, payload): (TcpHeader, Buffer) = TcpHeader::parse(&ipv4_hdr, tcp_payload, false).unwrap();
+
+    assert!(tcp_hdr.verify_md5(&ipv4_hdr, &payload[..], key));
+    assert!(!tcp_hdr.verify_md5(&ipv4_hdr, &payload[..], b"public"));
+    assert!(!tcp_hdr.verify_md5(&ipv4_hdr, &payload[..1], key));
+
+    // An unsigned segment is never authenticated.
+    let unsigned: TcpHeader = TcpHeader::new(80, 8080);
+    assert_eq!(unsigned.md5_option(), None);
+    assert!(!unsigned.verify_md5(&ipv4_hdr, &payload[..], key));
+}
@@ -2,22 +2,57 @@
 // Licensed under the MIT license.
 
 use crate::{
+    fail::{
+        ErrorKind,
+        FailExt,
+    },
+    futures::FutureOperation,
     protocols::{
         ethernet2::{
             EtherType2,
             Ethernet2Header,
+            MIN_FRAME_SIZE,
+        },
+        ip::{
+            EphemeralPorts,
+            IpProtocol,
         },
         ipv4::Ipv4Header,
         tcp::{
+            config::MAX_MD5_KEY_SIZE,
+            congestion_control,
+            constants::{
+                FALLBACK_MSS,
+                MAX_MSS,
+                MAX_WINDOW_SCALE,
+                MIN_MSS,
+                MSL,
+                TIMESTAMPS_OPTION_OVERHEAD,
+            },
+            established::ControlBlock,
             operations::{
                 AcceptFuture,
                 ConnectFuture,
+                PushFuture,
             },
+            passive_open::PassiveSocket,
             segment::{
                 TcpHeader,
+                TcpOptions2,
                 TcpSegment,
             },
+            tests::{
+                check_packet_pure_ack,
+                established::cook_buffer,
+            },
+            AcceptCallback,
+            AcceptFilter,
+            AcceptQueueStats,
+            ConnectionParams,
             SeqNumber,
+            State,
+            TcpConfigExt,
+            MIN_TCP_HEADER_SIZE,
         },
     },
     test_helpers::{
@@ -26,9 +61,22 @@ use crate::{
         TestRuntime,
     },
 };
-use ::futures::task::noop_waker_ref;
+use ::futures::{
+    channel::mpsc,
+    task::{
+        noop_waker_ref,
+        waker,
+        ArcWake,
+    },
+    FutureExt,
+    StreamExt,
+};
 use ::libc::{
+    EADDRINUSE,
     EBADMSG,
+    ECONNREFUSED,
+    EHOSTUNREACH,
+    EINVAL,
     ETIMEDOUT,
 };
 use ::runtime::{
@@ -37,6 +85,11 @@ use ::runtime::{
         DataBuffer,
     },
     network::{
+        config::{
+            ArpConfig,
+            TcpConfig,
+            UdpConfig,
+        },
         types::{
             Ipv4Addr,
             MacAddress,
@@ -44,16 +97,27 @@ use ::runtime::{
         NetworkRuntime,
         PacketBuf,
     },
+    scheduler::SchedulerHandle,
     task::SchedulerRuntime,
     QDesc,
 };
 use ::std::{
+    cell::RefCell,
     future::Future,
     net::SocketAddrV4,
     pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
     task::{
         Context,
         Poll,
+        Waker,
     },
     time::{
         Duration,
@@ -111,6 +175,134 @@ fn test_connection_timeout() {
 
 //=============================================================================
 
+/// Tests if a passive open fails with host unreachable when the remote link address cannot be resolved.
+#[test]
+fn test_passive_open_unresolved_link_addr() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The server has an empty ARP cache and nobody answers its ARP requests.
+    let mut server = test_helpers::new_bob(now);
+    let mut client = test_helpers::new_alice2(now);
+    let nretries: usize = server.rt().tcp_options().get_handshake_retries();
+    let arp_retries: usize = server.rt().arp_options().get_retry_count();
+    let arp_timeout: Duration = server.rt().arp_options().get_request_timeout();
+
+    // Server: LISTEN state at T(0).
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+
+    // T(0) -> T(1)
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Client: SYN_SENT state at T(1).
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Server: start resolving the link address of the client.
+    server.receive(bytes).unwrap();
+    server.rt().poll_scheduler();
+
+    // Let all handshake attempts run out.
+    for _ in 0..nretries {
+        for _ in 0..((arp_retries + 1) * arp_timeout.as_secs() as usize) {
+            advance_clock(Some(&mut server), None, &mut now);
+            server.rt().poll_scheduler();
+        }
+    }
+
+    match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Err(error)) if error.errno == EHOSTUNREACH => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+//=============================================================================
+
+/// Tests if a connection request that is answered with a RST is refused.
+#[test]
+fn test_connect_refused() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The server does not listen on the port.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+
+    // T(0) -> T(1)
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Client: SYN_SENT state at T(1).
+    let (_, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Server: answer with a RST that acknowledges the SYN.
+    server.receive(bytes).unwrap();
+    let bytes: Buffer = server.rt().pop_frame();
+    let (_, _, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    assert!(tcp_header.rst);
+    assert!(tcp_header.ack);
+
+    // Client: CLOSED state.
+    client.receive(bytes).unwrap();
+
+    match Future::poll(Pin::new(&mut connect_future), &mut ctx) {
+        Poll::Ready(Err(error)) if error.errno == ECONNREFUSED => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+//=============================================================================
+
+/// Tests if an active open fails with host unreachable when the remote link address cannot be resolved.
+#[test]
+fn test_connect_unresolved_link_addr() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup client. It has an empty ARP cache and nobody answers its ARP requests.
+    let mut client = test_helpers::new_alice(now);
+    let nretries: usize = client.rt().tcp_options().get_handshake_retries();
+    let arp_retries: usize = client.rt().arp_options().get_retry_count();
+    let arp_timeout: Duration = client.rt().arp_options().get_request_timeout();
+
+    // T(0) -> T(1)
+    advance_clock(None, Some(&mut client), &mut now);
+
+    // Client: start resolving the link address of the server.
+    let (_, mut connect_future, _): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Let all handshake attempts run out.
+    for _ in 0..nretries {
+        for _ in 0..((arp_retries + 1) * arp_timeout.as_secs() as usize) {
+            advance_clock(None, Some(&mut client), &mut now);
+            client.rt().poll_scheduler();
+        }
+    }
+
+    match Future::poll(Pin::new(&mut connect_future), &mut ctx) {
+        Poll::Ready(Err(error)) if error.errno == EHOSTUNREACH => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+}
+
+//=============================================================================
+
 /// Refuse a connection.
 #[test]
 fn test_refuse_connection_early_rst() {
@@ -132,7 +324,7 @@ fn test_refuse_connection_early_rst() {
     advance_clock(Some(&mut server), Some(&mut client), &mut now);
 
     // Client: SYN_SENT state at T(1).
-    let (_, _, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
         connection_setup_listen_syn_sent(&mut client, listen_addr);
 
     // Temper packet.
@@ -201,7 +393,7 @@ fn test_refuse_connection_early_ack() {
     advance_clock(Some(&mut server), Some(&mut client), &mut now);
 
     // Client: SYN_SENT state at T(1).
-    let (_, _, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
         connection_setup_listen_syn_sent(&mut client, listen_addr);
 
     // Temper packet.
@@ -270,7 +462,7 @@ fn test_refuse_connection_missing_syn() {
     advance_clock(Some(&mut server), Some(&mut client), &mut now);
 
     // Client: SYN_SENT state at T(1).
-    let (_, _, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
         connection_setup_listen_syn_sent(&mut client, listen_addr);
 
     // Sanity check packet.
@@ -328,8 +520,47 @@ fn test_refuse_connection_missing_syn() {
 
 //=============================================================================
 
+/// Tests if short segments are padded to the minimum Ethernet frame size.
+#[test]
+fn test_short_segment_padding() {
+    let now = Instant::now();
+    let mut client = test_helpers::new_alice2(now);
+
+    // Build a pure ACK.
+    let mut tcp_hdr: TcpHeader = TcpHeader::new(80, 80);
+    tcp_hdr.ack = true;
+    tcp_hdr.ack_num = SeqNumber::from(1);
+    let segment: TcpSegment = TcpSegment {
+        ethernet2_hdr: Ethernet2Header::new(test_helpers::BOB_MAC, test_helpers::ALICE_MAC, EtherType2::Ipv4),
+        ipv4_hdr: Ipv4Header::new(test_helpers::ALICE_IPV4, test_helpers::BOB_IPV4, IpProtocol::TCP),
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::empty()),
+        tx_checksum_offload: false,
+    };
+    let frame_size: usize = segment.header_size();
+    assert!(frame_size < MIN_FRAME_SIZE);
+    client.rt().transmit(segment);
+
+    // Check that the frame was padded with zeros.
+    let bytes: Buffer = client.rt().pop_frame();
+    assert_eq!(bytes.len(), MIN_FRAME_SIZE);
+    assert!(bytes[frame_size..].iter().all(|b| *b == 0));
+
+    // Padding should not show up as payload.
+    check_packet_pure_ack(
+        bytes,
+        test_helpers::ALICE_MAC,
+        test_helpers::BOB_MAC,
+        test_helpers::ALICE_IPV4,
+        test_helpers::BOB_IPV4,
+        SeqNumber::from(1),
+    );
+}
+
+//=============================================================================
+
 /// Extracts headers of a TCP packet.
-fn extract_headers(bytes: Buffer) -> (Ethernet2Header, Ipv4Header, TcpHeader) {
+pub fn extract_headers(bytes: Buffer) -> (Ethernet2Header, Ipv4Header, TcpHeader) {
     let (eth2_header, eth2_payload) = Ethernet2Header::parse(bytes).unwrap();
     let (ipv4_header, ipv4_payload) = Ipv4Header::parse(eth2_payload).unwrap();
     let (tcp_header, _) = TcpHeader::parse(&ipv4_header, ipv4_payload, false).unwrap();
@@ -340,7 +571,7 @@ fn extract_headers(bytes: Buffer) -> (Ethernet2Header, Ipv4Header, TcpHeader) {
 //=============================================================================
 
 /// Serializes a TCP segment.
-fn serialize_segment(pkt: TcpSegment) -> Buffer {
+pub fn serialize_segment(pkt: TcpSegment) -> Buffer {
     let header_size: usize = pkt.header_size();
     let body_size: usize = pkt.body_size();
     let mut buf = DataBuffer::new(header_size + body_size).unwrap();
@@ -575,6 +806,203 @@ pub fn connection_setup(
     (server_fd, client_fd)
 }
 
+/// Tests if the maximum segment size scales with the configured MTU.
+#[test]
+fn test_connect_jumbo_mtu() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let mtu: usize = 9000;
+    let mss: usize = mtu - 40;
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let config: TcpConfigExt = TcpConfigExt::default().mtu(Some(mtu)).unwrap();
+    assert_eq!(config.get_advertised_mss(&client.rt().tcp_options()), mss);
+    server.ipv4.tcp.set_config(config.clone());
+    client.ipv4.tcp.set_config(config);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Both peers should have advertised and adopted the larger segment size.
+    assert_eq!(server.tcp_mss(server_fd).unwrap(), mss);
+    assert_eq!(client.tcp_mss(client_fd).unwrap(), mss);
+}
+
+/// Tests if MTUs that cannot fit a TCP segment, or that do not fit in an IPv4 header, are rejected.
+#[test]
+fn test_mtu_out_of_range() {
+    for mtu in [0, 40, 65536] {
+        match TcpConfigExt::default().mtu(Some(mtu)) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("MTU {} should have been rejected", mtu),
+        }
+    }
+    assert!(TcpConfigExt::default().mtu(Some(41)).is_ok());
+    assert!(TcpConfigExt::default().mtu(Some(65535)).is_ok());
+}
+
+/// Tests if an explicitly configured segment size is advertised instead of the one derived from the MTU.
+#[test]
+fn test_connect_advertised_mss_override() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let mss: usize = 1200;
+
+    // Setup peers. Only the client overrides its segment size.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let config: TcpConfigExt = TcpConfigExt::default()
+        .mtu(Some(9000))
+        .unwrap()
+        .advertised_mss(Some(mss))
+        .unwrap();
+    assert_eq!(config.get_advertised_mss(&client.rt().tcp_options()), mss);
+    client.ipv4.tcp.set_config(config);
+
+    let (server_fd, _): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // The server should have adopted the segment size that the client advertised.
+    assert_eq!(server.tcp_mss(server_fd).unwrap(), mss);
+}
+
+/// Tests if advertised segment sizes that we do not support are rejected.
+#[test]
+fn test_advertised_mss_out_of_range() {
+    for mss in [0, MIN_MSS - 1, MAX_MSS + 1] {
+        match TcpConfigExt::default().advertised_mss(Some(mss)) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("MSS {} should have been rejected", mss),
+        }
+    }
+    assert!(TcpConfigExt::default().advertised_mss(Some(MIN_MSS)).is_ok());
+    assert!(TcpConfigExt::default().advertised_mss(Some(MAX_MSS)).is_ok());
+}
+
+/// Tests if the segment size that we send with is the smaller of the segment sizes that the two peers advertise.
+#[test]
+fn test_effective_send_mss() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let mtu: usize = 9000;
+
+    // Setup peers. Only the client has a jumbo MTU, so it advertises a larger segment size than the server.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    client
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().mtu(Some(mtu)).unwrap());
+    let client_mss: usize = mtu - 40;
+    let server_mss: usize = TcpConfigExt::default().get_advertised_mss(&server.rt().tcp_options());
+    assert_ne!(client_mss, server_mss);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    assert_eq!(client.ipv4.tcp.local_mss(client_fd).unwrap(), client_mss);
+    assert_eq!(client.ipv4.tcp.remote_mss(client_fd).unwrap(), server_mss);
+    assert_eq!(server.ipv4.tcp.local_mss(server_fd).unwrap(), server_mss);
+    assert_eq!(server.ipv4.tcp.remote_mss(server_fd).unwrap(), client_mss);
+    // The client clamps the segment size of the server to what fits in its own MTU.
+    assert_eq!(
+        client.ipv4.tcp.effective_send_mss(client_fd).unwrap(),
+        client_mss.min(server_mss)
+    );
+}
+
+/// Tests if agreeing on timestamps in the handshake shrinks the segment size that we send with by the size of the
+/// option, which every segment then carries.
+#[test]
+fn test_connect_timestamps() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. Both offer timestamps.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let config: TcpConfigExt = TcpConfigExt::default().timestamps(true);
+    server.ipv4.tcp.set_config(config.clone());
+    client.ipv4.tcp.set_config(config);
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    for (peer, fd) in [(&server, server_fd), (&client, client_fd)] {
+        assert!(peer.ipv4.tcp.negotiated(fd).unwrap().timestamps);
+        assert_eq!(
+            peer.ipv4.tcp.effective_send_mss(fd).unwrap(),
+            peer.ipv4.tcp.remote_mss(fd).unwrap() - TIMESTAMPS_OPTION_OVERHEAD
+        );
+    }
+
+    // A full-sized segment still fits in the segment size that the server advertised, along with the option.
+    let mss: usize = client.ipv4.tcp.effective_send_mss(client_fd).unwrap();
+    let mut push_future: PushFuture = client.tcp_push(client_fd, cook_buffer(mss, None));
+    match Future::poll(Pin::new(&mut push_future), &mut ctx) {
+        Poll::Ready(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+    client.rt().poll_scheduler();
+    let bytes: Buffer = client.rt().pop_frame();
+    let (_, ipv4_header, tcp_header): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    assert!(tcp_header.timestamp_option().is_some());
+    assert_eq!(
+        ipv4_header.get_total_length() as usize - ipv4_header.compute_size() - tcp_header.compute_size(),
+        mss
+    );
+    assert_eq!(
+        tcp_header.compute_size() - MIN_TCP_HEADER_SIZE,
+        TIMESTAMPS_OPTION_OVERHEAD
+    );
+}
+
+/// Tests if timestamps are not agreed, and thus leave the segment size alone, when only one of the peers offers them.
+#[test]
+fn test_connect_timestamps_one_sided() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. Only the server offers timestamps.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(TcpConfigExt::default().timestamps(true));
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    for (peer, fd) in [(&server, server_fd), (&client, client_fd)] {
+        assert!(!peer.ipv4.tcp.negotiated(fd).unwrap().timestamps);
+        assert_eq!(
+            peer.ipv4.tcp.effective_send_mss(fd).unwrap(),
+            peer.ipv4.tcp.remote_mss(fd).unwrap()
+        );
+    }
+}
+
 /// Tests basic 3-way connection setup.
 #[test]
 fn test_good_connect() {
@@ -592,3 +1020,1317 @@ fn test_good_connect() {
     let (_, _): (QDesc, QDesc) =
         connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
 }
+
+/// Tests if an actively opened connection reports the round-trip time of its handshake, which the SYN and SYN+ACK each
+/// take half of to cross the link.
+#[test]
+fn test_handshake_rtt() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let rtt: Duration = Duration::from_millis(10);
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (client_fd, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // The SYN crosses the link.
+    now += rtt / 2;
+    server.rt().advance_clock(now);
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+
+    // The SYN+ACK crosses the link back.
+    now += rtt / 2;
+    client.rt().advance_clock(now);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    match Future::poll(Pin::new(&mut connect_future), &mut ctx) {
+        Poll::Ready(Ok(())) => (),
+        r => panic!("connect should have completed: {:?}", r),
+    }
+    assert_eq!(client.ipv4.tcp.handshake_rtt(client_fd).unwrap(), Some(rtt));
+
+    // The passive side does not measure it.
+    server.rt().advance_clock(now);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        r => panic!("accept should have completed: {:?}", r),
+    };
+    assert_eq!(server.ipv4.tcp.handshake_rtt(server_fd).unwrap(), None);
+}
+
+/// Tests if connecting a socket that is not bound binds it to an ephemeral port on the local address.
+#[test]
+fn test_connect_unbound() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The client connects right after creating its socket.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    let (local, remote): (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+    assert_eq!(*local.ip(), test_helpers::ALICE_IPV4);
+    assert!(EphemeralPorts::is_private(local.port()));
+    assert_eq!(remote, listen_addr);
+    assert_eq!(server.ipv4.tcp.endpoints(server_fd).unwrap(), (listen_addr, local));
+}
+
+/// Tests if a connection that reuses the 4-tuple of a recently closed one starts past its sequence space.
+#[test]
+fn test_reuse_four_tuple() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let client_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::ALICE_IPV4, 8080);
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let listen_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(listen_fd, listen_addr).unwrap();
+    server.tcp_listen(listen_fd, 1).unwrap();
+
+    // Final sequence numbers of the previous connection.
+    let mut last_seq_nums: Option<(SeqNumber, SeqNumber)> = None;
+
+    for _ in 0..2 {
+        let mut accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(listen_fd);
+        server.rt().poll_scheduler();
+
+        // Open a connection from the same client address.
+        let client_fd: QDesc = client.tcp_socket().unwrap();
+        client.tcp_bind(client_fd, client_addr).unwrap();
+        let mut connect_future: ConnectFuture<TestRuntime> = client.tcp_connect(client_fd, listen_addr);
+        client.rt().poll_scheduler();
+        let bytes: Buffer = client.rt().pop_frame();
+        let (_, _, syn_header) = extract_headers(bytes.clone());
+        let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+        let (_, _, syn_ack_header) = extract_headers(bytes.clone());
+        let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+        connection_setup_sync_rcvd_established(&mut server, bytes);
+        let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+            Poll::Ready(Ok(server_fd)) => Ok(server_fd),
+            _ => Err(()),
+        }
+        .unwrap();
+        match Future::poll(Pin::new(&mut connect_future), &mut ctx) {
+            Poll::Ready(Ok(())) => Ok(()),
+            _ => Err(()),
+        }
+        .unwrap();
+
+        // Neither peer should start within the sequence space of the previous connection.
+        if let Some((client_last_seq_num, server_last_seq_num)) = last_seq_nums {
+            assert!(syn_header.seq_num > client_last_seq_num);
+            assert!(syn_ack_header.seq_num > server_last_seq_num);
+        }
+
+        // Close the connection, client first.
+        let server_endpoints: (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+        let client_endpoints: (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+        client.tcp_close(client_fd).unwrap();
+        let bytes: Buffer = client.rt().pop_frame();
+        let (_, _, client_fin_header) = extract_headers(bytes.clone());
+        server.receive(bytes).unwrap();
+        client.receive(server.rt().pop_frame()).unwrap();
+        server.tcp_close(server_fd).unwrap();
+        let bytes: Buffer = server.rt().pop_frame();
+        let (_, _, server_fin_header) = extract_headers(bytes.clone());
+        client.receive(bytes).unwrap();
+        server.receive(client.rt().pop_frame()).unwrap();
+        last_seq_nums = Some((client_fin_header.seq_num, server_fin_header.seq_num));
+
+        // Wait for the client to leave TIME-WAIT.
+        assert_eq!(server.ipv4.tcp.connection_state(server_endpoints), Some(State::Closed));
+        for _ in 0..(2 * MSL).as_secs() {
+            advance_clock(Some(&mut server), Some(&mut client), &mut now);
+        }
+        client.rt().poll_scheduler();
+        assert_eq!(client.ipv4.tcp.connection_state(client_endpoints), Some(State::Closed));
+    }
+}
+
+/// Tests if stepping both peers until they stall completes the three-way handshake, without any help from the clock.
+#[test]
+fn test_handshake_run_until_stalled() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let client_fd: QDesc = client.tcp_socket().unwrap();
+    let mut connect_future: ConnectFuture<TestRuntime> = client.tcp_connect(client_fd, listen_addr);
+
+    test_helpers::run_until_stalled(&mut [&mut server, &mut client]);
+
+    // Both ends of the connection are established, and they agree on its endpoints.
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        _ => panic!("accept should have completed"),
+    };
+    match Future::poll(Pin::new(&mut connect_future), &mut ctx) {
+        Poll::Ready(Ok(())) => (),
+        _ => panic!("connect should have completed"),
+    }
+    let (server_local, server_remote): (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+    let (client_local, client_remote): (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+    assert_eq!(server_local, client_remote);
+    assert_eq!(server_remote, client_local);
+    assert_eq!(server.rt().now(), now);
+}
+
+/// Tests if a socket that listens on the wildcard address accepts connections to any of the local addresses.
+#[test]
+fn test_listen_wildcard_addr() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, listen_port);
+    let local_addrs: [Ipv4Addr; 2] = [test_helpers::BOB_IPV4, test_helpers::CARRIE_IPV4];
+
+    // Setup peers. The server also goes by a second address, which the client resolves to the same link address.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.add_ipv4_addr(test_helpers::CARRIE_IPV4);
+    client
+        .arp
+        .insert_static(test_helpers::CARRIE_IPV4, test_helpers::BOB_MAC);
+
+    let listen_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(listen_fd, listen_addr).unwrap();
+    server.tcp_listen(listen_fd, local_addrs.len()).unwrap();
+
+    // Connect to the listening port on each of the addresses of the server.
+    let mut connect_futures: Vec<ConnectFuture<TestRuntime>> = Vec::new();
+    for local_addr in local_addrs {
+        let client_fd: QDesc = client.tcp_socket().unwrap();
+        connect_futures.push(client.tcp_connect(client_fd, SocketAddrV4::new(local_addr, listen_port)));
+    }
+    test_helpers::run_until_stalled(&mut [&mut server, &mut client]);
+    for mut connect_future in connect_futures {
+        match Future::poll(Pin::new(&mut connect_future), &mut ctx) {
+            Poll::Ready(Ok(())) => (),
+            _ => panic!("connect should have completed"),
+        }
+    }
+
+    // The server accepts both connections, each on the address that it was made to.
+    let mut accepted_addrs: Vec<Ipv4Addr> = Vec::new();
+    for _ in 0..local_addrs.len() {
+        let mut accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(listen_fd);
+        let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+            Poll::Ready(Ok(server_fd)) => server_fd,
+            _ => panic!("accept should have completed"),
+        };
+        let (local, _): (SocketAddrV4, SocketAddrV4) = server.ipv4.tcp.endpoints(server_fd).unwrap();
+        assert_eq!(local.port(), listen_port);
+        accepted_addrs.push(*local.ip());
+    }
+    accepted_addrs.sort();
+    assert_eq!(accepted_addrs, local_addrs);
+}
+
+/// Tests if a socket can connect from the local endpoint of a listening socket only on 4-tuples that the listening
+/// socket is not using.
+#[test]
+fn test_connect_from_listening_port() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, listen_port);
+    let local_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let listen_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(listen_fd, listen_addr).unwrap();
+    server.tcp_listen(listen_fd, 1).unwrap();
+
+    // The client connects, but the server has yet to accept the connection.
+    let client_fd: QDesc = client.tcp_socket().unwrap();
+    let _connect_future: ConnectFuture<TestRuntime> =
+        client.tcp_connect(client_fd, SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port));
+    test_helpers::run_until_stalled(&mut [&mut server, &mut client]);
+    let (client_addr, _): (SocketAddrV4, SocketAddrV4) = client.ipv4.tcp.endpoints(client_fd).unwrap();
+
+    // Another socket of the server binds to the listening port on a specific address.
+    let fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(fd, local_addr).unwrap();
+
+    // It cannot connect to the client, as that would collide with the connection being accepted.
+    match server.ipv4.tcp.connect(fd, client_addr) {
+        Err(e) if e.errno == EADDRINUSE => (),
+        _ => panic!("connect should have failed"),
+    }
+
+    // It can connect to another endpoint of the client, which makes for a distinct 4-tuple.
+    let other_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::ALICE_IPV4, client_addr.port() + 1);
+    assert!(server.ipv4.tcp.connect(fd, other_addr).is_ok());
+    assert_eq!(server.ipv4.tcp.local_endpoint(fd).unwrap(), local_addr);
+
+    // The connection of the client is still there to be accepted.
+    let mut accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(listen_fd);
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        _ => panic!("accept should have completed"),
+    };
+    assert_eq!(server.ipv4.tcp.endpoints(server_fd).unwrap(), (local_addr, client_addr));
+}
+
+/// Tests if the asynchronous accept of a passive socket yields an accepted connection.
+#[test]
+fn test_async_accept() {
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let mut socket: PassiveSocket<TestRuntime> = PassiveSocket::new(
+        listen_addr,
+        1,
+        server.rt().clone(),
+        server.arp.clone(),
+        mpsc::unbounded().0,
+        TcpConfigExt::default(),
+        congestion_control::None::new,
+        0,
+    );
+
+    // Client: SYN_SENT.
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Server: SYN_RCVD.
+    let (_, ipv4_header, tcp_header) = extract_headers(bytes);
+    socket
+        .receive(&ipv4_header, &tcp_header, Buffer::Heap(DataBuffer::empty()), None)
+        .unwrap();
+    server.rt().poll_scheduler();
+    let bytes: Buffer = server.rt().pop_frame();
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Client: ESTABLISHED.
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Server: ESTABLISHED.
+    let (_, ipv4_header, tcp_header) = extract_headers(bytes);
+    socket
+        .receive(&ipv4_header, &tcp_header, Buffer::Heap(DataBuffer::empty()), None)
+        .unwrap();
+
+    // Accept the connection from within an async block that is driven by the scheduler.
+    let remote: Rc<RefCell<Option<SocketAddrV4>>> = Rc::new(RefCell::new(None));
+    let remote_: Rc<RefCell<Option<SocketAddrV4>>> = remote.clone();
+    let future = async move {
+        let cb: ControlBlock<TestRuntime> = socket.accept().await.unwrap();
+        *remote_.borrow_mut() = Some(cb.get_remote());
+    };
+    let _handle: SchedulerHandle = server
+        .rt()
+        .spawn(FutureOperation::Background::<TestRuntime>(future.boxed_local()));
+    server.rt().poll_scheduler();
+
+    assert_eq!(
+        *remote.borrow(),
+        Some(SocketAddrV4::new(test_helpers::ALICE_IPV4, tcp_header.src_port))
+    );
+}
+
+/// Tests if the stream of incoming connections yields them in the order in which they are established.
+#[test]
+fn test_incoming_stream() {
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let mut socket: PassiveSocket<TestRuntime> = PassiveSocket::new(
+        listen_addr,
+        2,
+        server.rt().clone(),
+        server.arp.clone(),
+        mpsc::unbounded().0,
+        TcpConfigExt::default(),
+        congestion_control::None::new,
+        0,
+    );
+
+    // Two connections are established, one after the other.
+    let mut expected: Vec<SocketAddrV4> = Vec::new();
+    for _ in 0..2 {
+        let (_, _, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+            connection_setup_listen_syn_sent(&mut client, listen_addr);
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+        // Server: SYN_RCVD.
+        let (_, ipv4_header, tcp_header) = extract_headers(bytes);
+        expected.push(SocketAddrV4::new(ipv4_header.get_src_addr(), tcp_header.src_port));
+        socket
+            .receive(&ipv4_header, &tcp_header, Buffer::Heap(DataBuffer::empty()), None)
+            .unwrap();
+        server.rt().poll_scheduler();
+        let bytes: Buffer = server.rt().pop_frame();
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+        // Client: ESTABLISHED.
+        let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+        // Server: ESTABLISHED.
+        let (_, ipv4_header, tcp_header) = extract_headers(bytes);
+        socket
+            .receive(&ipv4_header, &tcp_header, Buffer::Heap(DataBuffer::empty()), None)
+            .unwrap();
+    }
+
+    // Consume the stream from within an async block that is driven by the scheduler.
+    let remotes: Rc<RefCell<Vec<SocketAddrV4>>> = Rc::new(RefCell::new(Vec::new()));
+    let remotes_: Rc<RefCell<Vec<SocketAddrV4>>> = remotes.clone();
+    let future = async move {
+        let mut incoming = Box::pin(socket.incoming());
+        while let Some(result) = incoming.next().await {
+            remotes_.borrow_mut().push(result.unwrap().get_remote());
+        }
+    };
+    let _handle: SchedulerHandle = server
+        .rt()
+        .spawn(FutureOperation::Background::<TestRuntime>(future.boxed_local()));
+    server.rt().poll_scheduler();
+
+    assert_eq!(*remotes.borrow(), expected);
+}
+
+/// Tests if both peers report the connection parameters that their SYN exchange agreed on.
+#[test]
+fn test_negotiated_params() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let server_window_scale: u8 = server.rt().tcp_options().get_window_scale();
+    let client_window_scale: u8 = client.rt().tcp_options().get_window_scale();
+
+    let (server_fd, client_fd): (QDesc, QDesc) =
+        connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // Both peers offer window scaling, but neither offers selective acknowledgements nor timestamps.
+    let server_params: ConnectionParams = server.ipv4.tcp.negotiated(server_fd).unwrap();
+    assert!(server_params.window_scaling);
+    assert_eq!(server_params.local_window_scale, server_window_scale);
+    assert_eq!(server_params.remote_window_scale, client_window_scale);
+    assert!(!server_params.sack_permitted);
+    assert!(!server_params.timestamps);
+    assert_eq!(server_params.mss, server.tcp_mss(server_fd).unwrap());
+
+    let client_params: ConnectionParams = client.ipv4.tcp.negotiated(client_fd).unwrap();
+    assert!(client_params.window_scaling);
+    assert_eq!(client_params.local_window_scale, client_window_scale);
+    assert_eq!(client_params.remote_window_scale, server_window_scale);
+    assert!(!client_params.sack_permitted);
+    assert!(!client_params.timestamps);
+    assert_eq!(client_params.mss, client.tcp_mss(client_fd).unwrap());
+}
+
+/// Rewrites the window scale option of a TCP segment.
+fn rewrite_window_scale(bytes: Buffer, window_scale: u8) -> Buffer {
+    let (ethernet2_hdr, ipv4_hdr, mut tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    for option in tcp_hdr.option_list[..tcp_hdr.num_options].iter_mut() {
+        if let TcpOptions2::WindowScale(_) = option {
+            *option = TcpOptions2::WindowScale(window_scale);
+        }
+    }
+    serialize_segment(TcpSegment {
+        ethernet2_hdr,
+        ipv4_hdr,
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::empty()),
+        tx_checksum_offload: false,
+    })
+}
+
+/// Rewrites the maximum segment size advertised in a handshake segment.
+fn rewrite_mss(bytes: Buffer, mss: u16) -> Buffer {
+    let (ethernet2_hdr, ipv4_hdr, mut tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    for option in tcp_hdr.option_list[..tcp_hdr.num_options].iter_mut() {
+        if let TcpOptions2::MaximumSegmentSize(_) = option {
+            *option = TcpOptions2::MaximumSegmentSize(mss);
+        }
+    }
+    serialize_segment(TcpSegment {
+        ethernet2_hdr,
+        ipv4_hdr,
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::empty()),
+        tx_checksum_offload: false,
+    })
+}
+
+/// Rewrites a handshake segment without the TCP options for which `strip` returns true.
+fn strip_options(bytes: Buffer, strip: fn(&TcpOptions2) -> bool) -> Buffer {
+    let (ethernet2_hdr, ipv4_hdr, mut tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    let options: Vec<TcpOptions2> = tcp_hdr.iter_options().copied().collect();
+    tcp_hdr.num_options = 0;
+    for option in options {
+        if !strip(&option) {
+            tcp_hdr.push_option(option);
+        }
+    }
+    serialize_segment(TcpSegment {
+        ethernet2_hdr,
+        ipv4_hdr,
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::empty()),
+        tx_checksum_offload: false,
+    })
+}
+
+/// Tests if a window scale larger than allowed is clamped, rather than aborting the handshake.
+#[test]
+fn test_handshake_oversized_window_scale() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (client_fd, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Both the SYN and the SYN+ACK carry a shift count that would overflow the window.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, rewrite_window_scale(bytes, u8::MAX));
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, rewrite_window_scale(bytes, u8::MAX));
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        _ => panic!("accept should have completed"),
+    };
+    assert!(matches!(
+        Future::poll(Pin::new(&mut connect_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+
+    // Both peers use the largest shift count allowed instead.
+    let server_params: ConnectionParams = server.ipv4.tcp.negotiated(server_fd).unwrap();
+    assert_eq!(server_params.remote_window_scale, MAX_WINDOW_SCALE);
+    let client_params: ConnectionParams = client.ipv4.tcp.negotiated(client_fd).unwrap();
+    assert_eq!(client_params.remote_window_scale, MAX_WINDOW_SCALE);
+}
+
+/// Tests if the configured fallback segment size is used when the remote peer does not advertise one.
+#[test]
+fn test_fallback_mss() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let fallback_mss: usize = 1000;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server
+        .ipv4
+        .tcp
+        .set_config(TcpConfigExt::default().fallback_mss(Some(fallback_mss)).unwrap());
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (_, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Strip the MSS option off the SYN.
+    let bytes: Buffer = strip_options(bytes, |option| matches!(option, TcpOptions2::MaximumSegmentSize(_)));
+
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        _ => panic!("accept should have completed"),
+    };
+    assert!(matches!(
+        Future::poll(Pin::new(&mut connect_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+
+    // The server did not learn the segment size of the client, so it falls back to the configured one.
+    assert_eq!(server.ipv4.tcp.get_config().get_fallback_mss(), fallback_mss);
+    assert_eq!(server.tcp_mss(server_fd).unwrap(), fallback_mss);
+}
+
+/// Tests if fallback segment sizes that we do not support are rejected.
+#[test]
+fn test_fallback_mss_out_of_range() {
+    for mss in [0, MIN_MSS - 1, MAX_MSS + 1] {
+        match TcpConfigExt::default().fallback_mss(Some(mss)) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("MSS {} should have been rejected", mss),
+        }
+    }
+    assert!(TcpConfigExt::default().fallback_mss(Some(MIN_MSS)).is_ok());
+    assert!(TcpConfigExt::default().fallback_mss(Some(MAX_MSS)).is_ok());
+}
+
+/// Tests if both peers report window scaling as disabled when the SYN does not offer it.
+#[test]
+fn test_negotiated_no_window_scaling() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (client_fd, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Strip the window scale option off the SYN, as a middlebox might.
+    let bytes: Buffer = strip_options(bytes, |option| matches!(option, TcpOptions2::WindowScale(_)));
+
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    let (_, _, tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    assert!(!tcp_hdr
+        .iter_options()
+        .any(|option| matches!(option, TcpOptions2::WindowScale(_))));
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        _ => panic!("accept should have completed"),
+    };
+    assert!(matches!(
+        Future::poll(Pin::new(&mut connect_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+
+    // Neither peer scales its window.
+    for params in [
+        server.ipv4.tcp.negotiated(server_fd).unwrap(),
+        client.ipv4.tcp.negotiated(client_fd).unwrap(),
+    ] {
+        assert!(!params.window_scaling);
+        assert_eq!(params.local_window_scale, 0);
+        assert_eq!(params.remote_window_scale, 0);
+    }
+}
+
+/// Tests if engines whose runtimes are seeded alike make the same random choices.
+#[test]
+fn test_seeded_rng() {
+    let now = Instant::now();
+    let new_engine = |seed: [u8; 32]| -> Engine<TestRuntime> {
+        let mut rt: TestRuntime = TestRuntime::new(
+            now,
+            ArpConfig::default(),
+            UdpConfig::default(),
+            TcpConfig::default(),
+            test_helpers::ALICE_MAC,
+            test_helpers::ALICE_IPV4,
+        );
+        rt.seed_rng(seed);
+        let rng_seed: [u8; 32] = rt.rng_seed();
+        Engine::new(rt, rng_seed).unwrap()
+    };
+
+    let first: Engine<TestRuntime> = new_engine([1; 32]);
+    let second: Engine<TestRuntime> = new_engine([1; 32]);
+    let other: Engine<TestRuntime> = new_engine([2; 32]);
+    assert_eq!(first.ipv4.tcp.isn_nonce(), second.ipv4.tcp.isn_nonce());
+    assert_ne!(first.ipv4.tcp.isn_nonce(), other.ipv4.tcp.isn_nonce());
+}
+
+/// Tests if a listening socket drops connection requests that its accept filter does not allow, without keeping any
+/// state for them.
+#[test]
+fn test_accept_filter() {
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::ALICE_IPV4, listen_port);
+
+    // Setup peers. The server only accepts connection requests from its own address.
+    let mut server: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let socket_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(socket_fd, listen_addr).unwrap();
+    server.tcp_listen(socket_fd, 1).unwrap();
+    let filter: AcceptFilter = Rc::new(|remote: &SocketAddrV4| *remote.ip() == test_helpers::ALICE_IPV4);
+    server.ipv4.tcp.set_accept_filter(socket_fd, Some(filter)).unwrap();
+    let _accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(socket_fd);
+    server.rt().poll_scheduler();
+
+    // The SYN of the client is refused, and the server does not answer it.
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    match server.receive(bytes.clone()) {
+        Err(e) if e.errno == ECONNREFUSED => (),
+        r => panic!("SYN should have been refused: {:?}", r),
+    }
+    server.rt().poll_scheduler();
+    assert!(server.rt().pop_frame_unchecked().is_none());
+
+    // Once the filter is removed, the same SYN starts a new connection rather than being taken for one in flight.
+    server.ipv4.tcp.set_accept_filter(socket_fd, None).unwrap();
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    let (_, _, tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    assert!(tcp_hdr.syn && tcp_hdr.ack);
+}
+
+/// Tests if a connection request that does not fit in the backlog is refused with a typed error kind.
+#[test]
+fn test_backlog_full_error_kind() {
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The server has a backlog of a single connection.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let _accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (_, _first_connect_future, first_syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let (_, _second_connect_future, second_syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // The first connection request fills up the backlog, so the second one is refused.
+    server.receive(first_syn).unwrap();
+    match server.receive(second_syn) {
+        Err(e) => {
+            assert_eq!(e.errno, ECONNREFUSED);
+            assert_eq!(e.kind(), ErrorKind::ConnectionRefused);
+        },
+        Ok(()) => panic!("connection request should have been refused"),
+    }
+}
+
+/// Waker that records whether it was woken.
+pub struct FlagWaker(pub AtomicBool);
+
+impl ArcWake for FlagWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Tests if concurrent accepts on a listening socket are all woken up and served as connections arrive.
+#[test]
+fn test_concurrent_accepts() {
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let socket_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(socket_fd, listen_addr).unwrap();
+    server.tcp_listen(socket_fd, 2).unwrap();
+
+    // Issue two accepts, each polled by a task of its own.
+    let flags: Vec<Arc<FlagWaker>> = (0..2).map(|_| Arc::new(FlagWaker(AtomicBool::new(false)))).collect();
+    let mut accept_futures: Vec<AcceptFuture<TestRuntime>> = (0..2).map(|_| server.tcp_accept(socket_fd)).collect();
+    for (accept_future, flag) in accept_futures.iter_mut().zip(flags.iter()) {
+        let flag_waker: Waker = waker(flag.clone());
+        let mut ctx = Context::from_waker(&flag_waker);
+        assert!(Future::poll(Pin::new(accept_future), &mut ctx).is_pending());
+    }
+
+    // Open two connections.
+    let (_, _first_connect_future, first_syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let (_, _second_connect_future, second_syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    for syn in [first_syn, second_syn] {
+        let syn_ack: Buffer = connection_setup_listen_syn_rcvd(&mut server, syn);
+        let ack: Buffer = connection_setup_syn_sent_established(&mut client, syn_ack);
+        connection_setup_sync_rcvd_established(&mut server, ack);
+    }
+
+    // Both accepts were woken up, and each of them gets a connection.
+    assert!(flags.iter().all(|flag| flag.0.load(Ordering::SeqCst)));
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut endpoints: Vec<(SocketAddrV4, SocketAddrV4)> = Vec::new();
+    for accept_future in accept_futures.iter_mut() {
+        match Future::poll(Pin::new(accept_future), &mut ctx) {
+            Poll::Ready(Ok(fd)) => endpoints.push(server.ipv4.tcp.endpoints(fd).unwrap()),
+            _ => panic!("accept should have completed"),
+        }
+    }
+    assert_ne!(endpoints[0], endpoints[1]);
+}
+
+/// Tests if the backlog of a listening socket accounts for connections in each stage, and for refused ones.
+#[test]
+fn test_accept_queue_stats() {
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let num_refused: u64 = 3;
+
+    // Setup peers. The server has a backlog of a single connection.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let socket_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(socket_fd, listen_addr).unwrap();
+    server.tcp_listen(socket_fd, 1).unwrap();
+    assert_eq!(
+        server.ipv4.tcp.accept_queue_stats(socket_fd).unwrap(),
+        AcceptQueueStats::default()
+    );
+
+    // The first connection request fills up the backlog.
+    let (_, _connect_future, syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let syn_ack: Buffer = connection_setup_listen_syn_rcvd(&mut server, syn);
+    let stats: AcceptQueueStats = server.ipv4.tcp.accept_queue_stats(socket_fd).unwrap();
+    assert_eq!((stats.ready, stats.inflight, stats.refused), (0, 1, 0));
+
+    // Further connection requests are refused, and counted.
+    let mut connect_futures: Vec<ConnectFuture<TestRuntime>> = Vec::new();
+    for i in 1..=num_refused {
+        let (_, connect_future, syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+            connection_setup_listen_syn_sent(&mut client, listen_addr);
+        connect_futures.push(connect_future);
+        match server.receive(syn) {
+            Err(e) if e.errno == ECONNREFUSED => (),
+            r => panic!("connection request should have been refused: {:?}", r),
+        }
+        assert_eq!(server.ipv4.tcp.accept_queue_stats(socket_fd).unwrap().refused, i);
+    }
+
+    // Once established, the first connection waits to be accepted.
+    let ack: Buffer = connection_setup_syn_sent_established(&mut client, syn_ack);
+    connection_setup_sync_rcvd_established(&mut server, ack);
+    let stats: AcceptQueueStats = server.ipv4.tcp.accept_queue_stats(socket_fd).unwrap();
+    assert_eq!((stats.ready, stats.inflight, stats.refused), (1, 0, num_refused));
+}
+
+/// Tests if a tiny maximum segment size advertised by a peer is raised to the configured minimum.
+#[test]
+fn test_min_mss() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let min_mss: usize = 1000;
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .fallback_mss(Some(min_mss))
+            .unwrap()
+            .min_mss(Some(min_mss))
+            .unwrap(),
+    );
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (_, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Make the client advertise a single byte.
+    let bytes: Buffer = rewrite_mss(bytes, 1);
+
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        _ => panic!("accept should have completed"),
+    };
+    assert!(matches!(
+        Future::poll(Pin::new(&mut connect_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+
+    assert_eq!(server.tcp_mss(server_fd).unwrap(), min_mss);
+    assert_eq!(TcpConfigExt::default().get_send_mss(1, false), MIN_MSS);
+}
+
+/// Tests if minimum segment sizes that are zero, or that exceed the segment sizes that the link is known to carry, are
+/// rejected.
+#[test]
+fn test_min_mss_out_of_range() {
+    for mss in [0, FALLBACK_MSS + 1, MAX_MSS + 1] {
+        match TcpConfigExt::default().min_mss(Some(mss)) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("MSS {} should have been rejected", mss),
+        }
+    }
+    assert!(TcpConfigExt::default().min_mss(Some(FALLBACK_MSS)).is_ok());
+
+    // The minimum may not exceed an advertised segment size, regardless of the order in which they are set.
+    let config: TcpConfigExt = TcpConfigExt::default().fallback_mss(Some(1200)).unwrap();
+    let advertised: TcpConfigExt = config.clone().advertised_mss(Some(1000)).unwrap();
+    assert_eq!(advertised.min_mss(Some(1100)).unwrap_err().errno, EINVAL);
+    let min: TcpConfigExt = config.min_mss(Some(1100)).unwrap();
+    assert_eq!(min.clone().advertised_mss(Some(1000)).unwrap_err().errno, EINVAL);
+    assert_eq!(min.fallback_mss(Some(1000)).unwrap_err().errno, EINVAL);
+}
+
+/// Tests if connections are only set up with peers that sign their segments with the shared MD5 key.
+#[test]
+fn test_md5_signature() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+    let key: Vec<u8> = b"secret".to_vec();
+
+    // Setup peers. Both share the same key.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .md5_key(test_helpers::ALICE_IPV4, Some(key.clone()))
+            .unwrap(),
+    );
+    client.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .md5_key(test_helpers::BOB_IPV4, Some(key))
+            .unwrap(),
+    );
+    connection_setup(&mut ctx, &mut now, &mut server, &mut client, listen_port, listen_addr);
+
+    // A client that signs with another key is refused, and the server does not answer it.
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    client.ipv4.tcp.set_config(
+        TcpConfigExt::default()
+            .md5_key(test_helpers::BOB_IPV4, Some(b"public".to_vec()))
+            .unwrap(),
+    );
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let (_, _, tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    assert!(tcp_hdr.md5_option().is_some());
+    match server.receive(bytes) {
+        Err(e) if e.errno == EBADMSG => (),
+        r => panic!("SYN should have been dropped: {:?}", r),
+    }
+
+    // So is a client that does not sign its segments at all.
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    match server.receive(bytes) {
+        Err(e) if e.errno == EBADMSG => (),
+        r => panic!("SYN should have been dropped: {:?}", r),
+    }
+    server.rt().poll_scheduler();
+    assert!(server.rt().pop_frame_unchecked().is_none());
+}
+
+/// Tests if MD5 keys that are empty or longer than RFC 2385 suggests are rejected.
+#[test]
+fn test_md5_key_out_of_range() {
+    for len in [0, MAX_MD5_KEY_SIZE + 1] {
+        match TcpConfigExt::default().md5_key(test_helpers::BOB_IPV4, Some(vec![0; len])) {
+            Err(e) => assert_eq!(e.errno, EINVAL),
+            Ok(_) => panic!("key of {} bytes should have been rejected", len),
+        }
+    }
+    assert!(TcpConfigExt::default()
+        .md5_key(test_helpers::BOB_IPV4, Some(vec![0; 1]))
+        .is_ok());
+    assert!(TcpConfigExt::default()
+        .md5_key(test_helpers::BOB_IPV4, Some(vec![0; MAX_MD5_KEY_SIZE]))
+        .is_ok());
+}
+
+/// Tests if a connection to ourselves is set up and carries data without any frame hitting the runtime.
+#[test]
+fn test_loopback() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::ALICE_IPV4, listen_port);
+
+    // Setup peer, which connects to its own listening socket.
+    let mut engine: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut engine, listen_addr);
+    let client_fd: QDesc = engine.tcp_socket().unwrap();
+    let mut connect_future: ConnectFuture<TestRuntime> = engine.tcp_connect(client_fd, listen_addr);
+    // Each step of the handshake takes a round of the scheduler.
+    for _ in 0..8 {
+        engine.rt().poll_scheduler();
+    }
+
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        r => panic!("accept should have completed: {:?}", r),
+    };
+    assert!(matches!(
+        Future::poll(Pin::new(&mut connect_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+
+    // Send data from the client to the server.
+    let data: Vec<u8> = (0..100).collect();
+    let mut push_future: PushFuture = engine.tcp_push(client_fd, Buffer::Heap(DataBuffer::from_slice(&data)));
+    assert!(matches!(
+        Future::poll(Pin::new(&mut push_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+    engine.rt().poll_scheduler();
+    let mut buf: [u8; 100] = [0; 100];
+    match engine.ipv4.tcp.poll_recv_into(server_fd, &mut buf, &mut ctx) {
+        Poll::Ready(Ok(nbytes)) => assert_eq!(&buf[..nbytes], &data[..]),
+        r => panic!("poll_recv_into should have returned data: {:?}", r),
+    }
+
+    // No frame was handed over to the runtime.
+    engine.rt().poll_scheduler();
+    assert!(engine.rt().pop_frame_unchecked().is_none());
+}
+
+/// Tests if data carried by the final ACK of the handshake is readable as soon as the connection is accepted.
+#[test]
+fn test_accept_data_on_final_ack() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+
+    // Piggyback data on the final ACK.
+    let data: Vec<u8> = (0..100).collect();
+    let (ethernet2_hdr, ipv4_hdr, mut tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    tcp_hdr.psh = true;
+    let bytes: Buffer = serialize_segment(TcpSegment {
+        ethernet2_hdr,
+        ipv4_hdr,
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::from_slice(&data)),
+        tx_checksum_offload: false,
+    });
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    // The accepted connection has the data ready to be read.
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        r => panic!("accept should have completed: {:?}", r),
+    };
+    let mut buf: [u8; 100] = [0; 100];
+    match server.ipv4.tcp.poll_recv_into(server_fd, &mut buf, &mut ctx) {
+        Poll::Ready(Ok(nbytes)) => assert_eq!(&buf[..nbytes], &data[..]),
+        r => panic!("poll_recv_into should have returned data: {:?}", r),
+    }
+}
+
+/// Tests if data carried by a SYN is queued until the handshake completes, and is then readable on the accepted
+/// connection.
+#[test]
+fn test_accept_data_on_syn() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // Carry data on the SYN.
+    let data: Vec<u8> = (0..100).collect();
+    let (ethernet2_hdr, ipv4_hdr, tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes);
+    let bytes: Buffer = serialize_segment(TcpSegment {
+        ethernet2_hdr,
+        ipv4_hdr,
+        tcp_hdr,
+        data: Buffer::Heap(DataBuffer::from_slice(&data)),
+        tx_checksum_offload: false,
+    });
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+
+    // The SYN+ACK only acknowledges the SYN, and there is no connection to read the data from yet.
+    check_packet_syn_ack(
+        bytes.clone(),
+        test_helpers::BOB_MAC,
+        test_helpers::ALICE_MAC,
+        test_helpers::BOB_IPV4,
+        test_helpers::ALICE_IPV4,
+        listen_port,
+    );
+    match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Pending => (),
+        r => panic!("accept should not have completed: {:?}", r),
+    }
+
+    // Complete the handshake. The accepted connection has the data ready to be read.
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        r => panic!("accept should have completed: {:?}", r),
+    };
+    let mut buf: [u8; 100] = [0; 100];
+    match server.ipv4.tcp.poll_recv_into(server_fd, &mut buf, &mut ctx) {
+        Poll::Ready(Ok(nbytes)) => assert_eq!(&buf[..nbytes], &data[..]),
+        r => panic!("poll_recv_into should have returned data: {:?}", r),
+    }
+}
+
+/// Tests if a SYN that requests Explicit Congestion Notification is answered with an ECN-setup SYN+ACK, and if both
+/// peers record ECN as negotiated.
+#[test]
+fn test_ecn_negotiation() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    server.ipv4.tcp.set_config(TcpConfigExt::default().ecn(true));
+    client.ipv4.tcp.set_config(TcpConfigExt::default().ecn(true));
+
+    let mut accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (client_fd, mut connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let (_, _, syn): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    assert!(syn.ece && syn.cwr);
+
+    // The SYN+ACK sets ECE, but not CWR.
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    let (_, _, syn_ack): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    assert!(syn_ack.ece);
+    assert!(!syn_ack.cwr);
+
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+    let server_fd: QDesc = match Future::poll(Pin::new(&mut accept_future), &mut ctx) {
+        Poll::Ready(Ok(server_fd)) => server_fd,
+        r => panic!("accept should have completed: {:?}", r),
+    };
+    assert!(matches!(
+        Future::poll(Pin::new(&mut connect_future), &mut ctx),
+        Poll::Ready(Ok(()))
+    ));
+
+    assert!(server.ipv4.tcp.negotiated(server_fd).unwrap().ecn);
+    assert!(client.ipv4.tcp.negotiated(client_fd).unwrap().ecn);
+}
+
+/// Tests if a retransmitted SYN for a connection that is being accepted is answered with a new SYN+ACK.
+#[test]
+fn test_syn_retransmission_resends_syn_ack() {
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let _accept_future: AcceptFuture<TestRuntime> = connection_setup_closed_listen(&mut server, listen_addr);
+    let (_, _connect_future, syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+
+    // The first SYN+ACK is lost.
+    let _: Buffer = connection_setup_listen_syn_rcvd(&mut server, syn.clone());
+
+    // The SYN retransmission is accepted, and answered without the clock being advanced.
+    server.receive(syn).unwrap();
+    server.rt().poll_scheduler();
+    let (_, _, syn_ack): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(server.rt().pop_frame());
+    assert!(syn_ack.syn && syn_ack.ack);
+    assert_eq!(syn_ack.ack_num, SeqNumber::from(1));
+}
+
+/// Tests if SYNs from endpoints that cannot exist, such as port zero or our own listening endpoint, are refused without
+/// starting a handshake.
+#[test]
+fn test_refuse_impossible_syn() {
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let socket_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(socket_fd, listen_addr).unwrap();
+    server.tcp_listen(socket_fd, 1).unwrap();
+    let (_, _connect_future, syn): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let (ethernet2_hdr, ipv4_hdr, tcp_hdr): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(syn);
+
+    // A SYN from port zero, and one from the listening endpoint itself.
+    let impossible_syns: [(Ipv4Header, TcpHeader); 2] = [
+        (
+            ipv4_hdr.clone(),
+            TcpHeader {
+                src_port: 0,
+                ..tcp_hdr.clone()
+            },
+        ),
+        (
+            Ipv4Header::new(test_helpers::BOB_IPV4, test_helpers::BOB_IPV4, IpProtocol::TCP),
+            TcpHeader {
+                src_port: listen_port,
+                ..tcp_hdr
+            },
+        ),
+    ];
+    for (ipv4_hdr, tcp_hdr) in impossible_syns {
+        let bytes: Buffer = serialize_segment(TcpSegment {
+            ethernet2_hdr: ethernet2_hdr.clone(),
+            ipv4_hdr,
+            tcp_hdr,
+            data: Buffer::Heap(DataBuffer::empty()),
+            tx_checksum_offload: false,
+        });
+        match server.receive(bytes) {
+            Err(e) if e.errno == EBADMSG => (),
+            r => panic!("SYN should have been refused: {:?}", r),
+        }
+        server.rt().poll_scheduler();
+        assert!(server.rt().pop_frame_unchecked().is_none());
+        assert_eq!(server.ipv4.tcp.accept_queue_stats(socket_fd).unwrap().inflight, 0);
+    }
+}
+
+/// Tests if a listening socket with an accept callback hands established connections to it, rather than queueing them
+/// to be accepted.
+#[test]
+fn test_accept_callback() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Connection parameters
+    let listen_port: u16 = 80;
+    let listen_addr: SocketAddrV4 = SocketAddrV4::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The server records the remote endpoint of each connection that it is handed.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let socket_fd: QDesc = server.tcp_socket().unwrap();
+    server.tcp_bind(socket_fd, listen_addr).unwrap();
+    server.tcp_listen(socket_fd, 1).unwrap();
+    let remotes: Rc<RefCell<Vec<SocketAddrV4>>> = Rc::new(RefCell::new(Vec::new()));
+    let remotes_: Rc<RefCell<Vec<SocketAddrV4>>> = remotes.clone();
+    let callback: AcceptCallback<TestRuntime> =
+        Rc::new(move |cb: ControlBlock<TestRuntime>| remotes_.borrow_mut().push(cb.get_remote()));
+    server.ipv4.tcp.set_accept_callback(socket_fd, Some(callback)).unwrap();
+    let mut accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(socket_fd);
+    server.rt().poll_scheduler();
+
+    let (_, _connect_future, bytes): (QDesc, ConnectFuture<TestRuntime>, Buffer) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    let (_, _, syn): (Ethernet2Header, Ipv4Header, TcpHeader) = extract_headers(bytes.clone());
+    let bytes: Buffer = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    let bytes: Buffer = connection_setup_syn_sent_established(&mut client, bytes);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    // The callback got the connection, so there is nothing to be accepted.
+    assert_eq!(
+        *remotes.borrow(),
+        [SocketAddrV4::new(test_helpers::ALICE_IPV4, syn.src_port)]
+    );
+    assert!(Future::poll(Pin::new(&mut accept_future), &mut ctx).is_pending());
+}
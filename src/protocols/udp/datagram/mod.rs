@@ -8,7 +8,11 @@ mod header;
 //==============================================================================
 
 use crate::protocols::{
-    ethernet2::Ethernet2Header,
+    ethernet2::{
+        pad_body,
+        padding_size,
+        Ethernet2Header,
+    },
     ipv4::Ipv4Header,
 };
 use ::runtime::{
@@ -79,7 +83,7 @@ impl PacketBuf for UdpDatagram {
 
     /// Computes the payload size of the target UDP datagram.
     fn body_size(&self) -> usize {
-        self.data.len()
+        self.data.len() + padding_size(self.header_size() + self.data.len())
     }
 
     /// Serializes the header of the target UDP datagram.
@@ -111,7 +115,11 @@ impl PacketBuf for UdpDatagram {
 
     /// Returns the payload of the target UDP datagram.
     fn take_body(self) -> Option<Buffer> {
-        Some(self.data)
+        // Pad short frames to the minimum Ethernet frame size.
+        match padding_size(self.header_size() + self.data.len()) {
+            0 => Some(self.data),
+            padding => Some(pad_body(&self.data[..], padding)),
+        }
     }
 }
 
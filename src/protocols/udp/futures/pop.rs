@@ -15,9 +15,11 @@ use ::runtime::{
     QDesc,
 };
 use ::std::{
+    cell::Cell,
     future::Future,
     net::SocketAddrV4,
     pin::Pin,
+    rc::Rc,
     task::{
         Context,
         Poll,
@@ -35,6 +37,10 @@ pub struct UdpPopFuture {
     qd: QDesc,
     /// Shared receiving queue.
     recv_queue: SharedQueue<SharedQueueSlot<Buffer>>,
+    /// Time to live of the popped datagram.
+    ttl: Option<u8>,
+    /// Time to live of the last datagram popped from the associated socket, which is shared with the UDP peer.
+    received_ttl: Rc<Cell<Option<u8>>>,
 }
 
 //==============================================================================
@@ -44,14 +50,28 @@ pub struct UdpPopFuture {
 /// Associate Functions for Pop Operation Descriptor
 impl UdpPopFuture {
     /// Creates a pop operation descritor.
-    pub fn new(qd: QDesc, recv_queue: SharedQueue<SharedQueueSlot<Buffer>>) -> Self {
-        Self { qd, recv_queue }
+    pub fn new(
+        qd: QDesc,
+        recv_queue: SharedQueue<SharedQueueSlot<Buffer>>,
+        received_ttl: Rc<Cell<Option<u8>>>,
+    ) -> Self {
+        Self {
+            qd,
+            recv_queue,
+            ttl: None,
+            received_ttl,
+        }
     }
 
     /// Returns the queue descriptor that is associated to the target pop operation descriptor.
     pub fn get_qd(&self) -> QDesc {
         self.qd
     }
+
+    /// Returns the time to live of the datagram that the target pop operation descriptor completed with, if any.
+    pub fn get_ttl(&self) -> Option<u8> {
+        self.ttl
+    }
 }
 
 //==============================================================================
@@ -64,8 +84,13 @@ impl Future for UdpPopFuture {
 
     /// Polls the target pop operation descriptor.
     fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
-        match self.get_mut().recv_queue.try_pop() {
-            Ok(Some(msg)) => Poll::Ready(Ok((msg.remote, msg.data))),
+        let self_: &mut Self = self.get_mut();
+        match self_.recv_queue.try_pop() {
+            Ok(Some(msg)) => {
+                self_.ttl = msg.ttl;
+                self_.received_ttl.set(msg.ttl);
+                Poll::Ready(Ok((msg.remote, msg.data)))
+            },
             Ok(None) => {
                 let waker: &Waker = ctx.waker();
                 waker.wake_by_ref();
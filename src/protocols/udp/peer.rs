@@ -9,6 +9,7 @@ use super::{
     datagram::{
         UdpDatagram,
         UdpHeader,
+        UDP_HEADER_SIZE,
     },
     futures::UdpPopFuture,
     queue::{
@@ -28,13 +29,17 @@ use crate::{
             EphemeralPorts,
             IpProtocol,
         },
-        ipv4::Ipv4Header,
+        ipv4::{
+            Ipv4Header,
+            IPV4_PAYLOAD_MAX_SIZE,
+        },
     },
 };
 use ::futures::FutureExt;
 use ::libc::{
     EBADF,
     EEXIST,
+    EMSGSIZE,
     ENOTCONN,
 };
 use ::rand::{
@@ -56,8 +61,10 @@ use ::runtime::{
     QDesc,
 };
 use ::std::{
+    cell::Cell,
     collections::HashMap,
     net::SocketAddrV4,
+    rc::Rc,
 };
 
 #[cfg(feature = "profiler")]
@@ -89,12 +96,17 @@ pub struct UdpPeer<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
     sockets: HashMap<QDesc, Option<SocketAddrV4>>,
     /// Bound sockets.
     bound: HashMap<SocketAddrV4, SharedQueue<SharedQueueSlot<Buffer>>>,
+    /// Time to live of the last datagram popped from each opened socket.
+    received_ttls: HashMap<QDesc, Rc<Cell<Option<u8>>>>,
     /// Queue of unset datagrams. This is shared across fast/slow paths.
     send_queue: SharedQueue<SharedQueueSlot<Buffer>>,
     /// Local link address.
     local_link_addr: MacAddress,
     /// Local IPv4 address.
     local_ipv4_addr: Ipv4Addr,
+    /// Address that sockets which are not bound to a specific one send from, instead of the local one. This is shared
+    /// across fast/slow paths.
+    source_ipv4_addr: Rc<Cell<Option<Ipv4Addr>>>,
     /// Offload checksum to hardware?
     checksum_offload: bool,
 
@@ -121,9 +133,11 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
     ) -> Self {
         let send_queue: SharedQueue<SharedQueueSlot<Buffer>> =
             SharedQueue::<SharedQueueSlot<Buffer>>::new(SEND_QUEUE_MAX_SIZE);
+        let source_ipv4_addr: Rc<Cell<Option<Ipv4Addr>>> = Rc::new(Cell::new(None));
         let future = Self::background_sender(
             rt.clone(),
             local_ipv4_addr,
+            source_ipv4_addr.clone(),
             local_link_addr,
             offload_checksum,
             arp.clone(),
@@ -138,9 +152,11 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
             ephemeral_ports,
             sockets: HashMap::new(),
             bound: HashMap::new(),
+            received_ttls: HashMap::new(),
             send_queue,
             local_link_addr,
             local_ipv4_addr,
+            source_ipv4_addr,
             checksum_offload: offload_checksum,
             background: handle,
         }
@@ -150,6 +166,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
     async fn background_sender(
         rt: RT,
         local_ipv4_addr: Ipv4Addr,
+        source_ipv4_addr: Rc<Cell<Option<Ipv4Addr>>>,
         local_link_addr: MacAddress,
         offload_checksum: bool,
         arp: ArpPeer<RT>,
@@ -159,14 +176,16 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
             // Grab next unsent datagram.
             match rx.pop().await {
                 // Resolve remote address.
-                Ok(SharedQueueSlot { local, remote, data }) => match arp.query(remote.ip().clone()).await {
+                Ok(SharedQueueSlot {
+                    local, remote, data, ..
+                }) => match arp.query(remote.ip().clone()).await {
                     // Send datagram.
                     Ok(link_addr) => {
                         Self::do_send(
                             rt.clone(),
-                            local_ipv4_addr,
-                            local_link_addr,
-                            link_addr,
+                            source_ipv4_addr.get().unwrap_or(local_ipv4_addr),
+                            Ethernet2Header::new(link_addr, local_link_addr, EtherType2::Ipv4)
+                                .with_vlan_tag(arp.vlan_tag()),
                             data,
                             &local,
                             &remote,
@@ -182,6 +201,12 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
         }
     }
 
+    /// Sets the address that sockets which are not bound to a specific one send from. If `None`, which is the default,
+    /// they send from the local address.
+    pub fn set_source_ipv4_addr(&self, addr: Option<Ipv4Addr>) {
+        self.source_ipv4_addr.set(addr);
+    }
+
     /// Opens a UDP socket.
     pub fn do_socket(&mut self, qd: QDesc) -> Result<(), Fail> {
         #[cfg(feature = "profiler")]
@@ -193,6 +218,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
             false => {
                 let socket: Option<SocketAddrV4> = None;
                 self.sockets.insert(qd, socket);
+                self.received_ttls.insert(qd, Rc::new(Cell::new(None)));
                 Ok(())
             },
             // Queue descriptor in use.
@@ -262,6 +288,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
             Some(s) => s,
             None => return Err(Fail::new(EBADF, "invalid queue descriptor")),
         };
+        self.received_ttls.remove(&qd);
 
         // Remove endpoint binding.
         match socket {
@@ -281,13 +308,18 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
             _ => return Err(Fail::new(EBADF, "invalid queue descriptor")),
         };
 
+        // The length of the datagram must fit in the IPv4 and UDP headers.
+        if data.len() > IPV4_PAYLOAD_MAX_SIZE - UDP_HEADER_SIZE {
+            return Err(Fail::new(EMSGSIZE, "datagram too large"));
+        }
+
         // Fast path: try to send the datagram immediately.
         if let Some(link_addr) = self.arp.try_query(remote.ip().clone()) {
             Self::do_send(
                 self.rt.clone(),
-                self.local_ipv4_addr,
-                self.local_link_addr,
-                link_addr,
+                self.source_ipv4_addr.get().unwrap_or(self.local_ipv4_addr),
+                Ethernet2Header::new(link_addr, self.local_link_addr, EtherType2::Ipv4)
+                    .with_vlan_tag(self.arp.vlan_tag()),
                 data,
                 &local,
                 &remote,
@@ -296,12 +328,34 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
         }
         // Slow path: Defer send operation to the async path.
         else {
-            self.send_queue.push(SharedQueueSlot { local, remote, data })?
+            self.send_queue.push(SharedQueueSlot {
+                local,
+                remote,
+                data,
+                ttl: None,
+            })?
         }
 
         Ok(())
     }
 
+    /// Gets the local endpoint of a socket, which is unspecified if the socket is not bound.
+    pub fn local_endpoint(&self, qd: QDesc) -> Result<SocketAddrV4, Fail> {
+        match self.sockets.get(&qd) {
+            Some(Some(local)) => Ok(*local),
+            Some(None) => Ok(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+            None => Err(Fail::new(EBADF, "invalid queue descriptor")),
+        }
+    }
+
+    /// Returns the time to live of the last datagram that was popped from the target socket, if any.
+    pub fn received_ttl(&self, qd: QDesc) -> Result<Option<u8>, Fail> {
+        match self.received_ttls.get(&qd) {
+            Some(ttl) => Ok(ttl.get()),
+            None => Err(Fail::new(EBADF, "invalid queue descriptor")),
+        }
+    }
+
     /// Pops data from a socket.
     pub fn do_pop(&self, qd: QDesc) -> UdpPopFuture {
         #[cfg(feature = "profiler")]
@@ -312,9 +366,10 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
             Some(s) if s.is_some() => self.bound.get(&s.unwrap()).unwrap().clone(),
             _ => panic!("invalid queue descriptor"),
         };
+        let received_ttl: Rc<Cell<Option<u8>>> = self.received_ttls.get(&qd).unwrap().clone();
 
         // Issue pop operation.
-        UdpPopFuture::new(qd, recv_queue)
+        UdpPopFuture::new(qd, recv_queue, received_ttl)
     }
 
     /// Consumes the payload from a buffer.
@@ -329,8 +384,12 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
         let local: SocketAddrV4 = SocketAddrV4::new(ipv4_hdr.get_dest_addr(), hdr.dest_port());
         let remote: SocketAddrV4 = SocketAddrV4::new(ipv4_hdr.get_src_addr(), hdr.src_port());
 
-        // Lookup associated receiver-side shared queue.
-        let recv_queue: &mut SharedQueue<SharedQueueSlot<Buffer>> = match self.bound.get_mut(&local) {
+        // Lookup associated receiver-side shared queue, falling back to a socket that is bound to the wildcard address.
+        let key: SocketAddrV4 = match self.bound.contains_key(&local) {
+            true => local,
+            false => SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, hdr.dest_port()),
+        };
+        let recv_queue: &mut SharedQueue<SharedQueueSlot<Buffer>> = match self.bound.get_mut(&key) {
             Some(q) => q,
             // TODO: Send ICMPv4 error in this condition.
             None => Err(Fail::new(ENOTCONN, "port not bound"))?,
@@ -340,7 +399,15 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
 
         // Push data to the receiver-side shared queue. This will cause the
         // associated pool operation to be ready.
-        recv_queue.push(SharedQueueSlot { local, remote, data }).unwrap();
+        let ttl: Option<u8> = Some(ipv4_hdr.get_ttl());
+        recv_queue
+            .push(SharedQueueSlot {
+                local,
+                remote,
+                data,
+                ttl,
+            })
+            .unwrap();
 
         Ok(())
     }
@@ -349,8 +416,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
     fn do_send(
         rt: RT,
         local_ipv4_addr: Ipv4Addr,
-        local_link_addr: MacAddress,
-        remote_link_addr: MacAddress,
+        ethernet2_hdr: Ethernet2Header,
         buf: Buffer,
         local: &SocketAddrV4,
         remote: &SocketAddrV4,
@@ -358,9 +424,14 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> UdpPeer<RT> {
     ) {
         let udp_header: UdpHeader = UdpHeader::new(local.port(), remote.port());
         debug!("UDP send {:?}", udp_header);
+        // Datagrams of a socket that is bound to a specific address are sent from that address.
+        let src_ipv4_addr: Ipv4Addr = match local.ip().is_unspecified() {
+            true => local_ipv4_addr,
+            false => *local.ip(),
+        };
         let datagram = UdpDatagram::new(
-            Ethernet2Header::new(remote_link_addr, local_link_addr, EtherType2::Ipv4),
-            Ipv4Header::new(local_ipv4_addr, remote.ip().clone(), IpProtocol::UDP),
+            ethernet2_hdr,
+            Ipv4Header::new(src_ipv4_addr, remote.ip().clone(), IpProtocol::UDP),
             udp_header,
             buf,
             offload_checksum,
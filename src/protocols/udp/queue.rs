@@ -33,6 +33,8 @@ pub struct SharedQueueSlot<T> {
     pub remote: SocketAddrV4,
     /// Associated data.
     pub data: T,
+    /// Time to live of the datagram that carried the data, if it was received.
+    pub ttl: Option<u8>,
 }
 
 /// Shared Queue
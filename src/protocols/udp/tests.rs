@@ -1,7 +1,18 @@
 // // Copyright (c) Microsoft Corporation.
 // // Licensed under the MIT license.
 
-use crate::test_helpers;
+use crate::{
+    operations::OperationResult,
+    protocols::{
+        ethernet2::ETHERNET2_HEADER_SIZE,
+        ipv4::Ipv4Header,
+    },
+    test_helpers::{
+        self,
+        TestRuntime,
+    },
+    InetStack,
+};
 use ::futures::task::{
     noop_waker_ref,
     Context,
@@ -9,6 +20,7 @@ use ::futures::task::{
 use ::libc::{
     EADDRINUSE,
     EBADF,
+    EMSGSIZE,
     ENOTCONN,
 };
 use ::runtime::{
@@ -16,7 +28,10 @@ use ::runtime::{
         Buffer,
         DataBuffer,
     },
+    scheduler::SchedulerHandle,
+    task::SchedulerRuntime,
     QDesc,
+    QToken,
 };
 use ::std::{
     convert::TryFrom,
@@ -105,6 +120,51 @@ fn udp_push_pop() {
     bob.udp_close(bob_fd).unwrap();
 }
 
+/// Tests if the receiver of a datagram can read the time to live that it arrived with.
+#[test]
+fn udp_pop_ttl() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let ttl: u8 = 7;
+
+    // Setup Alice.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_port = 80;
+    let alice_addr = SocketAddrV4::new(test_helpers::ALICE_IPV4, alice_port);
+    let alice_fd: QDesc = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, alice_addr).unwrap();
+
+    // Setup Bob.
+    let mut bob = test_helpers::new_bob2(now);
+    let bob_port = 80;
+    let bob_addr = SocketAddrV4::new(test_helpers::BOB_IPV4, bob_port);
+    let bob_fd: QDesc = bob.udp_socket().unwrap();
+    bob.udp_bind(bob_fd, bob_addr).unwrap();
+
+    // Send data to Bob, rewriting the time to live of the datagram on the way.
+    let buf: Buffer = Buffer::Heap(DataBuffer::from(&vec![0x5a; 32][..]));
+    alice.udp_pushto(alice_fd, buf.clone(), bob_addr).unwrap();
+    alice.rt().poll_scheduler();
+    let mut frame: Buffer = alice.rt().pop_frame();
+    let ipv4_hdr: &mut [u8] = &mut frame[ETHERNET2_HEADER_SIZE..];
+    ipv4_hdr[8] = ttl;
+    let checksum: u16 = Ipv4Header::compute_checksum(ipv4_hdr);
+    ipv4_hdr[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    // Receive data from Alice.
+    bob.receive(frame).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+    assert_eq!(pop_future.get_ttl(), None);
+    match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+        Poll::Ready(Ok((remote_addr, received_buf))) => {
+            assert_eq!(remote_addr, alice_addr);
+            assert_eq!(received_buf[..], buf[..]);
+        },
+        _ => panic!("pop should have completed"),
+    }
+    assert_eq!(pop_future.get_ttl(), Some(ttl));
+}
+
 //==============================================================================
 // Ping Pong
 //==============================================================================
@@ -500,3 +560,89 @@ fn udp_push_bad_file_descriptor() {
     alice.udp_close(alice_fd).unwrap();
     bob.udp_close(bob_fd).unwrap();
 }
+
+#[test]
+fn udp_push_too_large() {
+    let now = Instant::now();
+
+    // Setup Alice.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_port = 80;
+    let alice_addr = SocketAddrV4::new(test_helpers::ALICE_IPV4, alice_port);
+    let alice_fd: QDesc = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, alice_addr).unwrap();
+    let bob_addr = SocketAddrV4::new(test_helpers::BOB_IPV4, 80);
+
+    // A datagram whose length overflows the IPv4 total length field.
+    let buf: Buffer = Buffer::Heap(DataBuffer::from(&vec![0x5a; u16::MAX as usize][..]));
+    match alice.udp_pushto(alice_fd, buf, bob_addr) {
+        Err(e) if e.errno == EMSGSIZE => Ok(()),
+        _ => Err(()),
+    }
+    .unwrap();
+
+    // Nothing was sent.
+    alice.rt().poll_scheduler();
+    assert!(alice.rt().pop_frame_unchecked().is_none());
+
+    alice.udp_close(alice_fd).unwrap();
+}
+
+//==============================================================================
+// Event Loop
+//==============================================================================
+
+/// Checks if the operation of a queue token has completed, without releasing it.
+fn has_completed(stack: &InetStack<TestRuntime>, qt: QToken) -> bool {
+    let mut handle: SchedulerHandle = stack.rt().get_handle(qt.into()).unwrap();
+    let completed: bool = handle.has_completed();
+    // Return the operation to the scheduler, as dropping its handle would free it.
+    handle.take_key();
+    completed
+}
+
+/// Tests if an operation completes when the stack is driven one step at a time.
+#[test]
+fn udp_pop_poll_frames() {
+    let now = Instant::now();
+
+    // Setup Alice.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_port = 80;
+    let alice_addr = SocketAddrV4::new(test_helpers::ALICE_IPV4, alice_port);
+    let alice_fd: QDesc = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, alice_addr).unwrap();
+
+    // Setup Bob, whose stack is driven by an event loop of its own.
+    let bob_rt: TestRuntime = test_helpers::new_bob2(now).rt().clone();
+    let mut bob: InetStack<TestRuntime> = InetStack::new(bob_rt.clone(), [0; 32]).unwrap();
+    let bob_port = 80;
+    let bob_addr = SocketAddrV4::new(test_helpers::BOB_IPV4, bob_port);
+    let bob_fd: QDesc = bob.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    bob.bind(bob_fd, bob_addr).unwrap();
+    let qt: QToken = bob.pop(bob_fd).unwrap();
+
+    // Nothing has arrived yet, so there are no frames to process.
+    assert!(!bob.poll_frames());
+    assert!(!has_completed(&bob, qt));
+
+    // Send data to Bob.
+    let buf = Buffer::Heap(DataBuffer::from_slice(&[0x5a; 32]));
+    alice.udp_pushto(alice_fd, buf.clone(), bob_addr).unwrap();
+    alice.rt().poll_scheduler();
+    bob_rt.push_frame(alice.rt().pop_frame());
+
+    // Drive Bob until it has run everything that the frame woke.
+    while bob.poll_frames() {}
+    assert!(has_completed(&bob, qt));
+
+    match bob.wait2(qt) {
+        Ok((qd, OperationResult::Pop(Some(remote_addr), received_buf)))
+            if qd == bob_fd && remote_addr == alice_addr && received_buf[..] == buf[..] =>
+        {
+            Ok(())
+        },
+        _ => Err(()),
+    }
+    .unwrap();
+}
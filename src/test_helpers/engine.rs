@@ -47,11 +47,10 @@ pub struct Engine<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> {
 }
 
 impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Engine<RT> {
-    pub fn new(rt: RT) -> Result<Self, Fail> {
+    pub fn new(rt: RT, rng_seed: [u8; 32]) -> Result<Self, Fail> {
         let now = rt.now();
         let file_table = IoQueueTable::new();
         let arp = ArpPeer::new(now, rt.clone(), rt.arp_options())?;
-        let rng_seed: [u8; 32] = [0; 32];
         let ipv4 = Peer::new(rt.clone(), arp.clone(), rng_seed);
         Ok(Engine {
             rt,
@@ -68,7 +67,7 @@ impl<RT: SchedulerRuntime + NetworkRuntime + Clone + 'static> Engine<RT> {
     pub fn receive(&mut self, bytes: Buffer) -> Result<(), Fail> {
         let (header, payload) = Ethernet2Header::parse(bytes)?;
         debug!("Engine received {:?}", header);
-        if self.rt.local_link_addr() != header.dst_addr() && !header.dst_addr().is_broadcast() {
+        if !header.is_destined_to(self.rt.local_link_addr()) {
             return Err(Fail::new(EBADMSG, "physical destination address mismatch"));
         }
         match header.ether_type() {
@@ -7,13 +7,16 @@ pub mod runtime;
 pub use self::runtime::TestRuntime;
 pub use engine::Engine;
 
-use ::runtime::network::{
-    config::{
-        ArpConfig,
-        TcpConfig,
-        UdpConfig,
+use ::runtime::{
+    memory::Buffer,
+    network::{
+        config::{
+            ArpConfig,
+            TcpConfig,
+            UdpConfig,
+        },
+        types::MacAddress,
     },
-    types::MacAddress,
 };
 use ::std::{
     collections::HashMap,
@@ -57,7 +60,8 @@ pub fn new_alice(now: Instant) -> Engine<TestRuntime> {
     let udp_options = UdpConfig::default();
     let tcp_options = TcpConfig::default();
     let rt = TestRuntime::new(now, arp_options, udp_options, tcp_options, ALICE_MAC, ALICE_IPV4);
-    Engine::new(rt).unwrap()
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    Engine::new(rt, rng_seed).unwrap()
 }
 
 pub fn new_bob(now: Instant) -> Engine<TestRuntime> {
@@ -71,7 +75,8 @@ pub fn new_bob(now: Instant) -> Engine<TestRuntime> {
     let udp_options = UdpConfig::default();
     let tcp_options = TcpConfig::default();
     let rt = TestRuntime::new(now, arp_options, udp_options, tcp_options, BOB_MAC, BOB_IPV4);
-    Engine::new(rt).unwrap()
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    Engine::new(rt, rng_seed).unwrap()
 }
 
 pub fn new_alice2(now: Instant) -> Engine<TestRuntime> {
@@ -88,7 +93,8 @@ pub fn new_alice2(now: Instant) -> Engine<TestRuntime> {
     let udp_options = UdpConfig::default();
     let tcp_options = TcpConfig::default();
     let rt = TestRuntime::new(now, arp_options, udp_options, tcp_options, ALICE_MAC, ALICE_IPV4);
-    Engine::new(rt).unwrap()
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    Engine::new(rt, rng_seed).unwrap()
 }
 
 pub fn new_bob2(now: Instant) -> Engine<TestRuntime> {
@@ -105,7 +111,8 @@ pub fn new_bob2(now: Instant) -> Engine<TestRuntime> {
     let udp_options = UdpConfig::default();
     let tcp_options = TcpConfig::default();
     let rt = TestRuntime::new(now, arp_options, udp_options, tcp_options, BOB_MAC, BOB_IPV4);
-    Engine::new(rt).unwrap()
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    Engine::new(rt, rng_seed).unwrap()
 }
 
 pub fn new_carrie(now: Instant) -> Engine<TestRuntime> {
@@ -120,5 +127,32 @@ pub fn new_carrie(now: Instant) -> Engine<TestRuntime> {
     let tcp_options = TcpConfig::default();
 
     let rt = TestRuntime::new(now, arp_options, udp_options, tcp_options, CARRIE_MAC, CARRIE_IPV4);
-    Engine::new(rt).unwrap()
+    let rng_seed: [u8; 32] = rt.rng_seed();
+    Engine::new(rt, rng_seed).unwrap()
+}
+
+/// Takes one deterministic step on a set of engines that share a link: each engine, in the given order, runs its
+/// ready tasks up to their next yield point, and then the frames that they sent are delivered, in the order in which
+/// they were sent. Frames that an engine rejects, such as frames addressed to another engine, are dropped. Returns
+/// whether any frames were sent.
+pub fn step(engines: &mut [&mut TestEngine]) -> bool {
+    let mut frames: Vec<Buffer> = Vec::new();
+    for engine in engines.iter_mut() {
+        engine.rt().poll_scheduler();
+        while let Some(frame) = engine.rt().pop_frame_unchecked() {
+            frames.push(frame);
+        }
+    }
+    for frame in &frames {
+        for engine in engines.iter_mut() {
+            let _ = engine.receive(frame.clone());
+        }
+    }
+    !frames.is_empty()
+}
+
+/// Steps a set of engines that share a link until they stop exchanging frames. The clock does not advance, so the
+/// outcome does not depend on timers.
+pub fn run_until_stalled(engines: &mut [&mut TestEngine]) {
+    while step(engines) {}
 }
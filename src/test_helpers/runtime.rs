@@ -73,6 +73,7 @@ pub struct TestRuntime {
     arp_options: ArpConfig,
     udp_options: UdpConfig,
     tcp_options: TcpConfig,
+    rng_seed: [u8; 32],
     inner: Rc<RefCell<Inner>>,
     scheduler: Scheduler,
 }
@@ -105,9 +106,21 @@ impl TestRuntime {
             arp_options,
             udp_options,
             tcp_options,
+            rng_seed: [0; 32],
         }
     }
 
+    /// Sets the seed of the random number generators of engines that are built on this runtime, so that their random
+    /// choices (e.g. of initial sequence number nonces) are reproducible.
+    pub fn seed_rng(&mut self, seed: [u8; 32]) {
+        self.rng_seed = seed;
+    }
+
+    /// Gets the seed of the random number generators of engines that are built on this runtime.
+    pub fn rng_seed(&self) -> [u8; 32] {
+        self.rng_seed
+    }
+
     pub fn pop_frame(&self) -> Buffer {
         self.inner
             .borrow_mut()
@@ -61,6 +61,8 @@ struct SharedDummyRuntime {
     incoming: crossbeam_channel::Receiver<DataBuffer>,
     /// Outgoing Queue of Packets
     outgoing: crossbeam_channel::Sender<DataBuffer>,
+    /// Number of Packets Dropped Because the Outgoing Queue Was Full
+    dropped: usize,
 }
 
 /// Dummy Runtime
@@ -102,6 +104,7 @@ impl DummyRuntime {
             timer: TimerRc(Rc::new(Timer::new(now))),
             incoming,
             outgoing,
+            dropped: 0,
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -112,6 +115,12 @@ impl DummyRuntime {
             arp_options,
         }
     }
+
+    /// Gets the number of packets that were dropped because the outgoing queue was full.
+    #[allow(dead_code)]
+    pub fn dropped_frames(&self) -> usize {
+        self.inner.borrow().dropped
+    }
 }
 
 //==============================================================================
@@ -129,7 +138,14 @@ impl NetworkRuntime for DummyRuntime {
         if let Some(body) = pkt.take_body() {
             buf[header_size..].copy_from_slice(&body[..]);
         }
-        self.inner.borrow_mut().outgoing.try_send(buf).unwrap();
+        // A full outgoing queue is backpressure from the link, so drop the packet as a congested link would. Reliable
+        // protocols keep it queued and retransmit it later.
+        let mut inner = self.inner.borrow_mut();
+        match inner.outgoing.try_send(buf) {
+            Ok(()) => (),
+            Err(crossbeam_channel::TrySendError::Full(_)) => inner.dropped += 1,
+            Err(crossbeam_channel::TrySendError::Disconnected(_)) => panic!("outgoing queue is disconnected"),
+        }
     }
 
     fn receive(&self) -> ArrayVec<Buffer, RECEIVE_BATCH_SIZE> {
@@ -25,6 +25,7 @@ use ::crossbeam_channel::{
     Sender,
 };
 use ::inetstack::{
+    blocking::BlockingInetStack,
     operations::OperationResult,
     InetStack,
 };
@@ -33,6 +34,8 @@ use ::runtime::{
         Buffer,
         DataBuffer,
     },
+    network::NetworkRuntime,
+    task::SchedulerRuntime,
     QDesc,
     QToken,
 };
@@ -45,6 +48,10 @@ use ::std::{
         self,
         JoinHandle,
     },
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 //======================================================================================================================
@@ -780,6 +787,207 @@ fn tcp_bad_pop() {
     bob.join().unwrap();
 }
 
+//======================================================================================================================
+// Local Endpoint
+//======================================================================================================================
+
+/// Tests if the local endpoint of a socket that is bound when connecting can be inspected.
+#[test]
+fn tcp_local_endpoint_unbound() {
+    pub const PORT_EPHEMERAL_BASE: u16 = 49152;
+    let (alice_tx, alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (_bob_tx, bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let mut libos: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, bob_rx, arp());
+
+    // A socket that is not bound has no local endpoint yet.
+    let sockqd: QDesc = safe_socket(&mut libos);
+    let unspecified: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
+    assert_eq!(libos.local_endpoint(sockqd).unwrap(), unspecified);
+
+    // Connecting binds the socket to an ephemeral port, which the SYN comes from.
+    let remote: SocketAddrV4 = SocketAddrV4::new(BOB_IPV4, PORT_BASE);
+    let _qt: QToken = safe_connect(&mut libos, sockqd, remote);
+    libos.poll_bg_work();
+    let syn: DataBuffer = alice_rx.try_recv().unwrap();
+    let syn_src_port: u16 = u16::from_be_bytes([syn[34], syn[35]]);
+
+    let local: SocketAddrV4 = libos.local_endpoint(sockqd).unwrap();
+    assert_eq!(*local.ip(), ALICE_IPV4);
+    assert_eq!(local.port(), syn_src_port);
+    assert!(local.port() >= PORT_EPHEMERAL_BASE);
+}
+
+//======================================================================================================================
+// Received Time to Live
+//======================================================================================================================
+
+/// Tests if the time to live that the last datagram of a connection arrived with can be read from its socket.
+#[test]
+fn tcp_received_ttl() {
+    let (alice_tx, alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (bob_tx, bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+
+    let alice: JoinHandle<()> = thread::spawn(move || {
+        let mut libos: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, bob_rx, arp());
+
+        let port: u16 = PORT_BASE;
+        let local: SocketAddrV4 = SocketAddrV4::new(ALICE_IPV4, port);
+
+        // Open connection.
+        let sockqd: QDesc = safe_socket(&mut libos);
+        safe_bind(&mut libos, sockqd, local);
+        safe_listen(&mut libos, sockqd);
+        let qt: QToken = safe_accept(&mut libos, sockqd);
+        let (_, qr): (QDesc, OperationResult) = safe_wait2(&mut libos, qt);
+        let qd: QDesc = match qr {
+            OperationResult::Accept(qd) => qd,
+            _ => panic!("accept() has failed"),
+        };
+
+        // A listening socket has no connection to read the time to live from.
+        assert!(libos.received_ttl(sockqd).is_err());
+
+        // Pop data.
+        let qt: QToken = safe_pop(&mut libos, qd);
+        let (qd, qr): (QDesc, OperationResult) = safe_wait2(&mut libos, qt);
+        match qr {
+            OperationResult::Pop(_, _) => (),
+            _ => panic!("pop() has has failed {:?}", qr),
+        }
+
+        // Bob sends every datagram with the largest time to live, and nothing decrements it on the way.
+        assert_eq!(libos.received_ttl(qd).unwrap(), Some(u8::MAX));
+
+        // Close connection.
+        safe_close_active(&mut libos, qd);
+        safe_close_passive(&mut libos, sockqd);
+    });
+
+    let bob: JoinHandle<()> = thread::spawn(move || {
+        let mut libos: InetStack<DummyRuntime> = DummyLibOS::new(BOB_MAC, BOB_IPV4, bob_tx, alice_rx, arp());
+
+        let port: u16 = PORT_BASE;
+        let remote: SocketAddrV4 = SocketAddrV4::new(ALICE_IPV4, port);
+
+        // Open connection.
+        let sockqd: QDesc = safe_socket(&mut libos);
+        let qt: QToken = safe_connect(&mut libos, sockqd, remote);
+        let (_, qr): (QDesc, OperationResult) = safe_wait2(&mut libos, qt);
+        match qr {
+            OperationResult::Connect => (),
+            _ => panic!("connect() has failed"),
+        }
+
+        // Cook some data.
+        let bytes: Buffer = DummyLibOS::cook_data(32);
+
+        // Push data.
+        let qt: QToken = safe_push2(&mut libos, sockqd, &bytes);
+        let (_, qr): (QDesc, OperationResult) = safe_wait2(&mut libos, qt);
+        match qr {
+            OperationResult::Push => (),
+            _ => panic!("push() has failed"),
+        }
+
+        // Close connection.
+        safe_close_active(&mut libos, sockqd);
+    });
+
+    alice.join().unwrap();
+    bob.join().unwrap();
+}
+
+//======================================================================================================================
+// Full Outgoing Queue
+//======================================================================================================================
+
+/// Tests if segments that do not fit in a full outgoing queue are dropped and retransmitted later, instead of
+/// crashing the sender.
+#[test]
+fn tcp_full_outgoing_queue() {
+    let (alice_tx, alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::bounded(1);
+    let (_bob_tx, bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+
+    let mut libos: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, bob_rx, arp());
+    let handshake_timeout: Duration = libos.rt().tcp_options().get_handshake_timeout();
+    let mut now: Instant = libos.rt().now();
+
+    // Send a SYN to a peer that never answers, which fills up the outgoing queue.
+    let remote: SocketAddrV4 = SocketAddrV4::new(BOB_IPV4, PORT_BASE);
+    let sockqd: QDesc = safe_socket(&mut libos);
+    let _qt: QToken = safe_connect(&mut libos, sockqd, remote);
+    libos.poll_bg_work();
+    assert_eq!(alice_rx.len(), 1);
+
+    // The retransmitted SYN does not fit in the outgoing queue.
+    now += handshake_timeout;
+    libos.rt().advance_clock(now);
+    libos.poll_bg_work();
+    assert_eq!(alice_rx.len(), 1);
+    assert_eq!(libos.rt().dropped_frames(), 1);
+
+    // Once the queue is drained, the next retransmission goes through.
+    alice_rx.try_recv().unwrap();
+    now += handshake_timeout;
+    libos.rt().advance_clock(now);
+    libos.poll_bg_work();
+    assert_eq!(alice_rx.len(), 1);
+    assert_eq!(libos.rt().dropped_frames(), 1);
+}
+
+//======================================================================================================================
+// Blocking API
+//======================================================================================================================
+
+/// Tests if a connection can be set up, used and torn down through the blocking API.
+#[test]
+fn tcp_blocking_api() {
+    let (alice_tx, alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (bob_tx, bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    // Keeps Bob around until Alice is done, as neither can send to a peer that has gone away.
+    let (done_tx, done_rx): (Sender<()>, Receiver<()>) = crossbeam_channel::bounded(1);
+    let data: Vec<u8> = (0..100).collect();
+    let expected: Vec<u8> = data.clone();
+
+    let alice: JoinHandle<()> = thread::spawn(move || {
+        let libos: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, bob_rx, arp());
+        let mut libos: BlockingInetStack<DummyRuntime> = BlockingInetStack::new(libos);
+
+        let local: SocketAddrV4 = SocketAddrV4::new(ALICE_IPV4, PORT_BASE);
+        let sockqd: QDesc = libos.listen(local, 8).unwrap();
+        let (qd, remote): (QDesc, SocketAddrV4) = libos.accept(sockqd).unwrap();
+        assert_eq!(*remote.ip(), BOB_IPV4);
+
+        // Read in small chunks until Bob closes the connection.
+        let mut received: Vec<u8> = Vec::new();
+        let mut buf: [u8; 16] = [0; 16];
+        loop {
+            match libos.read(qd, &mut buf).unwrap() {
+                0 => break,
+                nbytes => received.extend_from_slice(&buf[..nbytes]),
+            }
+        }
+        assert_eq!(received, expected);
+
+        libos.close(qd).unwrap();
+        done_tx.send(()).unwrap();
+    });
+
+    let bob: JoinHandle<()> = thread::spawn(move || {
+        let libos: InetStack<DummyRuntime> = DummyLibOS::new(BOB_MAC, BOB_IPV4, bob_tx, alice_rx, arp());
+        let mut libos: BlockingInetStack<DummyRuntime> = BlockingInetStack::new(libos);
+
+        let remote: SocketAddrV4 = SocketAddrV4::new(ALICE_IPV4, PORT_BASE);
+        let qd: QDesc = libos.connect(remote).unwrap();
+        assert_eq!(libos.write(qd, &data).unwrap(), data.len());
+        libos.close(qd).unwrap();
+        done_rx.recv().unwrap();
+    });
+
+    alice.join().unwrap();
+    bob.join().unwrap();
+}
+
 //======================================================================================================================
 // Standalone Functions
 //======================================================================================================================
@@ -26,6 +26,10 @@ use ::crossbeam_channel::{
 };
 use ::inetstack::{
     operations::OperationResult,
+    protocols::{
+        ethernet2::VlanTag,
+        ipv4::Ipv4Header,
+    },
     InetStack,
 };
 use ::runtime::{
@@ -270,3 +274,114 @@ fn udp_loopback() {
     alice.join().unwrap();
     bob.join().unwrap();
 }
+
+//==============================================================================
+// Link Address Filtering
+//==============================================================================
+
+/// Tests if frames that are addressed to some other host are dropped.
+#[test]
+fn udp_drop_link_addr_mismatch() {
+    let (alice_tx, _alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (bob_tx, bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let mut libos: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, bob_rx, arp());
+
+    // Ethernet header addressed to Bob, followed by a bogus IPv4 payload.
+    let mut frame: DataBuffer = DataBuffer::new(60).unwrap();
+    for b in &mut frame[..] {
+        *b = 0;
+    }
+    frame[0..6].copy_from_slice(&BOB_MAC.octets());
+    frame[6..12].copy_from_slice(&ALICE_MAC.octets());
+    frame[12..14].copy_from_slice(&[0x08, 0x00]);
+    bob_tx.send(frame).unwrap();
+
+    libos.poll_bg_work();
+    assert_eq!(libos.dropped_link_addr_mismatch(), 1);
+}
+
+//==============================================================================
+// VLAN Tagging
+//==============================================================================
+
+/// Tests if datagrams are sent with the configured 802.1Q tag.
+#[test]
+fn udp_push_vlan_tagged() {
+    let (alice_tx, alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (_bob_tx, bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let mut libos: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, bob_rx, arp());
+    libos.set_vlan_tag(Some(VlanTag::new(5, false, 42).unwrap()));
+
+    let sockfd: QDesc = libos.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    libos.bind(sockfd, SocketAddrV4::new(ALICE_IPV4, PORT_BASE)).unwrap();
+    let bytes: Buffer = DummyLibOS::cook_data(32);
+    let qt: QToken = libos
+        .pushto2(sockfd, &bytes, SocketAddrV4::new(BOB_IPV4, PORT_BASE))
+        .unwrap();
+    match libos.wait2(qt) {
+        Ok((_, OperationResult::Push)) => (),
+        _ => panic!("push() failed"),
+    }
+
+    // The tag sits between the source link address and the EtherType of the payload.
+    let frame: DataBuffer = alice_rx.try_recv().unwrap();
+    assert_eq!(&frame[12..14], &[0x81, 0x00]);
+    let tci: u16 = u16::from_be_bytes([frame[14], frame[15]]);
+    assert_eq!(tci >> 13, 5);
+    assert_eq!(tci & 0x0fff, 42);
+    assert_eq!(&frame[16..18], &[0x08, 0x00]);
+
+    libos.close(sockfd).unwrap();
+}
+
+//==============================================================================
+// Time to Live
+//==============================================================================
+
+/// Tests if the time to live that a datagram arrived with can be read from the socket that it was popped from.
+#[test]
+fn udp_received_ttl() {
+    let (alice_tx, alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (bob_tx, _bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (_to_alice_tx, to_alice_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let (to_bob_tx, to_bob_rx): (Sender<DataBuffer>, Receiver<DataBuffer>) = crossbeam_channel::unbounded();
+    let mut alice: InetStack<DummyRuntime> = DummyLibOS::new(ALICE_MAC, ALICE_IPV4, alice_tx, to_alice_rx, arp());
+    let mut bob: InetStack<DummyRuntime> = DummyLibOS::new(BOB_MAC, BOB_IPV4, bob_tx, to_bob_rx, arp());
+    let ttl: u8 = 7;
+
+    let alice_addr: SocketAddrV4 = SocketAddrV4::new(ALICE_IPV4, PORT_BASE);
+    let alice_sockfd: QDesc = alice.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    alice.bind(alice_sockfd, alice_addr).unwrap();
+    let bob_addr: SocketAddrV4 = SocketAddrV4::new(BOB_IPV4, PORT_BASE);
+    let bob_sockfd: QDesc = bob.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+    bob.bind(bob_sockfd, bob_addr).unwrap();
+
+    // Nothing has been popped yet.
+    assert_eq!(bob.received_ttl(bob_sockfd).unwrap(), None);
+
+    // Send data to Bob, rewriting the time to live of the datagram on the way.
+    let bytes: Buffer = DummyLibOS::cook_data(32);
+    let qt: QToken = alice.pushto2(alice_sockfd, &bytes, bob_addr).unwrap();
+    match alice.wait2(qt) {
+        Ok((_, OperationResult::Push)) => (),
+        _ => panic!("push() failed"),
+    }
+    let mut frame: DataBuffer = alice_rx.try_recv().unwrap();
+    let ipv4_hdr: &mut [u8] = &mut frame[14..];
+    ipv4_hdr[8] = ttl;
+    let checksum: u16 = Ipv4Header::compute_checksum(ipv4_hdr);
+    ipv4_hdr[10..12].copy_from_slice(&checksum.to_be_bytes());
+    to_bob_tx.send(frame).unwrap();
+
+    // Pop data.
+    let qt: QToken = bob.pop(bob_sockfd).unwrap();
+    match bob.wait2(qt) {
+        Ok((_, OperationResult::Pop(Some(remote), _))) => assert_eq!(remote, alice_addr),
+        _ => panic!("pop() failed"),
+    }
+    assert_eq!(bob.received_ttl(bob_sockfd).unwrap(), Some(ttl));
+
+    alice.close(alice_sockfd).unwrap();
+    bob.close(bob_sockfd).unwrap();
+    assert!(bob.received_ttl(bob_sockfd).is_err());
+}